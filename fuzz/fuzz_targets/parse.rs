@@ -0,0 +1,23 @@
+#![no_main]
+
+// Lexes, parses and checks arbitrary text, which must give errors rather
+// than panic. Run with `cargo fuzz run parse` from the repository root.
+
+use bbvm::{check, lex_all, Lexer};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let source = match std::str::from_utf8(data) {
+        Ok(source) => source,
+        Err(_) => return,
+    };
+    if let Ok(mut program) = bbvm::parse(source) {
+        // What bbvm does to the tree before lowering it
+        let live = bbvm::prune::live(&program.body);
+        bbvm::prune::prune(&mut program.body, &live);
+        bbvm::simplify::simplify(&mut program.body);
+        let _ = program.to_string();
+    }
+    let (statements, spans, _) = lex_all(&mut Lexer::new(source));
+    check::check(source, &statements, &spans);
+});
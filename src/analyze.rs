@@ -0,0 +1,272 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::lexer::Span;
+use crate::lint;
+use crate::parser::{Block, Node, Program};
+use crate::token::{Bound, Call, Statement, While};
+
+// One while loop, with everything read and written anywhere inside it,
+// including in the loops it holds and the procs they call
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Loop<'a> {
+    pub header: String,
+    pub span: Span,
+    // 1 for a loop that isn't inside another
+    pub depth: usize,
+    pub reads: BTreeSet<&'a str>,
+    pub writes: BTreeSet<&'a str>,
+}
+
+// What `bbvm analyze` reports about a program, found without running it
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Analysis<'a> {
+    // How deeply the whiles are nested as written
+    pub max_depth: usize,
+    // How deeply they are nested once each call counts the loops of the
+    // proc it calls, the power of n in the estimate
+    pub order: usize,
+    // Whether a proc calls itself, so there is no bound to give
+    pub recursive: bool,
+    pub loops: Vec<Loop<'a>>,
+}
+
+impl Analysis<'_> {
+    // The estimate as big-O: each loop is taken to run up to n times, n
+    // growing with the inputs. It only counts nesting, so a loop running
+    // to a value an earlier loop built up is still one n.
+    pub fn complexity(&self) -> String {
+        match self.order {
+            _ if self.recursive => "unknown (recursive procs)".to_string(),
+            0 => "O(1)".to_string(),
+            1 => "O(n)".to_string(),
+            order => format!("O(n^{})", order),
+        }
+    }
+
+    pub fn render(&self, filename: &str) -> String {
+        let mut out = format!(
+            "{}\n  max loop nesting: {}\n  estimated complexity: {}\n",
+            filename,
+            self.max_depth,
+            self.complexity()
+        );
+        for l in &self.loops {
+            let join =
+                |names: &BTreeSet<&str>| names.iter().copied().collect::<Vec<_>>().join(", ");
+            out.push_str(&format!(
+                "  line {}: {} (depth {})\n    reads: {}\n    writes: {}\n",
+                l.span.line,
+                l.header,
+                l.depth,
+                join(&l.reads),
+                join(&l.writes)
+            ));
+        }
+        out
+    }
+
+    // The same as one JSON object
+    pub fn render_json(&self, filename: &str) -> String {
+        let list = |names: &BTreeSet<&str>| {
+            names
+                .iter()
+                .map(|n| format!("\"{}\"", n))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let loops: Vec<String> = self
+            .loops
+            .iter()
+            .map(|l| {
+                format!(
+                    "{{\"line\": {}, \"header\": \"{}\", \"depth\": {}, \"reads\": [{}], \"writes\": [{}]}}",
+                    l.span.line,
+                    l.header,
+                    l.depth,
+                    list(&l.reads),
+                    list(&l.writes)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"file\": \"{}\", \"max_depth\": {}, \"order\": {}, \"recursive\": {}, \"complexity\": \"{}\", \"loops\": [{}]}}\n",
+            filename.replace('\\', "\\\\").replace('"', "\\\""),
+            self.max_depth,
+            self.order,
+            self.recursive,
+            self.complexity(),
+            loops.join(", ")
+        )
+    }
+}
+
+// What a proc reads and writes and how deeply its loops nest, counting the
+// procs it calls
+#[derive(Debug, Default, Clone)]
+struct Summary<'a> {
+    reads: BTreeSet<&'a str>,
+    writes: BTreeSet<&'a str>,
+    order: usize,
+}
+
+struct Analyzer<'p, 'a> {
+    procs: HashMap<&'a str, &'p Block<'a>>,
+    summaries: HashMap<&'a str, Summary<'a>>,
+    // The procs being summarised, to catch them calling themselves
+    visiting: Vec<&'a str>,
+    recursive: bool,
+}
+
+impl<'p, 'a> Analyzer<'p, 'a> {
+    fn proc(&mut self, name: &'a str) -> Summary<'a> {
+        if let Some(summary) = self.summaries.get(name) {
+            return summary.clone();
+        }
+        if self.visiting.contains(&name) {
+            self.recursive = true;
+            return Summary::default();
+        }
+        let body = match self.procs.get(name) {
+            Some(&body) => body,
+            // Reported by the parser
+            None => return Summary::default(),
+        };
+        self.visiting.push(name);
+        let summary = self.block(body, 0, &mut None);
+        self.visiting.pop();
+        self.summaries.insert(name, summary.clone());
+        summary
+    }
+
+    // The summary of the block, depth loops in, adding its loops to loops if
+    // they are being collected
+    fn block(
+        &mut self,
+        block: &'p Block<'a>,
+        depth: usize,
+        loops: &mut Option<&mut Vec<Loop<'a>>>,
+    ) -> Summary<'a> {
+        let mut summary = Summary::default();
+        for node in block {
+            match node {
+                Node::Statement(Statement::Call(Call { name }), _) => {
+                    let called = self.proc(name.ident);
+                    summary.reads.extend(called.reads);
+                    summary.writes.extend(called.writes);
+                    summary.order = summary.order.max(called.order);
+                }
+                Node::Statement(statement, _) => {
+                    let (reads, writes) = lint::reads_and_writes(statement);
+                    summary.reads.extend(reads);
+                    summary.writes.extend(writes);
+                }
+                Node::Loop {
+                    var,
+                    cmp,
+                    check,
+                    body,
+                    span,
+                    ..
+                } => {
+                    // Filled in once the body has been, so loops stay in
+                    // source order
+                    let index = loops.as_mut().map(|loops| {
+                        loops.push(Loop {
+                            header: Statement::While(While {
+                                param: *var,
+                                cmp: *cmp,
+                                bound: *check,
+                            })
+                            .to_string(),
+                            span: *span,
+                            depth: depth + 1,
+                            reads: BTreeSet::new(),
+                            writes: BTreeSet::new(),
+                        });
+                        loops.len() - 1
+                    });
+                    let mut inner = self.block(body, depth + 1, loops);
+                    inner.reads.insert(var.ident);
+                    if let Bound::Variable(bound) = check {
+                        inner.reads.insert(bound.ident);
+                    }
+                    if let (Some(loops), Some(index)) = (loops.as_mut(), index) {
+                        loops[index].reads = inner.reads.clone();
+                        loops[index].writes = inner.writes.clone();
+                    }
+                    summary.reads.extend(inner.reads);
+                    summary.writes.extend(inner.writes);
+                    summary.order = summary.order.max(inner.order + 1);
+                }
+                Node::If {
+                    var,
+                    then,
+                    otherwise,
+                    ..
+                } => {
+                    summary.reads.insert(var.ident);
+                    let otherwise = otherwise.iter().map(|(_, block)| block);
+                    for branch in std::iter::once(then).chain(otherwise) {
+                        let inner = self.block(branch, depth, loops);
+                        summary.reads.extend(inner.reads);
+                        summary.writes.extend(inner.writes);
+                        summary.order = summary.order.max(inner.order);
+                    }
+                }
+                // Counted where they are called
+                Node::Proc { .. } => {}
+            }
+        }
+        summary
+    }
+}
+
+// How deeply the whiles in the block are nested as written
+fn nesting(block: &Block) -> usize {
+    block
+        .iter()
+        .map(|node| match node {
+            Node::Statement(..) => 0,
+            Node::Loop { body, .. } => nesting(body) + 1,
+            Node::If {
+                then, otherwise, ..
+            } => nesting(then).max(otherwise.as_ref().map_or(0, |(_, o)| nesting(o))),
+            Node::Proc { body, .. } => nesting(body),
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+pub fn analyze<'a>(program: &Program<'a>) -> Analysis<'a> {
+    let procs = program
+        .body
+        .iter()
+        .filter_map(|node| match node {
+            Node::Proc { name, body, .. } => Some((name.ident, body)),
+            _ => None,
+        })
+        .collect();
+    let mut analyzer = Analyzer {
+        procs,
+        summaries: HashMap::new(),
+        visiting: vec![],
+        recursive: false,
+    };
+    let mut loops = vec![];
+    let order = analyzer
+        .block(&program.body, 0, &mut Some(&mut loops))
+        .order;
+    // Loops in procs are listed too, as if the proc were at the top level
+    for node in &program.body {
+        if let Node::Proc { body, .. } = node {
+            analyzer.block(body, 0, &mut Some(&mut loops));
+        }
+    }
+    loops.sort_by_key(|l| l.span.start);
+    Analysis {
+        max_depth: nesting(&program.body),
+        order,
+        recursive: analyzer.recursive,
+        loops,
+    }
+}
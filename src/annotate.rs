@@ -0,0 +1,80 @@
+// Source lines as comments in the IR and assembly bbvm writes, for
+// --annotate-ir. Which instructions came from which line is read from the
+// debug info: each instruction's !dbg location in the IR, and the .loc
+// directives it becomes in the assembly.
+
+use std::collections::HashMap;
+
+// The comment for a line of source, or None for a blank one or one that
+// isn't in the source, such as line 0 for code no statement made
+fn describe(lines: &[&str], line: usize) -> Option<String> {
+    let text = lines.get(line.checked_sub(1)?)?.trim();
+    (!text.is_empty()).then(|| format!("line {}: {}", line, text))
+}
+
+// The line of a `!N = !DILocation(line: L, ...)` definition
+fn location(definition: &str) -> Option<(&str, usize)> {
+    let (id, rest) = definition.split_once(" = ")?;
+    let rest = rest.strip_prefix("distinct ").unwrap_or(rest);
+    let line = rest.strip_prefix("!DILocation(line: ")?;
+    let digits = line.find(|c: char| !c.is_ascii_digit())?;
+    Some((id, line[..digits].parse().ok()?))
+}
+
+// The IR with `; line N: ...` before the first instruction of each run of
+// instructions lowered from the same line of source
+pub fn ir(ir: &str, source: &str) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let locations: HashMap<&str, usize> = ir.lines().filter_map(location).collect();
+    let mut out = String::with_capacity(ir.len());
+    let mut last = None;
+    for text in ir.lines() {
+        if text.starts_with("define ") {
+            last = None;
+        }
+        let line = text
+            .rsplit_once("!dbg ")
+            .and_then(|(_, id)| id.split(|c: char| c == ',' || c.is_whitespace()).next())
+            .and_then(|id| locations.get(id).copied());
+        if line.is_some() && line != last {
+            if let Some(comment) = line.and_then(|line| describe(&lines, line)) {
+                let indent = &text[..text.len() - text.trim_start().len()];
+                out.push_str(&format!("{}; {}\n", indent, comment));
+            }
+            last = line;
+        }
+        out.push_str(text);
+        out.push('\n');
+    }
+    out
+}
+
+// The assembly with `<comment> line N: ...` after each .loc directive that
+// moves to another line of source, comment being how the target's
+// assembler starts a comment
+pub fn asm(asm: &str, source: &str, comment: &str) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut out = String::with_capacity(asm.len());
+    let mut last = None;
+    for text in asm.lines() {
+        out.push_str(text);
+        out.push('\n');
+        // A function's own label, rather than one of the .L labels inside
+        // it, starts it afresh
+        if !text.starts_with(char::is_whitespace) && !text.starts_with('.') && text.ends_with(':') {
+            last = None;
+        }
+        let mut words = text.split_whitespace();
+        if words.next() != Some(".loc") {
+            continue;
+        }
+        let line = words.nth(1).and_then(|line| line.parse().ok());
+        if line.is_some() && line != last {
+            if let Some(description) = line.and_then(|line| describe(&lines, line)) {
+                out.push_str(&format!("\t{} {}\n", comment, description));
+            }
+            last = line;
+        }
+    }
+    out
+}
@@ -0,0 +1,470 @@
+use std::collections::HashMap;
+
+use crate::output::OutputFormat;
+use crate::parser::{Block, Node, Program};
+use crate::random;
+use crate::token::{
+    Array, Assert, Bound, Call, Comparison, ElementOp, Identifier, Indexed, OneParam, OneParamType,
+    Rand, Set, Statement, TwoParam, TwoParamType,
+};
+
+// A variable's name in C. Every one is prefixed, so none can clash with a
+// keyword or a name from the headers or the generated code, and as
+// BareBones names start with a letter no two can clash either.
+fn name(var: &str) -> String {
+    format!("_bb_{}", var)
+}
+
+// A proc's name in C, with a prefix of its own so it can't clash with a
+// variable
+fn proc_name(proc: &str) -> String {
+    format!("_bbp_{}", proc)
+}
+
+fn number(n: i128) -> String {
+    format!("{}ULL", n as u64)
+}
+
+// s as the inside of a C string literal
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// A printf of format with value in place of its %lld, printed as an i64
+// as the JIT prints 64 bit variables
+fn printf(format: &str, value: &str) -> String {
+    format!(
+        "printf(\"{}\", (int64_t){});",
+        escape(format).replace("%lld", "%\" PRId64 \""),
+        value
+    )
+}
+
+fn condition(var: Identifier, cmp: Comparison, check: Bound) -> String {
+    let op = match cmp {
+        Comparison::Ne => "!=",
+        Comparison::Lt => "<",
+        Comparison::Gt => ">",
+    };
+    let check = match check {
+        Bound::Number(n) => number(n.value),
+        Bound::Variable(v) => name(v.ident),
+    };
+    format!("{} {} {}", name(var.ident), op, check)
+}
+
+struct Writer<'a> {
+    out: String,
+    depth: usize,
+    detect_overflow: bool,
+    // The size of each array
+    sizes: HashMap<&'a str, u64>,
+}
+
+impl<'a> Writer<'a> {
+    fn line(&mut self, text: &str) -> () {
+        self.out.push_str(&"    ".repeat(self.depth));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    // Prints message (with %lld standing for value, if any) and exits with
+    // status 1, as the JIT's runtime failures do
+    fn fail_if(&mut self, condition: &str, message: &str, value: Option<&str>) -> () {
+        self.line(&format!("if ({}) {{", condition));
+        self.depth += 1;
+        match value {
+            Some(value) => self.line(&printf(message, value)),
+            None => self.line(&format!("printf(\"{}\");", escape(message))),
+        }
+        self.line("exit(1);");
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn block(&mut self, block: &Block) -> () {
+        for node in block {
+            match node {
+                Node::Statement(statement, span) => self.statement(statement, span.line),
+                Node::Loop {
+                    var,
+                    cmp,
+                    check,
+                    body,
+                    ..
+                } => {
+                    self.line(&format!("while ({}) {{", condition(*var, *cmp, *check)));
+                    self.depth += 1;
+                    self.block(body);
+                    self.depth -= 1;
+                    self.line("}");
+                }
+                Node::If {
+                    var,
+                    cmp,
+                    check,
+                    then,
+                    otherwise,
+                    ..
+                } => {
+                    self.line(&format!(
+                        "if ({}) {{",
+                        condition(*var, *cmp, Bound::Number(*check))
+                    ));
+                    self.depth += 1;
+                    self.block(then);
+                    self.depth -= 1;
+                    if let Some((_, otherwise)) = otherwise {
+                        self.line("} else {");
+                        self.depth += 1;
+                        self.block(otherwise);
+                        self.depth -= 1;
+                    }
+                    self.line("}");
+                }
+                // Written out as functions of their own
+                Node::Proc { .. } => {}
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement, line: usize) -> () {
+        match *statement {
+            Statement::OneParam(OneParam { one, ty }) => {
+                let var = name(one.ident);
+                match ty {
+                    OneParamType::Clear => self.line(&format!("{} = 0;", var)),
+                    OneParamType::Decr => self.line(&format!("if ({0}) {0}--;", var)),
+                    OneParamType::Incr => {
+                        if self.detect_overflow {
+                            self.fail_if(
+                                &format!("{} == UINT64_MAX", var),
+                                &format!(
+                                    "ERROR[E0006]: incr {} on line {} overflowed\n",
+                                    one.ident, line
+                                ),
+                                None,
+                            );
+                        }
+                        self.line(&format!("{}++;", var));
+                    }
+                    // Read before the program starts
+                    OneParamType::Input => {}
+                    OneParamType::Print => {
+                        self.line(&printf(&format!("{}: %lld\n", one.ident), &var))
+                    }
+                    OneParamType::Halt => self.line(&format!("exit((int){});", var)),
+                }
+            }
+            Statement::TwoParam(TwoParam { one, two, ty }) => {
+                let (from, to) = (name(one.ident), name(two.ident));
+                let overflow = match ty {
+                    TwoParamType::Add => Some(format!("{} > UINT64_MAX - {}", to, from)),
+                    TwoParamType::Mul => {
+                        Some(format!("{0} != 0 && {1} > UINT64_MAX / {0}", from, to))
+                    }
+                    _ => None,
+                };
+                if let (Some(overflow), true) = (overflow, self.detect_overflow) {
+                    self.fail_if(
+                        &overflow,
+                        &format!(
+                            "ERROR[E0006]: {} {} on line {} overflowed\n",
+                            ty.keyword(),
+                            two.ident,
+                            line
+                        ),
+                        None,
+                    );
+                }
+                match ty {
+                    TwoParamType::Copy => self.line(&format!("{} = {};", to, from)),
+                    TwoParamType::Add => self.line(&format!("{} += {};", to, from)),
+                    TwoParamType::Sub => {
+                        self.line(&format!("{0} = {0} > {1} ? {0} - {1} : 0;", to, from))
+                    }
+                    TwoParamType::Mul => self.line(&format!("{} *= {};", to, from)),
+                    TwoParamType::Swap => self.line(&format!(
+                        "{{ uint64_t _swap = {0}; {0} = {1}; {1} = _swap; }}",
+                        from, to
+                    )),
+                    TwoParamType::Move => self.line(&format!("{1} = {0}; {0} = 0;", from, to)),
+                }
+            }
+            Statement::Assert(Assert { param, num }) => self.fail_if(
+                &format!("{} != {}", name(param.ident), number(num.value)),
+                &format!(
+                    "ERROR[E0005]: assertion failed on line {}: {} is %lld, expected {}\n",
+                    line, param.ident, num.value
+                ),
+                Some(&name(param.ident)),
+            ),
+            Statement::Set(Set { var, num }) => {
+                self.line(&format!("{} = {};", name(var.ident), number(num.value)))
+            }
+            Statement::Rand(Rand { var, bound }) => self.line(&format!(
+                "{} = bbvm_rand({});",
+                name(var.ident),
+                number(bound.value)
+            )),
+            Statement::Call(Call { name }) => self.line(&format!("{}();", proc_name(name.ident))),
+            Statement::Indexed(Indexed { element, op }) => {
+                let (array, index) = (element.array.ident, element.index.ident);
+                let size = self.sizes[array];
+                self.fail_if(
+                    &format!("{} >= {}", name(index), number(size as i128)),
+                    &format!(
+                        "ERROR[E0027]: {0}[{1}] on line {2} is out of bounds: {1} is %lld, but {0} has {3} elements\n",
+                        array, index, line, size
+                    ),
+                    Some(&name(index)),
+                );
+                let element = format!("{}[{}]", name(array), name(index));
+                match op {
+                    ElementOp::Clear => self.line(&format!("{} = 0;", element)),
+                    ElementOp::Decr => self.line(&format!("if ({0}) {0}--;", element)),
+                    ElementOp::Incr => {
+                        if self.detect_overflow {
+                            self.fail_if(
+                                &format!("{} == UINT64_MAX", element),
+                                &format!(
+                                    "ERROR[E0006]: incr {}[{}] on line {} overflowed\n",
+                                    array, index, line
+                                ),
+                                None,
+                            );
+                        }
+                        self.line(&format!("{}++;", element));
+                    }
+                    ElementOp::Load(to) => self.line(&format!("{} = {};", name(to.ident), element)),
+                    ElementOp::Store(from) => {
+                        self.line(&format!("{} = {};", element, name(from.ident)))
+                    }
+                }
+            }
+            Statement::Breakpoint => self.line("/* breakpoint */"),
+            // Declared before main
+            Statement::Array(_) => {}
+            // Not in a parsed program
+            _ => {}
+        }
+    }
+}
+
+// Calls f with every statement in block, and in the blocks inside it
+fn walk<'a>(block: &Block<'a>, f: &mut impl FnMut(&Statement<'a>)) -> () {
+    for node in block {
+        match node {
+            Node::Statement(statement, _) => f(statement),
+            Node::Loop { body, .. } | Node::Proc { body, .. } => walk(body, f),
+            Node::If {
+                then, otherwise, ..
+            } => {
+                walk(then, f);
+                if let Some((_, otherwise)) = otherwise {
+                    walk(otherwise, f);
+                }
+            }
+        }
+    }
+}
+
+// The program as C, with a uint64_t for each variable and a function for
+// each proc. The inputs come from the command line in sorted order of
+// their names, or stdin without them, and printed's final values are
+// printed as format says at the end.
+pub fn emit(
+    program: &Program,
+    source_name: &str,
+    variables: &[&str],
+    inputs: &[&str],
+    printed: &[&str],
+    format: OutputFormat,
+    detect_overflow: bool,
+) -> String {
+    let mut declared: Vec<Array> = vec![];
+    let mut uses_rand = false;
+    walk(&program.body, &mut |statement| match statement {
+        Statement::Array(array) => declared.push(*array),
+        Statement::Rand(_) => uses_rand = true,
+        _ => {}
+    });
+    let mut w = Writer {
+        out: format!("/* Generated by bbvm from {} */\n", source_name),
+        depth: 0,
+        detect_overflow,
+        sizes: declared
+            .iter()
+            .map(|a| (a.name.ident, a.size.value as u64))
+            .collect(),
+    };
+    if uses_rand {
+        w.out.push_str(random::RUNTIME);
+    }
+    w.line("#include <inttypes.h>");
+    w.line("#include <stdio.h>");
+    w.line("#include <stdlib.h>");
+    w.line("");
+
+    if !variables.is_empty() {
+        for var in variables {
+            w.line(&format!("static uint64_t {};", name(var)));
+        }
+        w.line("");
+    }
+    if !declared.is_empty() {
+        for array in &declared {
+            w.line(&format!(
+                "static uint64_t {}[{}];",
+                name(array.name.ident),
+                number(array.size.value)
+            ));
+        }
+        w.line("");
+    }
+
+    let procs: Vec<_> = program
+        .body
+        .iter()
+        .filter_map(|node| match node {
+            Node::Proc { name, body, .. } => Some((name.ident, body)),
+            _ => None,
+        })
+        .collect();
+    for (proc, _) in &procs {
+        w.line(&format!("static void {}(void);", proc_name(proc)));
+    }
+    for (proc, body) in &procs {
+        w.line("");
+        w.line(&format!("static void {}(void) {{", proc_name(proc)));
+        w.depth += 1;
+        w.block(body);
+        w.depth -= 1;
+        w.line("}");
+    }
+    if !procs.is_empty() {
+        w.line("");
+    }
+
+    if !inputs.is_empty() {
+        w.line("static uint64_t read_input(const char *name) {");
+        w.depth += 1;
+        w.line("unsigned long long value;");
+        w.line("if (scanf(\"%llu\", &value) != 1) {");
+        w.depth += 1;
+        w.line("fprintf(stderr, \"ERROR: no value was given for %s\\n\", name);");
+        w.line("exit(1);");
+        w.depth -= 1;
+        w.line("}");
+        w.line("return value;");
+        w.depth -= 1;
+        w.line("}");
+        w.line("");
+    }
+
+    w.line("int main(int argc, char **argv) {");
+    w.depth += 1;
+    if inputs.is_empty() {
+        w.line("(void)argc;");
+        w.line("(void)argv;");
+    } else {
+        w.line(&format!("if (argc > 1 && argc != {}) {{", inputs.len() + 1));
+        w.depth += 1;
+        w.line(&format!(
+            "fprintf(stderr, \"ERROR: expected {} inputs: {}\\n\");",
+            inputs.len(),
+            inputs.join(", ")
+        ));
+        w.line("return 1;");
+        w.depth -= 1;
+        w.line("}");
+        for (i, input) in inputs.iter().enumerate() {
+            w.line(&format!(
+                "{} = argc > 1 ? strtoull(argv[{}], NULL, 10) : read_input(\"{}\");",
+                name(input),
+                i + 1,
+                input
+            ));
+        }
+    }
+    w.block(&program.body);
+
+    if printed.is_empty() && !format.empty().is_empty() {
+        w.line(&format!("printf(\"{}\");", escape(format.empty())));
+    }
+    for (i, var) in printed.iter().enumerate() {
+        w.line(&printf(&format.line(var, i, printed.len()), &name(var)));
+    }
+    w.line("return 0;");
+    w.depth -= 1;
+    w.line("}");
+    w.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emits_a_small_program() {
+        // stdin is a BareBones variable like any other, so it mustn't clash
+        // with the one stdio.h declares
+        let program = crate::parse(
+            "input N; while N not 0 do; incr stdin; decr N; end; call double;
+             proc double; add stdin to stdin; endproc;",
+        )
+        .unwrap();
+        let c = emit(
+            &program,
+            "test.bb",
+            &["N", "stdin"],
+            &["N"],
+            &["stdin"],
+            OutputFormat::Plain,
+            false,
+        );
+        assert_eq!(
+            c,
+            r#"/* Generated by bbvm from test.bb */
+#include <inttypes.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+static uint64_t _bb_N;
+static uint64_t _bb_stdin;
+
+static void _bbp_double(void);
+
+static void _bbp_double(void) {
+    _bb_stdin += _bb_stdin;
+}
+
+static uint64_t read_input(const char *name) {
+    unsigned long long value;
+    if (scanf("%llu", &value) != 1) {
+        fprintf(stderr, "ERROR: no value was given for %s\n", name);
+        exit(1);
+    }
+    return value;
+}
+
+int main(int argc, char **argv) {
+    if (argc > 1 && argc != 2) {
+        fprintf(stderr, "ERROR: expected 1 inputs: N\n");
+        return 1;
+    }
+    _bb_N = argc > 1 ? strtoull(argv[1], NULL, 10) : read_input("N");
+    while (_bb_N != 0ULL) {
+        _bb_stdin++;
+        if (_bb_N) _bb_N--;
+    }
+    _bbp_double();
+    printf("stdin %" PRId64 "\n", (int64_t)_bb_stdin);
+    return 0;
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    fs, io,
+    iter::zip,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use chrono::{Duration, Utc};
+use inkwell::{
+    context::Context,
+    targets::{InitializationConfig, Target},
+    OptimizationLevel,
+};
+
+use crate::convert::{self, Converter, Temps};
+use crate::error::BbvmError;
+use crate::ext::{self, Extension};
+use crate::include::Source;
+use crate::input;
+use crate::interpret::Interpreter;
+use crate::lexer::Lexer;
+use crate::link::LinkOptions;
+use crate::runner::Stopped;
+use crate::suite;
+use crate::{collect_inputs, collect_variables, lex, lower};
+
+// How long a program took to compile, from reading it to having machine
+// code, and then to run
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Timing {
+    pub compile: Duration,
+    pub run: Duration,
+}
+
+// The inputs from the program's .expected file if it has one, as `bbvm
+// test` would run it, or else all 0
+fn input_values(program: &Path, inputs: &[&str]) -> Result<Vec<u64>, String> {
+    let text = match fs::read_to_string(program.with_extension("expected")) {
+        Ok(text) => text,
+        Err(_) => return Ok(vec![0; inputs.len()]),
+    };
+    let expected = suite::parse_expected(&text)?;
+    input::from_file(inputs, &expected.inputs).and_then(|args| input::parse_args(inputs, &args))
+}
+
+// Compiles and runs one program under the JIT, in a context of its own
+fn time(
+    program: &Path,
+    level: OptimizationLevel,
+    extensions: &[Extension],
+) -> Result<Timing, String> {
+    let start = Utc::now();
+    let source = Source::expand(program).map_err(|(_, rendered)| rendered)?;
+    let render = |e: BbvmError| source.render_error(&e);
+    let (statements, spans) = lex(&mut Lexer::new(&source.text)).map_err(render)?;
+    ext::check(&statements, &spans, extensions).map_err(render)?;
+    let (variables, inputs) = (collect_variables(&statements), collect_inputs(&statements));
+    let values = input_values(program, &inputs).map_err(|e| format!("bad inputs: {}", e))?;
+
+    let context = Context::create();
+    let mut converter = Converter::new(variables, &inputs, &context);
+    converter.set_optimization_level(level);
+    lower(&mut converter, &statements, &spans, &source.text, false).map_err(render)?;
+    converter.optimise();
+    let lowered = Utc::now() - start;
+    let (codegen, run, _, stopped) = converter.run_for_results(&values);
+    if stopped == Some(Stopped::Failed) {
+        return Err("it failed at run time".to_string());
+    }
+    Ok(Timing {
+        compile: lowered + codegen,
+        run,
+    })
+}
+
+// Compiles and runs every program under the JIT, jobs at a time on threads
+// of their own, giving each one's timing in the same order. A program that
+// crashed bbvm's thread has None. A program that halts is timed up to its
+// halt, and one that fails has an error.
+pub fn bench(
+    programs: Vec<PathBuf>,
+    level: OptimizationLevel,
+    extensions: &[Extension],
+    jobs: usize,
+) -> Vec<Option<Result<Timing, String>>> {
+    // Once, before any thread makes a JIT
+    Target::initialize_native(&InitializationConfig::default())
+        .expect("ERROR: Failed to initialize LLVM");
+    let programs = Arc::new(programs);
+    let results = Arc::new(Mutex::new(vec![None; programs.len()]));
+    let next = Arc::new(AtomicUsize::new(0));
+    let workers: Vec<_> = (0..jobs.clamp(1, programs.len().max(1)))
+        .map(|_| {
+            let (programs, results, next) = (programs.clone(), results.clone(), next.clone());
+            let extensions = extensions.to_vec();
+            thread::spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let program = match programs.get(i) {
+                    Some(program) => program,
+                    None => break,
+                };
+                let timing = time(program, level, &extensions);
+                results.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(timing);
+            })
+        })
+        .collect();
+    for worker in workers {
+        // A panic has already been reported by the hook
+        worker.join().ok();
+    }
+    let results = results.lock().unwrap_or_else(|e| e.into_inner());
+    results.clone()
+}
+
+// What `bbvm bench prog.bb` runs the program with
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Backend {
+    Jit,
+    Aot,
+    Interpreter,
+}
+
+impl Backend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Jit => "jit",
+            Backend::Aot => "aot",
+            Backend::Interpreter => "interpreter",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [Backend::Jit, Backend::Aot, Backend::Interpreter]
+            .into_iter()
+            .find(|backend| backend.name() == name)
+    }
+}
+
+// The spread of a program's run times, in milliseconds
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Summary {
+    pub min: f64,
+    pub median: f64,
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+pub fn milliseconds(duration: Duration) -> f64 {
+    duration.num_nanoseconds().unwrap_or_default() as f64 / 1e6
+}
+
+// The summary of at least one run's times. The standard deviation is the
+// sample one, 0 for a single run.
+pub fn summarise(durations: &[Duration]) -> Summary {
+    let mut times: Vec<f64> = durations.iter().copied().map(milliseconds).collect();
+    times.sort_by(f64::total_cmp);
+    let count = times.len() as f64;
+    let mean = times.iter().sum::<f64>() / count;
+    let middle = times.len() / 2;
+    let median = if times.len() % 2 == 0 {
+        (times[middle - 1] + times[middle]) / 2.0
+    } else {
+        times[middle]
+    };
+    let variance = if times.len() > 1 {
+        times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / (count - 1.0)
+    } else {
+        0.0
+    };
+    Summary {
+        min: times[0],
+        median,
+        mean,
+        stddev: variance.sqrt(),
+    }
+}
+
+// Runs one program warmup times and then runs more times with backend,
+// giving how long each of the timed runs took. Only running is timed: the
+// program is compiled once beforehand, or for the interpreter set up afresh
+// before each run. Executables are timed as a whole process, as that is how
+// they run, and are given the inputs as their arguments.
+pub fn repeat(
+    program: &Path,
+    backend: Backend,
+    level: OptimizationLevel,
+    extensions: &[Extension],
+    warmup: usize,
+    runs: usize,
+) -> Result<Vec<Duration>, String> {
+    let source = Source::expand(program).map_err(|(_, rendered)| rendered)?;
+    let render = |e: BbvmError| source.render_error(&e);
+    let (statements, spans) = lex(&mut Lexer::new(&source.text)).map_err(render)?;
+    ext::check(&statements, &spans, extensions).map_err(render)?;
+    let (variables, inputs) = (collect_variables(&statements), collect_inputs(&statements));
+    let values = input_values(program, &inputs).map_err(|e| format!("bad inputs: {}", e))?;
+
+    if backend == Backend::Interpreter {
+        let inputs: Vec<(&str, u64)> =
+            zip(inputs.iter().copied(), values.iter().copied()).collect();
+        let mut durations = Vec::with_capacity(runs);
+        for run in 0..warmup + runs {
+            let mut interpreter = Interpreter::new(&statements, &spans).map_err(render)?;
+            let start = Utc::now();
+            interpreter.execute(&inputs).map_err(render)?;
+            if run >= warmup {
+                durations.push(Utc::now() - start);
+            }
+        }
+        return Ok(durations);
+    }
+
+    Target::initialize_native(&InitializationConfig::default())
+        .expect("ERROR: Failed to initialize LLVM");
+    let context = Context::create();
+    let mut converter = Converter::new(variables, &inputs, &context);
+    converter.set_optimization_level(level);
+    lower(&mut converter, &statements, &spans, &source.text, false).map_err(render)?;
+    converter.optimise();
+    if backend == Backend::Jit {
+        return converter.run_repeatedly(&values, warmup, runs);
+    }
+
+    let stem = program
+        .file_stem()
+        .map_or("program".into(), |s| s.to_string_lossy());
+    let executable = std::env::temp_dir().join(format!(
+        "bbvm-bench-{}-{}{}",
+        std::process::id(),
+        stem,
+        std::env::consts::EXE_SUFFIX
+    ));
+    converter.write_executable(&executable, &Temps::default(), &LinkOptions::default())?;
+    let args: Vec<String> = values.iter().map(u64::to_string).collect();
+    let mut durations = Vec::with_capacity(runs);
+    let mut result = Ok(());
+    for run in 0..warmup + runs {
+        match convert::run_executable(&executable, &args, Some(&mut io::sink())) {
+            Ok((duration, _)) if run >= warmup => durations.push(duration),
+            Ok(_) => {}
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    fs::remove_file(&executable).ok();
+    result.map(|_| durations)
+}
@@ -0,0 +1,330 @@
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+// What the interpreter can hold in a variable. u64 behaves like the
+// generated code, wrapping around; Natural is unbounded, like BareBones on
+// paper.
+pub trait Value: Clone + Default + FromStr + Ord {
+    fn from_u64(n: u64) -> Self;
+    // The sum, and whether it wrapped around
+    fn overflowing_add(&self, other: &Self) -> (Self, bool);
+    // The product, and whether it wrapped around
+    fn overflowing_mul(&self, other: &Self) -> (Self, bool);
+    // The difference, or 0 if other is bigger
+    fn saturating_sub(&self, other: &Self) -> Self;
+    fn compare(&self, n: u64) -> Ordering;
+    // The value, if it fits in a u64
+    fn to_u64(&self) -> Option<u64>;
+    // As the final values are printed
+    fn show(&self) -> String;
+}
+
+impl Value for u64 {
+    fn from_u64(n: u64) -> Self {
+        n
+    }
+
+    fn overflowing_add(&self, other: &Self) -> (Self, bool) {
+        u64::overflowing_add(*self, *other)
+    }
+
+    fn overflowing_mul(&self, other: &Self) -> (Self, bool) {
+        u64::overflowing_mul(*self, *other)
+    }
+
+    fn saturating_sub(&self, other: &Self) -> Self {
+        u64::saturating_sub(*self, *other)
+    }
+
+    fn compare(&self, n: u64) -> Ordering {
+        self.cmp(&n)
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        Some(*self)
+    }
+
+    // Signed, the same as the JIT's printf
+    fn show(&self) -> String {
+        (*self as i64).to_string()
+    }
+}
+
+// For --int-width 128 under the JIT. Printed unsigned, as the generated
+// code prints them
+impl Value for u128 {
+    fn from_u64(n: u64) -> Self {
+        n as u128
+    }
+
+    fn overflowing_add(&self, other: &Self) -> (Self, bool) {
+        u128::overflowing_add(*self, *other)
+    }
+
+    fn overflowing_mul(&self, other: &Self) -> (Self, bool) {
+        u128::overflowing_mul(*self, *other)
+    }
+
+    fn saturating_sub(&self, other: &Self) -> Self {
+        u128::saturating_sub(*self, *other)
+    }
+
+    fn compare(&self, n: u64) -> Ordering {
+        self.cmp(&(n as u128))
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        u64::try_from(*self).ok()
+    }
+
+    fn show(&self) -> String {
+        self.to_string()
+    }
+}
+
+// A natural number of any size, for --bignum. The digits are base 2^32,
+// least significant first, with no zeros at the end (so 0 has none).
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Natural {
+    digits: Vec<u32>,
+}
+
+impl Natural {
+    fn trim(mut self) -> Natural {
+        while self.digits.last() == Some(&0) {
+            self.digits.pop();
+        }
+        self
+    }
+
+    // self * by + add, for small numbers
+    fn mul_add_small(&self, by: u32, add: u32) -> Natural {
+        let mut carry = add as u64;
+        let mut digits: Vec<u32> = self
+            .digits
+            .iter()
+            .map(|&d| {
+                let n = d as u64 * by as u64 + carry;
+                carry = n >> 32;
+                n as u32
+            })
+            .collect();
+        digits.push(carry as u32);
+        Natural { digits }.trim()
+    }
+
+    // self / by and the remainder, for small numbers
+    fn div_small(&self, by: u32) -> (Natural, u32) {
+        let mut remainder = 0u64;
+        let mut digits = self.digits.clone();
+        for d in digits.iter_mut().rev() {
+            let n = (remainder << 32) | *d as u64;
+            *d = (n / by as u64) as u32;
+            remainder = n % by as u64;
+        }
+        (Natural { digits }.trim(), remainder as u32)
+    }
+}
+
+impl Ord for Natural {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.digits
+            .len()
+            .cmp(&other.digits.len())
+            .then_with(|| self.digits.iter().rev().cmp(other.digits.iter().rev()))
+    }
+}
+
+impl PartialOrd for Natural {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Value for Natural {
+    fn from_u64(n: u64) -> Self {
+        Natural {
+            digits: vec![n as u32, (n >> 32) as u32],
+        }
+        .trim()
+    }
+
+    fn overflowing_add(&self, other: &Self) -> (Self, bool) {
+        let mut carry = 0u64;
+        let mut digits = vec![];
+        for i in 0..self.digits.len().max(other.digits.len()) {
+            let n = *self.digits.get(i).unwrap_or(&0) as u64
+                + *other.digits.get(i).unwrap_or(&0) as u64
+                + carry;
+            digits.push(n as u32);
+            carry = n >> 32;
+        }
+        digits.push(carry as u32);
+        (Natural { digits }.trim(), false)
+    }
+
+    fn overflowing_mul(&self, other: &Self) -> (Self, bool) {
+        let mut digits = vec![0u32; self.digits.len() + other.digits.len()];
+        for (i, &a) in self.digits.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.digits.iter().enumerate() {
+                let n = a as u64 * b as u64 + digits[i + j] as u64 + carry;
+                digits[i + j] = n as u32;
+                carry = n >> 32;
+            }
+            digits[i + other.digits.len()] = carry as u32;
+        }
+        (Natural { digits }.trim(), false)
+    }
+
+    fn saturating_sub(&self, other: &Self) -> Self {
+        if *self <= *other {
+            return Natural::default();
+        }
+        let mut borrow = 0i64;
+        let digits = self
+            .digits
+            .iter()
+            .enumerate()
+            .map(|(i, &d)| {
+                let mut n = d as i64 - *other.digits.get(i).unwrap_or(&0) as i64 - borrow;
+                borrow = 0;
+                if n < 0 {
+                    n += 1 << 32;
+                    borrow = 1;
+                }
+                n as u32
+            })
+            .collect();
+        Natural { digits }.trim()
+    }
+
+    fn compare(&self, n: u64) -> Ordering {
+        self.cmp(&Natural::from_u64(n))
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        match self.digits[..] {
+            [] => Some(0),
+            [low] => Some(low as u64),
+            [low, high] => Some((high as u64) << 32 | low as u64),
+            _ => None,
+        }
+    }
+
+    fn show(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Natural {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Nine decimal digits at a time, least significant first
+        let mut chunks = vec![];
+        let mut rest = self.clone();
+        loop {
+            let (quotient, chunk) = rest.div_small(1_000_000_000);
+            chunks.push(chunk);
+            if quotient.digits.is_empty() {
+                break;
+            }
+            rest = quotient;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Natural {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("{:?} is not a natural number", s));
+        }
+        Ok(s.bytes().fold(Natural::default(), |n, digit| {
+            n.mul_add_small(10, (digit - b'0') as u32)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn n(s: &str) -> Natural {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_prints() {
+        assert_eq!(Natural::default().to_string(), "0");
+        assert_eq!(n("000"), Natural::default());
+        assert_eq!(n("1000000000").to_string(), "1000000000");
+        let big = "340282366920938463463374607431768211456";
+        assert_eq!(n(big).to_string(), big);
+        assert!("".parse::<Natural>().is_err());
+        assert!("-1".parse::<Natural>().is_err());
+        assert!("1e3".parse::<Natural>().is_err());
+    }
+
+    #[test]
+    fn adds_past_64_bits() {
+        let max = Natural::from_u64(u64::MAX);
+        let (sum, overflowed) = max.overflowing_add(&Natural::from_u64(1));
+        assert!(!overflowed);
+        assert_eq!(sum.to_string(), "18446744073709551616");
+        assert_eq!(sum.to_u64(), None);
+        assert_eq!(max.to_u64(), Some(u64::MAX));
+        assert_eq!(sum.compare(u64::MAX), Ordering::Greater);
+        assert_eq!(max.compare(u64::MAX), Ordering::Equal);
+    }
+
+    #[test]
+    fn subtracts_down_to_zero() {
+        let sum = n("18446744073709551616");
+        assert_eq!(
+            sum.saturating_sub(&Natural::from_u64(1)),
+            Natural::from_u64(u64::MAX)
+        );
+        // Borrowing across every digit
+        assert_eq!(
+            n("79228162514264337593543950336").saturating_sub(&Natural::from_u64(1)),
+            n("79228162514264337593543950335")
+        );
+        assert_eq!(
+            Natural::from_u64(5).saturating_sub(&sum),
+            Natural::default()
+        );
+        assert_eq!(sum.saturating_sub(&sum), Natural::default());
+        assert_eq!(sum.saturating_sub(&sum).to_u64(), Some(0));
+    }
+
+    #[test]
+    fn multiplies_past_64_bits() {
+        let max = Natural::from_u64(u64::MAX);
+        let (square, overflowed) = max.overflowing_mul(&max);
+        assert!(!overflowed);
+        assert_eq!(
+            square.to_string(),
+            (u64::MAX as u128 * u64::MAX as u128).to_string()
+        );
+        let (zero, _) = max.overflowing_mul(&Natural::default());
+        assert_eq!(zero, Natural::default());
+        let (product, _) = n("123456789012345678901234567890").overflowing_mul(&n("987654321"));
+        assert_eq!(
+            product.to_string(),
+            "121932631124828532112482853211126352690"
+        );
+    }
+
+    #[test]
+    fn u64_wraps_like_the_generated_code() {
+        assert_eq!(Value::overflowing_add(&u64::MAX, &1), (0, true));
+        assert_eq!(Value::overflowing_mul(&u64::MAX, &2), (u64::MAX - 1, true));
+        assert_eq!(Value::saturating_sub(&3u64, &5), 0);
+        assert_eq!(u64::MAX.show(), "-1");
+    }
+}
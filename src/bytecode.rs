@@ -0,0 +1,522 @@
+use crate::token::{
+    Array, Assert, Bound, Call, Comparison, Element, ElementOp, Identifier, If, Indexed, Number,
+    OneParam, OneParamType, Proc, Rand, Set, Statement, TwoParam, TwoParamType, While,
+};
+
+// The .bbc format: a compact serialisation of the statements, with runs of
+// incr/decr on the same variable folded together.
+//
+//   "BBC" version:u8
+//   variable count:u32, then each name as length:u32 + UTF-8
+//   ops, each an opcode:u8 followed by its operands (variables and proc
+//   names as u32 indices, numbers as u64), all little endian
+const MAGIC: &[u8; 3] = b"BBC";
+const VERSION: u8 = 1;
+// How many incrs and decrs the folded runs in a file can add up to. Each
+// was a statement of the source, so a file with more wasn't written by bbvm,
+// and unfolding it could use up all the memory.
+pub const MAX_UNFOLDED: u64 = 1 << 20;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Op {
+    Clear(u32),
+    Incr(u32, u64),
+    Decr(u32, u64),
+    Input(u32),
+    Print(u32),
+    Halt(u32),
+    Copy(u32, u32),
+    Add(u32, u32),
+    Sub(u32, u32),
+    Mul(u32, u32),
+    Swap(u32, u32),
+    Move(u32, u32),
+    While(u32, Comparison, u64),
+    // A while against another variable, which comes second
+    WhileVar(u32, Comparison, u32),
+    If(u32, Comparison, u64),
+    Else,
+    EndIf,
+    Proc(u32),
+    EndProc,
+    Call(u32),
+    Assert(u32, u64),
+    Set(u32, u64),
+    Rand(u32, u64),
+    Array(u32, u64),
+    // The array, then the index
+    ClearElement(u32, u32),
+    IncrElement(u32, u32),
+    DecrElement(u32, u32),
+    // The array, the index, then the variable
+    LoadElement(u32, u32, u32),
+    StoreElement(u32, u32, u32),
+    End,
+    Breakpoint,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Bytecode {
+    pub names: Vec<String>,
+    pub ops: Vec<Op>,
+}
+
+impl Bytecode {
+    pub fn from_statements(statements: &[Statement]) -> Bytecode {
+        let mut bytecode = Bytecode::default();
+        for statement in statements {
+            let op = match *statement {
+                Statement::OneParam(OneParam { one, ty }) => {
+                    let var = bytecode.variable(one.ident);
+                    match (ty, bytecode.ops.last_mut()) {
+                        (OneParamType::Incr, Some(Op::Incr(v, n))) if *v == var => {
+                            *n += 1;
+                            continue;
+                        }
+                        (OneParamType::Decr, Some(Op::Decr(v, n))) if *v == var => {
+                            *n += 1;
+                            continue;
+                        }
+                        (OneParamType::Incr, _) => Op::Incr(var, 1),
+                        (OneParamType::Decr, _) => Op::Decr(var, 1),
+                        (OneParamType::Clear, _) => Op::Clear(var),
+                        (OneParamType::Input, _) => Op::Input(var),
+                        (OneParamType::Print, _) => Op::Print(var),
+                        (OneParamType::Halt, _) => Op::Halt(var),
+                    }
+                }
+                Statement::TwoParam(TwoParam { one, two, ty }) => {
+                    let (a, b) = (bytecode.variable(one.ident), bytecode.variable(two.ident));
+                    match ty {
+                        TwoParamType::Copy => Op::Copy(a, b),
+                        TwoParamType::Add => Op::Add(a, b),
+                        TwoParamType::Sub => Op::Sub(a, b),
+                        TwoParamType::Mul => Op::Mul(a, b),
+                        TwoParamType::Swap => Op::Swap(a, b),
+                        TwoParamType::Move => Op::Move(a, b),
+                    }
+                }
+                Statement::While(While { param, cmp, bound }) => match bound {
+                    Bound::Number(num) => {
+                        Op::While(bytecode.variable(param.ident), cmp, num.value as u64)
+                    }
+                    Bound::Variable(v) => Op::WhileVar(
+                        bytecode.variable(param.ident),
+                        cmp,
+                        bytecode.variable(v.ident),
+                    ),
+                },
+                Statement::If(If { param, cmp, num }) => {
+                    Op::If(bytecode.variable(param.ident), cmp, num.value as u64)
+                }
+                Statement::Else => Op::Else,
+                Statement::EndIf => Op::EndIf,
+                // Proc names share the table with the variables
+                Statement::Proc(Proc { name }) => Op::Proc(bytecode.variable(name.ident)),
+                Statement::EndProc => Op::EndProc,
+                Statement::Call(Call { name }) => Op::Call(bytecode.variable(name.ident)),
+                Statement::Assert(Assert { param, num }) => {
+                    Op::Assert(bytecode.variable(param.ident), num.value as u64)
+                }
+                Statement::Set(Set { var, num }) => {
+                    Op::Set(bytecode.variable(var.ident), num.value as u64)
+                }
+                Statement::Rand(Rand { var, bound }) => {
+                    Op::Rand(bytecode.variable(var.ident), bound.value as u64)
+                }
+                // Array names share the table too
+                Statement::Array(Array { name, size }) => {
+                    Op::Array(bytecode.variable(name.ident), size.value as u64)
+                }
+                Statement::Indexed(Indexed { element, op }) => {
+                    let array = bytecode.variable(element.array.ident);
+                    let index = bytecode.variable(element.index.ident);
+                    match op {
+                        ElementOp::Clear => Op::ClearElement(array, index),
+                        ElementOp::Incr => Op::IncrElement(array, index),
+                        ElementOp::Decr => Op::DecrElement(array, index),
+                        ElementOp::Load(to) => {
+                            Op::LoadElement(array, index, bytecode.variable(to.ident))
+                        }
+                        ElementOp::Store(from) => {
+                            Op::StoreElement(array, index, bytecode.variable(from.ident))
+                        }
+                    }
+                }
+                Statement::End => Op::End,
+                Statement::Breakpoint => Op::Breakpoint,
+                Statement::Fluff | Statement::EOF => continue,
+            };
+            bytecode.ops.push(op);
+        }
+        bytecode
+    }
+
+    fn variable(&mut self, name: &str) -> u32 {
+        match self.names.iter().position(|n| n == name) {
+            Some(i) => i as u32,
+            None => {
+                self.names.push(name.to_string());
+                (self.names.len() - 1) as u32
+            }
+        }
+    }
+
+    // The statements again, with folded runs unfolded. Ends with EOF like
+    // the lexer's output.
+    pub fn to_statements(&self) -> Vec<Statement<'_>> {
+        let ident = |v: u32| Identifier {
+            ident: &self.names[v as usize],
+        };
+        let number = |n: u64| Number { value: n as i128 };
+        let one_param = |v: u32, ty| Statement::OneParam(OneParam { one: ident(v), ty });
+        let indexed = |a: u32, i: u32, op| {
+            Statement::Indexed(Indexed {
+                element: Element {
+                    array: ident(a),
+                    index: ident(i),
+                },
+                op,
+            })
+        };
+        let two_param = |a: u32, b: u32, ty| {
+            Statement::TwoParam(TwoParam {
+                one: ident(a),
+                two: ident(b),
+                ty,
+            })
+        };
+        let mut statements = vec![];
+        for op in &self.ops {
+            match *op {
+                Op::Clear(v) => statements.push(one_param(v, OneParamType::Clear)),
+                Op::Incr(v, n) => {
+                    statements.extend((0..n).map(|_| one_param(v, OneParamType::Incr)))
+                }
+                Op::Decr(v, n) => {
+                    statements.extend((0..n).map(|_| one_param(v, OneParamType::Decr)))
+                }
+                Op::Input(v) => statements.push(one_param(v, OneParamType::Input)),
+                Op::Print(v) => statements.push(one_param(v, OneParamType::Print)),
+                Op::Halt(v) => statements.push(one_param(v, OneParamType::Halt)),
+                Op::Copy(a, b) => statements.push(two_param(a, b, TwoParamType::Copy)),
+                Op::Add(a, b) => statements.push(two_param(a, b, TwoParamType::Add)),
+                Op::Sub(a, b) => statements.push(two_param(a, b, TwoParamType::Sub)),
+                Op::Mul(a, b) => statements.push(two_param(a, b, TwoParamType::Mul)),
+                Op::Swap(a, b) => statements.push(two_param(a, b, TwoParamType::Swap)),
+                Op::Move(a, b) => statements.push(two_param(a, b, TwoParamType::Move)),
+                Op::While(v, cmp, n) => statements.push(Statement::While(While {
+                    param: ident(v),
+                    cmp,
+                    bound: Bound::Number(number(n)),
+                })),
+                Op::WhileVar(v, cmp, b) => statements.push(Statement::While(While {
+                    param: ident(v),
+                    cmp,
+                    bound: Bound::Variable(ident(b)),
+                })),
+                Op::If(v, cmp, n) => statements.push(Statement::If(If {
+                    param: ident(v),
+                    cmp,
+                    num: number(n),
+                })),
+                Op::Else => statements.push(Statement::Else),
+                Op::EndIf => statements.push(Statement::EndIf),
+                Op::Proc(v) => statements.push(Statement::Proc(Proc { name: ident(v) })),
+                Op::EndProc => statements.push(Statement::EndProc),
+                Op::Call(v) => statements.push(Statement::Call(Call { name: ident(v) })),
+                Op::Assert(v, n) => statements.push(Statement::Assert(Assert {
+                    param: ident(v),
+                    num: number(n),
+                })),
+                Op::Set(v, n) => statements.push(Statement::Set(Set {
+                    var: ident(v),
+                    num: number(n),
+                })),
+                Op::Rand(v, n) => statements.push(Statement::Rand(Rand {
+                    var: ident(v),
+                    bound: number(n),
+                })),
+                Op::Array(v, n) => statements.push(Statement::Array(Array {
+                    name: ident(v),
+                    size: number(n),
+                })),
+                Op::ClearElement(a, i) => statements.push(indexed(a, i, ElementOp::Clear)),
+                Op::IncrElement(a, i) => statements.push(indexed(a, i, ElementOp::Incr)),
+                Op::DecrElement(a, i) => statements.push(indexed(a, i, ElementOp::Decr)),
+                Op::LoadElement(a, i, v) => {
+                    statements.push(indexed(a, i, ElementOp::Load(ident(v))))
+                }
+                Op::StoreElement(a, i, v) => {
+                    statements.push(indexed(a, i, ElementOp::Store(ident(v))))
+                }
+                Op::End => statements.push(Statement::End),
+                Op::Breakpoint => statements.push(Statement::Breakpoint),
+            }
+        }
+        statements.push(Statement::EOF);
+        statements
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend((self.names.len() as u32).to_le_bytes());
+        for name in &self.names {
+            bytes.extend((name.len() as u32).to_le_bytes());
+            bytes.extend(name.as_bytes());
+        }
+        for op in &self.ops {
+            let (opcode, variables, number): (u8, &[u32], Option<u64>) = match op {
+                Op::Clear(v) => (0, std::slice::from_ref(v), None),
+                Op::Incr(v, n) => (1, std::slice::from_ref(v), Some(*n)),
+                Op::Decr(v, n) => (2, std::slice::from_ref(v), Some(*n)),
+                Op::Input(v) => (3, std::slice::from_ref(v), None),
+                Op::Copy(a, b) => (4, &[*a, *b][..], None),
+                Op::While(v, Comparison::Ne, n) => (5, std::slice::from_ref(v), Some(*n)),
+                Op::While(v, Comparison::Lt, n) => (13, std::slice::from_ref(v), Some(*n)),
+                Op::While(v, Comparison::Gt, n) => (14, std::slice::from_ref(v), Some(*n)),
+                Op::WhileVar(v, Comparison::Ne, b) => (32, &[*v, *b][..], None),
+                Op::WhileVar(v, Comparison::Lt, b) => (33, &[*v, *b][..], None),
+                Op::WhileVar(v, Comparison::Gt, b) => (34, &[*v, *b][..], None),
+                Op::If(v, Comparison::Ne, n) => (15, std::slice::from_ref(v), Some(*n)),
+                Op::If(v, Comparison::Lt, n) => (16, std::slice::from_ref(v), Some(*n)),
+                Op::If(v, Comparison::Gt, n) => (17, std::slice::from_ref(v), Some(*n)),
+                Op::Else => (18, &[], None),
+                Op::EndIf => (19, &[], None),
+                Op::Assert(v, n) => (6, std::slice::from_ref(v), Some(*n)),
+                Op::End => (7, &[], None),
+                Op::Breakpoint => (8, &[], None),
+                Op::Print(v) => (9, std::slice::from_ref(v), None),
+                Op::Add(a, b) => (10, &[*a, *b][..], None),
+                Op::Sub(a, b) => (11, &[*a, *b][..], None),
+                Op::Mul(a, b) => (12, &[*a, *b][..], None),
+                Op::Proc(v) => (20, std::slice::from_ref(v), None),
+                Op::EndProc => (21, &[], None),
+                Op::Call(v) => (22, std::slice::from_ref(v), None),
+                Op::Set(v, n) => (23, std::slice::from_ref(v), Some(*n)),
+                Op::Array(v, n) => (24, std::slice::from_ref(v), Some(*n)),
+                Op::ClearElement(a, i) => (25, &[*a, *i][..], None),
+                Op::IncrElement(a, i) => (26, &[*a, *i][..], None),
+                Op::DecrElement(a, i) => (27, &[*a, *i][..], None),
+                Op::LoadElement(a, i, v) => (28, &[*a, *i, *v][..], None),
+                Op::StoreElement(a, i, v) => (29, &[*a, *i, *v][..], None),
+                Op::Halt(v) => (30, std::slice::from_ref(v), None),
+                Op::Rand(v, n) => (31, std::slice::from_ref(v), Some(*n)),
+                Op::Swap(a, b) => (35, &[*a, *b][..], None),
+                Op::Move(a, b) => (36, &[*a, *b][..], None),
+            };
+            bytes.push(opcode);
+            for v in variables {
+                bytes.extend(v.to_le_bytes());
+            }
+            if let Some(n) = number {
+                bytes.extend(n.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Bytecode, String> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(3)? != MAGIC {
+            return Err("Not a bbvm bytecode file".to_string());
+        }
+        let version = reader.take(1)?[0];
+        if version != VERSION {
+            return Err(format!("Unsupported bytecode version {}", version));
+        }
+
+        let mut bytecode = Bytecode::default();
+        for _ in 0..reader.u32()? {
+            let len = reader.u32()? as usize;
+            let name = std::str::from_utf8(reader.take(len)?)
+                .map_err(|_| "Variable name is not valid UTF-8".to_string())?;
+            bytecode.names.push(name.to_string());
+        }
+
+        let mut unfolded: u64 = 0;
+        while reader.offset < bytes.len() {
+            let opcode = reader.take(1)?[0];
+            let op = match opcode {
+                0 => Op::Clear(reader.variable(&bytecode)?),
+                1 => Op::Incr(reader.variable(&bytecode)?, reader.u64()?),
+                2 => Op::Decr(reader.variable(&bytecode)?, reader.u64()?),
+                3 => Op::Input(reader.variable(&bytecode)?),
+                4 => Op::Copy(reader.variable(&bytecode)?, reader.variable(&bytecode)?),
+                5 => Op::While(reader.variable(&bytecode)?, Comparison::Ne, reader.u64()?),
+                13 => Op::While(reader.variable(&bytecode)?, Comparison::Lt, reader.u64()?),
+                14 => Op::While(reader.variable(&bytecode)?, Comparison::Gt, reader.u64()?),
+                32 => Op::WhileVar(
+                    reader.variable(&bytecode)?,
+                    Comparison::Ne,
+                    reader.variable(&bytecode)?,
+                ),
+                33 => Op::WhileVar(
+                    reader.variable(&bytecode)?,
+                    Comparison::Lt,
+                    reader.variable(&bytecode)?,
+                ),
+                34 => Op::WhileVar(
+                    reader.variable(&bytecode)?,
+                    Comparison::Gt,
+                    reader.variable(&bytecode)?,
+                ),
+                15 => Op::If(reader.variable(&bytecode)?, Comparison::Ne, reader.u64()?),
+                16 => Op::If(reader.variable(&bytecode)?, Comparison::Lt, reader.u64()?),
+                17 => Op::If(reader.variable(&bytecode)?, Comparison::Gt, reader.u64()?),
+                18 => Op::Else,
+                19 => Op::EndIf,
+                6 => Op::Assert(reader.variable(&bytecode)?, reader.u64()?),
+                7 => Op::End,
+                8 => Op::Breakpoint,
+                9 => Op::Print(reader.variable(&bytecode)?),
+                10 => Op::Add(reader.variable(&bytecode)?, reader.variable(&bytecode)?),
+                11 => Op::Sub(reader.variable(&bytecode)?, reader.variable(&bytecode)?),
+                12 => Op::Mul(reader.variable(&bytecode)?, reader.variable(&bytecode)?),
+                20 => Op::Proc(reader.variable(&bytecode)?),
+                21 => Op::EndProc,
+                22 => Op::Call(reader.variable(&bytecode)?),
+                23 => Op::Set(reader.variable(&bytecode)?, reader.u64()?),
+                24 => Op::Array(reader.variable(&bytecode)?, reader.u64()?),
+                25 => Op::ClearElement(reader.variable(&bytecode)?, reader.variable(&bytecode)?),
+                26 => Op::IncrElement(reader.variable(&bytecode)?, reader.variable(&bytecode)?),
+                27 => Op::DecrElement(reader.variable(&bytecode)?, reader.variable(&bytecode)?),
+                28 => Op::LoadElement(
+                    reader.variable(&bytecode)?,
+                    reader.variable(&bytecode)?,
+                    reader.variable(&bytecode)?,
+                ),
+                29 => Op::StoreElement(
+                    reader.variable(&bytecode)?,
+                    reader.variable(&bytecode)?,
+                    reader.variable(&bytecode)?,
+                ),
+                30 => Op::Halt(reader.variable(&bytecode)?),
+                31 => Op::Rand(reader.variable(&bytecode)?, reader.u64()?),
+                35 => Op::Swap(reader.variable(&bytecode)?, reader.variable(&bytecode)?),
+                36 => Op::Move(reader.variable(&bytecode)?, reader.variable(&bytecode)?),
+                _ => {
+                    return Err(format!(
+                        "Unknown opcode {} at byte {}",
+                        opcode,
+                        reader.offset - 1
+                    ))
+                }
+            };
+            if let Op::Incr(_, n) | Op::Decr(_, n) = op {
+                unfolded = unfolded.saturating_add(n);
+                if unfolded > MAX_UNFOLDED {
+                    return Err(format!(
+                        "The incrs and decrs add up to more than {} statements at byte {}",
+                        MAX_UNFOLDED, reader.offset
+                    ));
+                }
+            }
+            bytecode.ops.push(op);
+        }
+        Ok(bytecode)
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let taken = self
+            .bytes
+            .get(self.offset..self.offset + n)
+            .ok_or("Bytecode ends unexpectedly".to_string())?;
+        self.offset += n;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn variable(&mut self, bytecode: &Bytecode) -> Result<u32, String> {
+        let v = self.u32()?;
+        if v as usize >= bytecode.names.len() {
+            return Err(format!("Variable {} out of range", v));
+        }
+        Ok(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex;
+    use crate::lexer::Lexer;
+
+    const PROGRAM: &str = "input X;
+clear Y;
+incr Y;
+incr Y;
+incr Y;
+decr X;
+while X not 0 do;
+    decr X;
+    decr X;
+    copy X to Y;
+end;
+";
+
+    #[test]
+    fn round_trips() {
+        let (statements, _) = lex(&mut Lexer::new(PROGRAM)).unwrap();
+        let bytecode = Bytecode::from_statements(&statements);
+        assert!(bytecode.ops.contains(&Op::Incr(1, 3)));
+        assert!(bytecode.ops.contains(&Op::Decr(0, 2)));
+
+        let decoded = Bytecode::decode(&bytecode.encode()).unwrap();
+        assert_eq!(decoded, bytecode);
+        // Fluff words aren't kept
+        let statements: Vec<Statement> = statements
+            .into_iter()
+            .filter(|s| *s != Statement::Fluff)
+            .collect();
+        assert_eq!(decoded.to_statements(), statements);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert!(Bytecode::decode(b"BBX\x01").is_err());
+        assert!(Bytecode::decode(b"BBC\x02\0\0\0\0").is_err());
+        // The clear names a variable there isn't
+        assert!(Bytecode::decode(b"BBC\x01\0\0\0\0\0\0\0\0\0").is_err());
+        // Cut off in the middle of the incr's count
+        let mut bytes = Bytecode {
+            names: vec!["X".to_string()],
+            ops: vec![Op::Incr(0, 5)],
+        }
+        .encode();
+        bytes.truncate(bytes.len() - 1);
+        assert!(Bytecode::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_runs_too_long_to_unfold() {
+        let bytecode = |ops| Bytecode {
+            names: vec!["X".to_string()],
+            ops,
+        };
+        let most = bytecode(vec![
+            Op::Incr(0, MAX_UNFOLDED / 2),
+            Op::Decr(0, MAX_UNFOLDED / 2),
+        ]);
+        assert!(Bytecode::decode(&most.encode()).is_ok());
+        let more = bytecode(vec![Op::Incr(0, MAX_UNFOLDED), Op::Decr(0, 1)]);
+        assert!(Bytecode::decode(&more.encode()).is_err());
+        let huge = bytecode(vec![Op::Incr(0, u64::MAX), Op::Incr(0, u64::MAX)]);
+        assert!(Bytecode::decode(&huge.encode()).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+// Executables compiled before, kept so that compiling the same program with
+// the same options again skips LLVM and the linker and copies one instead.
+// Each is named after a hash of the source, the options that change the
+// code and bbvm's version.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+// FNV-1a, which unlike std's hashers is the same in every build of bbvm
+fn hash(bytes: &[u8], mut state: u64) -> u64 {
+    for &b in bytes {
+        state ^= b as u64;
+        state = state.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    state
+}
+
+impl Cache {
+    // $BBVM_CACHE_DIR, or bbvm in the user's cache directory
+    pub fn default_dir() -> Option<PathBuf> {
+        if let Some(dir) = env::var_os("BBVM_CACHE_DIR") {
+            return Some(PathBuf::from(dir));
+        }
+        let base = if cfg!(windows) {
+            env::var_os("LOCALAPPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        };
+        base.map(|base| base.join("bbvm"))
+    }
+
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // The key for source compiled with options, each a name and its value
+    pub fn key(source: &str, options: &[(&str, String)]) -> String {
+        let mut text = format!("bbvm {}\n", env!("CARGO_PKG_VERSION"));
+        for (name, value) in options {
+            text.push_str(&format!("{}={}\n", name, value));
+        }
+        text.push_str(source);
+        // Two hashes started differently, as one 64 bit hash could collide
+        // in a cache that is never emptied
+        let first = hash(text.as_bytes(), 0xcbf2_9ce4_8422_2325);
+        let second = hash(text.as_bytes(), 0x6c62_272e_07bb_0142);
+        format!("{:016x}{:016x}", first, second)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}{}", key, env::consts::EXE_SUFFIX))
+    }
+
+    // Copies the executable cached under key to output, if there is one
+    pub fn get(&self, key: &str, output: &Path) -> bool {
+        let cached = self.path(key);
+        cached.is_file() && fs::copy(cached, output).is_ok()
+    }
+
+    // Keeps a copy of the executable under key. It is copied next to where
+    // it goes and renamed into place, so a bbvm reading it at the same time
+    // never sees half of it.
+    pub fn put(&self, key: &str, executable: &Path) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let partial = self
+            .dir
+            .join(format!("{}.{}.partial", key, std::process::id()));
+        fs::copy(executable, &partial)?;
+        fs::rename(&partial, self.path(key)).map_err(|e| {
+            let _ = fs::remove_file(&partial);
+            e
+        })
+    }
+
+    // Deletes everything cached, giving how many executables there were
+    // and how many bytes they took
+    pub fn clean(&self) -> io::Result<(usize, u64)> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((0, 0)),
+            Err(e) => return Err(e),
+        };
+        let (mut count, mut bytes) = (0, 0);
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                bytes += entry.metadata()?.len();
+                fs::remove_file(entry.path())?;
+                count += 1;
+            }
+        }
+        Ok((count, bytes))
+    }
+}
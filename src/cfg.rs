@@ -0,0 +1,182 @@
+use crate::parser::{Block, Node, Program};
+use crate::token::{If, Statement, While};
+
+// The program's control flow as Graphviz DOT, for --emit cfg-dot. The
+// blocks are the ones the converter generates for whiles and ifs, named the
+// same way (loop1, loop1.body, if1.then, ...) so the graph can be read next
+// to the IR, and each lists the source lines of its statements. Asserts,
+// halts and bounds checks, which branch off only to stop the program, are
+// left out. Main and each proc are a cluster of their own, with calls as
+// dashed edges.
+pub fn dot(program: &Program, filename: &str) -> String {
+    let mut graph = Graph::default();
+    graph.function("main", &program.body);
+    format!(
+        "digraph \"{}\" {{\n    node [shape=box, fontname=monospace];\n{}{}}}\n",
+        escape(filename),
+        graph.out,
+        graph.calls
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[derive(Default)]
+struct Graph {
+    out: String,
+    // Drawn after every cluster, so each proc's entry is already in its own
+    calls: String,
+    loop_count: usize,
+    if_count: usize,
+    function: String,
+    // The name and lines of each block of the function being drawn
+    blocks: Vec<(String, Vec<String>)>,
+    edges: Vec<(usize, usize, &'static str)>,
+    current: usize,
+}
+
+impl Graph {
+    fn new_block(&mut self, name: String) -> usize {
+        self.blocks.push((name, vec![]));
+        self.blocks.len() - 1
+    }
+
+    fn id(&self, block: usize) -> String {
+        format!(
+            "\"{}.{}\"",
+            escape(&self.function),
+            escape(&self.blocks[block].0)
+        )
+    }
+
+    fn line(&mut self, line: usize, text: String) -> () {
+        self.blocks[self.current]
+            .1
+            .push(format!("{}: {}", line, text));
+    }
+
+    fn function(&mut self, name: &str, body: &Block) -> () {
+        self.function = name.to_string();
+        self.blocks.clear();
+        self.edges.clear();
+        self.current = self.new_block("entry".to_string());
+        self.block(body);
+
+        self.out.push_str(&format!(
+            "    subgraph \"cluster_{}\" {{\n        label=\"{}\";\n",
+            escape(name),
+            escape(name)
+        ));
+        for (i, (block, lines)) in self.blocks.iter().enumerate() {
+            let label: String = std::iter::once(block)
+                .chain(lines)
+                .map(|l| format!("{}\\l", escape(l)))
+                .collect();
+            self.out
+                .push_str(&format!("        {} [label=\"{}\"];\n", self.id(i), label));
+        }
+        for &(from, to, label) in &self.edges {
+            self.out.push_str(&format!(
+                "        {} -> {} [label=\"{}\"];\n",
+                self.id(from),
+                self.id(to),
+                label
+            ));
+        }
+        self.out.push_str("    }\n");
+    }
+
+    fn block(&mut self, block: &Block) -> () {
+        for node in block {
+            match node {
+                Node::Statement(Statement::Fluff, _) => {}
+                Node::Statement(statement, span) => {
+                    self.line(span.line, statement.to_string());
+                    if let Statement::Call(call) = statement {
+                        self.calls.push_str(&format!(
+                            "    {} -> \"{}.entry\" [style=dashed];\n",
+                            self.id(self.current),
+                            escape(call.name.ident)
+                        ));
+                    }
+                }
+                Node::Loop {
+                    var,
+                    cmp,
+                    check,
+                    body,
+                    span,
+                    ..
+                } => {
+                    self.loop_count += 1;
+                    let name = format!("loop{}", self.loop_count);
+                    let header = self.new_block(name.clone());
+                    let inner = self.new_block(format!("{}.body", name));
+                    let exit = self.new_block(format!("{}.exit", name));
+                    self.edges.push((self.current, header, ""));
+                    self.current = header;
+                    let start = Statement::While(While {
+                        param: *var,
+                        cmp: *cmp,
+                        bound: *check,
+                    });
+                    self.line(span.line, start.to_string());
+                    self.edges.push((header, inner, "true"));
+                    self.edges.push((header, exit, "false"));
+                    self.current = inner;
+                    self.block(body);
+                    self.edges.push((self.current, header, ""));
+                    self.current = exit;
+                }
+                Node::If {
+                    var,
+                    cmp,
+                    check,
+                    then,
+                    otherwise,
+                    span,
+                    ..
+                } => {
+                    self.if_count += 1;
+                    let name = format!("if{}", self.if_count);
+                    let start = Statement::If(If {
+                        param: *var,
+                        cmp: *cmp,
+                        num: *check,
+                    });
+                    self.line(span.line, start.to_string());
+                    let before = self.current;
+                    // The else block is there even without an else
+                    let parts = ["then", "else", "end"].map(|part| format!("{}.{}", name, part));
+                    let [then_block, else_block, merge] = parts.map(|part| self.new_block(part));
+                    self.edges.push((before, then_block, "true"));
+                    self.edges.push((before, else_block, "false"));
+                    self.current = then_block;
+                    self.block(then);
+                    self.edges.push((self.current, merge, ""));
+                    self.current = else_block;
+                    if let Some((_, otherwise)) = otherwise {
+                        self.block(otherwise);
+                    }
+                    self.edges.push((self.current, merge, ""));
+                    self.current = merge;
+                }
+                // Drawn as a function of its own where it is, so the loops
+                // and ifs are numbered in the order the converter meets them
+                Node::Proc { name, body, .. } => {
+                    let function = std::mem::take(&mut self.function);
+                    let blocks = std::mem::take(&mut self.blocks);
+                    let edges = std::mem::take(&mut self.edges);
+                    let current = self.current;
+                    self.function(name.ident, body);
+                    self.function = function;
+                    self.blocks = blocks;
+                    self.edges = edges;
+                    self.current = current;
+                }
+            }
+        }
+    }
+}
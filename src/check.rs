@@ -0,0 +1,363 @@
+use std::{collections::HashSet, iter::zip};
+
+use crate::decompile;
+use crate::error::BbvmError;
+use crate::highlight;
+use crate::lexer::Span;
+use crate::lint;
+use crate::parser;
+use crate::token::{
+    Comparison, ElementOp, Indexed, Number, Set, Statement, TokenImpl, TokenKind, TwoParam,
+    TwoParamType,
+};
+
+// The blocks still open while matching them up
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Open {
+    Loop,
+    If,
+    // An if that has had its else
+    Else,
+    Proc,
+}
+
+fn unclosed(open: Open, span: Span) -> BbvmError {
+    match open {
+        Open::Loop => BbvmError::UnclosedWhile { span },
+        Open::If | Open::Else => BbvmError::UnclosedIf { span },
+        Open::Proc => BbvmError::UnclosedProc { span },
+    }
+}
+
+// Closes the innermost open block of one of the kinds, reporting any blocks
+// still open inside it, and returns where it started. Without one the
+// closing statement is reported instead.
+fn close(
+    open: &mut Vec<(Open, Span)>,
+    kinds: &[Open],
+    unmatched: BbvmError,
+    errors: &mut Vec<BbvmError>,
+) -> Option<Span> {
+    match open.iter().rposition(|(kind, _)| kinds.contains(kind)) {
+        Some(i) => {
+            errors.extend(open.drain(i + 1..).map(|(kind, span)| unclosed(kind, span)));
+            open.pop().map(|(_, span)| span)
+        }
+        None => {
+            errors.push(unmatched);
+            None
+        }
+    }
+}
+
+// Every block that isn't closed, closing statement without a block and proc
+// inside another block. Unlike the parser this carries on past the first,
+// taking each closing statement to close the nearest block it can.
+fn block_errors(statements: &[Statement], spans: &[Span], errors: &mut Vec<BbvmError>) -> () {
+    let mut open: Vec<(Open, Span)> = vec![];
+    for (statement, &span) in zip(statements, spans) {
+        match statement {
+            Statement::While(_) => open.push((Open::Loop, span)),
+            Statement::If(_) => open.push((Open::If, span)),
+            Statement::Proc(_) => {
+                if !open.is_empty() {
+                    errors.push(BbvmError::NestedProc { span });
+                }
+                open.push((Open::Proc, span));
+            }
+            Statement::End => {
+                close(
+                    &mut open,
+                    &[Open::Loop],
+                    BbvmError::UnmatchedEnd { span },
+                    errors,
+                );
+            }
+            Statement::Else => {
+                let unmatched = BbvmError::UnmatchedElse { span };
+                if let Some(start) = close(&mut open, &[Open::If], unmatched, errors) {
+                    open.push((Open::Else, start));
+                }
+            }
+            Statement::EndIf => {
+                let unmatched = BbvmError::UnmatchedEndIf { span };
+                close(&mut open, &[Open::If, Open::Else], unmatched, errors);
+            }
+            Statement::EndProc => {
+                let unmatched = BbvmError::UnmatchedEndProc { span };
+                close(&mut open, &[Open::Proc], unmatched, errors);
+            }
+            _ => {}
+        }
+    }
+    errors.extend(open.into_iter().map(|(kind, span)| unclosed(kind, span)));
+}
+
+// The fluff word a statement takes, if any
+fn takes(statement: &Statement) -> Option<&'static str> {
+    match statement {
+        Statement::TwoParam(TwoParam { ty, .. }) => match ty {
+            TwoParamType::Copy | TwoParamType::Add | TwoParamType::Move => Some("to"),
+            TwoParamType::Sub => Some("from"),
+            TwoParamType::Mul => Some("into"),
+            TwoParamType::Swap => None,
+        },
+        Statement::Set(_)
+        | Statement::Indexed(Indexed {
+            op: ElementOp::Load(_) | ElementOp::Store(_),
+            ..
+        }) => Some("to"),
+        Statement::While(_) => Some("do"),
+        Statement::If(_) => Some("then"),
+        Statement::Assert(_) => Some("is"),
+        _ => None,
+    }
+}
+
+fn word_span(source: &str, offset: usize, len: usize) -> Span {
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    Span {
+        line: source[..offset].matches('\n').count() + 1,
+        column: offset - line_start + 1,
+        start: offset,
+        end: offset + len,
+    }
+}
+
+// A word of the source: where it starts, what the lexer makes of it and
+// its text
+type Word<'s> = (usize, Option<TokenKind>, &'s str);
+
+// A statement with the words written for it
+struct Tokenised<'s, 'b> {
+    statement: Statement<'b>,
+    span: Span,
+    words: Vec<Word<'s>>,
+    // The first word after them, or an empty one at the end of the source
+    next: Word<'s>,
+}
+
+// Every statement with its words, leaving out whitespace and comments. The
+// lexer skips fluff wherever it is, so a statement's words run from the end
+// of the statement before it up to its `;`, and stray fluff between
+// statements goes with the next one.
+fn tokenised<'s, 'b>(
+    source: &'s str,
+    statements: &[Statement<'b>],
+    spans: &[Span],
+) -> impl Iterator<Item = Tokenised<'s, 'b>> {
+    let mut words = vec![];
+    let mut offset = 0;
+    for (kind, text) in highlight::pieces(source) {
+        if kind.is_some() && kind != Some(TokenKind::Comment) || text == ";" {
+            words.push((offset, kind, text));
+        }
+        offset += text.len();
+    }
+    let mut words = words.into_iter().peekable();
+    let statements: Vec<(Statement, Span)> = zip(statements, spans.iter().copied())
+        .filter(|(s, _)| !matches!(s, Statement::Fluff | Statement::EOF))
+        .map(|(&s, span)| (s, span))
+        .collect();
+    let mut tokenised = vec![];
+    for (i, &(statement, span)) in statements.iter().enumerate() {
+        // The lexer lets a statement's `;` be left out before the next
+        let next = statements
+            .get(i + 1)
+            .map_or(source.len(), |(_, span)| span.start);
+        let mut own = vec![];
+        while let Some(&word) = words.peek() {
+            if word.0 >= next {
+                break;
+            }
+            words.next();
+            own.push(word);
+            // The statement ends here
+            if word.2 == ";" && word.0 >= span.end {
+                break;
+            }
+        }
+        tokenised.push(Tokenised {
+            statement,
+            span,
+            words: own,
+            next: words.peek().copied().unwrap_or((source.len(), None, "")),
+        });
+    }
+    tokenised.into_iter()
+}
+
+// Every fluff word in a statement other than the one it takes
+fn fluff_errors(
+    source: &str,
+    statements: &[Statement],
+    spans: &[Span],
+    errors: &mut Vec<BbvmError>,
+) -> () {
+    for Tokenised {
+        statement,
+        span,
+        words,
+        ..
+    } in tokenised(source, statements, spans)
+    {
+        let keyword = source[span.start..span.end]
+            .split(|c: char| c.is_whitespace() || c == ';')
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+        for (offset, kind, text) in words {
+            if kind == Some(TokenKind::Fluff)
+                && takes(&statement) != Some(text.to_lowercase().as_str())
+            {
+                errors.push(BbvmError::MisplacedFluff {
+                    word: text.to_string(),
+                    keyword: keyword.clone(),
+                    expected: takes(&statement).map(str::to_string),
+                    span: word_span(source, offset, text.len()),
+                })
+            }
+        }
+    }
+}
+
+// Whether word in the source is the word the standard way of writing the
+// statement has: the same keyword in any case, the same number in any
+// notation, or a comparison meaning the same
+fn same_word(word: &str, standard: &str) -> bool {
+    if Number::identify(word) && Number::identify(standard) {
+        return Number::parse(word) == Number::parse(standard);
+    }
+    if Comparison::identify(word) && Comparison::identify(standard) {
+        return word.parse::<Comparison>() == standard.parse::<Comparison>();
+    }
+    word.eq_ignore_ascii_case(standard)
+}
+
+// For --strict, the first word of each statement that isn't where the
+// standard grammar has it: a fluff word in the wrong place or missing, or a
+// while or if without its comparison. Stray fluff between statements counts
+// against the next one.
+pub fn strict(source: &str, statements: &[Statement], spans: &[Span]) -> Vec<BbvmError> {
+    let mut errors = vec![];
+    for Tokenised {
+        statement,
+        span,
+        words,
+        next,
+    } in tokenised(source, statements, spans)
+    {
+        let standard = match statement {
+            // `copy 5 to X;` is as standard as `set X to 5;`
+            Statement::Set(Set { var, num })
+                if source[span.start..].to_lowercase().starts_with("copy") =>
+            {
+                format!("copy {} to {};", num.value, var.ident)
+            }
+            statement => decompile::statement(&statement).unwrap_or_default(),
+        };
+        let mut expected = standard
+            .trim_end_matches(';')
+            .split(' ')
+            .chain(std::iter::once(";"));
+        let mut mismatch = None;
+        for (offset, _, text) in words {
+            match expected.next() {
+                Some(word) if same_word(text, word) => {}
+                word if mismatch.is_none() => {
+                    mismatch = Some((offset, text, word.unwrap_or(";")));
+                }
+                _ => {}
+            }
+        }
+        // Words the statement is missing at its end, found where the next
+        // one starts
+        if mismatch.is_none() {
+            let (offset, _, text) = next;
+            mismatch = expected.next().map(|word| (offset, text, word));
+        }
+        if let Some((offset, found, expected)) = mismatch {
+            errors.push(BbvmError::NonStandard {
+                found: if offset == source.len() {
+                    "the end of the file".to_string()
+                } else {
+                    format!("`{}`", found)
+                },
+                expected: format!("`{}`", expected),
+                statement: standard,
+                span: word_span(source, offset, found.len().min(source.len() - offset)),
+            });
+        }
+    }
+    errors
+}
+
+// The first read of each variable that nothing in the program writes to,
+// not even an input
+fn undefined_errors(statements: &[Statement], spans: &[Span], errors: &mut Vec<BbvmError>) -> () {
+    let written: HashSet<&str> = statements
+        .iter()
+        .flat_map(|s| lint::reads_and_writes(s).1)
+        .collect();
+    let mut reported = HashSet::new();
+    for (statement, &span) in zip(statements, spans) {
+        for var in lint::reads_and_writes(statement).0 {
+            if !written.contains(var) && reported.insert(var) {
+                errors.push(BbvmError::UndefinedVariable {
+                    name: var.to_string(),
+                    span,
+                });
+            }
+        }
+    }
+}
+
+// Everything `bbvm check` finds wrong with the lexed program, in the order
+// it is in source: blocks that don't match up, procs and arrays declared
+// wrongly or not at all, fluff words in the wrong statements and variables
+// that are read but never given a value. Nothing is run or lowered.
+pub fn check(source: &str, statements: &[Statement], spans: &[Span]) -> Vec<BbvmError> {
+    let mut errors = vec![];
+    block_errors(statements, spans, &mut errors);
+    errors.extend(parser::declaration_errors(statements, spans));
+    fluff_errors(source, statements, spans, &mut errors);
+    undefined_errors(statements, spans, &mut errors);
+    errors.sort_by_key(|e| e.span().start);
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn strict_errors(source: &str) -> Vec<BbvmError> {
+        let (statements, spans) = crate::lex(&mut Lexer::new(source)).unwrap();
+        strict(source, &statements, &spans)
+    }
+
+    #[test]
+    fn finds_where_a_statement_leaves_the_standard() {
+        let errors = strict_errors("clear X;\nwhile X not 0;\nend;\n");
+        assert_eq!(
+            errors,
+            vec![BbvmError::NonStandard {
+                found: "`;`".to_string(),
+                expected: "`do`".to_string(),
+                statement: "while X not 0 do;".to_string(),
+                span: Span {
+                    line: 2,
+                    column: 14,
+                    start: 22,
+                    end: 23,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn accepts_the_same_words_written_differently() {
+        let source = "CLEAR X;\nwhile X ne 0x0 do;\n    incr X;\nEnd;\ncopy 5 to Y;\n";
+        assert_eq!(strict_errors(source), vec![]);
+    }
+}
@@ -1,8 +1,12 @@
 use std::{
-    collections::HashMap,
-    io::{stdin, stdout, Write},
+    collections::{HashMap, HashSet},
+    ffi::{c_void, CStr},
+    fs,
+    io::{self, Write},
     iter::zip,
-    path::Path,
+    os::raw::c_char,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicBool, Arc},
 };
 
 use chrono::Duration;
@@ -10,351 +14,2829 @@ use inkwell::{
     basic_block::BasicBlock,
     builder::Builder,
     context::Context,
-    execution_engine::JitFunction,
-    module::Module,
+    debug_info::{
+        AsDIScope, DICompileUnit, DIFlags, DIFlagsConstants, DIScope, DIType, DWARFEmissionKind,
+        DWARFSourceLanguage, DebugInfoBuilder,
+    },
+    execution_engine::{ExecutionEngine, JitFunction},
+    module::{FlagBehavior, Linkage, Module},
     passes::{PassManager, PassManagerBuilder},
-    targets::{InitializationConfig, Target, TargetMachine},
-    types::IntType,
-    values::{FunctionValue, IntValue, PhiValue},
+    targets::{FileType, InitializationConfig, Target, TargetMachine, TargetTriple},
+    types::{FunctionType, IntType},
+    values::{
+        AsValueRef, BasicValueEnum, FunctionValue, GlobalValue, InstructionValue, IntValue,
+        PhiValue, PointerValue,
+    },
     AddressSpace, IntPredicate, OptimizationLevel,
 };
 
+use crate::annotate;
+use crate::bignum::Value;
+use crate::decompile;
+use crate::error::BbvmError;
+use crate::history;
+use crate::input;
+use crate::lexer::Span;
+use crate::link::{self, LinkOptions};
+use crate::output::OutputFormat;
+use crate::parser::{self, Node};
+use crate::passes;
+use crate::pgo;
+use crate::random;
+use crate::runner::{self, Interface, Stopped};
+use crate::runtime;
+use crate::timeout;
+use crate::token::{
+    Assert, Bound, Comparison, ElementOp, Indexed, OneParam, Rand, Set, Statement, StatementImpl,
+    TwoParam,
+};
+use crate::trace;
+
 type Label<'a> = (BasicBlock<'a>, BasicBlock<'a>);
 
+// What the program calls instead of bbvm_print once set_output_callback has
+// been: the variable's name, NUL terminated, and its value (its low 64
+// bits with wider variables)
+pub type OutputCallback = extern "C" fn(name: *const c_char, value: u64);
+
+// What the program's bbvm_output is under the JIT: the embedder's callback,
+// or keeping what it prints in the converter's captured
+#[derive(Clone, Copy)]
+enum Output {
+    Callback(OutputCallback),
+    Capture,
+}
+
+// LLVM's metadata functions that inkwell doesn't wrap, for loop metadata,
+// which has to refer to itself
+extern "C" {
+    fn LLVMGetModuleContext(module: *mut c_void) -> *mut c_void;
+    fn LLVMMDStringInContext2(context: *mut c_void, text: *const c_char, len: usize)
+        -> *mut c_void;
+    fn LLVMValueAsMetadata(value: *mut c_void) -> *mut c_void;
+    fn LLVMMDNodeInContext2(
+        context: *mut c_void,
+        operands: *mut *mut c_void,
+        count: usize,
+    ) -> *mut c_void;
+    fn LLVMTemporaryMDNode(
+        context: *mut c_void,
+        operands: *mut *mut c_void,
+        count: usize,
+    ) -> *mut c_void;
+    fn LLVMMetadataReplaceAllUsesWith(temporary: *mut c_void, replacement: *mut c_void);
+    fn LLVMMetadataAsValue(context: *mut c_void, metadata: *mut c_void) -> *mut c_void;
+    fn LLVMGetMDKindIDInContext(context: *mut c_void, name: *const c_char, len: u32) -> u32;
+    fn LLVMSetMetadata(instruction: *mut c_void, kind: u32, node: *mut c_void);
+}
+
+// bbvm_output for set_output_callback, context being the callback
+extern "C" fn call_back(context: *mut c_void, name: *const c_char, value: u64) {
+    let callback: OutputCallback = unsafe { std::mem::transmute(context) };
+    callback(name, value)
+}
+
+// bbvm_output for capture_output, context being the converter's captured
+extern "C" fn capture(context: *mut c_void, name: *const c_char, value: u64) {
+    let captured = unsafe { &mut *(context as *mut String) };
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+    // Signed, as bbvm_print prints it
+    captured.push_str(&format!("{}: {}\n", name, value as i64));
+}
+
+// Where write_executable puts the object file and runtime it links, and
+// whether they are left there afterwards. Their names have bbvm's process
+// id in them, so bbvms running at once don't write over each other's.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Temps {
+    pub dir: PathBuf,
+    pub keep: bool,
+}
+
+impl Default for Temps {
+    fn default() -> Self {
+        Self {
+            dir: std::env::temp_dir(),
+            keep: false,
+        }
+    }
+}
+
+impl Temps {
+    // The path of an intermediate file for output, ending in extension
+    fn path(&self, output: &Path, extension: &str) -> PathBuf {
+        let stem = output
+            .file_stem()
+            .map_or("bbvm".into(), |s| s.to_string_lossy());
+        self.dir
+            .join(format!("{}-{}.{}", stem, std::process::id(), extension))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Block {
+    Loop,
+    If,
+}
+
+// An if being lowered
+struct Branch<'a> {
+    otherwise: BasicBlock<'a>,
+    merge: BasicBlock<'a>,
+    // Whether the else has been reached
+    has_else: bool,
+}
+
+// What -g needs to describe the program to a debugger
+struct DebugInfo<'a> {
+    builder: DebugInfoBuilder<'a>,
+    unit: DICompileUnit<'a>,
+    // The type of every variable
+    ty: DIType<'a>,
+    // The function being generated, and main's
+    scope: DIScope<'a>,
+    main: DIScope<'a>,
+}
+
+// The loops --profile counts the iterations of
+struct Profile<'a> {
+    // An array of a count for each loop, which the JIT maps to counts
+    counters: GlobalValue<'a>,
+    // Where each loop is, and its while
+    loops: Vec<(Span, String)>,
+    counts: Vec<u64>,
+}
+
+// Counting which way each while and if goes, for --profile-generate, or
+// weighting their branches by a profile, for --profile-use
+enum Pgo<'a> {
+    Generate {
+        // Two counts for each branch, which the executable's runtime defines
+        counters: GlobalValue<'a>,
+        // Each branch, as the profile names it
+        sites: Vec<String>,
+        // Where the executable writes the profile
+        path: String,
+    },
+    Use {
+        profile: pgo::Profile,
+        // How many branches were found in the profile, and weren't
+        found: usize,
+        missing: usize,
+    },
+}
+
+// Where main was when a proc started
+struct Outer<'a> {
+    block: BasicBlock<'a>,
+    variables: Vec<PointerValue<'a>>,
+    span: Span,
+}
+
+// An alloca holding 0 for each of the variables, where the builder is. They
+// go at the start of a function, where mem2reg looks for them.
+fn allocate<'a>(builder: &Builder<'a>, int: IntType<'a>, names: &[&str]) -> Vec<PointerValue<'a>> {
+    names
+        .iter()
+        .map(|name| {
+            let variable = builder.build_alloca(int, name);
+            builder.build_store(variable, int.const_zero());
+            variable
+        })
+        .collect()
+}
+
 pub struct Converter<'a> {
     context: &'a Context,
     module: Module<'a>,
+    // What main and the procs are named after: bbvm, or bbvm_NAME for one of
+    // several files in a module
+    prefix: String,
     main: FunctionValue<'a>,
+    // The function statements are being lowered into, main or a proc
+    function: FunctionValue<'a>,
     builder: Builder<'a>,
-    variables: Vec<IntValue<'a>>,
-    phis: Vec<(Vec<PhiValue<'a>>, Label<'a>)>,
+    // Where each variable is kept in the function being generated: an
+    // alloca at its start, which mem2reg turns back into registers
+    variables: Vec<PointerValue<'a>>,
+    // The start and exit of each open loop, innermost last
+    loops: Vec<Label<'a>>,
+    // How many whiles and ifs have been lowered, so that each one's blocks
+    // are numbered the same way every time: loop1, loop1.body, if1.then...
+    loop_count: usize,
+    if_count: usize,
     mapping: HashMap<&'a str, usize>,
+    // The variables in the order they are first used
+    names: Vec<&'a str>,
+    // The ones given at the end, in the same order
+    printed: Vec<&'a str>,
+    // The ones read as inputs, sorted
+    inputs: Vec<&'a str>,
+    prune_unused: bool,
+    // The global counting down the fuel left, and how much there was
+    fuel: Option<(PointerValue<'a>, u64)>,
+    // How long run lets the program run for
+    // bbvm_timed_out, which the timer of a run sets, and the timeout
+    timeout: Option<(GlobalValue<'a>, std::time::Duration)>,
+    // Where the JIT maps bbvm_timed_out
+    timed_out: Arc<AtomicBool>,
+    format: OutputFormat,
     one: IntValue<'a>,
     zero: IntValue<'a>,
     l64: IntType<'a>,
-    block: BasicBlock<'a>,
+    // The type of the variables, and of the inputs and results passed to
+    // bbvm_main (at least 64 bits, so they are u64s or u128s)
+    int: IntType<'a>,
+    word: IntType<'a>,
+    trace: Option<(FunctionValue<'a>, FunctionValue<'a>)>,
+    // bbvm_output, the global holding the context it is called with, and
+    // what the JIT maps it to
+    output: Option<(FunctionValue<'a>, GlobalValue<'a>, Output)>,
+    // Where the JIT maps the context global, so each converter's program
+    // reports to its own callback or buffer
+    output_context: Box<*mut c_void>,
+    // What print statements printed under capture_output, until
+    // run_with_writer writes it out. Boxed so the pointer the JIT is given
+    // stays put when the converter moves.
+    #[allow(clippy::box_collection)]
+    captured: Box<String>,
+    trace_names: Vec<String>,
+    trace_loops: Vec<u64>,
+    // The trace id of each proc, given the first time it is called
+    trace_procs: HashMap<&'a str, u64>,
+    detect_overflow: bool,
+    // Whether to print each statement as it runs, for --trace
+    log_statements: bool,
+    debug: Option<DebugInfo<'a>>,
+    // The source, to write its lines into the IR and assembly as comments
+    annotate: Option<String>,
+    profile: Option<Profile<'a>>,
+    pgo: Option<Pgo<'a>>,
+    // bbvm_history, and the variables --history records
+    history: Option<(FunctionValue<'a>, Vec<String>)>,
+    span: Span,
+    // The open loops and ifs, innermost last, and where each starts
+    open: Vec<(Block, Span)>,
+    ifs: Vec<Branch<'a>>,
+    // Every proc defined or called so far, and where each call is
+    procs: HashMap<&'a str, FunctionValue<'a>>,
+    calls: Vec<(&'a str, Span)>,
+    // The variables of main are stored here around calls, made on the first
+    // call
+    env: Option<PointerValue<'a>>,
+    // main's state while a proc is being generated
+    outer: Option<Outer<'a>>,
+    // Each array declared so far and its size. They are globals rather than
+    // allocas in main so that procs can use them too.
+    arrays: HashMap<&'a str, (PointerValue<'a>, u64)>,
+    opt_level: OptimizationLevel,
+    // How many times to unroll each while with an unroll hint, by where the
+    // while starts
+    unrolls: HashMap<usize, u32>,
+    // The pipeline --passes gives instead of opt_level's
+    passes: Option<Vec<&'static str>>,
+    // Each pass or pipeline optimise ran and whether it changed the module
+    changed: Vec<(String, bool)>,
+    // The triple, cpu and features to emit code for, the host's when None
+    triple: Option<String>,
+    cpu: Option<String>,
+    features: Option<String>,
 }
 
 impl<'a> Converter<'a> {
     pub fn new(varib: Vec<&'a str>, inputs: &Vec<&'a str>, context: &'a Context) -> Converter<'a> {
-        let module: Module<'a> = context.create_module("bbvm");
+        Converter::with_int_width(varib, inputs, context, 64)
+    }
+
+    // A converter whose variables are width bits wide instead of 64, and
+    // wrap around at that size
+    pub fn with_int_width(
+        varib: Vec<&'a str>,
+        inputs: &Vec<&'a str>,
+        context: &'a Context,
+        width: u32,
+    ) -> Converter<'a> {
+        let converter = Converter::with_prefix(varib, inputs, context, width, "bbvm".to_string());
+        converter.add_native_main(converter.main, inputs);
+        converter
+    }
+
+    // A converter for one of several files compiled into the same module.
+    // Its program is bbvm_NAME_main and its procs bbvm_NAME_proc_P, and it
+    // has no native main until add_entry picks one of the files to run.
+    pub fn for_file(
+        varib: Vec<&'a str>,
+        inputs: &Vec<&'a str>,
+        context: &'a Context,
+        width: u32,
+        name: &str,
+    ) -> Converter<'a> {
+        Converter::with_prefix(varib, inputs, context, width, format!("bbvm_{}", name))
+    }
+
+    fn with_prefix(
+        varib: Vec<&'a str>,
+        inputs: &Vec<&'a str>,
+        context: &'a Context,
+        width: u32,
+        prefix: String,
+    ) -> Converter<'a> {
+        let module: Module<'a> = context.create_module(&prefix);
         let l64 = context.i64_type();
-        let one = l64.const_int(1, false);
-        let zero = l64.const_zero();
+        let int = context.custom_width_int_type(width);
+        let word = context.custom_width_int_type(width.max(64));
+        let one = int.const_int(1, false);
+        let zero = int.const_zero();
+        let input_ptr = word.ptr_type(AddressSpace::Generic);
+        // The program itself, reading its inputs from an array with one
+        // value per input in sorted order. If results isn't null the final
+        // values are written to it in first use order instead of printed. It
+        // returns how it stopped, see runner::FINISHED.
         let main = module.add_function(
-            "main",
-            context
-                .void_type()
-                .fn_type(&vec![l64.into(); inputs.len()], false),
+            &format!("{}_main", prefix),
+            l64.fn_type(&[input_ptr.into(), input_ptr.into()], false),
             None,
         );
-        let block = context.append_basic_block(main, "entry");
         let builder = context.create_builder();
-        builder.position_at_end(block);
 
-        let mut variables = vec![l64.const_int(0, false); varib.len()];
+        builder.position_at_end(context.append_basic_block(main, "entry"));
+        let variables = allocate(&builder, int, &varib);
 
-        let phis = vec![];
         let mut mapping = HashMap::new();
         for v in varib.iter().enumerate() {
-            mapping.insert(v.1.clone(), v.0);
+            mapping.insert(*v.1, v.0);
         }
-        for (input, param) in zip(inputs, main.get_params()) {
-            variables[mapping[input]] = param.into_int_value();
+        let input_array = main.get_nth_param(0).unwrap().into_pointer_value();
+        for (i, input) in inputs.iter().enumerate() {
+            let element = unsafe {
+                builder.build_in_bounds_gep(input_array, &[l64.const_int(i as u64, false)], "input")
+            };
+            let value = builder.build_load(element, input).into_int_value();
+            let value = if width < 64 {
+                builder.build_int_truncate(value, int, input)
+            } else {
+                value
+            };
+            builder.build_store(variables[mapping[input]], value);
         }
         Converter {
             context,
             module,
+            prefix,
             main,
+            function: main,
             builder,
             variables,
-            phis,
+            loops: vec![],
+            loop_count: 0,
+            if_count: 0,
             mapping,
+            printed: varib.clone(),
+            names: varib,
+            inputs: inputs.clone(),
+            prune_unused: false,
+            fuel: None,
+            timeout: None,
+            timed_out: Arc::new(AtomicBool::new(false)),
+            format: OutputFormat::Pretty,
             one,
             zero,
             l64,
-            block,
+            int,
+            word,
+            trace: None,
+            output: None,
+            output_context: Box::new(std::ptr::null_mut()),
+            captured: Box::default(),
+            trace_names: vec![],
+            trace_loops: vec![],
+            trace_procs: HashMap::new(),
+            detect_overflow: false,
+            log_statements: false,
+            debug: None,
+            annotate: None,
+            profile: None,
+            pgo: None,
+            history: None,
+            span: Span::default(),
+            open: vec![],
+            ifs: vec![],
+            procs: HashMap::new(),
+            calls: vec![],
+            env: None,
+            outer: None,
+            arrays: HashMap::new(),
+            opt_level: OptimizationLevel::Aggressive,
+            unrolls: HashMap::new(),
+            passes: None,
+            changed: vec![],
+            triple: None,
+            cpu: None,
+            features: None,
         }
     }
 
-    // var = var + 1
-    pub fn add_incr<'b: 'a>(&mut self, var: &'b str) -> () {
-        let pos = self.mapping[&var];
+    // A native entry point running program, whose inputs are inputs in
+    // sorted order. Each is read by the runtime's bbvm_read_input, from the
+    // executable's arguments or else stdin.
+    fn add_native_main(&self, program: FunctionValue<'a>, inputs: &[&str]) -> () {
+        let block = self.current_block();
+        let i32_type = self.context.i32_type();
+        let text = self.context.i8_type().ptr_type(AddressSpace::Generic);
+        let argv_type = text.ptr_type(AddressSpace::Generic);
+        let native_main = self.module.add_function(
+            "main",
+            i32_type.fn_type(&[i32_type.into(), argv_type.into()], false),
+            None,
+        );
+        self.builder
+            .position_at_end(self.context.append_basic_block(native_main, "entry"));
+        let array = self.builder.build_array_alloca(
+            self.word,
+            self.l64.const_int(inputs.len().max(1) as u64, false),
+            "inputs",
+        );
+        let (argc, argv) = (
+            native_main.get_nth_param(0).unwrap(),
+            native_main.get_nth_param(1).unwrap(),
+        );
+        for (i, input) in inputs.iter().enumerate() {
+            let read_input = self.get_or_add_function(
+                "bbvm_read_input",
+                self.l64.fn_type(
+                    &[
+                        i32_type.into(),
+                        argv_type.into(),
+                        self.l64.into(),
+                        self.l64.into(),
+                        text.into(),
+                    ],
+                    false,
+                ),
+            );
+            let index = self.l64.const_int(i as u64, false);
+            let name = self.builder.build_global_string_ptr(input, "name");
+            let value = self
+                .builder
+                .build_call(
+                    read_input,
+                    &[
+                        argc,
+                        argv,
+                        index.into(),
+                        self.l64.const_int(inputs.len() as u64, false).into(),
+                        name.as_pointer_value().into(),
+                    ],
+                    input,
+                )
+                .try_as_basic_value()
+                .left()
+                .unwrap()
+                .into_int_value();
+            let value = if self.word.get_bit_width() > 64 {
+                self.builder.build_int_z_extend(value, self.word, input)
+            } else {
+                value
+            };
+            let element = unsafe { self.builder.build_in_bounds_gep(array, &[index], "input") };
+            self.builder.build_store(element, value);
+        }
+        let null = self.word.ptr_type(AddressSpace::Generic).const_null();
+        let stopped = self
+            .builder
+            .build_call(program, &[array.into(), null.into()], "stopped")
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_int_value();
+        // Exits with a halt's status as it is, 0 at the end of the program
+        // and 1 after a runtime failure
+        let finished = self.builder.build_int_compare(
+            IntPredicate::EQ,
+            stopped,
+            self.l64.const_int(runner::FINISHED as u64, true),
+            "finished",
+        );
+        let failed = self.builder.build_int_compare(
+            IntPredicate::EQ,
+            stopped,
+            self.l64.const_int(runner::FAILED as u64, true),
+            "failed",
+        );
+        let status = self.builder.build_int_truncate(stopped, i32_type, "status");
+        let status = self
+            .builder
+            .build_select(failed, i32_type.const_int(1, false), status, "status")
+            .into_int_value();
+        let status = self
+            .builder
+            .build_select(finished, i32_type.const_zero(), status, "status");
+        self.builder.build_return(Some(&status));
+        self.builder.position_at_end(block);
+    }
+
+    // Moves the functions of another file's converter, once it has reached
+    // EOF, into this one's module
+    pub fn link(&mut self, other: Converter<'a>) -> Result<(), String> {
+        self.module
+            .link_in_module(other.module)
+            .map_err(|e| format!("Failed to link {}: {}", other.prefix, e))
+    }
+
+    // Adds the native main to a module of files made with for_file, running
+    // the file called name, whose inputs are inputs in sorted order
+    pub fn add_entry(&mut self, name: &str, inputs: &[&str]) -> Result<(), String> {
+        let program = self
+            .module
+            .get_function(&format!("bbvm_{}_main", name))
+            .ok_or_else(|| format!("There is no file called {} in the module", name))?;
+        if self.debug.is_some() {
+            self.builder.unset_current_debug_location();
+        }
+        self.add_native_main(program, inputs);
+        let entry = self
+            .context
+            .metadata_string(&program.get_name().to_string_lossy());
+        self.module
+            .add_global_metadata(runner::ENTRY, &self.context.metadata_node(&[entry.into()]))
+            .unwrap();
+        self.module
+            .verify()
+            .map_err(|e| format!("The module's IR is invalid: {}", e.to_string().trim()))
+    }
+
+    fn load(&self, var: &str) -> IntValue<'a> {
+        self.builder
+            .build_load(self.variables[self.mapping[var]], var)
+            .into_int_value()
+    }
+
+    fn store(&self, var: &str, value: IntValue<'a>) -> () {
+        self.builder
+            .build_store(self.variables[self.mapping[var]], value);
+    }
+
+    fn current_block(&self) -> BasicBlock<'a> {
+        self.builder.get_insert_block().unwrap()
+    }
+
+    // Makes incr check for wraparound and abort with the variable name and
+    // source line instead of silently wrapping to 0.
+    pub fn enable_overflow_detection(&mut self) -> () {
+        self.detect_overflow = true;
+    }
+
+    // Makes every statement print itself and the value it left in its
+    // variable as it runs, and every loop iteration its count.
+    pub fn enable_statement_log(&mut self) -> () {
+        self.log_statements = true;
+    }
+
+    // Stops the program once it has been through loop bodies and called
+    // procs fuel times in total, so it can't run forever
+    pub fn set_fuel(&mut self, fuel: u64) -> () {
+        let global = self.module.add_global(self.l64, None, "bbvm_fuel");
+        global.set_initializer(&self.l64.const_int(fuel, false));
+        global.set_linkage(Linkage::Internal);
+        self.fuel = Some((global.as_pointer_value(), fuel));
+    }
+
+    // Stops the program with an error if it is still running after timeout,
+    // checked where fuel is used. Only usable under the JIT, which maps the
+    // flag the check reads.
+    pub fn set_timeout(&mut self, timeout: std::time::Duration) -> () {
+        let global = self
+            .module
+            .add_global(self.context.i8_type(), None, "bbvm_timed_out");
+        self.timeout = Some((global, timeout));
+    }
+
+    // Leaves out the statements that can't affect a print, assert, while or
+    // if, and the variables they change from the final values
+    pub fn enable_pruning(&mut self) -> () {
+        self.prune_unused = true;
+    }
+
+    pub fn prunes_unused(&self) -> bool {
+        self.prune_unused
+    }
+
+    // Gives only these of the variables at the end
+    pub fn print_only(&mut self, variables: &HashSet<&str>) -> () {
+        self.printed.retain(|v| variables.contains(v));
+    }
+
+    // How hard optimise, the JIT and the native code generator try, -O3
+    // (Aggressive) by default
+    pub fn set_optimization_level(&mut self, level: OptimizationLevel) -> () {
+        self.opt_level = level;
+    }
+
+    // Whether anything is optimised, which is everything but -O0 unless
+    // there are passes to run
+    pub fn optimises(&self) -> bool {
+        self.opt_level != OptimizationLevel::None || self.passes.is_some()
+    }
 
-        self.variables[pos] = self
+    // Whether counting loops are rewritten as arithmetic before lowering,
+    // which is when optimising unless something counts or reports on the
+    // loops as they run
+    pub fn rewrites_loops(&self) -> bool {
+        self.optimises()
+            && self.fuel.is_none()
+            && self.trace.is_none()
+            && !self.log_statements
+            && self.profile.is_none()
+            && self.history.is_none()
+    }
+
+    // Asks LLVM to unroll the whiles starting at these places the number of
+    // times given, from the lexer's `#[unroll N]` hints
+    pub fn set_unrolls(&mut self, unrolls: &[(usize, u32)]) -> () {
+        self.unrolls.extend(unrolls.iter().copied());
+    }
+
+    // Runs these passes, in order, instead of the optimisation level's, for
+    // --passes. See passes::parse.
+    pub fn set_passes(&mut self, passes: Vec<&'static str>) -> () {
+        self.passes = Some(passes);
+    }
+
+    // What optimise ran and whether each changed the module: each of the
+    // --passes, or else the level's pipeline as a whole
+    pub fn passes_run(&self) -> &[(String, bool)] {
+        &self.changed
+    }
+
+    // How the final values of the variables are printed
+    pub fn set_output_format(&mut self, format: OutputFormat) -> () {
+        self.format = format;
+    }
+
+    // Emits code for another machine, e.g. `aarch64-linux-gnu`. The cpu and
+    // features default to the host's, or to a generic cpu for another triple.
+    pub fn set_target(
+        &mut self,
+        triple: Option<&str>,
+        cpu: Option<&str>,
+        features: Option<&str>,
+    ) -> Result<(), String> {
+        self.triple = triple.map(str::to_string);
+        self.cpu = cpu.map(str::to_string);
+        self.features = features.map(str::to_string);
+        let machine = self.target_machine()?;
+        self.module.set_triple(&machine.get_triple());
+        self.module
+            .set_data_layout(&machine.get_target_data().get_data_layout());
+        Ok(())
+    }
+
+    // Where the statement about to be lowered is, for diagnostics and the
+    // debug info
+    pub fn set_span(&mut self, span: Span) -> () {
+        self.span = span;
+        if let Some(debug) = &self.debug {
+            let location = debug.builder.create_debug_location(
+                self.context,
+                span.line as u32,
+                span.column as u32,
+                debug.scope,
+                None,
+            );
+            self.builder
+                .set_current_debug_location(self.context, location);
+        }
+    }
+
+    // Adds DWARF debug info, so that a debugger can step through the
+    // compiled program by the lines of path and show its variables. Must be
+    // called before anything is lowered.
+    pub fn enable_debug_info(&mut self, path: &Path) -> () {
+        let name = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |n| n.to_string_lossy().into_owned(),
+        );
+        let directory = fs::canonicalize(path)
+            .ok()
+            .and_then(|p| p.parent().map(|d| d.display().to_string()))
+            .unwrap_or_default();
+        let (builder, unit) = self.module.create_debug_info_builder(
+            true,
+            DWARFSourceLanguage::C,
+            &name,
+            &directory,
+            "bbvm",
+            self.optimises(),
+            "",
+            0,
+            "",
+            DWARFEmissionKind::Full,
+            0,
+            false,
+            false,
+            "",
+            "",
+        );
+        let i32_type = self.context.i32_type();
+        self.module.add_basic_value_flag(
+            "Debug Info Version",
+            FlagBehavior::Warning,
+            i32_type.const_int(3, false),
+        );
+        self.module.add_basic_value_flag(
+            "Dwarf Version",
+            FlagBehavior::Warning,
+            i32_type.const_int(4, false),
+        );
+        // DW_ATE_unsigned
+        let ty = builder
+            .create_basic_type(
+                "uint",
+                self.int.get_bit_width() as u64,
+                0x08,
+                DIFlags::PUBLIC,
+            )
+            .unwrap()
+            .as_type();
+        let scope = unit.as_debug_info_scope();
+        self.debug = Some(DebugInfo {
+            builder,
+            unit,
+            ty,
+            scope,
+            main: scope,
+        });
+        let name = format!("{}_main", self.prefix);
+        self.describe_function(self.main, &name, 1);
+        if let Some(debug) = &mut self.debug {
+            debug.main = debug.scope;
+        }
+    }
+
+    // Writes each line of source, which is at path, as a comment before
+    // the IR and assembly generated from it, for --annotate-ir. Lines are
+    // matched to the code by the debug info, which it adds if -g hasn't
+    // already, so it must also be called before anything is lowered.
+    pub fn annotate_source(&mut self, source: &str, path: &Path) -> () {
+        if self.debug.is_none() {
+            self.enable_debug_info(path);
+        }
+        self.annotate = Some(source.to_string());
+    }
+
+    // Gives function, which starts on line, its debug info and makes it
+    // the scope of what is generated next. The variables' allocas, at the
+    // end of the current block, are declared in it.
+    fn describe_function(&mut self, function: FunctionValue<'a>, name: &str, line: u32) -> () {
+        let block = self.current_block();
+        let debug = match &mut self.debug {
+            Some(debug) => debug,
+            None => return,
+        };
+        let file = debug.unit.get_file();
+        let ty = debug
             .builder
-            .build_int_add(self.variables[pos], self.one, "incr");
+            .create_subroutine_type(file, None, &[], DIFlags::PUBLIC);
+        let subprogram = debug.builder.create_function(
+            debug.unit.as_debug_info_scope(),
+            name,
+            None,
+            file,
+            line,
+            ty,
+            false,
+            true,
+            line,
+            DIFlags::PUBLIC,
+            self.opt_level != OptimizationLevel::None,
+        );
+        function.set_subprogram(subprogram);
+        debug.scope = subprogram.as_debug_info_scope();
+        let location =
+            debug
+                .builder
+                .create_debug_location(self.context, line, 0, debug.scope, None);
+        for (name, &variable) in zip(&self.names, &self.variables) {
+            let described = debug.builder.create_auto_variable(
+                debug.scope,
+                name,
+                file,
+                line,
+                debug.ty,
+                true,
+                DIFlags::ZERO,
+                0,
+            );
+            debug
+                .builder
+                .insert_declare_at_end(variable, Some(described), None, location, block);
+        }
+        self.builder
+            .set_current_debug_location(self.context, location);
     }
 
-    // if var != 0 {
-    //   var = var - 1
-    // }
-    pub fn add_decr<'b: 'a>(&mut self, var: &'b str) -> () {
-        let pos = self.mapping[&var];
+    fn get_or_add_function(&self, name: &str, ty: FunctionType<'a>) -> FunctionValue<'a> {
+        self.module
+            .get_function(name)
+            .unwrap_or_else(|| self.module.add_function(name, ty, None))
+    }
+
+    fn print_function(&self) -> FunctionValue<'a> {
+        let text = self.context.i8_type().ptr_type(AddressSpace::Generic);
+        let fun = self.context.void_type().fn_type(
+            &[
+                text.into(),
+                self.l64.into(),
+                self.l64.into(),
+                self.l64.into(),
+                text.into(),
+            ],
+            false,
+        );
+        self.get_or_add_function("bbvm_print", fun)
+    }
+
+    // Prints format through the runtime's bbvm_print, with each %lld in it
+    // replaced by the next of values. Variables up to 64 bits are printed
+    // as i64s, as printf's %lld would, and wider ones unsigned.
+    fn build_print(&mut self, format: &str, values: &[IntValue<'a>]) -> () {
+        let print = self.print_function();
+        let null = self
+            .context
+            .i8_type()
+            .ptr_type(AddressSpace::Generic)
+            .const_null();
+        let parts: Vec<&str> = format.split("%lld").collect();
+        let text = |converter: &Self, part: &str| match part {
+            "" => null,
+            part => converter
+                .builder
+                .build_global_string_ptr(part, "")
+                .as_pointer_value(),
+        };
+        if parts.len() == 1 {
+            let zero = self.l64.const_zero();
+            let kind = self.l64.const_int(runtime::NONE, false);
+            self.builder.build_call(
+                print,
+                &[
+                    text(self, format).into(),
+                    kind.into(),
+                    zero.into(),
+                    zero.into(),
+                    null.into(),
+                ],
+                "",
+            );
+            return;
+        }
+        let count = parts.len() - 1;
+        for (i, (&before, &value)) in zip(&parts[..count], values).enumerate() {
+            let (kind, low, high) = match value.get_type().get_bit_width() {
+                64 => (runtime::SIGNED, value, self.l64.const_zero()),
+                width if width < 64 => (
+                    runtime::SIGNED,
+                    self.builder.build_int_z_extend(value, self.l64, "widened"),
+                    self.l64.const_zero(),
+                ),
+                _ => {
+                    let shift = value.get_type().const_int(64, false);
+                    let high = self.builder.build_right_shift(value, shift, false, "high");
+                    (
+                        runtime::UNSIGNED,
+                        self.builder.build_int_truncate(value, self.l64, "low"),
+                        self.builder.build_int_truncate(high, self.l64, "high"),
+                    )
+                }
+            };
+            // What follows the last number goes with it
+            let after = if i + 1 == count { parts[count] } else { "" };
+            let kind = self.l64.const_int(kind, false);
+            let args = [
+                text(self, before).into(),
+                kind.into(),
+                low.into(),
+                high.into(),
+                text(self, after).into(),
+            ];
+            self.builder.build_call(print, &args, "");
+        }
+    }
+
+    // The llvm.*.with.overflow intrinsic for the variables' type
+    fn overflow_intrinsic(&self, op: &str) -> String {
+        format!("llvm.{}.with.overflow.i{}", op, self.int.get_bit_width())
+    }
 
-        let current = self.variables[pos];
+    // An IntValue of the variables' type, wrapped around to fit it
+    fn constant(&self, n: i128) -> IntValue<'a> {
+        self.int
+            .const_int_arbitrary_precision(&[n as u64, (n >> 64) as u64])
+    }
+
+    // Stops the program, returning stopped (see runner::FINISHED) from the
+    // function being generated, which terminates the current block. A proc
+    // stores the variables back first, and whatever called it stops in
+    // turn. main writes the variables to results first, if it was given
+    // somewhere to write them, so a halted program's values can be read.
+    fn build_stop(&mut self, stopped: IntValue<'a>) -> () {
+        if self.function != self.main {
+            self.store_env(self.function.get_nth_param(0).unwrap().into_pointer_value());
+            self.builder.build_return(Some(&stopped));
+            return;
+        }
+        let results = self.main.get_nth_param(1).unwrap().into_pointer_value();
+        let store = self.context.append_basic_block(self.main, "stop.results");
+        let stop = self.context.append_basic_block(self.main, "stop");
+        let no_results = self.builder.build_is_null(results, "no_results");
+        self.builder
+            .build_conditional_branch(no_results, stop, store);
+        self.builder.position_at_end(store);
+        self.build_store_results(results);
+        self.builder.build_unconditional_branch(stop);
+        self.builder.position_at_end(stop);
+        self.builder.build_return(Some(&stopped));
+    }
+
+    // Prints message (with %lld standing in for value, if any) and stops
+    // the program as failed. This terminates the current block.
+    fn add_runtime_failure(&mut self, message: &str, value: Option<IntValue<'a>>) -> () {
+        let values: Vec<IntValue> = value.into_iter().collect();
+        self.build_print(message, &values);
+        self.build_stop(self.l64.const_int(runner::FAILED as u64, true));
+    }
+
+    // Declares the trace callbacks so that loops report their start and end
+    // to the host. Only usable under the JIT, which maps the callbacks.
+    pub fn enable_trace(&mut self) -> () {
+        let fun = self.context.void_type().fn_type(&[self.l64.into()], false);
+        let begin = self.module.add_function("bbvm_trace_begin", fun, None);
+        let end = self.module.add_function("bbvm_trace_end", fun, None);
+        self.trace = Some((begin, end));
+    }
 
-        let cmp = self
+    // Sends what print statements and the end of the program would print to
+    // callback instead of stdout, for embedding bbvm. Must be called before
+    // anything is lowered. Only usable under the JIT, which maps the
+    // callback; see run_with_output for getting the final values this way.
+    pub fn set_output_callback(&mut self, callback: OutputCallback) -> () {
+        self.set_output(Output::Callback(callback));
+    }
+
+    fn set_output(&mut self, kind: Output) -> () {
+        let pointer = self.context.i8_type().ptr_type(AddressSpace::Generic);
+        let fun = self
+            .context
+            .void_type()
+            .fn_type(&[pointer.into(), pointer.into(), self.l64.into()], false);
+        let output = self.get_or_add_function("bbvm_output", fun);
+        let context = self
+            .module
+            .get_global("bbvm_output_context")
+            .unwrap_or_else(|| self.module.add_global(pointer, None, "bbvm_output_context"));
+        self.output = Some((output, context, kind));
+    }
+
+    // Keeps what print statements print for run_with_writer to write out,
+    // instead of printing it to stdout. Must be called before anything is
+    // lowered, and only for the JIT. Wider variables than 64 bits print
+    // their low 64 bits.
+    pub fn capture_output(&mut self) -> () {
+        self.set_output(Output::Capture);
+    }
+
+    // Calls the output callback with var's name and value
+    fn build_output(
+        &mut self,
+        (output, context): (FunctionValue<'a>, GlobalValue<'a>),
+        var: &str,
+        value: IntValue<'a>,
+    ) -> () {
+        let value = match self.int.get_bit_width() {
+            64 => value,
+            width if width < 64 => self.builder.build_int_z_extend(value, self.l64, "widened"),
+            _ => self.builder.build_int_truncate(value, self.l64, "narrowed"),
+        };
+        let name = self.builder.build_global_string_ptr(var, "name");
+        let context = self
             .builder
-            .build_int_compare(IntPredicate::EQ, current, self.zero, "cmp_to_0");
+            .build_load(context.as_pointer_value(), "context");
+        self.builder.build_call(
+            output,
+            &[context, name.as_pointer_value().into(), value.into()],
+            "",
+        );
+    }
 
-        let main = self.main;
+    // Counts the iterations of every loop, for --profile. Only under the
+    // JIT, where the counts can be read back.
+    pub fn enable_profile(&mut self) -> () {
+        let counters = self
+            .module
+            .add_global(self.l64.array_type(0), None, "bbvm_profile");
+        self.profile = Some(Profile {
+            counters,
+            loops: vec![],
+            counts: vec![],
+        });
+    }
 
-        let skip = self.context.append_basic_block(main, "alreadyZero");
-        let no_skip = self.context.append_basic_block(main, "notZero");
-        self.builder.build_conditional_branch(cmp, skip, no_skip);
+    // Records the values of the variables each time a loop goes round, for
+    // --history. Only under the JIT, which maps bbvm_history.
+    pub fn enable_history(&mut self, variables: &[&str]) -> Result<(), String> {
+        if let Some(unknown) = variables.iter().find(|v| !self.mapping.contains_key(*v)) {
+            return Err(format!("{} is not a variable in the program", unknown));
+        }
+        let fun = self
+            .context
+            .void_type()
+            .fn_type(&[self.l64.into(), self.l64.into()], false);
+        let record = self.get_or_add_function("bbvm_history", fun);
+        let variables = variables.iter().map(|v| v.to_string()).collect();
+        self.history = Some((record, variables));
+        Ok(())
+    }
 
-        self.builder.position_at_end(no_skip);
-        let new_var = self.builder.build_int_nuw_sub(current, self.one, "decr");
-        self.builder.build_unconditional_branch(skip);
+    // Counts which way each while and if goes, and has the executable write
+    // the counts to path when it exits, for --profile-generate. Only for
+    // executables, which have the runtime defining the counters linked in.
+    // Must be called before anything is lowered.
+    pub fn enable_profile_generate(&mut self, path: &str) -> () {
+        let counters = self
+            .module
+            .add_global(self.l64.array_type(0), None, "bbvm_pgo_counts");
+        let start = self.get_or_add_function(
+            "bbvm_pgo_start",
+            self.context.void_type().fn_type(&[], false),
+        );
+        self.builder.build_call(start, &[], "");
+        self.pgo = Some(Pgo::Generate {
+            counters,
+            sites: vec![],
+            path: path.to_string(),
+        });
+    }
 
-        self.builder.position_at_end(skip);
-        let res = self.builder.build_phi(self.l64, "result");
-        res.add_incoming(&[(&current, self.block), (&new_var, no_skip)]);
+    // Weights the branch of each while and if by how often it went each way
+    // in the profile, for --profile-use
+    pub fn use_profile(&mut self, profile: pgo::Profile) -> () {
+        self.pgo = Some(Pgo::Use {
+            profile,
+            found: 0,
+            missing: 0,
+        });
+    }
+
+    // How many whiles and ifs were weighted by the profile, and how many
+    // weren't in it, once the program is lowered
+    pub fn profile_matches(&self) -> (usize, usize) {
+        match self.pgo {
+            Some(Pgo::Use { found, missing, .. }) => (found, missing),
+            _ => (0, 0),
+        }
+    }
 
-        self.block = skip;
+    // The branch of a while or if, keyword saying which. A while's
+    // condition is whether the loop is done and an if's whether it takes
+    // its then, so held is the opposite of the condition for a while.
+    fn build_profiled_branch(
+        &mut self,
+        keyword: &str,
+        condition: IntValue<'a>,
+        then: BasicBlock<'a>,
+        otherwise: BasicBlock<'a>,
+    ) -> () {
+        let exits = keyword == "while";
+        if let Some(Pgo::Generate {
+            counters, sites, ..
+        }) = &mut self.pgo
+        {
+            let id = sites.len() as u64;
+            sites.push(format!(
+                "{} {}:{}",
+                keyword, self.span.line, self.span.column
+            ));
+            // Held is counted first, then not held
+            let not_held = if exits {
+                condition
+            } else {
+                self.builder.build_not(condition, "not_held")
+            };
+            let slot = self.builder.build_int_z_extend(not_held, self.l64, "slot");
+            let slot = self
+                .builder
+                .build_int_add(slot, self.l64.const_int(id * 2, false), "slot");
+            let counter = unsafe {
+                self.builder.build_gep(
+                    counters.as_pointer_value(),
+                    &[self.l64.const_zero(), slot],
+                    "counter",
+                )
+            };
+            let count = self.builder.build_load(counter, "count").into_int_value();
+            let count = self
+                .builder
+                .build_int_add(count, self.l64.const_int(1, false), "count");
+            self.builder.build_store(counter, count);
+        }
+        let branch = self
+            .builder
+            .build_conditional_branch(condition, then, otherwise);
+        if let Some(Pgo::Use {
+            profile,
+            found,
+            missing,
+        }) = &mut self.pgo
+        {
+            let (held, not_held) = match profile.get(self.span) {
+                Some(counts) => counts,
+                None => {
+                    *missing += 1;
+                    return;
+                }
+            };
+            *found += 1;
+            let (held, not_held) = pgo::weights(held, not_held);
+            // In the order of the branch's destinations
+            let (first, second) = if exits {
+                (not_held, held)
+            } else {
+                (held, not_held)
+            };
+            let i32_type = self.context.i32_type();
+            let weights = self.context.metadata_node(&[
+                self.context.metadata_string("branch_weights").into(),
+                i32_type.const_int(first as u64, false).into(),
+                i32_type.const_int(second as u64, false).into(),
+            ]);
+            branch
+                .set_metadata(weights, self.context.get_kind_id("prof"))
+                .expect("branch weights are metadata nodes");
+        }
+    }
+
+    // The variables --history recorded and what they were, once the program
+    // has run under the JIT
+    pub fn history(&self) -> (Vec<String>, Vec<history::Series>) {
+        match &self.history {
+            Some((_, variables)) => (variables.clone(), history::take(variables.len())),
+            None => (vec![], vec![]),
+        }
+    }
+
+    // Every loop, its while and how many times its body ran, the most
+    // first. All 0 until the program has run under the JIT.
+    pub fn profile(&self) -> Vec<(Span, String, u64)> {
+        let mut loops: Vec<(Span, String, u64)> = match &self.profile {
+            Some(profile) => profile
+                .loops
+                .iter()
+                .enumerate()
+                .map(|(i, (span, text))| {
+                    (
+                        *span,
+                        text.clone(),
+                        profile.counts.get(i).copied().unwrap_or(0),
+                    )
+                })
+                .collect(),
+            None => vec![],
+        };
+        loops.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.start.cmp(&b.0.start)));
+        loops
+    }
+
+    fn add_trace_event(&self, end: bool, id: u64) -> () {
+        if let Some((begin_fn, end_fn)) = self.trace {
+            self.builder.build_call(
+                if end { end_fn } else { begin_fn },
+                &[self.l64.const_int(id, false).into()],
+                "",
+            );
+        }
+    }
+
+    // Fails if the run's timer has gone off. The load is volatile so that it
+    // isn't taken out of the loop.
+    fn check_timeout(&mut self) -> () {
+        let (global, timeout) = match self.timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        let flag = self
+            .builder
+            .build_load(global.as_pointer_value(), "timedOut")
+            .into_int_value();
+        flag.as_instruction().unwrap().set_volatile(true).unwrap();
+        let zero = self.context.i8_type().const_zero();
+        let late = self
+            .builder
+            .build_int_compare(IntPredicate::NE, flag, zero, "late");
+        let function = self.function;
+        let timed_out = self.context.append_basic_block(function, "timedOut");
+        let ok = self.context.append_basic_block(function, "inTime");
+        self.builder.build_conditional_branch(late, timed_out, ok);
+
+        self.builder.position_at_end(timed_out);
+        self.add_runtime_failure(
+            &format!(
+                "ERROR[E0042]: the program was still running on line {} after {} seconds\n",
+                self.span.line,
+                timeout.as_secs_f64()
+            ),
+            None,
+        );
+        self.builder.position_at_end(ok);
+    }
+
+    // Takes one from the fuel, failing if there is none left
+    fn use_fuel(&mut self) -> () {
+        let (global, fuel) = match self.fuel {
+            Some(fuel) => fuel,
+            None => return,
+        };
+        let left = self.builder.build_load(global, "fuel").into_int_value();
+        let empty =
+            self.builder
+                .build_int_compare(IntPredicate::EQ, left, self.l64.const_zero(), "noFuel");
+        let function = self.function;
+        let exhausted = self.context.append_basic_block(function, "fuelExhausted");
+        let ok = self.context.append_basic_block(function, "fuelLeft");
+        self.builder.build_conditional_branch(empty, exhausted, ok);
+
+        self.builder.position_at_end(exhausted);
+        self.add_runtime_failure(
+            &format!(
+                "ERROR[E0018]: ran out of fuel on line {} after {} loop iterations and calls\n",
+                self.span.line, fuel
+            ),
+            None,
+        );
+
+        self.builder.position_at_end(ok);
+        let left = self
+            .builder
+            .build_int_sub(left, self.l64.const_int(1, false), "fuel");
+        self.builder.build_store(global, left);
+    }
+
+    // Prints `var: value` at this point of the program
+    pub fn add_print<'b: 'a>(&mut self, var: &'b str) -> () {
+        match self.output {
+            Some((output, context, _)) => self.build_output((output, context), var, self.load(var)),
+            None => {
+                let value = self.load(var);
+                self.build_print(&format!("{}: %lld\n", var), &[value]);
+            }
+        }
+    }
+
+    // lhs op rhs through one of the llvm.*.with.overflow intrinsics,
+    // stopping the program with the statement and line if it overflows
+    fn build_checked(
+        &mut self,
+        op: &str,
+        statement: &str,
+        var: &str,
+        lhs: IntValue<'a>,
+        rhs: IntValue<'a>,
+    ) -> IntValue<'a> {
+        let function = self.get_or_add_function(
+            &self.overflow_intrinsic(op),
+            self.context
+                .struct_type(&[self.int.into(), self.context.bool_type().into()], false)
+                .fn_type(&[self.int.into(), self.int.into()], false),
+        );
+        let checked = self
+            .builder
+            .build_call(function, &[lhs.into(), rhs.into()], "checked")
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_struct_value();
+        let result = self
+            .builder
+            .build_extract_value(checked, 0, statement)
+            .unwrap()
+            .into_int_value();
+        let overflowed = self
+            .builder
+            .build_extract_value(checked, 1, "overflowed")
+            .unwrap()
+            .into_int_value();
+
+        let function = self.function;
+        let overflow = self.context.append_basic_block(function, "overflow");
+        let ok = self.context.append_basic_block(function, "noOverflow");
+        self.builder
+            .build_conditional_branch(overflowed, overflow, ok);
+
+        self.builder.position_at_end(overflow);
+        self.add_runtime_failure(
+            &format!(
+                "ERROR[E0006]: {} {} on line {} overflowed\n",
+                statement, var, self.span.line
+            ),
+            None,
+        );
+
+        self.builder.position_at_end(ok);
+        result
+    }
+
+    // var = var + 1
+    pub fn add_incr<'b: 'a>(&mut self, var: &'b str) -> () {
+        let current = self.load(var);
+        let value = if self.detect_overflow {
+            self.build_checked("uadd", "incr", var, current, self.one)
+        } else {
+            self.builder
+                .build_int_add(current, self.one, &format!("{}.incr", var))
+        };
+        self.store(var, value);
+    }
+
+    // to = to + from
+    pub fn add_addition<'b: 'a>(&mut self, from: &'b str, to: &'b str) -> () {
+        let (from, current) = (self.load(from), self.load(to));
+        let value = if self.detect_overflow {
+            self.build_checked("uadd", "add", to, current, from)
+        } else {
+            self.builder
+                .build_int_add(current, from, &format!("{}.add", to))
+        };
+        self.store(to, value);
+    }
+
+    // to = to - from, or 0 if from is bigger
+    pub fn add_subtraction<'b: 'a>(&mut self, from: &'b str, to: &'b str) -> () {
+        let (from, current) = (self.load(from), self.load(to));
+        let smaller = self.builder.build_int_compare(
+            IntPredicate::ULT,
+            current,
+            from,
+            &format!("{}.smaller", to),
+        );
+        let name = format!("{}.sub", to);
+        let difference = self.builder.build_int_sub(current, from, &name);
+        let value = self
+            .builder
+            .build_select(smaller, self.zero, difference, &name)
+            .into_int_value();
+        self.store(to, value);
+    }
+
+    // to = to * from
+    pub fn add_multiplication<'b: 'a>(&mut self, from: &'b str, to: &'b str) -> () {
+        let (from, current) = (self.load(from), self.load(to));
+        let value = if self.detect_overflow {
+            self.build_checked("umul", "mul", to, current, from)
+        } else {
+            self.builder
+                .build_int_mul(current, from, &format!("{}.mul", to))
+        };
+        self.store(to, value);
+    }
+
+    // if var != 0 {
+    //   var = var - 1
+    // }
+    pub fn add_decr<'b: 'a>(&mut self, var: &'b str) -> () {
+        let current = self.load(var);
+        let value = self.build_saturating_decr(current, var);
+        self.store(var, value);
+    }
 
-        self.variables[pos] = res.as_basic_value().into_int_value();
+    // value - 1, or 0 if value is already 0. The values are named after
+    // var.
+    fn build_saturating_decr(&self, value: IntValue<'a>, var: &str) -> IntValue<'a> {
+        let zero = self.builder.build_int_compare(
+            IntPredicate::EQ,
+            value,
+            self.zero,
+            &format!("{}.is_0", var),
+        );
+        let name = format!("{}.decr", var);
+        let decr = self.builder.build_int_sub(value, self.one, &name);
+        self.builder
+            .build_select(zero, self.zero, decr, &name)
+            .into_int_value()
     }
 
     // var = 0
     pub fn add_clear<'b: 'a>(&mut self, var: &'b str) -> () {
-        self.variables[self.mapping[&var]] = self.zero;
+        self.store(var, self.zero);
+    }
+
+    pub fn add_set<'b: 'a>(&mut self, var: &'b str, value: i128) -> () {
+        self.store(var, self.constant(value));
+    }
+
+    // var = a random number below bound, from bbvm_rand. bbvm has it for
+    // the JIT, and it is compiled into executables.
+    pub fn add_rand<'b: 'a>(&mut self, var: &'b str, bound: i128) -> () {
+        let i64_type = self.context.i64_type();
+        let rand =
+            self.get_or_add_function("bbvm_rand", i64_type.fn_type(&[i64_type.into()], false));
+        let bound = i64_type.const_int(bound as u64, false);
+        let value = self
+            .builder
+            .build_call(rand, &[bound.into()], "rand")
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_int_value();
+        let value = match self.int.get_bit_width() {
+            64 => value,
+            width if width < 64 => self.builder.build_int_truncate(value, self.int, "rand"),
+            _ => self.builder.build_int_z_extend(value, self.int, "rand"),
+        };
+        self.store(var, value);
     }
 
     // to = from
     pub fn add_copy<'b: 'a>(&mut self, from: &'b str, to: &'b str) -> () {
-        self.variables[self.mapping[&to]] = self.variables[self.mapping[&from]];
+        self.store(to, self.load(from));
+    }
+
+    pub fn add_swap<'b: 'a>(&mut self, one: &'b str, two: &'b str) -> () {
+        let (a, b) = (self.load(one), self.load(two));
+        self.store(one, b);
+        self.store(two, a);
+    }
+
+    pub fn add_move<'b: 'a>(&mut self, from: &'b str, to: &'b str) -> () {
+        self.store(to, self.load(from));
+        self.store(from, self.zero);
     }
 
-    pub fn add_while<'b: 'a>(&mut self, var: &'b str, check: i128) -> () {
-        let main = self.main;
-        let lop = self.context.append_basic_block(main, "loop");
+    // while var cmp check do; ... end; where check is a number or another
+    // variable, loaded again at the top of each iteration
+    pub fn add_while<'b: 'a>(&mut self, var: &'b str, cmp: Comparison, check: Bound<'b>) -> () {
+        let function = self.function;
+        self.loop_count += 1;
+        let name = format!("loop{}", self.loop_count);
+        let lop = self.context.append_basic_block(function, &name);
+        let text = format!("while {} {} {} do;", var, cmp.keyword(), check);
+
+        // The loop gets one id, each of its iterations the next one
+        let trace_id = self.trace_names.len() as u64;
+        if self.trace.is_some() {
+            self.trace_names
+                .push(format!("while {} {} {}", var, cmp.keyword(), check));
+            self.trace_names.push(format!("{} iteration", var));
+            self.trace_loops.push(trace_id);
+        }
+        self.add_trace_event(false, trace_id);
+        // Counted from 0 each time the loop is reached
+        let iterations = self.log_statements.then(|| {
+            let iterations = self.entry_alloca("iterations");
+            self.builder.build_store(iterations, self.l64.const_zero());
+            let format = format!(
+                "[trace] line {}: {} iteration %lld, {} = %lld\n",
+                self.span.line, text, var
+            );
+            (iterations, format)
+        });
+
         self.builder.build_unconditional_branch(lop);
         self.builder.position_at_end(lop);
 
-        let phis = self
-            .variables
-            .iter()
-            .map(|var| {
-                let rf = self.builder.build_phi(self.l64, "whilePhi");
-                rf.add_incoming(&[(var, self.block)]);
-                rf
-            })
-            .collect::<Vec<PhiValue>>();
+        // The loop exits once the comparison no longer holds
+        let check = match check {
+            Bound::Number(n) => self.constant(n.value),
+            Bound::Variable(v) => self.load(v.ident),
+        };
+        let cmp = self.builder.build_int_compare(
+            match cmp {
+                Comparison::Ne => IntPredicate::EQ,
+                Comparison::Lt => IntPredicate::UGE,
+                Comparison::Gt => IntPredicate::ULE,
+            },
+            self.load(var),
+            check,
+            &format!("{}.{}.done", var, name),
+        );
+        let inner_loop = self
+            .context
+            .append_basic_block(function, &format!("{}.body", name));
+        let exit = self
+            .context
+            .append_basic_block(function, &format!("{}.exit", name));
+        self.build_profiled_branch("while", cmp, exit, inner_loop);
+        self.builder.position_at_end(inner_loop);
+        self.add_trace_event(false, trace_id + 1);
+        if let Some(profile) = &mut self.profile {
+            let id = self.l64.const_int(profile.loops.len() as u64, false);
+            profile.loops.push((self.span, text));
+            let counter = unsafe {
+                self.builder.build_gep(
+                    profile.counters.as_pointer_value(),
+                    &[self.l64.const_zero(), id],
+                    "counter",
+                )
+            };
+            let count = self.builder.build_load(counter, "count").into_int_value();
+            let count = self
+                .builder
+                .build_int_add(count, self.l64.const_int(1, false), "count");
+            self.builder.build_store(counter, count);
+        }
+        if let Some((iterations, format)) = iterations {
+            let count = self
+                .builder
+                .build_load(iterations, "iterations")
+                .into_int_value();
+            let count =
+                self.builder
+                    .build_int_add(count, self.l64.const_int(1, false), "iterations");
+            self.builder.build_store(iterations, count);
+            let value = self.load(var);
+            self.build_print(&format, &[count, value]);
+        }
 
-        self.variables = phis
-            .iter()
-            .map(|phi| phi.as_basic_value().into_int_value())
-            .collect::<Vec<IntValue>>();
+        self.use_fuel();
+        self.check_timeout();
+        self.loops.push((lop, exit));
+        self.open.push((Block::Loop, self.span));
+    }
+
+    // Asks LLVM to unroll the loop whose back edge is latch count times, with
+    // `!llvm.loop !{!self, !{!"llvm.loop.unroll.count", i32 count}}`. A loop
+    // ID's first operand is itself, so it is made around a temporary node
+    // that is then replaced with it.
+    fn add_unroll_count(&self, latch: InstructionValue<'a>, count: u32) -> () {
+        let key = "llvm.loop.unroll.count";
+        let kind = "llvm.loop";
+        let count = self.context.i32_type().const_int(count as u64, false);
+        unsafe {
+            let context = LLVMGetModuleContext(self.module.as_mut_ptr() as *mut c_void);
+            let mut hint = [
+                LLVMMDStringInContext2(context, key.as_ptr() as *const c_char, key.len()),
+                LLVMValueAsMetadata(count.as_value_ref() as *mut c_void),
+            ];
+            let hint = LLVMMDNodeInContext2(context, hint.as_mut_ptr(), hint.len());
+            let temporary = LLVMTemporaryMDNode(context, std::ptr::null_mut(), 0);
+            let mut operands = [temporary, hint];
+            let id = LLVMMDNodeInContext2(context, operands.as_mut_ptr(), operands.len());
+            LLVMMetadataReplaceAllUsesWith(temporary, id);
+            LLVMSetMetadata(
+                latch.as_value_ref() as *mut c_void,
+                LLVMGetMDKindIDInContext(
+                    context,
+                    kind.as_ptr() as *const c_char,
+                    kind.len() as u32,
+                ),
+                LLVMMetadataAsValue(context, id),
+            );
+        }
+    }
 
+    // An alloca at the start of the function being generated, so that it is
+    // made once however often the code asking for it runs
+    fn entry_alloca(&self, name: &str) -> PointerValue<'a> {
+        let entry = self.function.get_first_basic_block().unwrap();
+        let builder = self.context.create_builder();
+        match entry.get_first_instruction() {
+            Some(first) => builder.position_before(&first),
+            None => builder.position_at_end(entry),
+        }
+        builder.build_alloca(self.l64, name)
+    }
+
+    // if var cmp check {
+    //   ...
+    // } else {
+    //   ...
+    // }
+    pub fn add_if<'b: 'a>(&mut self, var: &'b str, cmp: Comparison, check: i128) -> () {
+        self.if_count += 1;
+        let name = format!("if{}", self.if_count);
+        let condition = self.builder.build_int_compare(
+            match cmp {
+                Comparison::Ne => IntPredicate::NE,
+                Comparison::Lt => IntPredicate::ULT,
+                Comparison::Gt => IntPredicate::UGT,
+            },
+            self.load(var),
+            self.constant(check),
+            &format!("{}.{}.taken", var, name),
+        );
+        let function = self.function;
+        let block = |part: &str| {
+            self.context
+                .append_basic_block(function, &format!("{}.{}", name, part))
+        };
+        let (then, otherwise, merge) = (block("then"), block("else"), block("end"));
+        self.build_profiled_branch("if", condition, then, otherwise);
+        self.builder.position_at_end(then);
+
+        self.ifs.push(Branch {
+            otherwise,
+            merge,
+            has_else: false,
+        });
+        self.open.push((Block::If, self.span));
+    }
+
+    pub fn add_else(&mut self) -> Result<(), BbvmError> {
+        let branch = match (self.open.last(), self.ifs.last_mut()) {
+            (Some((Block::If, _)), Some(branch)) if !branch.has_else => branch,
+            _ => return Err(BbvmError::UnmatchedElse { span: self.span }),
+        };
+        branch.has_else = true;
+        self.builder.build_unconditional_branch(branch.merge);
+        self.builder.position_at_end(branch.otherwise);
+        Ok(())
+    }
+
+    pub fn add_endif(&mut self) -> Result<(), BbvmError> {
+        if !matches!(self.open.last(), Some((Block::If, _))) {
+            return Err(BbvmError::UnmatchedEndIf { span: self.span });
+        }
+        self.open.pop();
+        let branch = self.ifs.pop().unwrap();
+        self.builder.build_unconditional_branch(branch.merge);
+        // No else, so it is an empty block going straight to the end
+        if !branch.has_else {
+            self.builder.position_at_end(branch.otherwise);
+            self.builder.build_unconditional_branch(branch.merge);
+        }
+        self.builder.position_at_end(branch.merge);
+        Ok(())
+    }
+
+    // if var != check {
+    //   fail with the actual value
+    // }
+    pub fn add_assert<'b: 'a>(&mut self, var: &'b str, check: i128) -> () {
+        let current = self.load(var);
         let cmp = self.builder.build_int_compare(
             IntPredicate::EQ,
-            self.variables[self.mapping[&var]],
-            self.l64.const_int(check as u64, false),
-            "exitCondition",
+            current,
+            self.constant(check),
+            "assertCondition",
         );
-        let inner_loop = self.context.append_basic_block(main, "innerLoop");
-        let exit = self.context.append_basic_block(main, "loopExit");
-        self.builder.build_conditional_branch(cmp, exit, inner_loop);
-        self.builder.position_at_end(inner_loop);
 
-        self.block = inner_loop;
-        self.phis.push((phis, (lop, exit)));
+        let function = self.function;
+        let failed = self.context.append_basic_block(function, "assertFailed");
+        let passed = self.context.append_basic_block(function, "assertPassed");
+        self.builder.build_conditional_branch(cmp, passed, failed);
+
+        self.builder.position_at_end(failed);
+        self.add_runtime_failure(
+            &format!(
+                "ERROR[E0005]: assertion failed on line {}: {} is %lld, expected {}\n",
+                self.span.line, var, check
+            ),
+            Some(current),
+        );
+
+        self.builder.position_at_end(passed);
+    }
+
+    pub fn add_array<'b: 'a>(&mut self, name: &'b str, size: i128) -> Result<(), BbvmError> {
+        if self.arrays.contains_key(name) {
+            return Err(BbvmError::DuplicateArray {
+                name: name.to_string(),
+                span: self.span,
+            });
+        }
+        if size > u32::MAX as i128 {
+            return Err(BbvmError::ArrayTooBig {
+                name: name.to_string(),
+                size,
+                span: self.span,
+            });
+        }
+        let ty = self.int.array_type(size as u32);
+        let global = self
+            .module
+            .add_global(ty, None, &format!("bbvm_array_{}", name));
+        global.set_initializer(&ty.const_zero());
+        global.set_linkage(Linkage::Internal);
+        self.arrays
+            .insert(name, (global.as_pointer_value(), size as u64));
+        Ok(())
+    }
+
+    // A pointer to array[index], stopping the program if index is past the
+    // end
+    fn element_pointer(&mut self, array: &str, index: &str) -> Result<PointerValue<'a>, BbvmError> {
+        let (pointer, size) = *self.arrays.get(array).ok_or(BbvmError::UnknownArray {
+            name: array.to_string(),
+            span: self.span,
+        })?;
+        let value = self.load(index);
+        let wide = if self.int.get_bit_width() < 64 {
+            self.builder.build_int_z_extend(value, self.word, "index")
+        } else {
+            value
+        };
+        let inside = self.builder.build_int_compare(
+            IntPredicate::ULT,
+            wide,
+            self.word.const_int(size, false),
+            "inBounds",
+        );
+        let function = self.function;
+        let outside = self.context.append_basic_block(function, "outOfBounds");
+        let ok = self.context.append_basic_block(function, "inBounds");
+        self.builder.build_conditional_branch(inside, ok, outside);
+
+        self.builder.position_at_end(outside);
+        self.add_runtime_failure(
+            &format!(
+                "ERROR[E0027]: {}[{}] on line {} is out of bounds: {} is %lld, but {} has {} elements\n",
+                array, index, self.span.line, index, array, size
+            ),
+            Some(value),
+        );
+
+        self.builder.position_at_end(ok);
+        Ok(unsafe {
+            self.builder
+                .build_in_bounds_gep(pointer, &[self.word.const_zero(), wide], "element")
+        })
+    }
+
+    // A statement on array[index] instead of a variable
+    pub fn add_indexed<'b: 'a>(
+        &mut self,
+        array: &'b str,
+        index: &'b str,
+        op: ElementOp<'b>,
+    ) -> Result<(), BbvmError> {
+        let element = self.element_pointer(array, index)?;
+        let load = |converter: &Self| {
+            converter
+                .builder
+                .build_load(element, &format!("{}.element", array))
+                .into_int_value()
+        };
+        let value = match op {
+            ElementOp::Clear => self.zero,
+            ElementOp::Incr => {
+                let current = load(self);
+                if self.detect_overflow {
+                    let name = format!("{}[{}]", array, index);
+                    self.build_checked("uadd", "incr", &name, current, self.one)
+                } else {
+                    self.builder
+                        .build_int_add(current, self.one, &format!("{}.incr", array))
+                }
+            }
+            ElementOp::Decr => self.build_saturating_decr(load(self), array),
+            ElementOp::Load(to) => {
+                self.store(to.ident, load(self));
+                return Ok(());
+            }
+            ElementOp::Store(from) => self.load(from.ident),
+        };
+        self.builder.build_store(element, value);
+        Ok(())
+    }
+
+    // Stops the program with var as the exit status, without printing the
+    // final values. Anything after it is generated into a block of its own
+    // that nothing reaches.
+    pub fn add_halt<'b: 'a>(&mut self, var: &'b str) -> () {
+        let value = self.load(var);
+        let i32_type = self.context.i32_type();
+        let status = if self.int.get_bit_width() > 32 {
+            self.builder.build_int_truncate(value, i32_type, "status")
+        } else {
+            self.builder.build_int_z_extend(value, i32_type, "status")
+        };
+        let status = self.builder.build_int_z_extend(status, self.l64, "status");
+        self.build_stop(status);
+        let after = self.context.append_basic_block(self.function, "afterHalt");
+        self.builder.position_at_end(after);
+    }
+
+    // Stops in an attached debugger (SIGTRAP without one)
+    pub fn add_breakpoint(&mut self) -> () {
+        let debugtrap = self.get_or_add_function(
+            "llvm.debugtrap",
+            self.context.void_type().fn_type(&[], false),
+        );
+        self.builder.build_call(debugtrap, &[], "");
+    }
+
+    // Each proc is a function taking a pointer to every variable, which it
+    // loads into its own allocas at the start and stores back at the end
+    fn proc_function(&mut self, name: &'a str) -> FunctionValue<'a> {
+        if let Some(&function) = self.procs.get(name) {
+            return function;
+        }
+        let function = self.module.add_function(
+            &format!("{}_proc_{}", self.prefix, name),
+            self.l64
+                .fn_type(&[self.int.ptr_type(AddressSpace::Generic).into()], false),
+            None,
+        );
+        self.procs.insert(name, function);
+        function
+    }
+
+    // Where the variables go around a call: the proc's own parameter, or an
+    // array at the start of main
+    fn env_pointer(&mut self) -> PointerValue<'a> {
+        if self.function != self.main {
+            return self.function.get_nth_param(0).unwrap().into_pointer_value();
+        }
+        if let Some(env) = self.env {
+            return env;
+        }
+        let block = self.current_block();
+        let entry = self.main.get_first_basic_block().unwrap();
+        match entry.get_first_instruction() {
+            Some(first) => self.builder.position_before(&first),
+            None => self.builder.position_at_end(entry),
+        }
+        let env = self.builder.build_array_alloca(
+            self.int,
+            self.l64
+                .const_int(self.variables.len().max(1) as u64, false),
+            "env",
+        );
+        self.builder.position_at_end(block);
+        self.env = Some(env);
+        env
+    }
+
+    fn store_env(&self, env: PointerValue<'a>) -> () {
+        for (i, name) in self.names.iter().enumerate() {
+            let element = unsafe {
+                self.builder
+                    .build_in_bounds_gep(env, &[self.l64.const_int(i as u64, false)], "env")
+            };
+            self.builder.build_store(element, self.load(name));
+        }
+    }
+
+    fn load_env(&self, env: PointerValue<'a>) -> () {
+        for (i, name) in self.names.iter().enumerate() {
+            let element = unsafe {
+                self.builder
+                    .build_in_bounds_gep(env, &[self.l64.const_int(i as u64, false)], "env")
+            };
+            self.store(
+                name,
+                self.builder.build_load(element, name).into_int_value(),
+            );
+        }
+    }
+
+    pub fn add_proc<'b: 'a>(&mut self, name: &'b str) -> Result<(), BbvmError> {
+        if self.outer.is_some() || !self.open.is_empty() {
+            return Err(BbvmError::NestedProc { span: self.span });
+        }
+        let function = self.proc_function(name);
+        if function.count_basic_blocks() > 0 {
+            return Err(BbvmError::DuplicateProc {
+                name: name.to_string(),
+                span: self.span,
+            });
+        }
+        self.outer = Some(Outer {
+            block: self.current_block(),
+            variables: self.variables.clone(),
+            span: self.span,
+        });
+
+        let entry = self.context.append_basic_block(function, "entry");
+        self.builder.position_at_end(entry);
+        self.function = function;
+        // main's location can't be used in the proc
+        if self.debug.is_some() {
+            self.builder.unset_current_debug_location();
+        }
+        self.variables = allocate(&self.builder, self.int, &self.names);
+        self.describe_function(function, name, self.span.line as u32);
+        self.load_env(function.get_nth_param(0).unwrap().into_pointer_value());
+        Ok(())
+    }
+
+    pub fn add_endproc(&mut self) -> Result<(), BbvmError> {
+        match self.open.last() {
+            Some(&(Block::Loop, span)) => return Err(BbvmError::UnclosedWhile { span }),
+            Some(&(Block::If, span)) => return Err(BbvmError::UnclosedIf { span }),
+            None => {}
+        }
+        let outer = self
+            .outer
+            .take()
+            .ok_or(BbvmError::UnmatchedEndProc { span: self.span })?;
+        self.store_env(self.function.get_nth_param(0).unwrap().into_pointer_value());
+        self.builder.build_return(Some(&self.finished()));
+
+        self.function = self.main;
+        self.variables = outer.variables;
+        self.builder.position_at_end(outer.block);
+        if let Some(debug) = &mut self.debug {
+            debug.scope = debug.main;
+        }
+        self.set_span(self.span);
+        Ok(())
+    }
+
+    pub fn add_call<'b: 'a>(&mut self, name: &'b str) -> () {
+        let function = self.proc_function(name);
+        self.calls.push((name, self.span));
+        self.use_fuel();
+        self.check_timeout();
+        let env = self.env_pointer();
+        self.store_env(env);
+        // The call is one event, with the loops in the proc nested in it
+        let trace_id = match self.trace {
+            Some(_) => {
+                let names = &mut self.trace_names;
+                *self.trace_procs.entry(name).or_insert_with(|| {
+                    names.push(format!("call {}", name));
+                    names.len() as u64 - 1
+                })
+            }
+            None => 0,
+        };
+        self.add_trace_event(false, trace_id);
+        let stopped = self
+            .builder
+            .build_call(function, &[env.into()], "stopped")
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_int_value();
+        self.add_trace_event(true, trace_id);
+        self.load_env(env);
+        // A halt or failure in the proc stops its caller too
+        let finished =
+            self.builder
+                .build_int_compare(IntPredicate::EQ, stopped, self.finished(), "finished");
+        let stop = self.context.append_basic_block(self.function, "call.stop");
+        let next = self.context.append_basic_block(self.function, "call.next");
+        self.builder.build_conditional_branch(finished, next, stop);
+        self.builder.position_at_end(stop);
+        self.build_stop(stopped);
+        self.builder.position_at_end(next);
+    }
+
+    // What main and the procs return when they reach their end
+    fn finished(&self) -> IntValue<'a> {
+        self.l64.const_int(runner::FINISHED as u64, true)
     }
 
-    pub fn add_end(&mut self) -> () {
-        let (phis, (start, end)) = self
-            .phis
-            .pop()
-            .expect("ERROR: Phis list empty (too many \"end\"s?)");
-        self.builder.build_unconditional_branch(start);
+    pub fn add_end(&mut self) -> Result<(), BbvmError> {
+        if !matches!(self.open.last(), Some((Block::Loop, _))) {
+            return Err(BbvmError::UnmatchedEnd { span: self.span });
+        }
+        let (_, span) = self.open.pop().unwrap();
+        let (start, end) = self.loops.pop().unwrap();
+        let trace_id = self.trace_loops.pop().unwrap_or_default();
+        self.add_trace_event(true, trace_id + 1);
+        if let Some((record, variables)) = &self.history {
+            for (id, var) in variables.iter().enumerate() {
+                // Wider variables are recorded by their low 64 bits
+                let value = match self.int.get_bit_width() {
+                    64 => self.load(var),
+                    width if width < 64 => {
+                        self.builder
+                            .build_int_z_extend(self.load(var), self.l64, var)
+                    }
+                    _ => self
+                        .builder
+                        .build_int_truncate(self.load(var), self.l64, var),
+                };
+                let id = self.l64.const_int(id as u64, false);
+                self.builder
+                    .build_call(*record, &[id.into(), value.into()], "");
+            }
+        }
+        let latch = self.builder.build_unconditional_branch(start);
+        if let Some(&count) = self.unrolls.get(&span.start) {
+            self.add_unroll_count(latch, count);
+        }
         self.builder.position_at_end(end);
-        for (phi, var) in zip(&phis, &self.variables) {
-            phi.add_incoming(&[(var, self.block)]);
+        self.add_trace_event(true, trace_id);
+        Ok(())
+    }
+
+    // Generates the IR for the statements of the block, and the blocks inside
+    // it, in order. after_each is called once each statement is lowered, and
+    // stops it with the error it gives.
+    pub fn add_block(
+        &mut self,
+        block: &parser::Block<'a>,
+        after_each: &mut dyn FnMut(&Converter<'a>, Span) -> Result<(), BbvmError>,
+    ) -> Result<(), BbvmError> {
+        for node in block {
+            match node {
+                Node::Statement(statement, span) => {
+                    self.set_span(*span);
+                    self.add_statement(*statement)?;
+                    after_each(self, *span)?;
+                }
+                Node::Loop {
+                    var,
+                    cmp,
+                    check,
+                    body,
+                    span,
+                    end,
+                } => {
+                    self.set_span(*span);
+                    self.add_while(var.ident, *cmp, *check);
+                    after_each(self, *span)?;
+                    self.add_block(body, after_each)?;
+                    self.set_span(*end);
+                    self.add_end()?;
+                    after_each(self, *end)?;
+                }
+                Node::If {
+                    var,
+                    cmp,
+                    check,
+                    then,
+                    otherwise,
+                    span,
+                    end,
+                } => {
+                    self.set_span(*span);
+                    self.add_if(var.ident, *cmp, check.value);
+                    after_each(self, *span)?;
+                    self.add_block(then, after_each)?;
+                    if let Some((span, otherwise)) = otherwise {
+                        self.set_span(*span);
+                        self.add_else()?;
+                        after_each(self, *span)?;
+                        self.add_block(otherwise, after_each)?;
+                    }
+                    self.set_span(*end);
+                    self.add_endif()?;
+                    after_each(self, *end)?;
+                }
+                Node::Proc {
+                    name,
+                    body,
+                    span,
+                    end,
+                } => {
+                    self.set_span(*span);
+                    self.add_proc(name.ident)?;
+                    after_each(self, *span)?;
+                    self.add_block(body, after_each)?;
+                    self.set_span(*end);
+                    self.add_endproc()?;
+                    after_each(self, *end)?;
+                }
+            }
         }
-        self.variables = phis
+        Ok(())
+    }
+
+    // Generates the IR for one statement as the lexer gives them, opening
+    // or closing a block for the statements that do
+    pub fn add_statement(&mut self, statement: Statement<'a>) -> Result<(), BbvmError> {
+        match statement {
+            Statement::While(v) => v.compile(self),
+            Statement::If(v) => v.compile(self),
+            Statement::Else => self.add_else(),
+            Statement::EndIf => self.add_endif(),
+            Statement::Proc(v) => v.compile(self),
+            Statement::EndProc => self.add_endproc(),
+            Statement::Call(v) => v.compile(self),
+            Statement::Assert(v) => v.compile(self),
+            Statement::Set(v) => v.compile(self),
+            Statement::Rand(v) => v.compile(self),
+            Statement::Array(v) => v.compile(self),
+            Statement::Indexed(v) => v.compile(self),
+            Statement::TwoParam(v) => v.compile(self),
+            Statement::OneParam(v) => v.compile(self),
+            Statement::Fluff => Ok(()),
+            Statement::End => self.add_end(),
+            Statement::Breakpoint => {
+                self.add_breakpoint();
+                Ok(())
+            }
+            Statement::EOF => self.add_eof(),
+        }?;
+        if self.log_statements {
+            self.log_statement(statement);
+        }
+        Ok(())
+    }
+
+    // Prints `[trace] line N: statement` as it runs, with the value the
+    // statement left in its variable. Blocks aren't logged, loops log their
+    // iterations instead.
+    fn log_statement(&mut self, statement: Statement<'a>) -> () {
+        let text = match decompile::statement(&statement) {
+            Some(text) => text,
+            None => return,
+        };
+        let var = match statement {
+            Statement::OneParam(OneParam { one: var, .. })
+            | Statement::TwoParam(TwoParam { two: var, .. })
+            | Statement::Set(Set { var, .. })
+            | Statement::Rand(Rand { var, .. })
+            | Statement::Assert(Assert { param: var, .. })
+            | Statement::Indexed(Indexed {
+                op: ElementOp::Load(var),
+                ..
+            }) => Some(var.ident),
+            // Reading the element back would check its index again, so only
+            // the statement is shown
+            Statement::Indexed(_) => None,
+            _ => return,
+        };
+        let line = self.span.line;
+        match var {
+            Some(var) => {
+                let value = self.load(var);
+                let format = format!("[trace] line {}: {} {} = %lld\n", line, text, var);
+                self.build_print(&format, &[value]);
+            }
+            None => {
+                let format = format!("[trace] line {}: {}\n", line, text);
+                self.build_print(&format, &[]);
+            }
+        }
+    }
+
+    // Writes the variables given at the end into results, in order
+    fn build_store_results(&self, results: PointerValue<'a>) -> () {
+        for (i, var) in self.printed.iter().enumerate() {
+            let element = unsafe {
+                self.builder.build_in_bounds_gep(
+                    results,
+                    &[self.l64.const_int(i as u64, false)],
+                    "result",
+                )
+            };
+            let value = self.load(var);
+            let value = if self.int.get_bit_width() < 64 {
+                self.builder.build_int_z_extend(value, self.word, var)
+            } else {
+                value
+            };
+            self.builder.build_store(element, value);
+        }
+    }
+
+    pub fn add_eof(&mut self) -> Result<(), BbvmError> {
+        match self.open.last() {
+            Some(&(Block::Loop, span)) => return Err(BbvmError::UnclosedWhile { span }),
+            Some(&(Block::If, span)) => return Err(BbvmError::UnclosedIf { span }),
+            None => {}
+        }
+        if let Some(outer) = &self.outer {
+            return Err(BbvmError::UnclosedProc { span: outer.span });
+        }
+        for &(name, span) in &self.calls {
+            if self.procs[name].count_basic_blocks() == 0 {
+                return Err(BbvmError::UnknownProc {
+                    name: name.to_string(),
+                    span,
+                });
+            }
+        }
+        let results = self.main.get_nth_param(1).unwrap().into_pointer_value();
+        let store = self.context.append_basic_block(self.main, "store_results");
+        let print = self.context.append_basic_block(self.main, "print_results");
+        let no_results = self.builder.build_is_null(results, "no_results");
+        self.builder
+            .build_conditional_branch(no_results, print, store);
+
+        self.builder.position_at_end(store);
+        self.build_store_results(results);
+        self.builder.build_return(Some(&self.finished()));
+
+        self.builder.position_at_end(print);
+        if let Some((output, context, _)) = self.output {
+            for var in self.printed.clone() {
+                self.build_output((output, context), var, self.load(var));
+            }
+        } else {
+            if self.printed.is_empty() && !self.format.empty().is_empty() {
+                self.build_print(self.format.empty(), &[]);
+            }
+            for (i, var) in self.printed.clone().iter().enumerate() {
+                let value = self.load(var);
+                self.build_print(&self.format.line(var, i, self.printed.len()), &[value]);
+            }
+        }
+
+        self.builder.build_return(Some(&self.finished()));
+
+        if let Some(debug) = &self.debug {
+            debug.builder.finalize();
+        }
+        self.add_interface();
+        self.verify().map_err(|reason| BbvmError::InvalidIr {
+            statement: "the end of the program".to_string(),
+            reason,
+            span: self.span,
+        })
+    }
+
+    // Records how to call the program in the module's metadata, for running
+    // it from bitcode
+    fn add_interface(&self) -> () {
+        let interface = Interface {
+            main: format!("{}_main", self.prefix),
+            width: self.word.get_bit_width(),
+            inputs: self.inputs.iter().map(|i| i.to_string()).collect(),
+            printed: self.printed.iter().map(|v| v.to_string()).collect(),
+        };
+        let fields: Vec<_> = interface
+            .fields()
+            .iter()
+            .map(|field| self.context.metadata_string(field).into())
+            .collect();
+        self.module
+            .add_global_metadata(runner::INTERFACE, &self.context.metadata_node(&fields))
+            .unwrap();
+    }
+
+    // Runs the LLVM verifier over the whole module, giving what it found
+    // wrong if it is invalid
+    pub fn verify(&self) -> Result<(), String> {
+        self.module.verify().map_err(|e| e.to_string())
+    }
+
+    // verify, for the module as generated so far. Blocks still waiting for
+    // their terminator (the current block, the exits of open loops and
+    // main's while a proc is being lowered) are temporarily given one.
+    pub fn verify_so_far(&self) -> Result<(), String> {
+        let block = self.current_block();
+        let unterminated: Vec<BasicBlock> = self
+            .module
+            .get_functions()
+            .flat_map(|function| function.get_basic_blocks())
+            .filter(|block| block.get_terminator().is_none())
+            .collect();
+        let placeholders: Vec<_> = unterminated
             .iter()
-            .map(|phi| phi.as_basic_value().into_int_value())
+            .map(|block| {
+                self.builder.position_at_end(*block);
+                self.builder.build_unreachable()
+            })
             .collect();
-        self.block = end;
+
+        let verified = self.verify();
+
+        for placeholder in placeholders {
+            placeholder.erase_from_basic_block();
+        }
+        self.builder.position_at_end(block);
+        verified
     }
-    pub fn add_eof<'b>(&'b mut self) -> () {
-        if self.phis.len() > 0 {
-            panic!("Too many opening while loops!")
+
+    // The module's textual LLVM IR
+    pub fn ir_string(&self) -> String {
+        let ir = self.module.print_to_string().to_string();
+        match &self.annotate {
+            Some(source) => annotate::ir(&ir, source),
+            None => ir,
         }
-        let fun = self.context.void_type().fn_type(
-            &[
-                self.context
-                    .i8_type()
-                    .ptr_type(AddressSpace::Generic)
-                    .into(),
-                self.l64.into(),
-            ],
-            false,
+    }
+
+    // Number of basic blocks and instructions in main and the procs
+    pub fn ir_size(&self) -> (u32, usize) {
+        let functions: Vec<FunctionValue> = std::iter::once(self.main)
+            .chain(self.procs.values().copied())
+            .collect();
+        let instructions = functions
+            .iter()
+            .flat_map(|f| f.get_basic_blocks())
+            .map(|block| {
+                let mut count = 0;
+                let mut instruction = block.get_first_instruction();
+                while let Some(i) = instruction {
+                    count += 1;
+                    instruction = i.get_next_instruction();
+                }
+                count
+            })
+            .sum();
+        (
+            functions.iter().map(|f| f.count_basic_blocks()).sum(),
+            instructions,
+        )
+    }
+
+    // Runs the module passes for the optimisation level, none at all at -O0,
+    // or the --passes. The variables are allocas until mem2reg and SROA
+    // promote them.
+    pub fn optimise(&mut self) -> bool {
+        if !self.optimises() {
+            return false;
+        }
+        let changed = match &self.passes {
+            // One at a time, to know which of them changed anything
+            Some(passes) => {
+                for &name in passes {
+                    let pass_manager = PassManager::create(());
+                    passes::add(&pass_manager, name);
+                    let changed = pass_manager.run_on(&self.module);
+                    self.changed.push((name.to_string(), changed));
+                }
+                self.changed.iter().any(|&(_, changed)| changed)
+            }
+            None => {
+                let pm_builder = PassManagerBuilder::create();
+                pm_builder.set_optimization_level(self.opt_level);
+                let pass_manager = PassManager::create(());
+                pass_manager.add_promote_memory_to_register_pass();
+                pass_manager.add_scalar_repl_aggregates_pass_ssa();
+                pm_builder.populate_module_pass_manager(&pass_manager);
+                let changed = pass_manager.run_on(&self.module);
+                let level = match self.opt_level {
+                    OptimizationLevel::None => 0,
+                    OptimizationLevel::Less => 1,
+                    OptimizationLevel::Default => 2,
+                    OptimizationLevel::Aggressive => 3,
+                };
+                self.changed
+                    .push((format!("-O{} pipeline", level), changed));
+                changed
+            }
+        };
+        self.name_phis();
+        changed
+    }
+
+    // mem2reg names the phis it makes for a variable X.0, X.1 and so on in
+    // the order it makes them. They are renamed after the variable and the
+    // block they are in instead, X.loop1.phi, so that IR from two versions
+    // of bbvm can be diffed. Phis the other passes make keep their names.
+    fn name_phis(&self) -> () {
+        for function in self.module.get_functions() {
+            for block in function.get_basic_blocks() {
+                let block_name = block.get_name().to_string_lossy();
+                let mut instruction = block.get_first_instruction();
+                while let Some(current) = instruction {
+                    instruction = current.get_next_instruction();
+                    // Phis all come first in a block
+                    let value = match PhiValue::try_from(current) {
+                        Ok(phi) => phi.as_basic_value(),
+                        Err(_) => break,
+                    };
+                    if let BasicValueEnum::IntValue(value) = value {
+                        let name = value.get_name().to_string_lossy();
+                        match name.split_once('.') {
+                            Some((var, _)) if self.mapping.contains_key(var) => {
+                                value.set_name(&format!("{}.{}.phi", var, block_name))
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // The generated main reads a value for every input from the array it is
+    // given, without knowing how long it is
+    fn check_inputs(&self, count: usize) -> () {
+        assert_eq!(
+            count,
+            self.inputs.len(),
+            "the program has {} inputs, but {} values were given",
+            self.inputs.len(),
+            count
         );
-        let printf = self.module.add_function("printf", fun, None);
-        for var in &self.mapping {
-            let fmt = self
-                .builder
-                .build_global_string_ptr(format!("{}: %lld\n", var.0).as_str(), "");
-            self.builder.build_call(
-                printf,
-                &[fmt.as_pointer_value().into(), self.variables[*var.1].into()],
-                "printf",
-            );
+    }
+
+    // Runs the program under the JIT, with the inputs from args (as given
+    // to --args) or stdin. Returns how long compiling the program took, and
+    // then running it, and how it stopped if it halted or failed, in which
+    // case the final values aren't printed. Exiting with the status is left
+    // to the caller.
+    pub fn run(
+        &mut self,
+        inputs: Vec<&'a str>,
+        args: Option<&str>,
+    ) -> (Duration, Duration, Option<Stopped>) {
+        self.run_with_writer(inputs, args, &mut io::stdout())
+    }
+
+    // run, writing the results to out instead of stdout, and before them
+    // what print statements printed if capture_output was called. The
+    // prompts for inputs read from stdin are still on stdout.
+    pub fn run_with_writer(
+        &mut self,
+        inputs: Vec<&'a str>,
+        args: Option<&str>,
+        out: &mut impl Write,
+    ) -> (Duration, Duration, Option<Stopped>) {
+        if self.word.get_bit_width() > 64 {
+            self.run_with::<u128>(inputs, args, out)
+        } else {
+            self.run_with::<u64>(inputs, args, out)
         }
+    }
 
-        self.builder.build_return(None);
+    // run_with_writer, passing the inputs and results as V, which must be
+    // the same size as word
+    fn run_with<V: Value + 'a>(
+        &mut self,
+        inputs: Vec<&'a str>,
+        args: Option<&str>,
+        out: &mut impl Write,
+    ) -> (Duration, Duration, Option<Stopped>) {
+        let mut write = |text: &str| {
+            out.write_all(text.as_bytes())
+                .expect("ERROR: Failed to write the program's output")
+        };
+        let compile_start = chrono::Utc::now();
+        let execution_engine = self.execution_engine();
+        let main: JitFunction<'a, unsafe extern "C" fn(*const V, *mut V) -> i64> = unsafe {
+            execution_engine
+                .get_function(&format!("{}_main", self.prefix))
+                .expect("Unable to load function")
+        };
+        let compile_time = chrono::Utc::now() - compile_start;
+        if self.format.separators() {
+            write("-----\n");
+        }
+        let values: Vec<V> = input::read(&inputs, args);
+        self.check_inputs(values.len());
+        if self.format.separators() {
+            write("-----\n");
+        }
 
-        if let Err(e) = self.module.verify() {
-            eprintln!("{}", e.to_str().unwrap());
-            panic!("Module has errors");
+        // The results come back in a buffer rather than being printed, so
+        // they print the same way as the interpreter's
+        let mut results = vec![V::default(); self.printed.len().max(1)];
+        let _timer = self
+            .timeout
+            .map(|(_, timeout)| timeout::start(timeout, self.timed_out.clone()));
+        let start = chrono::Utc::now();
+        let stopped =
+            Stopped::from_main(unsafe { main.call(values.as_ptr(), results.as_mut_ptr()) });
+        let duration = chrono::Utc::now() - start;
+        write(&std::mem::take(&mut *self.captured));
+        if stopped.is_some() {
+            return (compile_time, duration, stopped);
         }
+        let results: Vec<(&str, V)> = zip(self.printed.iter().copied(), results).collect();
+        write(&self.format.render(&results));
+        if self.format.separators() {
+            write("-----\n");
+        }
+        (compile_time, duration, None)
     }
 
-    pub fn optimise(&mut self) -> bool {
-        let pm_builder = PassManagerBuilder::create();
-        pm_builder.set_optimization_level(OptimizationLevel::Aggressive);
-        let pass_manager = PassManager::create(());
-        pm_builder.populate_module_pass_manager(&pass_manager);
-        pass_manager.run_on(&self.module)
+    // Runs the program under the JIT with values for the inputs, in the
+    // order they were given to the converter, and returns the final values
+    // instead of printing them, in the order the variables were given, with
+    // how long compiling and running took and how it stopped if it halted or
+    // failed. A halted program gives the values it had when it did. Only for
+    // 64 bit words, and values must have one for each input.
+    pub fn run_for_results(
+        &mut self,
+        values: &[u64],
+    ) -> (Duration, Duration, Vec<u64>, Option<Stopped>) {
+        assert_eq!(self.word.get_bit_width(), 64);
+        self.check_inputs(values.len());
+        let compile_start = chrono::Utc::now();
+        let execution_engine = self.execution_engine();
+        let main: JitFunction<'a, unsafe extern "C" fn(*const u64, *mut u64) -> i64> = unsafe {
+            execution_engine
+                .get_function(&format!("{}_main", self.prefix))
+                .expect("Unable to load function")
+        };
+        let compile_time = chrono::Utc::now() - compile_start;
+        let mut results = vec![0; self.printed.len().max(1)];
+        let _timer = self
+            .timeout
+            .map(|(_, timeout)| timeout::start(timeout, self.timed_out.clone()));
+        let start = chrono::Utc::now();
+        let stopped =
+            Stopped::from_main(unsafe { main.call(values.as_ptr(), results.as_mut_ptr()) });
+        let duration = chrono::Utc::now() - start;
+        results.truncate(self.printed.len());
+        (compile_time, duration, results, stopped)
     }
 
-    pub fn run(&mut self, inputs: Vec<&'a str>) -> Duration {
+    // Runs the program under the JIT warmup times and then runs more times
+    // with the same inputs, compiling it once, for `bbvm bench`. Returns how
+    // long each of the timed runs took, or an error if one of them failed.
+    pub fn run_repeatedly(
+        &mut self,
+        values: &[u64],
+        warmup: usize,
+        runs: usize,
+    ) -> Result<Vec<Duration>, String> {
+        assert_eq!(self.word.get_bit_width(), 64);
+        self.check_inputs(values.len());
+        let execution_engine = self.execution_engine();
+        let main: JitFunction<'a, unsafe extern "C" fn(*const u64, *mut u64) -> i64> = unsafe {
+            execution_engine
+                .get_function(&format!("{}_main", self.prefix))
+                .expect("Unable to load function")
+        };
+        let mut results = vec![0; self.printed.len().max(1)];
+        let mut durations = Vec::with_capacity(runs);
+        for run in 0..warmup + runs {
+            let start = chrono::Utc::now();
+            let stopped = unsafe { main.call(values.as_ptr(), results.as_mut_ptr()) };
+            if stopped == runner::FAILED {
+                return Err("it failed at run time".to_string());
+            }
+            if run >= warmup {
+                durations.push(chrono::Utc::now() - start);
+            }
+        }
+        Ok(durations)
+    }
+
+    // Runs the program under the JIT like run_for_results, but gives the
+    // final values to the output callback, one call each in the order the
+    // variables were given, after anything the program printed. Returns how
+    // long compiling and running took, and how it stopped if it halted or
+    // failed, in which case the final values aren't given.
+    pub fn run_with_output(&mut self, values: &[u64]) -> (Duration, Duration, Option<Stopped>) {
+        assert!(
+            self.output.is_some(),
+            "run_with_output needs set_output_callback"
+        );
+        assert_eq!(self.word.get_bit_width(), 64);
+        self.check_inputs(values.len());
+        let compile_start = chrono::Utc::now();
+        let execution_engine = self.execution_engine();
+        let main: JitFunction<'a, unsafe extern "C" fn(*const u64, *mut u64) -> i64> = unsafe {
+            execution_engine
+                .get_function(&format!("{}_main", self.prefix))
+                .expect("Unable to load function")
+        };
+        let compile_time = chrono::Utc::now() - compile_start;
+        let _timer = self
+            .timeout
+            .map(|(_, timeout)| timeout::start(timeout, self.timed_out.clone()));
+        let start = chrono::Utc::now();
+        // Without a buffer for the results, main gives them as it would
+        // print them
+        let stopped =
+            Stopped::from_main(unsafe { main.call(values.as_ptr(), std::ptr::null_mut()) });
+        (compile_time, chrono::Utc::now() - start, stopped)
+    }
+
+    // A JIT for the module, with the runtime's functions and the profile's
+    // counters mapped in
+    fn execution_engine(&mut self) -> ExecutionEngine<'a> {
         let execution_engine = self
             .module
-            .create_jit_execution_engine(OptimizationLevel::Aggressive)
+            .create_jit_execution_engine(self.opt_level)
             .expect("Unable to create execution engine");
-        unsafe {
-            match inputs[..] {
-                [] => {
-                    println!("-----");
-
-                    let start = chrono::Utc::now();
-                    let main: JitFunction<'a, unsafe extern "C" fn() -> ()> = execution_engine
-                        .get_function("main")
-                        .expect("Unable to load function");
-                    main.call();
-                    println!("-----");
-                    chrono::Utc::now() - start
-                }
-                [a] => {
-                    println!("-----");
-                    print!("{}: ", a);
-                    stdout().flush().unwrap();
-                    let mut a = String::new();
-                    stdin().read_line(&mut a).unwrap();
-                    let a = a.trim().parse().unwrap();
-                    println!("-----");
-
-                    let start = chrono::Utc::now();
-                    let main: JitFunction<'a, unsafe extern "C" fn(u64) -> ()> = execution_engine
-                        .get_function("main")
-                        .expect("Unable to load function");
-                    main.call(a);
-                    println!("-----");
-                    chrono::Utc::now() - start
-                }
-                [a, b] => {
-                    println!("-----");
-                    print!("{}: ", a);
-                    stdout().flush().unwrap();
-                    let mut a = String::new();
-                    stdin().read_line(&mut a).unwrap();
-                    let a = a.trim().parse().unwrap();
-                    print!("{}: ", b);
-                    stdout().flush().unwrap();
-                    let mut b = String::new();
-                    stdin().read_line(&mut b).unwrap();
-                    let b = b.trim().parse().unwrap();
-                    println!("-----");
-
-                    let start = chrono::Utc::now();
-                    let main: JitFunction<'a, unsafe extern "C" fn(u64, u64) -> ()> =
-                        execution_engine
-                            .get_function("main")
-                            .expect("Unable to load function");
-                    main.call(a, b);
-                    println!("-----");
-                    chrono::Utc::now() - start
-                }
-                [a, b, c] => {
-                    println!("-----");
-                    print!("{}: ", a);
-                    stdout().flush().unwrap();
-                    let mut a = String::new();
-                    stdin().read_line(&mut a).unwrap();
-                    let a = a.trim().parse().unwrap();
-                    print!("{}: ", b);
-                    stdout().flush().unwrap();
-                    let mut b = String::new();
-                    stdin().read_line(&mut b).unwrap();
-                    let b = b.trim().parse().unwrap();
-                    print!("{}: ", c);
-                    stdout().flush().unwrap();
-                    let mut c = String::new();
-                    stdin().read_line(&mut c).unwrap();
-                    let c = c.trim().parse().unwrap();
-                    println!("-----");
-
-                    let start = chrono::Utc::now();
-                    let main: JitFunction<'a, unsafe extern "C" fn(u64, u64, u64) -> ()> =
-                        execution_engine
-                            .get_function("main")
-                            .expect("Unable to load function");
-                    main.call(a, b, c);
-                    println!("-----");
-                    chrono::Utc::now() - start
+        if let Some(profile) = &mut self.profile {
+            profile.counts = vec![0; profile.loops.len().max(1)];
+            execution_engine
+                .add_global_mapping(&profile.counters, profile.counts.as_mut_ptr() as usize);
+        }
+        map_runtime(&self.module, &execution_engine);
+        if let Some((timed_out, _)) = self.timeout {
+            execution_engine.add_global_mapping(&timed_out, Arc::as_ptr(&self.timed_out) as usize);
+        }
+        if let Some((output, context, kind)) = self.output {
+            let (function, pointer) = match kind {
+                Output::Callback(callback) => {
+                    (call_back as *const () as usize, callback as *mut c_void)
                 }
-                [..] => todo!(),
-            }
+                Output::Capture => (
+                    capture as *const () as usize,
+                    &mut *self.captured as *mut String as *mut c_void,
+                ),
+            };
+            *self.output_context = pointer;
+            execution_engine.add_global_mapping(&output, function);
+            execution_engine
+                .add_global_mapping(&context, &*self.output_context as *const _ as usize);
         }
+        execution_engine
     }
 
-    pub fn dump_code(&mut self) -> () {
-        Target::initialize_native(&InitializationConfig::default())
-            .expect("Failed to initialize llvm");
-        let target = Target::get_first().expect("Could not find target");
+    pub fn write_trace(&self, path: &Path) -> io::Result<()> {
+        trace::write(path, &self.trace_names)
+    }
+
+    fn target_machine(&self) -> Result<TargetMachine, String> {
+        let triple = match &self.triple {
+            Some(triple) => {
+                Target::initialize_all(&InitializationConfig::default());
+                TargetTriple::create(triple)
+            }
+            None => {
+                Target::initialize_native(&InitializationConfig::default())
+                    .map_err(|e| format!("Failed to initialize LLVM: {}", e))?;
+                TargetMachine::get_default_triple()
+            }
+        };
+        let target = Target::from_triple(&triple)
+            .map_err(|e| format!("Unknown target {}: {}", triple, e))?;
+        let cpu = match (&self.cpu, &self.triple) {
+            (Some(cpu), _) => cpu.clone(),
+            (None, Some(_)) => "generic".to_string(),
+            (None, None) => TargetMachine::get_host_cpu_name().to_string(),
+        };
+        let features = match (&self.features, &self.triple) {
+            (Some(features), _) => features.clone(),
+            (None, Some(_)) => String::new(),
+            (None, None) => TargetMachine::get_host_cpu_features().to_string(),
+        };
 
-        let target_machine = target
+        // Position independent, so that the default (PIE) link works
+        target
             .create_target_machine(
-                &TargetMachine::get_default_triple(),
-                TargetMachine::get_host_cpu_name()
-                    .as_ref()
-                    .to_str()
-                    .unwrap(),
-                TargetMachine::get_host_cpu_features()
-                    .as_ref()
-                    .to_str()
-                    .unwrap(),
-                OptimizationLevel::Aggressive,
-                inkwell::targets::RelocMode::Default,
+                &triple,
+                &cpu,
+                &features,
+                self.opt_level,
+                inkwell::targets::RelocMode::PIC,
                 inkwell::targets::CodeModel::Default,
             )
-            .expect("Could not make target machine");
+            .ok_or_else(|| format!("Could not make a target machine for {} ({})", triple, cpu))
+    }
 
-        target_machine
-            .write_to_file(
-                &self.module,
-                inkwell::targets::FileType::Assembly,
-                &Path::new("./out.s"),
-            )
-            .unwrap();
+    pub fn write_ir(&self, path: &Path) -> Result<(), String> {
+        if self.annotate.is_some() {
+            return fs::write(path, self.ir_string())
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e));
+        }
+        self.module
+            .print_to_file(path)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
 
-        let mut gcc = std::process::Command::new("gcc");
-        gcc.args(["-no-pie", "out.s", "-o", "bbvm.out"]);
-        if !gcc.status().expect("Failed to run GCC").success() {
-            panic!("GCC failed to compile the assembly code");
+    pub fn write_bitcode(&self, path: &Path) -> Result<(), String> {
+        if self.module.write_bitcode_to_path(path) {
+            Ok(())
+        } else {
+            Err(format!("Failed to write {}", path.display()))
         }
+    }
 
-        std::io::stdout()
-            .write_all(
-                &std::process::Command::new("./bbvm.out")
-                    .output()
-                    .expect("Failed to run compiled code")
-                    .stdout,
-            )
-            .unwrap();
+    fn write_machine_code(&self, path: &Path, file_type: FileType) -> Result<(), String> {
+        self.target_machine()?
+            .write_to_file(&self.module, file_type, path)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn write_assembly(&self, path: &Path) -> Result<(), String> {
+        let source = match &self.annotate {
+            Some(source) => source,
+            None => return self.write_machine_code(path, FileType::Assembly),
+        };
+        let buffer = self
+            .target_machine()?
+            .write_to_memory_buffer(&self.module, FileType::Assembly)
+            .map_err(|e| format!("Failed to generate assembly: {}", e))?;
+        let triple = match &self.triple {
+            Some(triple) => triple.clone(),
+            None => TargetMachine::get_default_triple().to_string(),
+        };
+        let comment = if triple.starts_with("aarch64") || triple.starts_with("arm64") {
+            "//"
+        } else if triple.starts_with("arm") || triple.starts_with("thumb") {
+            "@"
+        } else {
+            "#"
+        };
+        let assembly = String::from_utf8_lossy(buffer.as_slice());
+        fs::write(path, annotate::asm(&assembly, source, comment))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn write_object(&self, path: &Path) -> Result<(), String> {
+        self.write_machine_code(path, FileType::Object)
+    }
+
+    // Whether the code is for Windows, whose executables end in .exe and
+    // are linked by MSVC
+    pub fn targets_windows(&self) -> bool {
+        self.triple
+            .as_ref()
+            .map_or(cfg!(windows), |triple| triple.contains("windows"))
+    }
+
+    // Writes an object file into temps and links it into an executable at
+    // path, with the runtimes for printing and rand if the program uses them
+    pub fn write_executable(
+        &self,
+        path: &Path,
+        temps: &Temps,
+        options: &LinkOptions,
+    ) -> Result<(), String> {
+        fs::create_dir_all(&temps.dir)
+            .map_err(|e| format!("Failed to create {}: {}", temps.dir.display(), e))?;
+        let object = temps.path(path, if self.targets_windows() { "obj" } else { "o" });
+        self.write_object(&object)?;
+        let mut runtime = vec![];
+        if self.module.get_function("bbvm_print").is_some()
+            || self.module.get_function("bbvm_read_input").is_some()
+        {
+            let source = temps.path(path, "print.c");
+            fs::write(&source, runtime::RUNTIME)
+                .map_err(|e| format!("Failed to write {}: {}", source.display(), e))?;
+            runtime.push(source);
+        }
+        if self.module.get_function("bbvm_rand").is_some() {
+            let source = temps.path(path, "rand.c");
+            fs::write(&source, random::RUNTIME)
+                .map_err(|e| format!("Failed to write {}: {}", source.display(), e))?;
+            runtime.push(source);
+        }
+        if let Some(Pgo::Generate {
+            sites,
+            path: profile,
+            ..
+        }) = &self.pgo
+        {
+            let source = temps.path(path, "pgo.c");
+            fs::write(&source, pgo::runtime(sites, profile))
+                .map_err(|e| format!("Failed to write {}: {}", source.display(), e))?;
+            runtime.push(source);
+        }
+        let runtime_paths: Vec<&Path> = runtime.iter().map(|p| p.as_path()).collect();
+        let linked = link::link(&object, &runtime_paths, path, options);
+        if !temps.keep {
+            let _ = fs::remove_file(&object);
+            for source in &runtime {
+                let _ = fs::remove_file(source);
+            }
+        }
+        linked
+    }
+
+    // Compiles the program into an executable at path without running it,
+    // returning how long it took. See run_executable for running it.
+    pub fn dump_code(
+        &mut self,
+        path: &Path,
+        temps: &Temps,
+        options: &LinkOptions,
+    ) -> Result<Duration, String> {
+        let start = chrono::Utc::now();
+        self.write_executable(path, temps, options)?;
+        Ok(chrono::Utc::now() - start)
     }
 }
+
+// Maps the functions generated code calls in bbvm (printing, rand,
+// tracing and --history) to bbvm's own, for whichever of them the module uses
+pub fn map_runtime(module: &Module, execution_engine: &ExecutionEngine) -> () {
+    let functions: [(&str, *const ()); 5] = [
+        ("bbvm_print", runtime::bbvm_print as *const ()),
+        ("bbvm_rand", random::bbvm_rand as *const ()),
+        ("bbvm_trace_begin", trace::bbvm_trace_begin as *const ()),
+        ("bbvm_trace_end", trace::bbvm_trace_end as *const ()),
+        ("bbvm_history", history::bbvm_history as *const ()),
+    ];
+    for (name, function) in functions {
+        if let Some(f) = module.get_function(name) {
+            execution_engine.add_global_mapping(&f, function as usize);
+        }
+    }
+}
+
+// Runs a compiled program with args, returning how long it ran and the
+// status it exited with. args are its inputs in sorted order of their
+// names, or empty to have it read them from stdin. Its stdin and stderr are
+// bbvm's, and so is its stdout unless out is given, which then gets
+// everything it printed once it ends. Being killed by a signal is an error.
+pub fn run_executable(
+    executable: &Path,
+    args: &[String],
+    out: Option<&mut dyn Write>,
+) -> Result<(Duration, i32), String> {
+    // A bare name would be looked for on $PATH
+    let executable = match executable.parent() {
+        Some(dir) if dir.as_os_str().is_empty() => Path::new(".").join(executable),
+        _ => executable.to_path_buf(),
+    };
+    let executable = executable.as_path();
+    let mut command = std::process::Command::new(executable);
+    command.args(args);
+    let start = chrono::Utc::now();
+    let status = match out {
+        Some(out) => {
+            let output = command
+                .stdin(std::process::Stdio::inherit())
+                .stderr(std::process::Stdio::inherit())
+                .output()
+                .map_err(|e| format!("Failed to run {}: {}", executable.display(), e))?;
+            out.write_all(&output.stdout)
+                .map_err(|e| format!("Failed to write the program's output: {}", e))?;
+            output.status
+        }
+        None => command
+            .status()
+            .map_err(|e| format!("Failed to run {}: {}", executable.display(), e))?,
+    };
+    let duration = chrono::Utc::now() - start;
+    let code = status
+        .code()
+        .ok_or_else(|| format!("{} was killed ({})", executable.display(), status))?;
+    Ok((duration, code))
+}
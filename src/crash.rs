@@ -0,0 +1,116 @@
+use std::{backtrace::Backtrace, fs, panic, sync::Mutex};
+
+use clap::crate_version;
+use lazy_static::lazy_static;
+
+use bbvm::highlight;
+use bbvm::token::{Element, TokenKind};
+
+// What the compiler was doing, for the crash report
+struct CrashState {
+    phase: &'static str,
+    source: Option<String>,
+    redact: bool,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<CrashState> = Mutex::new(CrashState {
+        phase: "starting up",
+        source: None,
+        redact: false,
+    });
+}
+
+pub fn set_phase(phase: &'static str) -> () {
+    STATE.lock().unwrap_or_else(|e| e.into_inner()).phase = phase;
+}
+
+pub fn set_source(source: &str) -> () {
+    STATE.lock().unwrap_or_else(|e| e.into_inner()).source = Some(source.to_string());
+}
+
+// Keeps the shape of the program but not its names, numbers or comments
+fn redact(source: &str) -> String {
+    let mut names: Vec<&str> = vec![];
+    let mut rename = |name| {
+        let index = names.iter().position(|n| *n == name).unwrap_or_else(|| {
+            names.push(name);
+            names.len() - 1
+        });
+        format!("v{}", index)
+    };
+    highlight::pieces(source)
+        .into_iter()
+        .map(|(kind, text)| match kind {
+            Some(TokenKind::Identifier) => rename(text),
+            Some(TokenKind::Element) => {
+                let element = Element::parse(text);
+                format!(
+                    "{}[{}]",
+                    rename(element.array.ident),
+                    rename(element.index.ident)
+                )
+            }
+            Some(TokenKind::Number) => "0".to_string(),
+            Some(TokenKind::Comment) => "#".to_string(),
+            _ => text.to_string(),
+        })
+        .collect()
+}
+
+// Replaces the default panic output. Panics starting with `ERROR` are
+// mistakes in the program or the command line and are printed as they are;
+// anything else is a bug in bbvm, so a report is written for it.
+pub fn install(redact_source: bool) -> () {
+    STATE.lock().unwrap_or_else(|e| e.into_inner()).redact = redact_source;
+    panic::set_hook(Box::new(|info| {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        if message.starts_with("ERROR") {
+            eprintln!("{}", message);
+            return;
+        }
+
+        let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}", l.file(), l.line()))
+            .unwrap_or_default();
+        let source = match &state.source {
+            Some(source) if state.redact => redact(source),
+            Some(source) => source.clone(),
+            None => "(not read yet)".to_string(),
+        };
+        let report = format!(
+            "bbvm {} (LLVM 12)\nphase: {}\npanicked at {}: {}\n\nsource{}:\n{}\n\nbacktrace:\n{}\n",
+            crate_version!(),
+            state.phase,
+            location,
+            message,
+            if state.redact { " (redacted)" } else { "" },
+            source,
+            Backtrace::force_capture()
+        );
+        let filename = format!(
+            "bbvm-crash-{}.txt",
+            chrono::Utc::now().format("%Y%m%d-%H%M%S")
+        );
+
+        eprintln!("ERROR: bbvm crashed while {}: {}", state.phase, message);
+        eprintln!("       This is a bug in bbvm, not in your program.");
+        match fs::write(&filename, report) {
+            Ok(()) => eprintln!(
+                "       A crash report was written to ./{}, please attach it to a bug report.",
+                filename
+            ),
+            Err(e) => eprintln!("       The crash report couldn't be written: {}", e),
+        }
+        if !state.redact {
+            eprintln!("       The report includes your source; use --redact-crash-report to leave out names and numbers.");
+        }
+    }));
+}
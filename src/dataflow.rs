@@ -0,0 +1,300 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::analyze::Analysis;
+use crate::lexer::Span;
+use crate::parser::{Block, Node, Program};
+use crate::token::{
+    Bound, Call, ElementOp, Indexed, OneParam, OneParamType, Rand, Set, Statement, TwoParam,
+    TwoParamType,
+};
+
+// The inputs a value can depend on
+type Inputs<'a> = BTreeSet<&'a str>;
+
+// Which inputs can influence what the program prints, found by following
+// the values through the program without running it. A value depends on
+// the inputs it is computed from, and on the ones the whiles and ifs around
+// where it is set check. Loops are followed until nothing changes, so an
+// input listed might not change the value in practice, but one left out
+// never can.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Flow<'a> {
+    pub inputs: Vec<&'a str>,
+    // Each print and halt, as `print X` or `halt X`
+    pub prints: Vec<(Span, String, Inputs<'a>)>,
+    // Each variable's final value, in first use order
+    pub outputs: Vec<(&'a str, Inputs<'a>)>,
+}
+
+// What each variable and array depends on at a point in the program. An
+// array is one value, all of its elements together.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+struct State<'a> {
+    variables: HashMap<&'a str, Inputs<'a>>,
+    arrays: HashMap<&'a str, Inputs<'a>>,
+}
+
+impl<'a> State<'a> {
+    fn get(&self, var: &str) -> Inputs<'a> {
+        self.variables.get(var).cloned().unwrap_or_default()
+    }
+
+    // What either state could have
+    fn join(&mut self, other: &State<'a>) -> () {
+        for (var, inputs) in &other.variables {
+            self.variables.entry(var).or_default().extend(inputs);
+        }
+        for (array, inputs) in &other.arrays {
+            self.arrays.entry(array).or_default().extend(inputs);
+        }
+    }
+}
+
+struct Walker<'p, 'a> {
+    procs: HashMap<&'a str, &'p Block<'a>>,
+    // The procs being walked, as a recursive call adds nothing the walk of
+    // its proc isn't already finding
+    visiting: Vec<&'a str>,
+    // By where they are, as a print in a loop is walked more than once
+    prints: BTreeMap<usize, (Span, String, Inputs<'a>)>,
+}
+
+impl<'p, 'a> Walker<'p, 'a> {
+    // Walks the block, control being what decides whether it runs
+    fn block(&mut self, block: &'p Block<'a>, state: &mut State<'a>, control: &Inputs<'a>) -> () {
+        for node in block {
+            match node {
+                Node::Statement(statement, span) => {
+                    self.statement(statement, *span, state, control)
+                }
+                Node::Loop {
+                    var, check, body, ..
+                } => loop {
+                    let before = state.clone();
+                    let mut inner = control.clone();
+                    inner.extend(state.get(var.ident));
+                    if let Bound::Variable(bound) = check {
+                        inner.extend(state.get(bound.ident));
+                    }
+                    self.block(body, state, &inner);
+                    // The body may have run any number of times, even none
+                    state.join(&before);
+                    if *state == before {
+                        break;
+                    }
+                },
+                Node::If {
+                    var,
+                    then,
+                    otherwise,
+                    ..
+                } => {
+                    let mut inner = control.clone();
+                    inner.extend(state.get(var.ident));
+                    let mut other = state.clone();
+                    self.block(then, state, &inner);
+                    if let Some((_, otherwise)) = otherwise {
+                        self.block(otherwise, &mut other, &inner);
+                    }
+                    state.join(&other);
+                }
+                // Walked where they are called
+                Node::Proc { .. } => {}
+            }
+        }
+    }
+
+    fn statement(
+        &mut self,
+        statement: &Statement<'a>,
+        span: Span,
+        state: &mut State<'a>,
+        control: &Inputs<'a>,
+    ) -> () {
+        let mut from = control.clone();
+        match *statement {
+            Statement::TwoParam(TwoParam {
+                one,
+                two,
+                ty: TwoParamType::Swap,
+            }) => {
+                let mut other = from.clone();
+                from.extend(state.get(one.ident));
+                other.extend(state.get(two.ident));
+                state.variables.insert(two.ident, from);
+                state.variables.insert(one.ident, other);
+            }
+            Statement::TwoParam(TwoParam {
+                one,
+                two,
+                ty: TwoParamType::Move,
+            }) => {
+                let cleared = from.clone();
+                from.extend(state.get(one.ident));
+                state.variables.insert(two.ident, from);
+                state.variables.insert(one.ident, cleared);
+            }
+            Statement::TwoParam(TwoParam { one, two, ty }) => {
+                from.extend(state.get(one.ident));
+                if ty != TwoParamType::Copy {
+                    from.extend(state.get(two.ident));
+                }
+                state.variables.insert(two.ident, from);
+            }
+            Statement::OneParam(OneParam { one, ty }) => match ty {
+                OneParamType::Incr | OneParamType::Decr => {
+                    state.variables.entry(one.ident).or_default().extend(from);
+                }
+                OneParamType::Clear => {
+                    state.variables.insert(one.ident, from);
+                }
+                OneParamType::Print | OneParamType::Halt => {
+                    from.extend(state.get(one.ident));
+                    let keyword = if ty == OneParamType::Halt {
+                        "halt"
+                    } else {
+                        "print"
+                    };
+                    self.prints
+                        .entry(span.start)
+                        .or_insert_with(|| {
+                            (span, format!("{} {}", keyword, one.ident), Inputs::new())
+                        })
+                        .2
+                        .extend(from);
+                }
+                // Read before the program starts
+                OneParamType::Input => {}
+            },
+            Statement::Set(Set { var, .. }) | Statement::Rand(Rand { var, .. }) => {
+                state.variables.insert(var.ident, from);
+            }
+            Statement::Indexed(Indexed { element, op }) => {
+                from.extend(state.get(element.index.ident));
+                match op {
+                    ElementOp::Load(to) => {
+                        from.extend(
+                            state
+                                .arrays
+                                .get(element.array.ident)
+                                .cloned()
+                                .unwrap_or_default(),
+                        );
+                        state.variables.insert(to.ident, from);
+                    }
+                    ElementOp::Store(value) => {
+                        from.extend(state.get(value.ident));
+                        state
+                            .arrays
+                            .entry(element.array.ident)
+                            .or_default()
+                            .extend(from);
+                    }
+                    ElementOp::Clear | ElementOp::Incr | ElementOp::Decr => {
+                        state
+                            .arrays
+                            .entry(element.array.ident)
+                            .or_default()
+                            .extend(from);
+                    }
+                }
+            }
+            Statement::Call(Call { name }) => {
+                // Unknown procs are reported by the parser
+                if let Some(&body) = self.procs.get(name.ident) {
+                    if !self.visiting.contains(&name.ident) {
+                        self.visiting.push(name.ident);
+                        self.block(body, state, control);
+                        self.visiting.pop();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Follows the inputs through the program, variables being every variable
+// it uses in first use order
+pub fn flow<'a>(program: &Program<'a>, inputs: &[&'a str], variables: &[&'a str]) -> Flow<'a> {
+    let mut walker = Walker {
+        procs: program
+            .body
+            .iter()
+            .filter_map(|node| match node {
+                Node::Proc { name, body, .. } => Some((name.ident, body)),
+                _ => None,
+            })
+            .collect(),
+        visiting: vec![],
+        prints: BTreeMap::new(),
+    };
+    let mut state = State::default();
+    for &input in inputs {
+        state.variables.insert(input, Inputs::from([input]));
+    }
+    walker.block(&program.body, &mut state, &Inputs::new());
+    Flow {
+        inputs: inputs.to_vec(),
+        prints: walker.prints.into_values().collect(),
+        outputs: variables.iter().map(|&v| (v, state.get(v))).collect(),
+    }
+}
+
+fn list(names: impl IntoIterator<Item = impl AsRef<str>>, none: &str) -> String {
+    let names: Vec<String> = names.into_iter().map(|n| n.as_ref().to_string()).collect();
+    if names.is_empty() {
+        none.to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+// What `bbvm explain` prints: what each loop reads, writes and leaves
+// alone, and which inputs reach which outputs
+pub fn explain(filename: &str, analysis: &Analysis, flow: &Flow, variables: &[&str]) -> String {
+    let mut out = format!("{}\n", filename);
+    if analysis.loops.is_empty() {
+        out.push_str("  no loops\n");
+    }
+    for l in &analysis.loops {
+        let unchanged = variables.iter().filter(|v| !l.writes.contains(*v));
+        out.push_str(&format!(
+            "  line {}: {}\n    reads: {}\n    writes: {}\n    unchanged: {}\n",
+            l.span.line,
+            l.header,
+            list(&l.reads, "nothing"),
+            list(&l.writes, "nothing"),
+            list(unchanged, "nothing")
+        ));
+    }
+    out.push_str("  inputs:\n");
+    if flow.inputs.is_empty() {
+        out.push_str("    none\n");
+    }
+    for input in &flow.inputs {
+        let affected = flow
+            .outputs
+            .iter()
+            .filter(|(_, from)| from.contains(input))
+            .map(|(v, _)| v);
+        out.push_str(&format!(
+            "    {} affects {}\n",
+            input,
+            list(affected, "nothing")
+        ));
+    }
+    out.push_str("  outputs:\n");
+    for (span, print, from) in &flow.prints {
+        out.push_str(&format!(
+            "    {} on line {} from {}\n",
+            print,
+            span.line,
+            list(from, "no inputs")
+        ));
+    }
+    for (var, from) in &flow.outputs {
+        out.push_str(&format!("    {} from {}\n", var, list(from, "no inputs")));
+    }
+    out
+}
@@ -0,0 +1,227 @@
+use std::{
+    collections::BTreeSet,
+    io::{stdin, stdout, Write},
+    iter::zip,
+};
+
+use crate::error::BbvmError;
+use crate::ext::{self, Extension};
+use crate::include::Source;
+use crate::input;
+use crate::interpret::Interpreter;
+use crate::lexer::{Lexer, Span};
+use crate::token::Statement;
+use crate::{collect_inputs, collect_variables, lex};
+
+const HELP: &str = "Commands:
+  step, s          run the next statement, going into procs
+  next, n          run the next statement, running calls to the end
+  continue, c      run until a breakpoint or the end
+  break, b LINE    stop before the statements on LINE
+  delete, d LINE   remove the breakpoint on LINE
+  print, p [X...]  show the variables (all of them without names)
+  list, l          show the lines around this one
+  quit, q          stop debugging
+An empty line repeats step or next.";
+
+// How far to run before asking for the next command
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Mode {
+    Step,
+    // Until back at this many procs deep or fewer
+    Next(usize),
+    Continue,
+}
+
+struct Debugger<'s> {
+    source: &'s Source,
+    // The file being debugged, which the line numbers are in
+    name: &'s str,
+    variables: Vec<&'s str>,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    // The last statement run and its line, so that a line with a few
+    // statements on it only stops once each time it is reached
+    last: Option<(usize, usize)>,
+    repeat: Option<Mode>,
+}
+
+impl<'s> Debugger<'s> {
+    // The line of the debugged file span is on, None in an included file
+    fn line(&self, span: Span) -> Option<usize> {
+        let location = self.source.locate(span);
+        (location.name == self.name).then_some(location.span.line)
+    }
+
+    fn text(&self, line: usize) -> &'s str {
+        let (_, text) = self.source.root();
+        text.lines().nth(line - 1).unwrap_or("").trim_end()
+    }
+
+    fn show_line(&self, line: usize, current: bool) -> () {
+        let marker = match (current, self.breakpoints.contains(&line)) {
+            (true, _) => '>',
+            (false, true) => '*',
+            (false, false) => ' ',
+        };
+        println!("{} {:>4} | {}", marker, line, self.text(line));
+    }
+
+    // Whether to stop before the statement at pc, depth procs deep
+    fn should_stop(&mut self, pc: usize, statement: Statement, line: usize, depth: usize) -> bool {
+        // Blocks are stopped at on their while or if, and procs when called
+        if matches!(
+            statement,
+            Statement::Fluff
+                | Statement::EOF
+                | Statement::Else
+                | Statement::EndIf
+                | Statement::End
+                | Statement::Proc(_)
+                | Statement::EndProc
+        ) {
+            return false;
+        }
+        let arrived = match self.last {
+            Some((last_pc, last_line)) => last_line != line || pc <= last_pc,
+            None => true,
+        };
+        self.last = Some((pc, line));
+        let at_breakpoint =
+            arrived && (self.breakpoints.contains(&line) || statement == Statement::Breakpoint);
+        match self.mode {
+            Mode::Step => true,
+            Mode::Next(from) => depth <= from || at_breakpoint,
+            Mode::Continue => at_breakpoint,
+        }
+    }
+
+    fn print(&self, interpreter: &Interpreter<'s>, names: &[&str]) -> () {
+        let names = if names.is_empty() {
+            self.variables.clone()
+        } else {
+            names.to_vec()
+        };
+        for name in names {
+            if let Some(elements) = interpreter.array(name) {
+                let shown: Vec<String> = elements.iter().map(|e| (*e as i64).to_string()).collect();
+                println!("{}: [{}]", name, shown.join(", "));
+            } else if self.variables.contains(&name) {
+                let (_, value) = interpreter.results(&[name])[0];
+                println!("{}: {}", name, value as i64);
+            } else {
+                println!("There is no variable or array called {}", name);
+            }
+        }
+    }
+
+    // Reads commands until one that runs the program
+    fn prompt(&mut self, interpreter: &Interpreter<'s>, line: usize, depth: usize) -> () {
+        self.show_line(line, true);
+        loop {
+            print!("(bbdb) ");
+            stdout().flush().unwrap();
+            let mut command = String::new();
+            if stdin().read_line(&mut command).unwrap() == 0 {
+                println!();
+                std::process::exit(0);
+            }
+            let words: Vec<&str> = command.split_whitespace().collect();
+            let mode = match words.as_slice() {
+                [] => match self.repeat {
+                    Some(Mode::Next(_)) => Mode::Next(depth),
+                    Some(mode) => mode,
+                    None => continue,
+                },
+                ["step" | "s"] => Mode::Step,
+                ["next" | "n"] => Mode::Next(depth),
+                ["continue" | "c"] => Mode::Continue,
+                ["break" | "b", line] | ["delete" | "d", line] => {
+                    match line.parse::<usize>() {
+                        Ok(line) if line >= 1 && line <= self.text_lines() => {
+                            if words[0].starts_with('b') {
+                                self.breakpoints.insert(line);
+                                println!("Breakpoint on line {}", line);
+                            } else if self.breakpoints.remove(&line) {
+                                println!("Removed the breakpoint on line {}", line);
+                            } else {
+                                println!("There is no breakpoint on line {}", line);
+                            }
+                        }
+                        _ => println!("{} is not a line of {}", line, self.name),
+                    }
+                    continue;
+                }
+                ["print" | "p", names @ ..] => {
+                    self.print(interpreter, names);
+                    continue;
+                }
+                ["list" | "l"] => {
+                    for l in line.saturating_sub(3).max(1)..=(line + 3).min(self.text_lines()) {
+                        self.show_line(l, l == line);
+                    }
+                    continue;
+                }
+                ["quit" | "q"] => std::process::exit(0),
+                _ => {
+                    println!("{}", HELP);
+                    continue;
+                }
+            };
+            self.mode = mode;
+            self.repeat = matches!(mode, Mode::Step | Mode::Next(_)).then(|| mode);
+            return;
+        }
+    }
+
+    fn text_lines(&self) -> usize {
+        self.source.root().1.lines().count()
+    }
+}
+
+// Runs the program in the interpreter, stopping before the first statement
+// and then as the commands typed ask to. Breakpoints are set by the line
+// numbers of the file that was read, not the ones it includes.
+pub fn debug(
+    source: &Source,
+    detect_overflow: bool,
+    extensions: &[Extension],
+) -> Result<(), BbvmError> {
+    let (name, _) = source.root();
+    let (statements, spans) = lex(&mut Lexer::new(&source.text))?;
+    ext::check(&statements, &spans, extensions)?;
+    let mut interpreter = Interpreter::new(&statements, &spans)?;
+    if detect_overflow {
+        interpreter.enable_overflow_detection();
+    }
+    let inputs = collect_inputs(&statements);
+    let values: Vec<(&str, u64)> = zip(inputs.iter().copied(), input::prompt(&inputs)).collect();
+
+    println!("Debugging {}, type help for the commands", name);
+    let mut debugger = Debugger {
+        source,
+        name,
+        variables: collect_variables(&statements),
+        breakpoints: BTreeSet::new(),
+        mode: Mode::Step,
+        last: None,
+        repeat: None,
+    };
+    let halted =
+        interpreter.execute_with(&values, |interpreter, pc, (statement, span), depth| {
+            let line = match debugger.line(span) {
+                Some(line) => line,
+                // Included files are run through
+                None => return,
+            };
+            if debugger.should_stop(pc, statement, line, depth) {
+                debugger.prompt(interpreter, line, depth);
+            }
+        })?;
+    match halted {
+        Some(code) => println!("The program halted with {}", code as i64),
+        None => println!("The program finished"),
+    }
+    debugger.print(&interpreter, &[]);
+    Ok(())
+}
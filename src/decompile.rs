@@ -0,0 +1,92 @@
+use crate::token::{
+    Array, Assert, Call, ElementOp, If, Indexed, OneParam, OneParamType, Proc, Rand, Set,
+    Statement, TwoParam, TwoParamType, While,
+};
+
+// One statement as BareBones source, or None for fluff and EOF, which
+// aren't written out
+pub fn statement(statement: &Statement) -> Option<String> {
+    let line = match statement {
+        Statement::OneParam(OneParam { one, ty }) => format!(
+            "{} {};",
+            match ty {
+                OneParamType::Clear => "clear",
+                OneParamType::Decr => "decr",
+                OneParamType::Incr => "incr",
+                OneParamType::Input => "input",
+                OneParamType::Print => "print",
+                OneParamType::Halt => "halt",
+            },
+            one.ident
+        ),
+        Statement::TwoParam(TwoParam { one, two, ty }) => format!(
+            "{} {}{} {};",
+            ty.keyword(),
+            one.ident,
+            match ty {
+                TwoParamType::Copy | TwoParamType::Add | TwoParamType::Move => " to",
+                TwoParamType::Sub => " from",
+                TwoParamType::Mul => " into",
+                TwoParamType::Swap => "",
+            },
+            two.ident
+        ),
+        Statement::While(While { param, cmp, bound }) => {
+            format!("while {} {} {} do;", param.ident, cmp.keyword(), bound)
+        }
+        Statement::If(If { param, cmp, num }) => {
+            format!("if {} {} {} then;", param.ident, cmp.keyword(), num.value)
+        }
+        Statement::Else => "else;".to_string(),
+        Statement::EndIf => "endif;".to_string(),
+        Statement::Proc(Proc { name }) => format!("proc {};", name.ident),
+        Statement::EndProc => "endproc;".to_string(),
+        Statement::Call(Call { name }) => format!("call {};", name.ident),
+        Statement::Assert(Assert { param, num }) => {
+            format!("assert {} is {};", param.ident, num.value)
+        }
+        Statement::Set(Set { var, num }) => format!("set {} to {};", var.ident, num.value),
+        Statement::Rand(Rand { var, bound }) => format!("rand {} {};", var.ident, bound.value),
+        Statement::Array(Array { name, size }) => {
+            format!("array {} {};", name.ident, size.value)
+        }
+        Statement::Indexed(Indexed { element, op }) => {
+            let element = format!("{}[{}]", element.array.ident, element.index.ident);
+            match op {
+                ElementOp::Clear => format!("clear {};", element),
+                ElementOp::Decr => format!("decr {};", element),
+                ElementOp::Incr => format!("incr {};", element),
+                ElementOp::Load(to) => format!("copy {} to {};", element, to.ident),
+                ElementOp::Store(from) => format!("copy {} to {};", from.ident, element),
+            }
+        }
+        Statement::Breakpoint => "breakpoint;".to_string(),
+        Statement::End => "end;".to_string(),
+        Statement::Fluff | Statement::EOF => return None,
+    };
+    Some(line)
+}
+
+// Writes statements back out as BareBones source, one statement per line
+// with loop bodies indented. Comments and formatting of the original are
+// lost, but lexing the result gives the same statements (minus fluff).
+pub fn decompile(statements: &[Statement]) -> String {
+    let mut source = String::new();
+    let mut depth: usize = 0;
+    for s in statements {
+        let line = match statement(s) {
+            Some(line) => line,
+            None => continue,
+        };
+        if let Statement::Else | Statement::EndIf | Statement::EndProc | Statement::End = s {
+            depth = depth.saturating_sub(1);
+        }
+        source.push_str(&"    ".repeat(depth));
+        source.push_str(&line);
+        source.push('\n');
+        if let Statement::While(_) | Statement::If(_) | Statement::Else | Statement::Proc(_) = s {
+            depth += 1;
+        }
+    }
+    source
+}
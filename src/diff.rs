@@ -0,0 +1,205 @@
+// Line diffs of LLVM IR, for showing what the optimiser did
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Edit<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+// Past this many differing lines Myers' trace gets too big to keep, so the
+// diff just replaces everything
+const MAX_EDITS: usize = 2000;
+
+// The shortest edit script from old to new (Myers' algorithm)
+pub fn diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m).min(MAX_EDITS as isize);
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace: Vec<Vec<isize>> = vec![];
+    let mut found = false;
+
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                found = true;
+                break 'search;
+            }
+        }
+    }
+
+    if !found {
+        return old
+            .iter()
+            .map(|l| Edit::Removed(l))
+            .chain(new.iter().map(|l| Edit::Added(l)))
+            .collect();
+    }
+
+    let mut edits = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let i = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Same(old[x as usize - 1]));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Added(new[y as usize - 1]));
+            } else {
+                edits.push(Edit::Removed(old[x as usize - 1]));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+// Unified diff hunks with context lines around each change, coloured for
+// a terminal if asked
+pub fn unified(old: &[&str], new: &[&str], context: usize, colour: bool) -> String {
+    let edits = diff(old, new);
+    let paint = |code: &str, line: String| {
+        if colour {
+            format!("\x1b[{}m{}\x1b[0m\n", code, line)
+        } else {
+            format!("{}\n", line)
+        }
+    };
+
+    // Ranges of edits to show, merged when their context overlaps
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for (i, edit) in edits.iter().enumerate() {
+        if let Edit::Same(_) = edit {
+            continue;
+        }
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if last.1 >= start => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut output = String::new();
+    for (start, end) in hunks {
+        // Line numbers where the hunk starts in old and new
+        let before = &edits[..start];
+        let old_line = before
+            .iter()
+            .filter(|e| !matches!(e, Edit::Added(_)))
+            .count();
+        let new_line = before
+            .iter()
+            .filter(|e| !matches!(e, Edit::Removed(_)))
+            .count();
+        let hunk = &edits[start..end];
+        let old_len = hunk.iter().filter(|e| !matches!(e, Edit::Added(_))).count();
+        let new_len = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Removed(_)))
+            .count();
+        output.push_str(&paint(
+            "36",
+            format!(
+                "@@ -{},{} +{},{} @@",
+                old_line + 1,
+                old_len,
+                new_line + 1,
+                new_len
+            ),
+        ));
+        for edit in hunk {
+            match edit {
+                Edit::Same(l) => output.push_str(&format!(" {}\n", l)),
+                Edit::Removed(l) => output.push_str(&paint("31", format!("-{}", l))),
+                Edit::Added(l) => output.push_str(&paint("32", format!("+{}", l))),
+            }
+        }
+    }
+    output
+}
+
+// Splits a module's IR into its functions, by name, with everything outside
+// a function body under "(module)"
+fn functions(ir: &str) -> Vec<(String, Vec<&str>)> {
+    let mut functions: Vec<(String, Vec<&str>)> = vec![("(module)".to_string(), vec![])];
+    let mut current: Option<usize> = None;
+    for line in ir.lines() {
+        if line.starts_with("define ") {
+            let name = line
+                .split_once('@')
+                .and_then(|(_, rest)| rest.split_once('('))
+                .map_or(line, |(name, _)| name);
+            functions.push((format!("@{}", name), vec![]));
+            current = Some(functions.len() - 1);
+        }
+        functions[current.unwrap_or(0)].1.push(line);
+        if line == "}" {
+            current = None;
+        }
+    }
+    functions
+}
+
+fn lines_of<'a>(functions: &[(String, Vec<&'a str>)], name: &str) -> Vec<&'a str> {
+    functions
+        .iter()
+        .find(|f| f.0 == name)
+        .map(|f| f.1.clone())
+        .unwrap_or_default()
+}
+
+// A unified diff of two versions of a module, function by function
+pub fn ir_diff(before: &str, after: &str, colour: bool) -> String {
+    let before = functions(before);
+    let after = functions(after);
+    let mut names: Vec<&String> = before.iter().map(|f| &f.0).collect();
+    for (name, _) in &after {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    let mut output = String::new();
+    for name in names {
+        let hunks = unified(&lines_of(&before, name), &lines_of(&after, name), 3, colour);
+        if !hunks.is_empty() {
+            let header = format!("--- before/{}\n+++ after/{}", name, name);
+            output.push_str(&if colour {
+                format!("\x1b[1m{}\x1b[0m\n", header)
+            } else {
+                format!("{}\n", header)
+            });
+            output.push_str(&hunks);
+        }
+    }
+    output
+}
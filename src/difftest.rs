@@ -0,0 +1,282 @@
+// Differential testing of the backends, for `bbvm difftest`: random small
+// programs are run in the interpreter and under the JIT, and any variable
+// they end with different values in is a bug in one of them. The programs
+// are built so that they always end: each loop counts down a counter of its
+// own that nothing else in the program touches.
+
+use std::iter::zip;
+
+use inkwell::{context::Context, OptimizationLevel};
+
+use crate::convert::Converter;
+use crate::decompile;
+use crate::error::BbvmError;
+use crate::interpret::Interpreter;
+use crate::lexer::Lexer;
+use crate::token::{
+    Bound, Comparison, Identifier, If, Number, OneParam, OneParamType, Set, Statement, TwoParam,
+    TwoParamType, While,
+};
+use crate::{collect_inputs, collect_variables, lex, lower};
+
+const VARIABLES: [&str; 4] = ["A", "B", "C", "D"];
+// The loop counters, one for each loop, so a program has at most this many
+const COUNTERS: [&str; 8] = ["L1", "L2", "L3", "L4", "L5", "L6", "L7", "L8"];
+// How deeply loops and ifs are nested at most
+const DEPTH: usize = 3;
+
+// A generated program and the value of each of its inputs
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Case {
+    pub seed: u64,
+    pub source: String,
+    pub inputs: Vec<(&'static str, u64)>,
+}
+
+impl Case {
+    // The inputs as an .expected file or --input-file has them
+    pub fn input_file(&self) -> String {
+        self.inputs
+            .iter()
+            .map(|(name, value)| format!("{}={}\n", name, value))
+            .collect()
+    }
+}
+
+struct Generator {
+    // xorshift64*, never 0
+    state: u64,
+    arith: bool,
+    statements: Vec<Statement<'static>>,
+    loops: usize,
+}
+
+fn id(ident: &'static str) -> Identifier<'static> {
+    Identifier { ident }
+}
+
+fn number(value: u64) -> Number {
+    Number {
+        value: value as i128,
+    }
+}
+
+impl Generator {
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn variable(&mut self) -> Identifier<'static> {
+        id(VARIABLES[self.below(VARIABLES.len() as u64) as usize])
+    }
+
+    fn comparison(&mut self) -> Comparison {
+        [Comparison::Ne, Comparison::Lt, Comparison::Gt][self.below(3) as usize]
+    }
+
+    // Mostly small values, and sometimes ones next to the biggest, to
+    // catch the backends wrapping around differently
+    fn value(&mut self) -> u64 {
+        if self.below(8) == 0 {
+            u64::MAX - self.below(3)
+        } else {
+            self.below(8)
+        }
+    }
+
+    fn block(&mut self, size: usize, depth: usize) -> () {
+        for _ in 0..size.max(1) {
+            let nested = if depth < DEPTH { 10 } else { 7 };
+            let statement = match self.below(nested) {
+                0 => OneParamType::Clear,
+                1 | 2 => OneParamType::Incr,
+                3 => OneParamType::Decr,
+                4 | 5 => {
+                    let ty = [
+                        TwoParamType::Copy,
+                        TwoParamType::Swap,
+                        TwoParamType::Move,
+                        TwoParamType::Add,
+                        TwoParamType::Sub,
+                        TwoParamType::Mul,
+                    ][self.below(if self.arith { 6 } else { 3 }) as usize];
+                    let (one, two) = (self.variable(), self.variable());
+                    self.statements
+                        .push(Statement::TwoParam(TwoParam { one, two, ty }));
+                    continue;
+                }
+                6 => {
+                    let var = self.variable();
+                    let num = number(self.below(8));
+                    self.statements.push(Statement::Set(Set { var, num }));
+                    continue;
+                }
+                7 => {
+                    self.branch(size, depth);
+                    continue;
+                }
+                _ => {
+                    self.count(size, depth);
+                    continue;
+                }
+            };
+            let one = self.variable();
+            self.statements
+                .push(Statement::OneParam(OneParam { one, ty: statement }));
+        }
+    }
+
+    // if X cmp N then; ... else; ... endif;
+    fn branch(&mut self, size: usize, depth: usize) -> () {
+        let param = self.variable();
+        let (cmp, num) = (self.comparison(), number(self.below(4)));
+        self.statements.push(Statement::If(If { param, cmp, num }));
+        self.block(size / 2, depth + 1);
+        if self.below(2) == 0 {
+            self.statements.push(Statement::Else);
+            self.block(size / 2, depth + 1);
+        }
+        self.statements.push(Statement::EndIf);
+    }
+
+    // set L to N; while L not 0 do; ... decr L; end;
+    fn count(&mut self, size: usize, depth: usize) -> () {
+        let counter = match COUNTERS.get(self.loops) {
+            Some(&counter) => id(counter),
+            None => return self.block(1, DEPTH),
+        };
+        self.loops += 1;
+        let num = number(self.below(4));
+        self.statements
+            .push(Statement::Set(Set { var: counter, num }));
+        self.statements.push(Statement::While(While {
+            param: counter,
+            cmp: Comparison::Ne,
+            bound: Bound::Number(number(0)),
+        }));
+        self.block(size / 2, depth + 1);
+        self.statements.push(Statement::OneParam(OneParam {
+            one: counter,
+            ty: OneParamType::Decr,
+        }));
+        self.statements.push(Statement::End);
+    }
+}
+
+// The program for seed, of about size statements at the top level. arith
+// lets it use add, sub and mul.
+pub fn generate(seed: u64, size: usize, arith: bool) -> Case {
+    let mut generator = Generator {
+        state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1,
+        arith,
+        statements: vec![],
+        loops: 0,
+    };
+    let count = generator.below(VARIABLES.len() as u64 - 1) as usize;
+    let inputs: Vec<(&'static str, u64)> = VARIABLES[..count]
+        .iter()
+        .map(|&name| (name, generator.value()))
+        .collect();
+    for &(name, _) in &inputs {
+        generator.statements.push(Statement::OneParam(OneParam {
+            one: id(name),
+            ty: OneParamType::Input,
+        }));
+    }
+    generator.block(size, 0);
+    Case {
+        seed,
+        source: decompile::decompile(&generator.statements),
+        inputs,
+    }
+}
+
+// Where the backends disagreed: each variable that ended differently, with
+// what the interpreter and the JIT gave it, and what the interpreter gave
+// every variable
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Divergence {
+    pub variables: Vec<(String, u64, u64)>,
+    pub interpreted: Vec<(String, u64)>,
+}
+
+impl Divergence {
+    // An .expected file for the case with the interpreter's values, so
+    // `bbvm test --jit` fails on it until the JIT is fixed
+    pub fn expected(&self, case: &Case) -> String {
+        let values: String = self
+            .interpreted
+            .iter()
+            .map(|(name, value)| format!("{}={}\n", name, value))
+            .collect();
+        format!("{}---\n{}", case.input_file(), values)
+    }
+
+    pub fn render(&self) -> String {
+        let width = self
+            .variables
+            .iter()
+            .map(|(name, _, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("variable".len());
+        let mut out = format!(
+            "{:width$}  {:>20}  {:>20}\n",
+            "variable", "interpreter", "jit"
+        );
+        for (name, interpreted, compiled) in &self.variables {
+            out.push_str(&format!(
+                "{:width$}  {:>20}  {:>20}\n",
+                name, interpreted, compiled
+            ));
+        }
+        out
+    }
+}
+
+// Runs the case in the interpreter and under the JIT at level, giving
+// where they disagree if they do
+pub fn compare(case: &Case, level: OptimizationLevel) -> Result<Option<Divergence>, BbvmError> {
+    let (statements, spans) = lex(&mut Lexer::new(&case.source))?;
+    let (variables, inputs) = (collect_variables(&statements), collect_inputs(&statements));
+    let values: Vec<u64> = inputs
+        .iter()
+        .map(|input| {
+            case.inputs
+                .iter()
+                .find(|(name, _)| name == input)
+                .map_or(0, |&(_, value)| value)
+        })
+        .collect();
+
+    let mut interpreter = Interpreter::new(&statements, &spans)?;
+    interpreter
+        .execute(&zip(inputs.iter().copied(), values.iter().copied()).collect::<Vec<_>>())?;
+    let interpreted = interpreter.results(&variables);
+
+    let context = Context::create();
+    let mut converter = Converter::new(variables.clone(), &inputs, &context);
+    converter.set_optimization_level(level);
+    lower(&mut converter, &statements, &spans, &case.source, false)?;
+    converter.optimise();
+    let (_, _, compiled, _) = converter.run_for_results(&values);
+
+    let differing: Vec<(String, u64, u64)> = zip(&interpreted, compiled)
+        .filter(|(&(_, interpreted), compiled)| interpreted != *compiled)
+        .map(|(&(name, interpreted), compiled)| (name.to_string(), interpreted, compiled))
+        .collect();
+    Ok((!differing.is_empty()).then(|| Divergence {
+        variables: differing,
+        interpreted: interpreted
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+    }))
+}
@@ -0,0 +1,54 @@
+use std::str::FromStr;
+
+use crate::error::BbvmError;
+use crate::lexer::Span;
+
+// How the bytes of a source file are read as text, for --encoding
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Encoding {
+    // Anything that isn't UTF-8 is an error
+    #[default]
+    Utf8,
+    // Bytes that aren't UTF-8 become U+FFFD
+    Lossy,
+    // Each byte is the character with that number
+    Latin1,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Self::Utf8),
+            "lossy" => Ok(Self::Lossy),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(Self::Latin1),
+            _ => Err(format!("Unknown encoding {}", s)),
+        }
+    }
+}
+
+// The bytes as text. With Utf8 the first byte that isn't valid is an
+// error, given with the text as Lossy would read it so it can be rendered.
+pub fn decode(bytes: Vec<u8>, encoding: Encoding) -> Result<String, (BbvmError, String)> {
+    match encoding {
+        Encoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+        Encoding::Lossy => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+        Encoding::Utf8 => String::from_utf8(bytes).map_err(|e| {
+            let valid = e.utf8_error().valid_up_to();
+            let bytes = e.into_bytes();
+            // Up to the bad byte it decodes the same either way
+            let before = std::str::from_utf8(&bytes[..valid]).unwrap();
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            let span = Span {
+                line: before.matches('\n').count() + 1,
+                column: valid - line_start + 1,
+                start: valid,
+                // The replacement character the bad bytes are shown as
+                end: valid + char::REPLACEMENT_CHARACTER.len_utf8(),
+            };
+            let text = String::from_utf8_lossy(&bytes).into_owned();
+            (BbvmError::InvalidUtf8 { span }, text)
+        }),
+    }
+}
@@ -0,0 +1,574 @@
+use std::fmt;
+
+use crate::lexer::Span;
+
+// Everything that can be wrong with a program, found while lexing,
+// lowering or running it. Each has a stable code for bbvm --explain.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BbvmError {
+    ExpectedIdentifier {
+        keyword: String,
+        found: String,
+        span: Span,
+    },
+    ExpectedNumber {
+        keyword: String,
+        found: String,
+        span: Span,
+    },
+    UnmatchedEnd {
+        span: Span,
+    },
+    UnclosedWhile {
+        span: Span,
+    },
+    AssertionFailed {
+        var: String,
+        // As the final values are printed
+        value: String,
+        expected: i128,
+        span: Span,
+    },
+    Overflow {
+        op: String,
+        var: String,
+        span: Span,
+    },
+    NotAStatement {
+        found: String,
+        span: Span,
+    },
+    UnknownWord {
+        word: String,
+        span: Span,
+    },
+    ExtensionDisabled {
+        keyword: String,
+        extension: String,
+        span: Span,
+    },
+    UnmatchedElse {
+        span: Span,
+    },
+    UnmatchedEndIf {
+        span: Span,
+    },
+    UnclosedIf {
+        span: Span,
+    },
+    UnmatchedEndProc {
+        span: Span,
+    },
+    UnclosedProc {
+        span: Span,
+    },
+    NestedProc {
+        span: Span,
+    },
+    UnknownProc {
+        name: String,
+        span: Span,
+    },
+    DuplicateProc {
+        name: String,
+        span: Span,
+    },
+    FuelExhausted {
+        fuel: u64,
+        span: Span,
+    },
+    IncludeCycle {
+        file: String,
+        span: Span,
+    },
+    IncludeFailed {
+        file: String,
+        reason: String,
+        span: Span,
+    },
+    UnclosedMacro {
+        span: Span,
+    },
+    UnmatchedEndMacro {
+        span: Span,
+    },
+    NestedMacro {
+        span: Span,
+    },
+    DuplicateMacro {
+        name: String,
+        span: Span,
+    },
+    MacroArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    BadMacro {
+        found: String,
+        span: Span,
+    },
+    IndexOutOfBounds {
+        array: String,
+        index: String,
+        // As the final values are printed
+        value: String,
+        size: u64,
+        span: Span,
+    },
+    UnknownArray {
+        name: String,
+        span: Span,
+    },
+    DuplicateArray {
+        name: String,
+        span: Span,
+    },
+    ArrayTooBig {
+        name: String,
+        size: i128,
+        span: Span,
+    },
+    MisplacedFluff {
+        word: String,
+        keyword: String,
+        // The fluff word the statement takes, if any
+        expected: Option<String>,
+        span: Span,
+    },
+    UndefinedVariable {
+        name: String,
+        span: Span,
+    },
+    InvalidUtf8 {
+        span: Span,
+    },
+    // The LLVM verifier rejected the IR generated for a statement, which is
+    // a bug in bbvm
+    InvalidIr {
+        statement: String,
+        reason: String,
+        span: Span,
+    },
+    NumberTooBig {
+        number: String,
+        span: Span,
+    },
+    // A number that doesn't fit in the variables, which are width bits
+    NumberOutOfRange {
+        number: String,
+        width: u32,
+        span: Span,
+    },
+    NegativeNumber {
+        number: String,
+        span: Span,
+    },
+    // A statement not written the standard way, for --strict: found where
+    // expected should be in statement
+    NonStandard {
+        found: String,
+        expected: String,
+        statement: String,
+        span: Span,
+    },
+    // A keyword where a variable should be, most likely meant as one
+    KeywordAsVariable {
+        word: String,
+        keyword: String,
+        case_sensitive: bool,
+        span: Span,
+    },
+    // A comment starting `#[unroll` that isn't `#[unroll N]`
+    BadUnroll {
+        found: String,
+        span: Span,
+    },
+    // An unroll hint before something other than a while
+    UnrollWithoutWhile {
+        span: Span,
+    },
+    // The interpreter's --timeout, at the loop or call it was checked at
+    TimedOut {
+        timeout: std::time::Duration,
+        span: Span,
+    },
+}
+
+impl BbvmError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            BbvmError::ExpectedIdentifier { .. } => "E0001",
+            BbvmError::ExpectedNumber { .. } => "E0002",
+            BbvmError::UnmatchedEnd { .. } => "E0003",
+            BbvmError::UnclosedWhile { .. } => "E0004",
+            BbvmError::AssertionFailed { .. } => "E0005",
+            BbvmError::Overflow { .. } => "E0006",
+            BbvmError::NotAStatement { .. } => "E0007",
+            BbvmError::UnknownWord { .. } => "E0008",
+            BbvmError::ExtensionDisabled { .. } => "E0009",
+            BbvmError::UnmatchedElse { .. } => "E0010",
+            BbvmError::UnmatchedEndIf { .. } => "E0011",
+            BbvmError::UnclosedIf { .. } => "E0012",
+            BbvmError::UnmatchedEndProc { .. } => "E0013",
+            BbvmError::UnclosedProc { .. } => "E0014",
+            BbvmError::NestedProc { .. } => "E0015",
+            BbvmError::UnknownProc { .. } => "E0016",
+            BbvmError::DuplicateProc { .. } => "E0017",
+            BbvmError::FuelExhausted { .. } => "E0018",
+            BbvmError::IncludeCycle { .. } => "E0019",
+            BbvmError::IncludeFailed { .. } => "E0020",
+            BbvmError::UnclosedMacro { .. } => "E0021",
+            BbvmError::UnmatchedEndMacro { .. } => "E0022",
+            BbvmError::NestedMacro { .. } => "E0023",
+            BbvmError::DuplicateMacro { .. } => "E0024",
+            BbvmError::MacroArguments { .. } => "E0025",
+            BbvmError::BadMacro { .. } => "E0026",
+            BbvmError::IndexOutOfBounds { .. } => "E0027",
+            BbvmError::UnknownArray { .. } => "E0028",
+            BbvmError::DuplicateArray { .. } => "E0029",
+            BbvmError::ArrayTooBig { .. } => "E0030",
+            BbvmError::MisplacedFluff { .. } => "E0031",
+            BbvmError::UndefinedVariable { .. } => "E0032",
+            BbvmError::InvalidUtf8 { .. } => "E0033",
+            BbvmError::InvalidIr { .. } => "E0034",
+            BbvmError::NumberTooBig { .. } => "E0035",
+            BbvmError::NumberOutOfRange { .. } => "E0036",
+            BbvmError::NegativeNumber { .. } => "E0037",
+            BbvmError::NonStandard { .. } => "E0038",
+            BbvmError::KeywordAsVariable { .. } => "E0039",
+            BbvmError::BadUnroll { .. } => "E0040",
+            BbvmError::UnrollWithoutWhile { .. } => "E0041",
+            BbvmError::TimedOut { .. } => "E0042",
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            BbvmError::ExpectedIdentifier { span, .. }
+            | BbvmError::ExpectedNumber { span, .. }
+            | BbvmError::UnmatchedEnd { span }
+            | BbvmError::UnclosedWhile { span }
+            | BbvmError::AssertionFailed { span, .. }
+            | BbvmError::Overflow { span, .. }
+            | BbvmError::NotAStatement { span, .. }
+            | BbvmError::UnknownWord { span, .. }
+            | BbvmError::ExtensionDisabled { span, .. }
+            | BbvmError::UnmatchedElse { span }
+            | BbvmError::UnmatchedEndIf { span }
+            | BbvmError::UnclosedIf { span }
+            | BbvmError::UnmatchedEndProc { span }
+            | BbvmError::UnclosedProc { span }
+            | BbvmError::NestedProc { span }
+            | BbvmError::UnknownProc { span, .. }
+            | BbvmError::DuplicateProc { span, .. }
+            | BbvmError::FuelExhausted { span, .. }
+            | BbvmError::IncludeCycle { span, .. }
+            | BbvmError::IncludeFailed { span, .. }
+            | BbvmError::UnclosedMacro { span }
+            | BbvmError::UnmatchedEndMacro { span }
+            | BbvmError::NestedMacro { span }
+            | BbvmError::DuplicateMacro { span, .. }
+            | BbvmError::MacroArguments { span, .. }
+            | BbvmError::BadMacro { span, .. }
+            | BbvmError::IndexOutOfBounds { span, .. }
+            | BbvmError::UnknownArray { span, .. }
+            | BbvmError::DuplicateArray { span, .. }
+            | BbvmError::ArrayTooBig { span, .. }
+            | BbvmError::MisplacedFluff { span, .. }
+            | BbvmError::UndefinedVariable { span, .. }
+            | BbvmError::InvalidUtf8 { span }
+            | BbvmError::InvalidIr { span, .. }
+            | BbvmError::NumberTooBig { span, .. }
+            | BbvmError::NumberOutOfRange { span, .. }
+            | BbvmError::NegativeNumber { span, .. }
+            | BbvmError::NonStandard { span, .. }
+            | BbvmError::KeywordAsVariable { span, .. }
+            | BbvmError::BadUnroll { span, .. }
+            | BbvmError::UnrollWithoutWhile { span }
+            | BbvmError::TimedOut { span, .. } => *span,
+        }
+    }
+
+    // The same error pointing somewhere else
+    pub fn with_span(mut self, new: Span) -> BbvmError {
+        match &mut self {
+            BbvmError::ExpectedIdentifier { span, .. }
+            | BbvmError::ExpectedNumber { span, .. }
+            | BbvmError::UnmatchedEnd { span }
+            | BbvmError::UnclosedWhile { span }
+            | BbvmError::AssertionFailed { span, .. }
+            | BbvmError::Overflow { span, .. }
+            | BbvmError::NotAStatement { span, .. }
+            | BbvmError::UnknownWord { span, .. }
+            | BbvmError::ExtensionDisabled { span, .. }
+            | BbvmError::UnmatchedElse { span }
+            | BbvmError::UnmatchedEndIf { span }
+            | BbvmError::UnclosedIf { span }
+            | BbvmError::UnmatchedEndProc { span }
+            | BbvmError::UnclosedProc { span }
+            | BbvmError::NestedProc { span }
+            | BbvmError::UnknownProc { span, .. }
+            | BbvmError::DuplicateProc { span, .. }
+            | BbvmError::FuelExhausted { span, .. }
+            | BbvmError::IncludeCycle { span, .. }
+            | BbvmError::IncludeFailed { span, .. }
+            | BbvmError::UnclosedMacro { span }
+            | BbvmError::UnmatchedEndMacro { span }
+            | BbvmError::NestedMacro { span }
+            | BbvmError::DuplicateMacro { span, .. }
+            | BbvmError::MacroArguments { span, .. }
+            | BbvmError::BadMacro { span, .. }
+            | BbvmError::IndexOutOfBounds { span, .. }
+            | BbvmError::UnknownArray { span, .. }
+            | BbvmError::DuplicateArray { span, .. }
+            | BbvmError::ArrayTooBig { span, .. }
+            | BbvmError::MisplacedFluff { span, .. }
+            | BbvmError::UndefinedVariable { span, .. }
+            | BbvmError::InvalidUtf8 { span }
+            | BbvmError::InvalidIr { span, .. }
+            | BbvmError::NumberTooBig { span, .. }
+            | BbvmError::NumberOutOfRange { span, .. }
+            | BbvmError::NegativeNumber { span, .. }
+            | BbvmError::NonStandard { span, .. }
+            | BbvmError::KeywordAsVariable { span, .. }
+            | BbvmError::BadUnroll { span, .. }
+            | BbvmError::UnrollWithoutWhile { span }
+            | BbvmError::TimedOut { span, .. } => *span = new,
+        }
+        self
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            BbvmError::ExpectedIdentifier { keyword, found, .. } => {
+                format!("expected a variable after `{}`, found {}", keyword, found)
+            }
+            BbvmError::ExpectedNumber { keyword, found, .. } => {
+                format!("expected a number after `{}`, found {}", keyword, found)
+            }
+            BbvmError::UnmatchedEnd { .. } => "`end` has no `while` to close".to_string(),
+            BbvmError::UnclosedWhile { .. } => "`while` is never closed by an `end`".to_string(),
+            BbvmError::AssertionFailed {
+                var,
+                value,
+                expected,
+                ..
+            } => format!(
+                "assertion failed: {} is {}, expected {}",
+                var, value, expected
+            ),
+            BbvmError::Overflow { op, var, .. } => format!("{} {} overflowed", op, var),
+            BbvmError::NotAStatement { found, .. } => {
+                format!("expected a statement, found {}", found)
+            }
+            BbvmError::UnknownWord { word, .. } => format!("`{}` is not a BareBones word", word),
+            BbvmError::ExtensionDisabled {
+                keyword, extension, ..
+            } => format!(
+                "`{}` is part of the {} extension, enable it with `--ext {}`",
+                keyword, extension, extension
+            ),
+            BbvmError::UnmatchedElse { .. } => "`else` has no `if` to belong to".to_string(),
+            BbvmError::UnmatchedEndIf { .. } => "`endif` has no `if` to close".to_string(),
+            BbvmError::UnclosedIf { .. } => "`if` is never closed by an `endif`".to_string(),
+            BbvmError::UnmatchedEndProc { .. } => "`endproc` has no `proc` to close".to_string(),
+            BbvmError::UnclosedProc { .. } => "`proc` is never closed by an `endproc`".to_string(),
+            BbvmError::NestedProc { .. } => {
+                "a `proc` can only be defined outside other blocks".to_string()
+            }
+            BbvmError::UnknownProc { name, .. } => format!("there is no proc called `{}`", name),
+            BbvmError::DuplicateProc { name, .. } => {
+                format!("the proc `{}` is defined more than once", name)
+            }
+            BbvmError::FuelExhausted { fuel, .. } => {
+                format!("ran out of fuel after {} loop iterations and calls", fuel)
+            }
+            BbvmError::IncludeCycle { file, .. } => {
+                format!(
+                    "`{}` is already being included, so this include never ends",
+                    file
+                )
+            }
+            BbvmError::IncludeFailed { file, reason, .. } => {
+                format!("can't include `{}`: {}", file, reason)
+            }
+            BbvmError::UnclosedMacro { .. } => {
+                "`macro` is never closed by an `endmacro`".to_string()
+            }
+            BbvmError::UnmatchedEndMacro { .. } => "`endmacro` has no `macro` to close".to_string(),
+            BbvmError::NestedMacro { .. } => {
+                "a `macro` can't be defined inside another one".to_string()
+            }
+            BbvmError::DuplicateMacro { name, .. } => {
+                format!("the macro `{}` is defined more than once", name)
+            }
+            BbvmError::MacroArguments {
+                name,
+                expected,
+                found,
+                ..
+            } => format!(
+                "the macro `{}` takes {} variables, but {} were given",
+                name, expected, found
+            ),
+            BbvmError::BadMacro { found, .. } => {
+                format!("expected `macro NAME(A, B)`, found {}", found)
+            }
+            BbvmError::IndexOutOfBounds {
+                array,
+                index,
+                value,
+                size,
+                ..
+            } => format!(
+                "{}[{}] is out of bounds: {} is {}, but {} has {} elements",
+                array, index, index, value, array, size
+            ),
+            BbvmError::UnknownArray { name, .. } => {
+                format!("there is no array called `{}` declared before this", name)
+            }
+            BbvmError::DuplicateArray { name, .. } => {
+                format!("the array `{}` is declared more than once", name)
+            }
+            BbvmError::ArrayTooBig { name, size, .. } => format!(
+                "the array `{}` can't have {} elements, the most is {}",
+                name,
+                size,
+                u32::MAX
+            ),
+            BbvmError::MisplacedFluff {
+                word,
+                keyword,
+                expected: Some(expected),
+                ..
+            } => format!(
+                "`{}` doesn't belong in `{}`, which takes `{}`",
+                word, keyword, expected
+            ),
+            BbvmError::MisplacedFluff {
+                word,
+                keyword,
+                expected: None,
+                ..
+            } => format!("`{}` doesn't belong in `{}`", word, keyword),
+            BbvmError::UndefinedVariable { name, .. } => format!(
+                "`{}` is read, but nothing in the program ever gives it a value",
+                name
+            ),
+            BbvmError::InvalidUtf8 { .. } => {
+                "the file isn't valid UTF-8 here, read it with `--encoding lossy` or `--encoding latin1`"
+                    .to_string()
+            }
+            BbvmError::InvalidIr {
+                statement, reason, ..
+            } => format!(
+                "bbvm generated invalid LLVM IR for `{}`, please report this: {}",
+                statement,
+                reason.trim()
+            ),
+            BbvmError::NumberTooBig { number, .. } => format!(
+                "`{}` is too big, numbers can be at most {}",
+                number,
+                i128::MAX
+            ),
+            BbvmError::NumberOutOfRange { number, width, .. } => format!(
+                "`{}` doesn't fit in a variable, which has {} bits and holds at most {}",
+                number,
+                width,
+                u128::MAX >> (128 - width)
+            ),
+            BbvmError::NegativeNumber { number, .. } => format!(
+                "`{}` is negative, but variables only hold whole numbers from 0 up",
+                number
+            ),
+            BbvmError::NonStandard {
+                found,
+                expected,
+                statement,
+                ..
+            } => format!(
+                "expected {} here, as in `{}`, but found {}",
+                expected, statement, found
+            ),
+            // With --case-sensitive only lower case words are keywords
+            BbvmError::KeywordAsVariable {
+                word,
+                keyword,
+                case_sensitive: false,
+                ..
+            } if word != keyword => format!(
+                "`{}` is the keyword `{}`, so it can't be a variable. Rename it, or use --case-sensitive to make only `{}` a keyword",
+                word, keyword, keyword
+            ),
+            BbvmError::KeywordAsVariable { word, .. } => format!(
+                "`{}` is a keyword, so it can't be a variable. Rename it, such as to `{}_`",
+                word, word
+            ),
+            BbvmError::BadUnroll { found, .. } => format!(
+                "`{}` isn't an unroll hint, which is `#[unroll N]` with N a whole number from 1",
+                found
+            ),
+            BbvmError::UnrollWithoutWhile { .. } => {
+                "an unroll hint must be on the line before a while".to_string()
+            }
+            BbvmError::TimedOut { timeout, .. } => format!(
+                "the program was still running after {} seconds",
+                timeout.as_secs_f64()
+            ),
+        }
+    }
+
+    // rustc style, with the line of source and a caret under the problem:
+    //
+    //   ERROR[E0001]: expected a variable after `incr`, found `5`
+    //    --> prog.bb:3:6
+    //     |
+    //   3 | incr 5;
+    //     |      ^
+    pub fn render(&self, source: &str, filename: &str) -> String {
+        let span = self.span();
+        let line_start = source[..span.start.min(source.len())]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let line_text = source[line_start..].lines().next().unwrap_or("");
+        let column = source[line_start..span.start.min(source.len())]
+            .chars()
+            .count();
+        let width = source[span.start.min(source.len())..span.end.min(source.len())]
+            .lines()
+            .next()
+            .map_or(1, |l| l.chars().count().max(1));
+        let gutter = " ".repeat(span.line.to_string().len());
+        format!(
+            "ERROR[{}]: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self.code(),
+            self.message(),
+            gutter,
+            filename,
+            span.line,
+            column + 1,
+            gutter,
+            span.line,
+            line_text,
+            gutter,
+            " ".repeat(column),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for BbvmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ERROR[{}]: line {}: {}",
+            self.code(),
+            self.span().line,
+            self.message()
+        )
+    }
+}
+
+impl std::error::Error for BbvmError {}
@@ -0,0 +1,803 @@
+// Extended explanations for the diagnostic codes, shown by `bbvm --explain`.
+// Codes are stable: once given out, a code keeps its meaning even if the
+// diagnostic goes away.
+const CODES: &[(&str, &str)] = &[
+    (
+        "E0001",
+        "A statement keyword was not followed by a variable name.
+
+`clear`, `incr`, `decr`, `input`, `copy`, `while` and `assert` all work on
+variables, so the word after them has to be one. Variable names start with a
+letter, and can't be a keyword.
+
+Erroneous code example:
+
+    incr 5;
+    clear while;
+
+Give the statement a variable instead:
+
+    incr X;
+    clear Y;
+",
+    ),
+    (
+        "E0002",
+        "A `while` or `assert` was not followed by a number.
+
+Both compare a variable against a constant, written after the comparison
+(`not`, `ne`, `lt` or `gt` for a `while`) or `is`.
+
+Erroneous code example:
+
+    while X not Y do;
+    end;
+
+Compare against a number. To compare two variables, count one down in a copy:
+
+    while X not 0 do;
+        decr X;
+    end;
+",
+    ),
+    (
+        "E0003",
+        "An `end` was found with no `while` left to close.
+
+Every `end` closes the nearest `while` before it that hasn't been closed yet.
+
+Erroneous code example:
+
+    while X not 0 do;
+        decr X;
+    end;
+    end;
+
+Remove the extra `end`, or add the `while` it was meant to close.
+",
+    ),
+    (
+        "E0004",
+        "The program ended with a `while` that was never closed.
+
+Erroneous code example:
+
+    while X not 0 do;
+        decr X;
+
+Close the loop with an `end`:
+
+    while X not 0 do;
+        decr X;
+    end;
+",
+    ),
+    (
+        "E0005",
+        "An `assert` failed while the program was running.
+
+`assert X is N;` stops the program when X isn't N, printing the line and
+the value X actually had.
+
+Example:
+
+    clear X;
+    incr X;
+    assert X is 2;
+
+Here X is 1 when the assert runs. Either the program has a bug before the
+assert, or the assert is expecting the wrong value.
+",
+    ),
+    (
+        "E0006",
+        "An `incr` (or an `add` or `mul` from `--ext arith`) made a variable wrap
+around past the largest value it can hold. This is only checked with
+`--detect-overflow`.
+
+Variables are 64 bit, so the largest value is 18446744073709551615. Without
+`--detect-overflow` the variable silently wraps around to a small value.
+
+Example:
+
+    input X;
+    incr X;
+
+This overflows when X is given as 18446744073709551615. Check the inputs,
+or that a loop counting upwards has the limit it should.
+",
+    ),
+    (
+        "E0007",
+        "A variable or number was found where a statement should start.
+
+Statements start with a keyword such as `incr` or `while`. This usually
+means a statement has too many words, or the keyword is misspelt.
+
+Erroneous code example:
+
+    incr X Y;
+    icnr X;
+
+Each statement works on one thing, and keywords have to be spelt exactly:
+
+    incr X;
+    incr Y;
+    incr X;
+",
+    ),
+    (
+        "E0008",
+        "A word was found that isn't a keyword, a variable or a number.
+
+Variable names start with a letter and contain only letters, digits and
+underscores. Numbers are digits, or `0x` and hex digits, with underscores
+allowed between the digits.
+
+Erroneous code example:
+
+    incr my-var;
+    clear 2X;
+
+Rename the variables:
+
+    incr my_var;
+    clear X2;
+",
+    ),
+    (
+        "E0009",
+        "A statement from a language extension was used without enabling it.
+
+bbvm sticks to plain BareBones unless asked otherwise. Extensions add
+statements on top, and are turned on with `--ext NAME`:
+
+  * arith: `add X to Y;`, `sub X from Y;` and `mul X into Y;` set Y to Y + X,
+    Y - X (stopping at 0, like `decr`) and Y * X
+
+Erroneous code example, compiled without `--ext arith`:
+
+    add X to Y;
+
+Enable the extension, or write it in plain BareBones:
+
+    copy X to T;
+    while T not 0 do;
+        decr T;
+        incr Y;
+    end;
+",
+    ),
+    (
+        "E0010",
+        "An `else` was found outside an `if`, or a second `else` in the same `if`.
+
+Erroneous code example:
+
+    if X not 0 then;
+        clear Y;
+    else;
+        incr Y;
+    else;
+        decr Y;
+    endif;
+
+Each `if` can have one `else`, before its `endif`.
+",
+    ),
+    (
+        "E0011",
+        "An `endif` was found with no `if` left to close.
+
+`endif` closes the nearest open `if`. Loops are closed by `end` instead, so an
+`endif` inside a loop that is still open is also an error.
+
+Erroneous code example:
+
+    while X not 0 do;
+        decr X;
+    endif;
+
+Close the loop with `end`, or add the `if` the `endif` was meant to close.
+",
+    ),
+    (
+        "E0012",
+        "The program ended with an `if` that was never closed.
+
+Erroneous code example:
+
+    if X not 0 then;
+        clear Y;
+
+Close it with an `endif`:
+
+    if X not 0 then;
+        clear Y;
+    endif;
+",
+    ),
+    (
+        "E0013",
+        "An `endproc` was found outside of a `proc`.
+
+Erroneous code example:
+
+    incr X;
+    endproc;
+
+Every `endproc` closes the `proc` before it. Remove the extra `endproc`,
+or add the `proc` it was meant to close.
+",
+    ),
+    (
+        "E0014",
+        "The program ended with a `proc` that was never closed.
+
+Erroneous code example:
+
+    proc double;
+        add X to X;
+
+Close it with an `endproc`:
+
+    proc double;
+        add X to X;
+    endproc;
+",
+    ),
+    (
+        "E0015",
+        "A `proc` was defined inside a `while`, `if` or another `proc`.
+
+Erroneous code example:
+
+    while X not 0 do;
+        proc step;
+            decr X;
+        endproc;
+    end;
+
+Procs are only run when they are called, so define them at the top level
+and `call` them from inside the block:
+
+    proc step;
+        decr X;
+    endproc;
+    while X not 0 do;
+        call step;
+    end;
+",
+    ),
+    (
+        "E0016",
+        "A `call` names a proc that the program never defines.
+
+Erroneous code example:
+
+    call dobule;
+    proc double;
+        add X to X;
+    endproc;
+
+Check the spelling of the name. Procs can be called before or after they
+are defined, but they have to be defined somewhere.
+",
+    ),
+    (
+        "E0017",
+        "Two procs were given the same name.
+
+Erroneous code example:
+
+    proc step;
+        decr X;
+    endproc;
+    proc step;
+        incr X;
+    endproc;
+
+Rename one of them, and update its calls.
+",
+    ),
+    (
+        "E0018",
+        "The program ran out of fuel: it went round its loops and called its
+procs more times in total than `--fuel` allows.
+
+Erroneous code example, with `--fuel 1000`:
+
+    clear X;
+    incr X;
+    while X not 0 do;
+        incr X;
+    end;
+
+X never gets back to 0, so this loop would run forever (or until X wraps
+around). The line given is the loop or call that was about to run when
+the fuel ran out. If the program is just long running, give it more fuel
+or leave `--fuel` out.
+",
+    ),
+    (
+        "E0019",
+        "A file includes itself, directly or through other files, so splicing
+it in would never end.
+
+Erroneous code example, in a.bb:
+
+    #include \"b.bb\"
+
+with b.bb:
+
+    #include \"a.bb\"
+
+The include pointed at is the one that closes the cycle. Move what both
+files need into a third file that includes neither of them.
+",
+    ),
+    (
+        "E0020",
+        "An `#include` couldn't be read, or wasn't written as `#include
+\"file.bb\"`.
+
+Erroneous code example:
+
+    #include helpers.bb
+
+The file name needs quotes. Relative paths are looked up next to the file
+doing the including, not the directory bbvm was run from.
+",
+    ),
+    (
+        "E0021",
+        "A `macro` was started but no `endmacro` follows it.
+
+Erroneous code example:
+
+    macro double(X, Y);
+        copy X to Y;
+        add X to Y;
+
+Every macro body needs an `endmacro;` after it.
+",
+    ),
+    (
+        "E0022",
+        "An `endmacro` was found without a `macro` before it.
+
+Erroneous code example:
+
+    incr X;
+    endmacro;
+
+Remove the `endmacro`, or add the `macro NAME(A, B);` it was meant to
+close.
+",
+    ),
+    (
+        "E0023",
+        "A `macro` was defined inside the body of another one.
+
+Erroneous code example:
+
+    macro outer(X);
+        macro inner(Y);
+            incr Y;
+        endmacro;
+    endmacro;
+
+Define the inner macro first, on its own. A macro can use any macro
+defined before it.
+",
+    ),
+    (
+        "E0024",
+        "Two macros were defined with the same name.
+
+Erroneous code example:
+
+    macro bump(X);
+        incr X;
+    endmacro;
+    macro bump(X);
+        incr X;
+        incr X;
+    endmacro;
+
+Rename one of them.
+",
+    ),
+    (
+        "E0025",
+        "A macro was used with the wrong number of variables.
+
+Erroneous code example:
+
+    macro plus(A, B);
+        copy A to T;
+        while T not 0 do;
+            incr B;
+            decr T;
+        end;
+    endmacro;
+    plus(X);
+
+Give exactly as many variables as the macro names between its brackets.
+",
+    ),
+    (
+        "E0026",
+        "A macro definition wasn't written as `macro NAME(A, B)`.
+
+Erroneous code example:
+
+    macro bump X;
+        incr X;
+    endmacro;
+
+The name and every parameter must be variable names, not keywords, and
+the parameters must be in brackets and different from each other.
+",
+    ),
+    (
+        "E0027",
+        "An element past the end of an array was used.
+
+Erroneous code example, with `--ext arrays`:
+
+    array A 10;
+    set I to 10;
+    incr A[I];
+
+The elements of an array of 10 are A[0] to A[9]. The message gives the
+value the index had, check the loop or count that worked it out.
+",
+    ),
+    (
+        "E0028",
+        "An array was used before any `array` statement declaring it.
+
+Erroneous code example, with `--ext arrays`:
+
+    clear I;
+    incr A[I];
+    array A 10;
+
+Declare each array, with how many elements it has, above the first
+statement using it.
+",
+    ),
+    (
+        "E0029",
+        "Two `array` statements declared the same array.
+
+Erroneous code example, with `--ext arrays`:
+
+    array A 10;
+    array A 20;
+
+An array has one size for the whole program. Remove one of them, or
+rename it.
+",
+    ),
+    (
+        "E0030",
+        "An array was declared with more elements than an array can hold.
+
+Erroneous code example, with `--ext arrays`:
+
+    array A 5000000000;
+
+Arrays can have up to 4294967295 elements.
+",
+    ),
+    (
+        "E0031",
+        "A fluff word was used in a statement it doesn't belong to. Reported
+by `bbvm check`.
+
+Erroneous code example:
+
+    while X not 0 then;
+    incr to Y;
+
+Fluff words are skipped when the program is compiled, so this runs, but
+each one belongs to particular statements: `to` in `copy`, `move`, `add`
+and `set`, `from` in `sub`, `into` in `mul`, `do` in `while`, `then` in `if`
+and `is` in `assert`. One in the wrong place is most likely a mistake.
+",
+    ),
+    (
+        "E0032",
+        "A variable was read, but no statement anywhere in the program gives
+it a value. Reported by `bbvm check`.
+
+Erroneous code example:
+
+    clear X;
+    copy Y to X;
+
+Y is never an `input` and nothing else sets it, so it is always 0. This
+is most likely a misspelled variable name.
+",
+    ),
+    (
+        "E0033",
+        "The file has bytes that aren't valid UTF-8, so bbvm can't read it as
+text. The error points at the first of them, shown as a replacement
+character.
+
+This usually means the file was saved in another encoding, such as
+Latin-1 or Windows-1252, or that a tool generating it wrote something
+other than text. BareBones itself only needs ASCII, so such bytes are most
+likely in a comment. `--encoding latin1` reads every byte as the
+character with that number, and `--encoding lossy` replaces the bad bytes
+with the replacement character and carries on.
+",
+    ),
+    (
+        "E0034",
+        "The LLVM IR bbvm generated for a statement is invalid. This is a bug in
+bbvm rather than in the program, and the error points at the statement
+that was being lowered when the LLVM verifier found it.
+
+It is only checked statement by statement with `--verify-ir`; otherwise
+the whole module is checked once it is finished, and the error points at
+the end of the file. Running again with `--verify-ir` narrows it down.
+`--interpret` runs the program without LLVM in the meantime.
+",
+    ),
+    (
+        "E0035",
+        "A number in the program is too big for bbvm to hold, even before it is
+given to a variable.
+
+Example:
+
+    set X to 999999999999999999999999999999999999999999;
+
+Numbers can be at most 170141183460469231731687303715884105727 (the
+biggest i128), though a variable only keeps as many bits of it as
+`--int-width` gives it. A number this big is most likely a typo.
+",
+    ),
+    (
+        "E0036",
+        "A number in the program is bigger than a variable can hold.
+
+Example:
+
+    set X to 300;    # with --int-width 8
+
+Variables have 64 bits, or as many as `--int-width` gives them, so with
+8-bit variables the biggest number is 255. Rather than losing the number's
+top bits when it is given to a variable, this is an error. Use a bigger
+`--int-width`, or a smaller number.
+",
+    ),
+    (
+        "E0037",
+        "A number in the program is negative.
+
+Erroneous code example:
+
+    while X not -1 do;
+
+Variables hold whole numbers from 0 up, and `decr` stops at 0, so a
+variable can never be negative. A loop like the one above waits for a value
+that never comes. Compare with 0 instead:
+
+    while X not 0 do;
+",
+    ),
+    (
+        "E0038",
+        "A statement wasn't written the standard way. Reported with `--strict`.
+
+Erroneous code example:
+
+    copy to X Y;
+    while do X not 0;
+    while X 0 do;
+
+Normally fluff words are skipped wherever they are and a while without a
+comparison loops until its variable is 0, so all of these run. `--strict`
+holds programs to the standard grammar instead, with each statement's words
+in the order `bbvm fmt` writes them:
+
+    copy X to Y;
+    while X not 0 do;
+",
+    ),
+    (
+        "E0039",
+        "A keyword was used where a variable should be.
+
+Erroneous code example:
+
+    incr End;
+    copy X to while;
+
+Keywords are matched whatever their case, so `End` is `end` and can't name
+a variable. Rename the variable:
+
+    incr Finish;
+    copy X to while_;
+
+Or, for programs written with capitalised variables, `--case-sensitive`
+makes keywords only keywords in lower case, so `End` and `Copy` are free
+to use as variables.
+",
+    ),
+    (
+        "E0040",
+        "An unroll hint was written wrongly.
+
+Erroneous code example:
+
+    #[unroll eight]
+    while X not 0 do;
+
+An unroll hint is a comment `#[unroll N]`, with N how many copies of the
+loop's body LLVM should make, a whole number from 1:
+
+    #[unroll 8]
+    while X not 0 do;
+",
+    ),
+    (
+        "E0041",
+        "An unroll hint was given for something other than a loop.
+
+Erroneous code example:
+
+    #[unroll 4]
+    incr X;
+    while X not 0 do;
+
+The hint applies to the statement after it, which must be the `while`
+whose body is to be unrolled:
+
+    incr X;
+    #[unroll 4]
+    while X not 0 do;
+",
+    ),
+    (
+        "E0042",
+        "The program was still running when `--timeout` ran out.
+
+Erroneous code example, with `--timeout 1`:
+
+    incr X;
+    while X not 0 do;
+        incr Y;
+    end;
+
+X is never changed in the loop, so it runs forever. The line given is the
+loop or call the program was at when the time was checked. If the
+program is just long running, give it longer or leave `--timeout` out.
+",
+    ),
+    (
+        "W0001",
+        "A variable was copied to itself. This is the `copy_to_self` lint.
+
+Example:
+
+    copy X to X;
+    swap X X;
+
+This has no effect, so it is most likely a typo for another variable.
+`bbvm fix` removes the statement. `move X to X;` is reported too, as all
+it does is clear X.
+",
+    ),
+    (
+        "W0002",
+        "An `incr` was immediately followed by a `decr` of the same variable.
+This is the `incr_decr` lint.
+
+Example:
+
+    incr X;
+    decr X;
+
+The two cancel out. `bbvm fix` removes both statements.
+",
+    ),
+    (
+        "W0003",
+        "A loop has nothing in its body. This is the `empty_loop` lint.
+
+Example:
+
+    while X not 0 do;
+    end;
+
+Nothing inside the loop changes X, so unless X is already 0 the program
+never finishes. Add the statements that should bring X to 0.
+",
+    ),
+    (
+        "W0004",
+        "A loop's condition is already false the first time it is checked, so
+its body never runs. This is the `constant_false_while` lint.
+
+Example:
+
+    clear X;
+    while X not 0 do;
+        decr X;
+    end;
+
+X is always 0 at the `while`, so the whole loop does nothing. `bbvm fix`
+removes it.
+",
+    ),
+    (
+        "W0005",
+        "An `input` statement comes after its variable is used, or inside a
+loop. This is the `late_input` lint.
+
+Inputs are all read before the program starts, wherever the `input`
+statement is, so it doesn't do anything at that point.
+
+Example:
+
+    clear X;
+    input X;
+
+Here X is the input value, not 0, when the program starts, and then
+`clear X` sets it to 0. Put the `input` statements at the top of the
+program, where they are read:
+
+    input X;
+    clear X;
+",
+    ),
+    (
+        "W0006",
+        "A variable is read before anything gives it a value. This is the
+`read_before_write` lint.
+
+Every variable starts at 0, so this is allowed, but it is often a typo
+for another variable or a missing `input` or `clear`.
+
+Example:
+
+    clear TOTAL;
+    copy TOTL to RESULT;
+
+Here TOTL is always 0. A variable is given a value by `input`, `clear`,
+`incr`, `decr`, by being copied to, or by a proc called earlier. Write
+`clear X;` first to show that starting from 0 is intended.
+",
+    ),
+    (
+        "W0007",
+        "Nothing in a loop's body changes the variable its `while` checks. This
+is the `infinite_loop` lint.
+
+Example:
+
+    while X not 0 do;
+        incr Y;
+    end;
+
+Nothing inside the loop changes X, so unless X was already 0 when the
+loop started it runs forever. Usually a `decr X;` is missing, or the
+wrong variable is decremented. A `copy` to X, a `clear X`, a call to a
+proc that changes X or a `halt` inside the loop all count as a way out.
+",
+    ),
+];
+
+// The explanation for a code such as E0001
+pub fn explain(code: &str) -> Option<&'static str> {
+    CODES
+        .iter()
+        .find(|(c, _)| c.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+}
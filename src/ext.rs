@@ -0,0 +1,70 @@
+use std::{iter::zip, str::FromStr};
+
+use crate::error::BbvmError;
+use crate::lexer::Span;
+use crate::token::{Statement, TwoParam, TwoParamType};
+
+// Language extensions beyond plain BareBones, each turned on with --ext
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Extension {
+    // add, sub and mul
+    Arith,
+    // array and A[I]
+    Arrays,
+}
+
+impl FromStr for Extension {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "arith" => Ok(Self::Arith),
+            "arrays" => Ok(Self::Arrays),
+            _ => Err(format!("Unknown extension {}", s)),
+        }
+    }
+}
+
+impl Extension {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Extension::Arith => "arith",
+            Extension::Arrays => "arrays",
+        }
+    }
+}
+
+// The extension a statement needs, and the keyword that needs it
+pub fn required(statement: &Statement) -> Option<(Extension, &'static str)> {
+    match statement {
+        Statement::TwoParam(TwoParam { ty, .. }) => match ty {
+            TwoParamType::Copy | TwoParamType::Swap | TwoParamType::Move => None,
+            TwoParamType::Add | TwoParamType::Sub | TwoParamType::Mul => {
+                Some((Extension::Arith, ty.keyword()))
+            }
+        },
+        Statement::Array(_) => Some((Extension::Arrays, "array")),
+        Statement::Indexed(_) => Some((Extension::Arrays, "[]")),
+        _ => None,
+    }
+}
+
+// Fails on the first statement using an extension that isn't enabled
+pub fn check(
+    statements: &[Statement],
+    spans: &[Span],
+    enabled: &[Extension],
+) -> Result<(), BbvmError> {
+    for (statement, &span) in zip(statements, spans) {
+        if let Some((extension, keyword)) = required(statement) {
+            if !enabled.contains(&extension) {
+                return Err(BbvmError::ExtensionDisabled {
+                    keyword: keyword.to_string(),
+                    extension: extension.name().to_string(),
+                    span,
+                });
+            }
+        }
+    }
+    Ok(())
+}
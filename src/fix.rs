@@ -0,0 +1,98 @@
+use crate::lexer::Span;
+
+// A machine-applicable edit: replace start..end of the source with
+// replacement.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Suggestion {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+    pub replacement: String,
+}
+
+impl Suggestion {
+    pub fn remove(message: String, start: usize, end: usize) -> Suggestion {
+        Suggestion {
+            message,
+            start,
+            end,
+            replacement: String::new(),
+        }
+    }
+}
+
+// Applies the suggestions to source, skipping any that overlap one already
+// applied. Removals also take the statement's `;`, and the whole line if
+// nothing else is left on it.
+pub fn apply(source: &str, suggestions: &[Suggestion]) -> (String, usize) {
+    let mut sorted: Vec<&Suggestion> = suggestions.iter().collect();
+    sorted.sort_by_key(|s| std::cmp::Reverse(s.start));
+
+    let mut output = source.to_string();
+    let mut applied = 0;
+    let mut limit = source.len();
+    for suggestion in sorted {
+        if suggestion.end > limit {
+            continue;
+        }
+        let (mut start, mut end) = (suggestion.start, suggestion.end);
+        if suggestion.replacement.is_empty() {
+            if output[end..].starts_with(';') {
+                end += 1;
+            }
+            let line_start = output[..start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = output[end..].find('\n').map_or(output.len(), |i| end + i);
+            if output[line_start..start].trim().is_empty()
+                && output[end..line_end].trim().is_empty()
+            {
+                start = line_start;
+                end = (line_end + 1).min(output.len());
+            }
+        }
+        output.replace_range(start..end, &suggestion.replacement);
+        limit = start;
+        applied += 1;
+    }
+    (output, applied)
+}
+
+// Edits renaming every use of the variable old within the statements
+pub fn rename(source: &str, spans: &[Span], old: &str, new: &str) -> Vec<Suggestion> {
+    let mut edits = vec![];
+    for span in spans {
+        let text = &source[span.start..span.end];
+        // Words are split the same way the lexer splits them
+        let mut words = vec![];
+        let mut offset = 0;
+        let mut comment = false;
+        for (i, c) in text.char_indices() {
+            if c == '#' && i == offset {
+                comment = true;
+            }
+            if c.is_whitespace() || c == ';' {
+                if !comment {
+                    words.push((offset, i));
+                }
+                if c == '\n' {
+                    comment = false;
+                }
+                offset = i + c.len_utf8();
+            }
+        }
+        if !comment {
+            words.push((offset, text.len()));
+        }
+
+        for (start, end) in words {
+            if &text[start..end] == old {
+                edits.push(Suggestion {
+                    message: format!("rename {} to {}", old, new),
+                    start: span.start + start,
+                    end: span.start + end,
+                    replacement: new.to_string(),
+                });
+            }
+        }
+    }
+    edits
+}
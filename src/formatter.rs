@@ -0,0 +1,100 @@
+use std::iter::zip;
+
+use crate::decompile;
+use crate::error::BbvmError;
+use crate::lex;
+use crate::lexer::{Lexer, Span};
+use crate::token::Statement;
+
+const INDENT: &str = "    ";
+
+// What goes on each line of the output
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Item<'a> {
+    Statement(Statement<'a>),
+    Comment(&'a str),
+}
+
+// The statements that aren't fluff, and the comments
+fn items(source: &str) -> Result<Vec<(Span, Item<'_>)>, BbvmError> {
+    let mut l = Lexer::new(source);
+    let (statements, spans) = lex(&mut l)?;
+    let mut items: Vec<(Span, Item)> = zip(statements, spans)
+        .filter(|(s, _)| !matches!(s, Statement::Fluff | Statement::EOF))
+        .map(|(s, span)| (span, Item::Statement(s)))
+        .chain(
+            l.comments()
+                .into_iter()
+                .map(|(span, text)| (span, Item::Comment(text))),
+        )
+        .collect();
+    items.sort_by_key(|(span, _)| span.start);
+    Ok(items)
+}
+
+// The source laid out the canonical way: one statement per line with its
+// keywords in lower case and its fluff words in their usual places, the
+// bodies of whiles, ifs and procs indented by four spaces, and no more than
+// one blank line in a row. Comments stay where they were, at the end of the
+// statement before them on the same line or on a line of their own.
+pub fn format(source: &str) -> Result<String, BbvmError> {
+    let original = items(source)?;
+    let mut formatted = String::new();
+    let mut depth: usize = 0;
+    // Where the last item ends in source, and whether it was a statement
+    let mut last: Option<(usize, bool)> = None;
+    for &(span, item) in &original {
+        let gap = last.map_or("", |(end, _)| &source[end..span.start]);
+        match item {
+            // Following a statement on the same line
+            Item::Comment(text) if matches!(last, Some((_, true))) && !gap.contains('\n') => {
+                formatted.pop();
+                formatted.push_str("  ");
+                formatted.push_str(text);
+            }
+            Item::Comment(text) => {
+                if gap.matches('\n').count() > 1 {
+                    formatted.push('\n');
+                }
+                formatted.push_str(&INDENT.repeat(depth));
+                formatted.push_str(text);
+            }
+            Item::Statement(statement) => {
+                if let Statement::Else | Statement::EndIf | Statement::EndProc | Statement::End =
+                    statement
+                {
+                    depth = depth.saturating_sub(1);
+                }
+                if gap.matches('\n').count() > 1 {
+                    formatted.push('\n');
+                }
+                formatted.push_str(&INDENT.repeat(depth));
+                formatted.push_str(&decompile::statement(&statement).unwrap());
+                if let Statement::While(_)
+                | Statement::If(_)
+                | Statement::Else
+                | Statement::Proc(_) = statement
+                {
+                    depth += 1;
+                }
+            }
+        }
+        formatted.push('\n');
+        last = Some((span.end, matches!(item, Item::Statement(_))));
+    }
+
+    // Anything lost or changed on the way is a bug here, not in the program
+    let reformatted = items(&formatted).expect("Formatting gave source that doesn't lex");
+    let unchanged = reformatted.len() == original.len()
+        && zip(&reformatted, &original).all(|((_, a), (_, b))| match (a, b) {
+            (Item::Comment(a), Item::Comment(b)) => a.trim() == b.trim(),
+            _ => a == b,
+        });
+    if !unchanged {
+        panic!(
+            "Formatting changed the program, the formatted source was:\n{}",
+            formatted
+        );
+    }
+    Ok(formatted)
+}
@@ -0,0 +1,155 @@
+use std::str::FromStr;
+
+use crate::token::{classify, TokenKind};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    Html,
+    Ansi,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(Self::Html),
+            "ansi" => Ok(Self::Ansi),
+            _ => Err(format!("Unknown highlight format {}", s)),
+        }
+    }
+}
+
+// Splits source the way the lexer does, into (kind, text) pieces covering
+// all of it. Whitespace and `;` come back with no kind.
+pub fn pieces(source: &str) -> Vec<(Option<TokenKind>, &str)> {
+    let mut pieces = vec![];
+    let mut rest = source;
+    while !rest.is_empty() {
+        let end = if rest.starts_with('#') {
+            rest.find('\n').unwrap_or(rest.len())
+        } else {
+            rest.find(|c: char| c.is_whitespace() || c == ';')
+                .unwrap_or(rest.len())
+        };
+        if end == 0 {
+            let c = rest.chars().next().unwrap();
+            pieces.push((None, &rest[..c.len_utf8()]));
+            rest = &rest[c.len_utf8()..];
+        } else {
+            pieces.push((Some(classify(&rest[..end])), &rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    pieces
+}
+
+fn escape_json(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '"' => "\\\"".to_string(),
+            '\\' => "\\\\".to_string(),
+            c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+// Every word of source and every `;`, with the byte offset it starts at, its
+// length in bytes, its line and column and its kind. One per line, or with
+// json as an array of objects for editors to read.
+pub fn tokens(source: &str, json: bool) -> String {
+    let mut offset = 0;
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut tokens = vec![];
+    for (kind, text) in pieces(source) {
+        let kind = match kind {
+            Some(kind) => Some(kind.name()),
+            None if text == ";" => Some("semicolon"),
+            None => None,
+        };
+        if let Some(kind) = kind {
+            let column = offset - line_start + 1;
+            tokens.push(if json {
+                format!(
+                    "{{\"offset\":{},\"length\":{},\"line\":{},\"column\":{},\"kind\":\"{}\",\"text\":\"{}\"}}",
+                    offset,
+                    text.len(),
+                    line,
+                    column,
+                    kind,
+                    escape_json(text)
+                )
+            } else {
+                format!(
+                    "{}:{}\t{}\t{}\t{}\t{}",
+                    line,
+                    column,
+                    offset,
+                    text.len(),
+                    kind,
+                    text
+                )
+            });
+        }
+        if text == "\n" {
+            line += 1;
+            line_start = offset + 1;
+        }
+        offset += text.len();
+    }
+    if json {
+        format!("[\n{}\n]\n", tokens.join(",\n"))
+    } else {
+        tokens.into_iter().map(|t| t + "\n").collect()
+    }
+}
+
+fn html_colour(kind: TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Keyword => "color:#0033b3;font-weight:bold",
+        TokenKind::Fluff => "color:#808080",
+        TokenKind::Identifier | TokenKind::Element => "color:#871094",
+        TokenKind::Number => "color:#1750eb",
+        TokenKind::Comment => "color:#067d17;font-style:italic",
+        TokenKind::Unknown => "color:#ff0000;text-decoration:underline",
+    }
+}
+
+fn ansi_colour(kind: TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Keyword => "\x1b[1;34m",
+        TokenKind::Fluff => "\x1b[2m",
+        TokenKind::Identifier | TokenKind::Element => "\x1b[35m",
+        TokenKind::Number => "\x1b[36m",
+        TokenKind::Comment => "\x1b[3;32m",
+        TokenKind::Unknown => "\x1b[4;31m",
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub fn highlight(source: &str, format: Format) -> String {
+    let body: String = pieces(source)
+        .into_iter()
+        .map(|(kind, text)| match (format, kind) {
+            (Format::Html, None) => escape_html(text),
+            (Format::Html, Some(kind)) => format!(
+                "<span style=\"{}\">{}</span>",
+                html_colour(kind),
+                escape_html(text)
+            ),
+            (Format::Ansi, None) => text.to_string(),
+            (Format::Ansi, Some(kind)) => format!("{}{}\x1b[0m", ansi_colour(kind), text),
+        })
+        .collect();
+    match format {
+        Format::Html => format!("<pre class=\"bbvm\"><code>{}</code></pre>\n", body),
+        Format::Ansi => body,
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+// The values of the --history variables, recorded by the JIT'd code each
+// time a loop goes round through bbvm_history, the id being the variable's
+// place in the list
+lazy_static! {
+    static ref SERIES: Mutex<Vec<Series>> = Mutex::new(vec![]);
+}
+
+// At most this many values are kept for a variable. Once there are this
+// many every other one is dropped and only every other one after that is
+// kept, so a loop running billions of times still fits and the plot still
+// covers the whole run.
+const KEPT: usize = 4096;
+// How many characters wide the plots are
+const WIDTH: usize = 60;
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Series {
+    pub values: Vec<u64>,
+    // How many values were recorded between each kept one
+    stride: u64,
+    // How many values were recorded in all
+    pub recorded: u64,
+    pub last: u64,
+    pub min: u64,
+    pub max: u64,
+}
+
+impl Series {
+    fn record(&mut self, value: u64) -> () {
+        if self.recorded == 0 {
+            self.stride = 1;
+            self.min = value;
+            self.max = value;
+        }
+        self.last = value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if self.recorded % self.stride == 0 {
+            self.values.push(value);
+            if self.values.len() == KEPT {
+                let halved = self.values.iter().copied().step_by(2).collect();
+                self.values = halved;
+                self.stride *= 2;
+            }
+        }
+        self.recorded += 1;
+    }
+
+    // The values as a line of bars from the smallest to the biggest, each
+    // bar the last value of its part of the run
+    pub fn sparkline(&self) -> String {
+        let columns = self.values.len().min(WIDTH);
+        let range = (self.max - self.min) as f64;
+        (0..columns)
+            .map(|column| {
+                let value = self.values[(column + 1) * self.values.len() / columns - 1];
+                let height = if range == 0.0 {
+                    0
+                } else {
+                    ((value - self.min) as f64 / range * (BARS.len() - 1) as f64).round() as usize
+                };
+                BARS[height]
+            })
+            .collect()
+    }
+}
+
+pub extern "C" fn bbvm_history(id: u64, value: u64) {
+    let mut series = SERIES.lock().unwrap();
+    let id = id as usize;
+    if series.len() <= id {
+        series.resize(id + 1, Series::default());
+    }
+    series[id].record(value);
+}
+
+// What was recorded for each of the count variables, taking it so the next
+// run starts afresh
+pub fn take(count: usize) -> Vec<Series> {
+    let mut series = std::mem::take(&mut *SERIES.lock().unwrap());
+    series.resize(count, Series::default());
+    series
+}
+
+// A plot of each variable with its first, last, smallest and biggest value
+pub fn render(names: &[String], series: &[Series]) -> String {
+    let width = names.iter().map(|n| n.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for (name, series) in names.iter().zip(series) {
+        if series.recorded == 0 {
+            out.push_str(&format!(
+                "{:width$}  (never recorded, no loop went round)\n",
+                name
+            ));
+            continue;
+        }
+        out.push_str(&format!(
+            "{:width$}  {}  first {}, last {}, min {}, max {} over {} iterations\n",
+            name,
+            series.sparkline(),
+            series.values[0],
+            series.last,
+            series.min,
+            series.max,
+            series.recorded
+        ));
+    }
+    out
+}
@@ -0,0 +1,385 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::encoding::{self, Encoding};
+use crate::error::BbvmError;
+use crate::lexer::Span;
+use crate::macros::{self, Piece};
+
+// A file as it was read, once for each time it is included
+struct File {
+    name: String,
+    path: PathBuf,
+    text: String,
+    // The file and line of the #include that brought this one in
+    included_from: Option<(usize, usize)>,
+}
+
+// A run of the spliced text copied from one file
+struct Part {
+    // Where it starts in the spliced text, and in the file
+    start: usize,
+    file: usize,
+    offset: usize,
+}
+
+// A program with every `#include "file.bb"` line replaced by the contents
+// of that file, and a map back to the files for error messages
+pub struct Source {
+    pub text: String,
+    files: Vec<File>,
+    parts: Vec<Part>,
+    // How the files are read
+    encoding: Encoding,
+}
+
+// Where a span of the spliced text really is
+pub struct Location<'s> {
+    pub name: &'s str,
+    pub text: &'s str,
+    pub span: Span,
+}
+
+impl Source {
+    // Reads the file at path, splices its includes in and expands its
+    // macros. Fails with the error for the bad include or macro, and it
+    // rendered against the file it is in.
+    pub fn expand(path: &Path) -> Result<Source, (BbvmError, String)> {
+        Source::expand_with(path, Encoding::Utf8)
+    }
+
+    // expand, reading the file and its includes with the encoding
+    pub fn expand_with(path: &Path, encoding: Encoding) -> Result<Source, (BbvmError, String)> {
+        let bytes = fs::read(path).expect("ERROR: Failed to read the file");
+        let name = path.display().to_string();
+        let text = encoding::decode(bytes, encoding).map_err(|(error, text)| {
+            let rendered = error.render(&text, &name);
+            (error, rendered)
+        })?;
+        let mut source = Source {
+            text: String::new(),
+            files: vec![File {
+                name,
+                path: path.to_path_buf(),
+                text,
+                included_from: None,
+            }],
+            parts: vec![],
+            encoding,
+        };
+        let mut stack = vec![fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())];
+        if let Err((error, file)) = source.splice(0, &mut stack) {
+            let rendered = source.render(&error, file);
+            return Err((error, rendered));
+        }
+        match macros::expand(&source.text) {
+            Ok(pieces) => {
+                source.rebuild(&pieces);
+                Ok(source)
+            }
+            Err(error) => {
+                let rendered = source.render_error(&error);
+                Err((error, rendered))
+            }
+        }
+    }
+
+    // Source that wasn't read from a file, with nothing to splice in or
+    // expand: a program built some other way, or none at all while
+    // --stream reads it a chunk at a time
+    pub fn new(name: &str, text: String) -> Source {
+        Source {
+            parts: vec![Part {
+                start: 0,
+                file: 0,
+                offset: 0,
+            }],
+            files: vec![File {
+                name: name.to_string(),
+                path: PathBuf::from(name),
+                text: text.clone(),
+                included_from: None,
+            }],
+            text,
+            encoding: Encoding::Utf8,
+        }
+    }
+
+    // Replaces the text with the expanded pieces of it, keeping every piece
+    // mapped back to where it came from
+    fn rebuild(&mut self, pieces: &[Piece]) -> () {
+        let old = std::mem::take(&mut self.text);
+        let parts = std::mem::take(&mut self.parts);
+        let end = |i: usize| parts.get(i + 1).map_or(old.len(), |p| p.start);
+        for piece in pieces {
+            match piece {
+                Piece::Copy(from, to) => {
+                    for (i, part) in parts.iter().enumerate() {
+                        let (start, stop) = (part.start.max(*from), end(i).min(*to));
+                        if start < stop {
+                            self.parts.push(Part {
+                                start: self.text.len(),
+                                file: part.file,
+                                offset: part.offset + start - part.start,
+                            });
+                            self.text.push_str(&old[start..stop]);
+                        }
+                    }
+                }
+                Piece::Word(word, at) => {
+                    let i = parts.partition_point(|p| p.start <= *at).max(1) - 1;
+                    if let Some(part) = parts.get(i) {
+                        self.parts.push(Part {
+                            start: self.text.len(),
+                            file: part.file,
+                            offset: part.offset + at - part.start,
+                        });
+                    }
+                    self.text.push_str(word);
+                }
+            }
+        }
+    }
+
+    // Copies file into the text, and the files it includes in their places.
+    // stack is the files being spliced, to catch an include cycle.
+    fn splice(&mut self, file: usize, stack: &mut Vec<PathBuf>) -> Result<(), (BbvmError, usize)> {
+        let text = self.files[file].text.clone();
+        let mut copied = 0;
+        let mut offset = 0;
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            let directive = line.trim_start();
+            if let Some(rest) = directive.strip_prefix("#include") {
+                let start = offset + line.len() - directive.len();
+                let span = Span {
+                    line: i + 1,
+                    column: start - offset + 1,
+                    start,
+                    end: offset + line.trim_end().len(),
+                };
+                let name = rest
+                    .trim()
+                    .strip_prefix('"')
+                    .and_then(|n| n.strip_suffix('"'))
+                    .ok_or_else(|| {
+                        let error = BbvmError::IncludeFailed {
+                            file: rest.trim().to_string(),
+                            reason: "the file name should be in quotes".to_string(),
+                            span,
+                        };
+                        (error, file)
+                    })?;
+                let failed = |reason: String| {
+                    let error = BbvmError::IncludeFailed {
+                        file: name.to_string(),
+                        reason,
+                        span,
+                    };
+                    (error, file)
+                };
+
+                self.copy(file, copied, offset);
+                // Relative to the including file, not where bbvm was run
+                let path = self.files[file]
+                    .path
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(name);
+                let canonical = fs::canonicalize(&path).map_err(|e| failed(e.to_string()))?;
+                if stack.contains(&canonical) {
+                    let error = BbvmError::IncludeCycle {
+                        file: name.to_string(),
+                        span,
+                    };
+                    return Err((error, file));
+                }
+                let bytes = fs::read(&path).map_err(|e| failed(e.to_string()))?;
+                let (included, invalid) = match encoding::decode(bytes, self.encoding) {
+                    Ok(text) => (text, None),
+                    Err((error, text)) => (text, Some(error)),
+                };
+                self.files.push(File {
+                    name: path.display().to_string(),
+                    path,
+                    text: included,
+                    included_from: Some((file, i + 1)),
+                });
+                if let Some(error) = invalid {
+                    return Err((error, self.files.len() - 1));
+                }
+                stack.push(canonical);
+                self.splice(self.files.len() - 1, stack)?;
+                stack.pop();
+                if !self.text.ends_with('\n') {
+                    self.text.push('\n');
+                }
+                copied = offset + line.len();
+            }
+            offset += line.len();
+        }
+        self.copy(file, copied, text.len());
+        Ok(())
+    }
+
+    fn copy(&mut self, file: usize, from: usize, to: usize) -> () {
+        if from == to {
+            return;
+        }
+        self.parts.push(Part {
+            start: self.text.len(),
+            file,
+            offset: from,
+        });
+        self.text.push_str(&self.files[file].text[from..to]);
+    }
+
+    // The name and text of the file that was read, the one the includes are
+    // in
+    pub fn root(&self) -> (&str, &str) {
+        (&self.files[0].name, &self.files[0].text)
+    }
+
+    // The file span of the spliced text is in, and where in that file
+    pub fn locate(&self, span: Span) -> Location<'_> {
+        let (file, span) = self.find(span);
+        Location {
+            name: &self.files[file].name,
+            text: &self.files[file].text,
+            span,
+        }
+    }
+
+    fn find(&self, span: Span) -> (usize, Span) {
+        let (file, offset) = match self.parts.partition_point(|p| p.start <= span.start) {
+            0 => (0, 0),
+            i => {
+                let part = &self.parts[i - 1];
+                (part.file, part.offset + span.start - part.start)
+            }
+        };
+        let text = &self.files[file].text;
+        let offset = offset.min(text.len());
+        let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let span = Span {
+            line: text[..offset].matches('\n').count() + 1,
+            column: offset - line_start + 1,
+            start: offset,
+            end: (offset + span.end - span.start).min(text.len()),
+        };
+        (file, span)
+    }
+
+    // The error rendered against the file it is in, followed by the chain of
+    // includes that led there
+    pub fn render_error(&self, error: &BbvmError) -> String {
+        let (file, span) = self.find(error.span());
+        self.render(&error.clone().with_span(span), file)
+    }
+
+    fn render(&self, error: &BbvmError, file: usize) -> String {
+        let mut rendered = error.render(&self.files[file].text, &self.files[file].name);
+        let mut from = self.files[file].included_from;
+        while let Some((file, line)) = from {
+            rendered += &format!("\nnote: included from {}:{}", self.files[file].name, line);
+            from = self.files[file].included_from;
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes the files to a directory of their own and expands the first
+    fn expand(test: &str, files: &[(&str, &str)]) -> Source {
+        let dir = std::env::temp_dir().join(format!("bbvm-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, text) in files {
+            fs::write(dir.join(name), text).unwrap();
+        }
+        let source = Source::expand(&dir.join(files[0].0));
+        fs::remove_dir_all(&dir).unwrap();
+        source.unwrap_or_else(|(_, rendered)| panic!("{}", rendered))
+    }
+
+    // The file, line and column that len bytes of the expanded text from
+    // where it first has find came from, skipping skip bytes into it
+    fn locate<'s>(
+        source: &'s Source,
+        find: &str,
+        skip: usize,
+        len: usize,
+    ) -> (&'s str, usize, usize) {
+        let start = source.text.find(find).unwrap() + skip;
+        let location = source.locate(Span {
+            start,
+            end: start + len,
+            ..Span::default()
+        });
+        assert_eq!(location.span.end - location.span.start, len);
+        let name = Path::new(location.name)
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap();
+        (name, location.span.line, location.span.column)
+    }
+
+    #[test]
+    fn maps_includes_back_to_their_files() {
+        let source = expand(
+            "includes",
+            &[
+                ("main.bb", "clear X;\n#include \"lib.bb\"\nprint Y;\n"),
+                ("lib.bb", "# lib\nclear Y;\n#include \"inner.bb\"\n"),
+                ("inner.bb", "incr Y;\n"),
+            ],
+        );
+        assert_eq!(locate(&source, "clear X", 0, 7), ("main.bb", 1, 1));
+        assert_eq!(locate(&source, "clear Y", 0, 7), ("lib.bb", 2, 1));
+        assert_eq!(locate(&source, "incr Y", 0, 6), ("inner.bb", 1, 1));
+        // After the include, back in the file that has it
+        assert_eq!(locate(&source, "print Y", 0, 7), ("main.bb", 3, 1));
+    }
+
+    #[test]
+    fn maps_macros_back_to_their_bodies() {
+        let main = "clear X;
+macro twice(A);
+    incr A;
+    decr T;
+endmacro;
+twice(X);
+print X;
+";
+        let source = expand("macros", &[("main.bb", main)]);
+        assert!(source.text.contains("incr X;"));
+        // What is copied from the body is found there
+        assert_eq!(locate(&source, "incr X", 0, 4), ("main.bb", 3, 5));
+        // and so is an argument, at the parameter it replaced
+        assert_eq!(locate(&source, "incr X", 5, 1), ("main.bb", 3, 10));
+        // and a temporary, at the name it was given a fresh one for
+        assert!(!source.text.contains("decr T;"));
+        assert_eq!(locate(&source, "decr ", 5, 1), ("main.bb", 4, 10));
+        assert_eq!(locate(&source, "print X", 0, 7), ("main.bb", 7, 1));
+    }
+
+    #[test]
+    fn maps_source_without_a_file_to_itself() {
+        let source = Source::new("stdin", "clear X;\nincr X;\n".to_string());
+        let location = source.locate(Span {
+            start: 9,
+            end: 15,
+            ..Span::default()
+        });
+        assert_eq!(location.name, "stdin");
+        assert_eq!((location.span.line, location.span.column), (2, 1));
+        assert_eq!(
+            &location.text[location.span.start..location.span.end],
+            "incr X"
+        );
+    }
+}
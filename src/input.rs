@@ -0,0 +1,139 @@
+use std::{
+    collections::HashMap,
+    io::{stdin, stdout, Write},
+    str::FromStr,
+};
+
+#[cfg(unix)]
+fn stdin_is_terminal() -> bool {
+    extern "C" {
+        fn isatty(fd: i32) -> i32;
+    }
+    unsafe { isatty(0) == 1 }
+}
+
+#[cfg(windows)]
+fn stdin_is_terminal() -> bool {
+    extern "C" {
+        fn _isatty(fd: i32) -> i32;
+    }
+    unsafe { _isatty(0) != 0 }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn stdin_is_terminal() -> bool {
+    true
+}
+
+// Asks for each input on stdin, in order, as `NAME: `. When stdin is piped
+// in the values are read one per line without asking.
+pub fn prompt<T: FromStr>(names: &[&str]) -> Vec<T> {
+    let interactive = stdin_is_terminal();
+    names
+        .iter()
+        .map(|name| {
+            if interactive {
+                print!("{}: ", name);
+                stdout().flush().unwrap();
+            }
+            let mut line = String::new();
+            if stdin().read_line(&mut line).unwrap() == 0 {
+                panic!("ERROR: stdin ended before a value for {} was given", name);
+            }
+            line.trim().parse().unwrap_or_else(|_| {
+                panic!("ERROR: {:?} is not a valid value for {}", line.trim(), name)
+            })
+        })
+        .collect()
+}
+
+// Parses --args, a comma separated value for each input in sorted order
+pub fn parse_args<T: FromStr>(names: &[&str], args: &str) -> Result<Vec<T>, String> {
+    let values: Vec<&str> = args
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect();
+    if values.len() != names.len() {
+        return Err(format!(
+            "the program has {} inputs ({}) but --args gave {} values",
+            names.len(),
+            names.join(", "),
+            values.len()
+        ));
+    }
+    values
+        .iter()
+        .zip(names)
+        .map(|(value, name)| {
+            value
+                .parse()
+                .map_err(|_| format!("{:?} is not a valid value for {}", value, name))
+        })
+        .collect()
+}
+
+// Reads --input-file, which has a value on each line in the same order as
+// --args, or a `NAME=value` line for each input in any order. Blank lines
+// and lines starting with # are skipped. Gives the values in the form
+// --args takes them, or which inputs have no value.
+pub fn from_file(names: &[&str], text: &str) -> Result<String, String> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .collect();
+    let values: Vec<&str> = if lines.iter().any(|l| l.contains('=')) {
+        let mut given = HashMap::new();
+        for line in &lines {
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{:?} should be NAME=value like the other lines", line))?;
+            let name = name.trim();
+            if !names.contains(&name) {
+                return Err(format!("the program has no input called {}", name));
+            }
+            if given.insert(name, value.trim()).is_some() {
+                return Err(format!("{} is given more than once", name));
+            }
+        }
+        let missing: Vec<&str> = names
+            .iter()
+            .copied()
+            .filter(|n| !given.contains_key(n))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("no value for {}", missing.join(", ")));
+        }
+        names.iter().map(|n| given[n]).collect()
+    } else {
+        if lines.len() < names.len() {
+            return Err(format!(
+                "no value for {} (a line each is expected, in the order {})",
+                names[lines.len()..].join(", "),
+                names.join(", ")
+            ));
+        }
+        if lines.len() > names.len() {
+            return Err(format!(
+                "the program has {} inputs ({}) but there are {} values",
+                names.len(),
+                names.join(", "),
+                lines.len()
+            ));
+        }
+        lines
+    };
+    if let Some(bad) = values.iter().find(|v| v.contains(',')) {
+        return Err(format!("{:?} is not a valid value", bad));
+    }
+    Ok(values.join(","))
+}
+
+// The values of the inputs, from --args if it was given or stdin otherwise
+pub fn read<T: FromStr>(names: &[&str], args: Option<&str>) -> Vec<T> {
+    match args {
+        Some(args) => parse_args(names, args).unwrap_or_else(|e| panic!("ERROR: {}", e)),
+        None => prompt(names),
+    }
+}
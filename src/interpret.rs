@@ -0,0 +1,496 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    io::{stdout, Write},
+    iter::zip,
+    time::Instant,
+};
+
+use chrono::Duration;
+
+use crate::bignum::Value;
+use crate::error::BbvmError;
+use crate::input;
+use crate::lexer::Span;
+use crate::output::OutputFormat;
+use crate::parser::{self, Block, Node};
+use crate::random;
+use crate::token::{
+    Array, Assert, Bound, Call, ElementOp, If, Indexed, OneParam, OneParamType, Proc, Rand, Set,
+    Statement, TwoParam, TwoParamType, While,
+};
+
+// Runs the statements directly, without LLVM. Reads the inputs and prints
+// the results the same way the JIT does. Variables are u64s like the
+// JIT's, or any other Value such as --bignum's Natural.
+pub struct Interpreter<'a, V: Value = u64> {
+    statements: Vec<(Statement<'a>, Span)>,
+    // Index of the matching end for each while, and the while for each end.
+    // Also the else (or endif) for each if, the endif for each else and the
+    // endproc for each proc.
+    jumps: HashMap<usize, usize>,
+    // Index of the proc statement for each proc name
+    procs: HashMap<&'a str, usize>,
+    values: HashMap<&'a str, V>,
+    // The size of each array, and its elements while running
+    sizes: HashMap<&'a str, u64>,
+    arrays: HashMap<&'a str, Vec<V>>,
+    detect_overflow: bool,
+    // The most loop iterations and calls execute runs
+    fuel: Option<u64>,
+    timeout: Option<std::time::Duration>,
+    format: OutputFormat,
+    // What print statements printed, while run_with_writer is running
+    captured: Option<String>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(statements: &[Statement<'a>], spans: &[Span]) -> Result<Interpreter<'a>, BbvmError> {
+        let program = parser::parse(statements, spans)?;
+        let mut interpreter = Interpreter {
+            statements: vec![],
+            jumps: HashMap::new(),
+            procs: HashMap::new(),
+            values: HashMap::new(),
+            sizes: HashMap::new(),
+            arrays: HashMap::new(),
+            detect_overflow: false,
+            fuel: None,
+            timeout: None,
+            format: OutputFormat::Pretty,
+            captured: None,
+        };
+        interpreter.flatten(&program.body);
+        Ok(interpreter)
+    }
+}
+
+impl<'a, V: Value> Interpreter<'a, V> {
+    // The same program and settings, holding its variables as W instead
+    pub fn holding<W: Value>(self) -> Interpreter<'a, W> {
+        Interpreter {
+            statements: self.statements,
+            jumps: self.jumps,
+            procs: self.procs,
+            values: HashMap::new(),
+            sizes: self.sizes,
+            arrays: HashMap::new(),
+            detect_overflow: self.detect_overflow,
+            fuel: self.fuel,
+            timeout: self.timeout,
+            format: self.format,
+            captured: None,
+        }
+    }
+
+    // Lays the block out as statements again, noting where each one that
+    // opens or closes a block jumps to
+    fn flatten(&mut self, block: &Block<'a>) -> () {
+        for node in block {
+            let start = self.statements.len();
+            match node {
+                Node::Statement(statement, span) => {
+                    if let Statement::Array(Array { name, size }) = statement {
+                        self.sizes.insert(name.ident, size.value as u64);
+                    }
+                    self.statements.push((*statement, *span))
+                }
+                Node::Loop {
+                    var,
+                    cmp,
+                    check,
+                    body,
+                    span,
+                    end,
+                } => {
+                    self.statements.push((
+                        Statement::While(While {
+                            param: *var,
+                            cmp: *cmp,
+                            bound: *check,
+                        }),
+                        *span,
+                    ));
+                    self.flatten(body);
+                    self.jumps.insert(start, self.statements.len());
+                    self.jumps.insert(self.statements.len(), start);
+                    self.statements.push((Statement::End, *end));
+                }
+                Node::If {
+                    var,
+                    cmp,
+                    check,
+                    then,
+                    otherwise,
+                    span,
+                    end,
+                } => {
+                    self.statements.push((
+                        Statement::If(If {
+                            param: *var,
+                            cmp: *cmp,
+                            num: *check,
+                        }),
+                        *span,
+                    ));
+                    self.flatten(then);
+                    // The if jumps to the else, or straight to the endif
+                    // without one, and the else to the endif
+                    let mut from = start;
+                    if let Some((span, otherwise)) = otherwise {
+                        self.jumps.insert(from, self.statements.len());
+                        from = self.statements.len();
+                        self.statements.push((Statement::Else, *span));
+                        self.flatten(otherwise);
+                    }
+                    self.jumps.insert(from, self.statements.len());
+                    self.statements.push((Statement::EndIf, *end));
+                }
+                Node::Proc {
+                    name,
+                    body,
+                    span,
+                    end,
+                } => {
+                    self.procs.insert(name.ident, start);
+                    self.statements
+                        .push((Statement::Proc(Proc { name: *name }), *span));
+                    self.flatten(body);
+                    self.jumps.insert(start, self.statements.len());
+                    self.statements.push((Statement::EndProc, *end));
+                }
+            }
+        }
+    }
+
+    // Makes incr fail instead of wrapping around, like the JIT's
+    // --detect-overflow
+    pub fn enable_overflow_detection(&mut self) -> () {
+        self.detect_overflow = true;
+    }
+
+    // Stops the program once it has been through loop bodies and called
+    // procs fuel times in total, like the JIT's --fuel
+    pub fn set_fuel(&mut self, fuel: u64) -> () {
+        self.fuel = Some(fuel);
+    }
+
+    // Stops the program with an error if it is still running after timeout,
+    // like the JIT's --timeout
+    pub fn set_timeout(&mut self, timeout: std::time::Duration) -> () {
+        self.timeout = Some(timeout);
+    }
+
+    // How run prints the final values of the variables
+    pub fn set_output_format(&mut self, format: OutputFormat) -> () {
+        self.format = format;
+    }
+
+    fn get(&self, var: &str) -> V {
+        self.values.get(var).cloned().unwrap_or_default()
+    }
+
+    // Where array[index] is in the array's elements
+    fn position(&self, array: &str, index: &str, span: Span) -> Result<usize, BbvmError> {
+        let size = self.sizes[array];
+        let value = self.get(index);
+        match value.to_u64() {
+            Some(i) if i < size => Ok(i as usize),
+            _ => Err(BbvmError::IndexOutOfBounds {
+                array: array.to_string(),
+                index: index.to_string(),
+                value: value.show(),
+                size,
+                span,
+            }),
+        }
+    }
+
+    // The elements of an array, while the program is running
+    pub fn array(&self, name: &str) -> Option<&[V]> {
+        self.arrays.get(name).map(|a| a.as_slice())
+    }
+
+    // Runs the program from the start with the given inputs, returning the
+    // value given to halt if it stopped at one. On a runtime failure (a
+    // failed assert, or an overflow) returns its message.
+    pub fn execute(&mut self, inputs: &[(&'a str, V)]) -> Result<Option<V>, BbvmError> {
+        self.execute_with(inputs, |_, _, _, _| {})
+    }
+
+    // execute, calling before with the index and the statement about to
+    // run, and how many procs deep it is, first. Used by the debugger.
+    pub fn execute_with<F>(
+        &mut self,
+        inputs: &[(&'a str, V)],
+        mut before: F,
+    ) -> Result<Option<V>, BbvmError>
+    where
+        F: FnMut(&Self, usize, (Statement<'a>, Span), usize),
+    {
+        self.values = inputs.iter().cloned().collect();
+        self.arrays = self
+            .sizes
+            .iter()
+            .map(|(&name, &size)| (name, vec![V::default(); size as usize]))
+            .collect();
+        let unit = V::from_u64(1);
+        let mut pc = 0;
+        // Where to carry on from when each running proc reaches its endproc
+        let mut returns: Vec<usize> = vec![];
+        let mut fuel = self.fuel;
+        // Only loops and calls can keep a program running, so going round a
+        // loop or calling a proc is when the time is checked, every so many
+        // times as reading the clock is slow next to a statement
+        let deadline = self
+            .timeout
+            .map(|timeout| (Instant::now() + timeout, timeout));
+        let mut steps: u32 = 0;
+        let mut step = |span| {
+            if let Some((deadline, timeout)) = deadline {
+                steps = steps.wrapping_add(1);
+                if steps % 1024 == 0 && Instant::now() >= deadline {
+                    return Err(BbvmError::TimedOut { timeout, span });
+                }
+            }
+            match &mut fuel {
+                Some(0) => Err(BbvmError::FuelExhausted {
+                    fuel: self.fuel.unwrap_or_default(),
+                    span,
+                }),
+                Some(left) => {
+                    *left -= 1;
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        };
+        while let Some(&(statement, span)) = self.statements.get(pc) {
+            before(self, pc, (statement, span), returns.len());
+            match statement {
+                Statement::OneParam(OneParam { one, ty }) => {
+                    let value = self.get(one.ident);
+                    let value = match ty {
+                        OneParamType::Clear => V::default(),
+                        OneParamType::Decr => value.saturating_sub(&unit),
+                        OneParamType::Incr => match value.overflowing_add(&unit) {
+                            (_, true) if self.detect_overflow => {
+                                return Err(BbvmError::Overflow {
+                                    op: "incr".to_string(),
+                                    var: one.ident.to_string(),
+                                    span,
+                                })
+                            }
+                            (value, _) => value,
+                        },
+                        OneParamType::Input => value,
+                        OneParamType::Print => {
+                            let line = format!("{}: {}", one.ident, value.show());
+                            match &mut self.captured {
+                                Some(captured) => {
+                                    captured.push_str(&line);
+                                    captured.push('\n');
+                                }
+                                None => println!("{}", line),
+                            }
+                            value
+                        }
+                        OneParamType::Halt => return Ok(Some(value)),
+                    };
+                    self.values.insert(one.ident, value);
+                }
+                Statement::TwoParam(TwoParam {
+                    one,
+                    two,
+                    ty: TwoParamType::Swap,
+                }) => {
+                    let (a, b) = (self.get(one.ident), self.get(two.ident));
+                    self.values.insert(one.ident, b);
+                    self.values.insert(two.ident, a);
+                }
+                Statement::TwoParam(TwoParam {
+                    one,
+                    two,
+                    ty: TwoParamType::Move,
+                }) => {
+                    let value = self.get(one.ident);
+                    self.values.insert(two.ident, value);
+                    self.values.insert(one.ident, V::default());
+                }
+                Statement::TwoParam(TwoParam { one, two, ty }) => {
+                    let (from, to) = (self.get(one.ident), self.get(two.ident));
+                    let (value, overflowed) = match ty {
+                        TwoParamType::Copy | TwoParamType::Swap | TwoParamType::Move => {
+                            (from, false)
+                        }
+                        TwoParamType::Add => to.overflowing_add(&from),
+                        TwoParamType::Sub => (to.saturating_sub(&from), false),
+                        TwoParamType::Mul => to.overflowing_mul(&from),
+                    };
+                    if overflowed && self.detect_overflow {
+                        return Err(BbvmError::Overflow {
+                            op: ty.keyword().to_string(),
+                            var: two.ident.to_string(),
+                            span,
+                        });
+                    }
+                    self.values.insert(two.ident, value);
+                }
+                Statement::While(While { param, cmp, bound }) => {
+                    let value = self.get(param.ident);
+                    let ordering = match bound {
+                        Bound::Number(num) => value.compare(num.value as u64),
+                        Bound::Variable(v) => value.cmp(&self.get(v.ident)),
+                    };
+                    if cmp.holds_for(ordering) {
+                        step(span)?;
+                    } else {
+                        pc = self.jumps[&pc];
+                    }
+                }
+                Statement::End => {
+                    pc = self.jumps[&pc];
+                    continue;
+                }
+                Statement::If(If { param, cmp, num }) => {
+                    if !cmp.holds_for(self.get(param.ident).compare(num.value as u64)) {
+                        pc = self.jumps[&pc];
+                    }
+                }
+                // Reached at the end of the if's statements, skip the else's
+                Statement::Else => pc = self.jumps[&pc],
+                Statement::EndIf => {}
+                // Only run when called
+                Statement::Proc(_) => pc = self.jumps[&pc],
+                Statement::EndProc => pc = returns.pop().unwrap(),
+                Statement::Call(Call { name }) => {
+                    step(span)?;
+                    returns.push(pc);
+                    pc = self.procs[name.ident];
+                }
+                Statement::Assert(Assert { param, num }) => {
+                    let value = self.get(param.ident);
+                    if value.compare(num.value as u64) != Ordering::Equal {
+                        return Err(BbvmError::AssertionFailed {
+                            var: param.ident.to_string(),
+                            value: value.show(),
+                            expected: num.value,
+                            span,
+                        });
+                    }
+                }
+                Statement::Set(Set { var, num }) => {
+                    self.values.insert(var.ident, V::from_u64(num.value as u64));
+                }
+                Statement::Rand(Rand { var, bound }) => {
+                    let value = random::bbvm_rand(bound.value as u64);
+                    self.values.insert(var.ident, V::from_u64(value));
+                }
+                // Made when the program starts
+                Statement::Array(_) => {}
+                Statement::Indexed(Indexed { element, op }) => {
+                    let (array, index) = (element.array.ident, element.index.ident);
+                    let i = self.position(array, index, span)?;
+                    let current = self.arrays[array][i].clone();
+                    let value = match op {
+                        ElementOp::Clear => V::default(),
+                        ElementOp::Decr => current.saturating_sub(&unit),
+                        ElementOp::Incr => match current.overflowing_add(&unit) {
+                            (_, true) if self.detect_overflow => {
+                                return Err(BbvmError::Overflow {
+                                    op: "incr".to_string(),
+                                    var: format!("{}[{}]", array, index),
+                                    span,
+                                })
+                            }
+                            (value, _) => value,
+                        },
+                        // Leaves the element as it is
+                        ElementOp::Load(to) => {
+                            self.values.insert(to.ident, current.clone());
+                            current
+                        }
+                        ElementOp::Store(from) => self.get(from.ident),
+                    };
+                    self.arrays.get_mut(array).unwrap()[i] = value;
+                }
+                Statement::Breakpoint | Statement::Fluff | Statement::EOF => {}
+            }
+            pc += 1;
+        }
+        Ok(None)
+    }
+
+    // The current value of each of the variables
+    pub fn results(&self, variables: &[&'a str]) -> Vec<(&'a str, V)> {
+        variables.iter().map(|&v| (v, self.get(v))).collect()
+    }
+
+    // Reads the inputs from args (as given to --args) or stdin, runs the
+    // program and prints every variable. Runtime failures are returned once
+    // the inputs are read. A program that halts prints nothing more, and
+    // gives the exit code it halted with.
+    pub fn run(
+        &mut self,
+        inputs: &[&'a str],
+        args: Option<&str>,
+        variables: &[&'a str],
+    ) -> Result<(Duration, Option<i32>), BbvmError> {
+        self.run_to(inputs, args, variables, &mut stdout())
+    }
+
+    // run, writing everything the program prints to out instead of stdout.
+    // The prompts for inputs read from stdin are still on stdout. Print
+    // statements are kept until the program stops, even if it fails.
+    pub fn run_with_writer(
+        &mut self,
+        inputs: &[&'a str],
+        args: Option<&str>,
+        variables: &[&'a str],
+        out: &mut impl Write,
+    ) -> Result<(Duration, Option<i32>), BbvmError> {
+        self.captured = Some(String::new());
+        let ran = self.run_to(inputs, args, variables, out);
+        self.captured = None;
+        ran
+    }
+
+    fn run_to(
+        &mut self,
+        inputs: &[&'a str],
+        args: Option<&str>,
+        variables: &[&'a str],
+        out: &mut impl Write,
+    ) -> Result<(Duration, Option<i32>), BbvmError> {
+        let mut write = |text: &str| {
+            out.write_all(text.as_bytes())
+                .expect("ERROR: Failed to write the program's output")
+        };
+        if self.format.separators() {
+            write("-----\n");
+        }
+        let values = input::read(inputs, args);
+        let inputs: Vec<(&str, V)> = zip(inputs.iter().copied(), values).collect();
+        if self.format.separators() {
+            write("-----\n");
+        }
+
+        let start = chrono::Utc::now();
+        let halted = self.execute(&inputs);
+        if let Some(captured) = &mut self.captured {
+            write(&std::mem::take(captured));
+        }
+        if let Some(code) = halted? {
+            // Wrapped to an int, as exit does in the generated code
+            return Ok((
+                chrono::Utc::now() - start,
+                Some(code.to_u64().unwrap_or(u64::MAX) as i32),
+            ));
+        }
+        write(&self.format.render(&self.results(variables)));
+        if self.format.separators() {
+            write("-----\n");
+        }
+        Ok((chrono::Utc::now() - start, None))
+    }
+}
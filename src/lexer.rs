@@ -1,116 +1,507 @@
-use std::{str::FromStr, sync::Mutex};
+use std::str::FromStr;
 
+use crate::error::BbvmError;
 use crate::token::*;
 
-macro_rules! incorrect {
-    ($t:ident,$l:literal,$c:ident) => {
-        panic!("Token {} should be followed by {}, not {:?}", $t, $l, $c)
-    };
+// Where a statement is in the source. Lines and columns count from 1,
+// start..end is the byte range of the statement's words.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
 }
 
+// Splits the source into tokens, yielding them up to and including EOF.
+// After an error or EOF it yields nothing more, unless recover is called
+// after the error.
 pub struct Lexer<'a> {
-    input: Mutex<&'a str>,
+    source: &'a str,
+    input: &'a str,
+    // position of the next character in input
+    offset: usize,
+    line: usize,
+    line_start: usize,
+    // end of the last word read
+    end: usize,
+    // span of the last token returned by get_token
+    span: Span,
+    // lints allowed by pragmas waiting for the next word
+    pending: Vec<&'a str>,
+    // (start of the statement, lint) for every allow pragma
+    allowed: Vec<(usize, &'a str)>,
+    // Every comment read so far and where it is, from its # to the end of
+    // its line
+    comments: Vec<(Span, &'a str)>,
+    // The count of an unroll hint waiting for its while
+    unroll: Option<u32>,
+    // (start of the while, count) for every unroll hint
+    unrolls: Vec<(usize, u32)>,
+    // How many bits the numbers must fit in, if set
+    width: Option<u32>,
+    // Whether keywords are only keywords in lower case, leaving `End` or
+    // `Copy` free for variables
+    case_sensitive: bool,
+    finished: bool,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(input: &str) -> Lexer {
+    pub fn new(input: &str) -> Lexer<'_> {
         Lexer {
-            input: Mutex::new(input),
+            source: input,
+            input,
+            offset: 0,
+            line: 1,
+            line_start: 0,
+            end: 0,
+            span: Span::default(),
+            pending: vec![],
+            allowed: vec![],
+            comments: vec![],
+            unroll: None,
+            unrolls: vec![],
+            width: None,
+            case_sensitive: false,
+            finished: false,
         }
     }
 
-    fn get_not_fluff(&self) -> Token {
+    // Makes a number too big for a variable of width bits an error, rather
+    // than losing its top bits when it is given to one
+    pub fn set_int_width(&mut self, width: u32) -> () {
+        self.width = Some(width);
+    }
+
+    pub fn set_case_sensitive(&mut self) -> () {
+        self.case_sensitive = true;
+    }
+
+    fn advance(&mut self, remaining: &'a str) {
+        let consumed = &self.input[..self.input.len() - remaining.len()];
+        if let Some(last) = consumed.rfind('\n') {
+            self.line += consumed.matches('\n').count();
+            self.line_start = self.offset + last + 1;
+        }
+        self.offset += consumed.len();
+        self.input = remaining;
+    }
+
+    fn here(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.offset - self.line_start + 1,
+            start: self.offset,
+            end: self.offset,
+        }
+    }
+
+    // Where the last token is in the source
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    // The lints named by `# allow(lint, ...)` comments, with the start of the
+    // statement following each comment
+    pub fn allowed(&self) -> Vec<(usize, &'a str)> {
+        self.allowed.clone()
+    }
+
+    // How many times to unroll each while with a `#[unroll N]` comment on
+    // the line before it, by the start of the while
+    pub fn unrolls(&self) -> Vec<(usize, u32)> {
+        self.unrolls.clone()
+    }
+
+    // The comments skipped so far, for the formatter to put back
+    pub fn comments(&self) -> Vec<(Span, &'a str)> {
+        self.comments.clone()
+    }
+
+    // After an error, skips what is left of the statement it was in so the
+    // lexer can carry on with the next one. The statement ends at its `;`,
+    // or before a line starting with a keyword if its `;` is missing.
+    pub fn recover(&mut self) -> () {
+        self.finished = false;
+        self.pending.clear();
+        self.unroll = None;
+        if self.source[..self.offset].ends_with(';') {
+            return;
+        }
+        let mut rest = self.input;
+        let mut line_start = self.source[..self.offset].ends_with('\n');
+        while !rest.is_empty() {
+            let word = rest.split_whitespace().next().unwrap_or("");
+            let word = word.split(';').next().unwrap();
+            if line_start
+                && is_keyword(word)
+                && !Fluff::identify(word)
+                && !Comparison::identify(word)
+            {
+                break;
+            }
+            let (line, after) = rest.split_once('\n').unwrap_or((rest, ""));
+            let code = line.split('#').next().unwrap();
+            if let Some(i) = code.find(';') {
+                rest = &rest[i + 1..];
+                break;
+            }
+            rest = after;
+            line_start = true;
+        }
+        self.advance(rest);
+    }
+
+    // The first word of the last token and where it is, for error messages
+    fn found(&self) -> (String, Span) {
+        let mut span = self.span;
+        match self.source[span.start..span.end]
+            .split(|c: char| c.is_whitespace() || c == ';')
+            .next()
+        {
+            Some(word) if !word.is_empty() => {
+                span.end = span.start + word.len();
+                (format!("`{}`", word), span)
+            }
+            _ => ("the end of the file".to_string(), span),
+        }
+    }
+
+    // Whether word is a keyword, which with case sensitivity it only is in
+    // lower case
+    fn is_keyword(&self, word: &str) -> bool {
+        is_keyword(word) && !(self.case_sensitive && word != word.to_lowercase())
+    }
+
+    // The error for the last token when a variable should have been there
+    fn not_identifier(&self, keyword: &str) -> BbvmError {
+        let (found, span) = self.found();
+        let word = &self.source[span.start..span.end];
+        if Identifier::identify(word) && self.is_keyword(word) {
+            return BbvmError::KeywordAsVariable {
+                word: word.to_string(),
+                keyword: word.to_lowercase(),
+                case_sensitive: self.case_sensitive,
+                span,
+            };
+        }
+        BbvmError::ExpectedIdentifier {
+            keyword: keyword.to_string(),
+            found,
+            span,
+        }
+    }
+
+    fn expect_identifier(&mut self, keyword: &str) -> Result<Identifier<'a>, BbvmError> {
+        match self.get_not_fluff()? {
+            Token::Identifier(i) => Ok(i),
+            _ => Err(self.not_identifier(keyword)),
+        }
+    }
+
+    fn expect_number(&mut self, keyword: &str) -> Result<Number, BbvmError> {
+        match self.get_not_fluff()? {
+            Token::Number(n) => Ok(n),
+            _ => {
+                let (found, span) = self.found();
+                Err(BbvmError::ExpectedNumber {
+                    keyword: keyword.to_string(),
+                    found,
+                    span,
+                })
+            }
+        }
+    }
+
+    // The comparison and number of a while. Without a comparison, as in
+    // `while X 0 do;`, it is `not`.
+    fn expect_comparison(&mut self, keyword: &str) -> Result<(Comparison, Number), BbvmError> {
+        match self.get_not_fluff()? {
+            Token::Comparison(c) => Ok((c, self.expect_number(keyword)?)),
+            Token::Number(n) => Ok((Comparison::Ne, n)),
+            _ => {
+                let (found, span) = self.found();
+                Err(BbvmError::ExpectedNumber {
+                    keyword: keyword.to_string(),
+                    found,
+                    span,
+                })
+            }
+        }
+    }
+
+    // The comparison and bound of a while, which may be a variable as well
+    // as a number
+    fn expect_bound(&mut self, keyword: &str) -> Result<(Comparison, Bound<'a>), BbvmError> {
+        let (cmp, bound) = match self.get_not_fluff()? {
+            Token::Comparison(c) => (c, self.get_not_fluff()?),
+            t => (Comparison::Ne, t),
+        };
+        match bound {
+            Token::Number(n) => Ok((cmp, Bound::Number(n))),
+            Token::Identifier(i) => Ok((cmp, Bound::Variable(i))),
+            _ => {
+                let (found, span) = self.found();
+                Err(BbvmError::ExpectedNumber {
+                    keyword: keyword.to_string(),
+                    found,
+                    span,
+                })
+            }
+        }
+    }
+
+    fn get_not_fluff(&mut self) -> Result<Token<'a>, BbvmError> {
         loop {
-            let t = self.get_token();
+            let t = self.get_token(true)?;
             if t != Token::Fluff {
-                return t;
+                return Ok(t);
             }
         }
     }
 
-    pub fn get_token(&self) -> Token {
+    // The next token. Where an operand is expected a statement's keyword is
+    // given as End, for the statement wanting the operand to report,
+    // rather than lexing the statement's own operands, which for a long
+    // run of keywords would recurse once for each of them.
+    fn get_token(&mut self, operand: bool) -> Result<Token<'a>, BbvmError> {
         let token;
-        let mut input = self.input.lock().unwrap();
+        let mut span;
         loop {
-            if input.is_empty() {
-                return Token::EOF;
+            let trimmed = self.input.trim_start();
+            self.advance(trimmed);
+            self.span = self.here();
+            if self.input.is_empty() {
+                return Ok(Token::EOF);
             }
-            let split = input
-                .trim_start()
+            let split = self
+                .input
                 .split_once(|c: char| c.is_whitespace() || c == ';');
             if split.is_none() {
-                return Token::EOF;
+                return Ok(Token::EOF);
             }
             let (t, remaining) = split.unwrap();
+            let line_text = self.input.split('\n').next().unwrap();
 
-            *input = remaining;
+            span = self.span;
+            span.end = span.start + t.len();
+            self.advance(remaining);
 
             if t.starts_with("#") {
-                if input.is_empty() {
-                    return Token::EOF;
+                let comment = line_text.trim_end();
+                self.comments.push((
+                    Span {
+                        end: span.start + comment.len(),
+                        ..span
+                    },
+                    comment,
+                ));
+                let pragma = line_text[1..].trim();
+                if let Some(lints) = pragma
+                    .strip_prefix("allow(")
+                    .and_then(|p| p.strip_suffix(')'))
+                {
+                    self.pending.extend(lints.split(',').map(|l| l.trim()));
+                }
+                if pragma.starts_with("[unroll") {
+                    let count = pragma
+                        .strip_prefix("[unroll ")
+                        .and_then(|p| p.strip_suffix(']'))
+                        .and_then(|count| count.trim().parse().ok())
+                        .filter(|&count| count > 0);
+                    let span = Span {
+                        end: span.start + comment.len(),
+                        ..span
+                    };
+                    let count = count.ok_or_else(|| BbvmError::BadUnroll {
+                        found: comment.to_string(),
+                        span,
+                    })?;
+                    // Checked here rather than once the next statement is
+                    // lexed, so that the statement isn't lost with the error
+                    let next = self
+                        .input
+                        .split_once('\n')
+                        .map_or("", |(_, rest)| rest)
+                        .lines()
+                        .map(str::trim)
+                        .find(|line| !line.is_empty() && !line.starts_with('#'))
+                        .and_then(|line| {
+                            line.split(|c: char| c.is_whitespace() || c == ';').next()
+                        });
+                    if !next.map_or(false, |word| self.is_keyword(word) && While::identify(word)) {
+                        return Err(BbvmError::UnrollWithoutWhile { span });
+                    }
+                    self.unroll = Some(count);
+                }
+                if self.input.is_empty() {
+                    return Ok(Token::EOF);
                 }
-                let split = input.split_once("\n");
+                let split = self.input.split_once("\n");
                 if split.is_none() {
-                    return Token::EOF;
+                    return Ok(Token::EOF);
                 }
-                *input = split.unwrap().1;
+                self.advance(split.unwrap().1);
             } else if !t.is_empty() {
                 token = t;
+                self.end = span.end;
+                let pending: Vec<&str> = self.pending.drain(..).collect();
+                self.allowed
+                    .extend(pending.into_iter().map(|l| (span.start, l)));
                 break;
             }
         }
-        drop(input);
-
-        if TwoParam::identify(token) {
-            let get = self.get_not_fluff().clone();
-            if let Token::Identifier(one) = get {
-                let get = self.get_not_fluff();
-                if let Token::Identifier(two) = get {
-                    Token::TwoParam(TwoParam {
-                        one,
-                        two,
-                        ty: TwoParamType::from_str(token).unwrap(),
-                    })
-                } else {
-                    incorrect!(token, "identifier", get);
-                }
-            } else {
-                incorrect!(token, "identifier", get);
+
+        let keyword = self.is_keyword(token);
+        if operand && keyword && !Fluff::identify(token) && !Comparison::identify(token) {
+            self.span = span;
+            return Ok(Token::End);
+        }
+        let result = if !keyword && Identifier::identify(token) {
+            Token::Identifier(Identifier { ident: token })
+        } else if TwoParam::identify(token) {
+            let ty = TwoParamType::from_str(token).unwrap();
+            match self.get_not_fluff()? {
+                // `copy 5 to X;` is the same as `set X to 5;`
+                Token::Number(num) if ty == TwoParamType::Copy => Token::Set(Set {
+                    var: self.expect_identifier(token)?,
+                    num,
+                }),
+                Token::Element(element) if ty == TwoParamType::Copy => Token::Indexed(Indexed {
+                    element,
+                    op: ElementOp::Load(self.expect_identifier(token)?),
+                }),
+                Token::Identifier(one) => match self.get_not_fluff()? {
+                    Token::Identifier(two) => Token::TwoParam(TwoParam { one, two, ty }),
+                    Token::Element(element) if ty == TwoParamType::Copy => {
+                        Token::Indexed(Indexed {
+                            element,
+                            op: ElementOp::Store(one),
+                        })
+                    }
+                    _ => return Err(self.not_identifier(token)),
+                },
+                _ => return Err(self.not_identifier(token)),
             }
+        } else if Set::identify(token) {
+            Token::Set(Set {
+                var: self.expect_identifier(token)?,
+                num: self.expect_number(token)?,
+            })
+        } else if Rand::identify(token) {
+            Token::Rand(Rand {
+                var: self.expect_identifier(token)?,
+                bound: self.expect_number(token)?,
+            })
         } else if OneParam::identify(token) {
-            let get = self.get_not_fluff();
-            if let Token::Identifier(one) = get {
-                Token::OneParam(OneParam {
-                    one,
-                    ty: OneParamType::from_str(token).unwrap(),
-                })
-            } else {
-                incorrect!(token, "identifier", get);
+            let ty = OneParamType::from_str(token).unwrap();
+            match (self.get_not_fluff()?, ty) {
+                (Token::Identifier(one), _) => Token::OneParam(OneParam { one, ty }),
+                (Token::Element(element), OneParamType::Clear) => Token::Indexed(Indexed {
+                    element,
+                    op: ElementOp::Clear,
+                }),
+                (Token::Element(element), OneParamType::Decr) => Token::Indexed(Indexed {
+                    element,
+                    op: ElementOp::Decr,
+                }),
+                (Token::Element(element), OneParamType::Incr) => Token::Indexed(Indexed {
+                    element,
+                    op: ElementOp::Incr,
+                }),
+                _ => return Err(self.not_identifier(token)),
             }
+        } else if Array::identify(token) {
+            Token::Array(Array {
+                name: self.expect_identifier(token)?,
+                size: self.expect_number(token)?,
+            })
         } else if While::identify(token) {
-            let get = self.get_not_fluff();
-            if let Token::Identifier(param) = get {
-                let get = self.get_not_fluff();
-                if let Token::Number(num) = get {
-                    Token::While(While { param, num })
-                } else {
-                    incorrect!(token, "number", get);
-                }
-            } else {
-                incorrect!(token, "identifier", get);
-            }
+            let param = self.expect_identifier(token)?;
+            let (cmp, bound) = self.expect_bound(token)?;
+            Token::While(While { param, cmp, bound })
+        } else if If::identify(token) {
+            let param = self.expect_identifier(token)?;
+            let (cmp, num) = self.expect_comparison(token)?;
+            Token::If(If { param, cmp, num })
+        } else if Else::identify(token) {
+            Token::Else
+        } else if EndIf::identify(token) {
+            Token::EndIf
+        } else if Proc::identify(token) {
+            Token::Proc(Proc {
+                name: self.expect_identifier(token)?,
+            })
+        } else if EndProc::identify(token) {
+            Token::EndProc
+        } else if Call::identify(token) {
+            Token::Call(Call {
+                name: self.expect_identifier(token)?,
+            })
+        } else if Assert::identify(token) {
+            Token::Assert(Assert {
+                param: self.expect_identifier(token)?,
+                num: self.expect_number(token)?,
+            })
+        } else if Comparison::identify(token) {
+            Token::Comparison(Comparison::from_str(token).unwrap())
         } else if Fluff::identify(token) {
             Token::Fluff
         } else if End::identify(token) {
             Token::End
+        } else if Breakpoint::identify(token) {
+            Token::Breakpoint
         } else if Identifier::identify(token) {
             Token::Identifier(Identifier { ident: token })
+        } else if Element::identify(token) {
+            Token::Element(Element::parse(token))
         } else if Number::identify(token) {
-            Token::Number(Number {
-                value: i128::from_str(token).unwrap(),
-            })
+            let value = Number::parse(token).ok_or_else(|| BbvmError::NumberTooBig {
+                number: token.to_string(),
+                span,
+            })?;
+            match self.width {
+                Some(width) if width < 128 && value >> width != 0 => {
+                    return Err(BbvmError::NumberOutOfRange {
+                        number: token.to_string(),
+                        width,
+                        span,
+                    })
+                }
+                _ => Token::Number(Number { value }),
+            }
+        } else if token.strip_prefix('-').map_or(false, Number::identify) {
+            return Err(BbvmError::NegativeNumber {
+                number: token.to_string(),
+                span,
+            });
         } else {
-            Token::EOF
+            return Err(BbvmError::UnknownWord {
+                word: token.to_string(),
+                span,
+            });
+        };
+
+        if let (false, Token::While(_)) = (operand, &result) {
+            if let Some(count) = self.unroll.take() {
+                self.unrolls.push((span.start, count));
+            }
+        }
+        span.end = self.end;
+        self.span = span;
+        Ok(result)
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, BbvmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
         }
+        let token = self.get_token(false);
+        self.finished = matches!(token, Ok(Token::EOF) | Err(_));
+        Some(token)
     }
 }
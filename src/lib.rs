@@ -0,0 +1,351 @@
+// The BareBones compiler as a library. compile_source checks a program and
+// CompiledProgram::run runs it, returning the variables instead of printing
+// them. The modules underneath are what the bbvm command line is built on.
+
+// Functions returning nothing say so with -> (), and errors travel with the
+// text they are rendered against
+#![allow(clippy::unused_unit, clippy::result_large_err)]
+
+use std::{
+    borrow::Borrow,
+    collections::{HashMap, HashSet},
+    io::Write,
+};
+
+use inkwell::{context::Context, OptimizationLevel};
+
+use crate::convert::{Converter, OutputCallback};
+use crate::ext::Extension;
+use crate::runner::Stopped;
+use crate::token::{OneParamType, StatementImpl};
+
+pub use crate::error::BbvmError;
+pub use crate::interpret::Interpreter;
+pub use crate::lexer::{Lexer, Span};
+pub use crate::token::Statement;
+
+pub mod analyze;
+pub mod annotate;
+pub mod backend_c;
+pub mod bench;
+pub mod bignum;
+pub mod bytecode;
+pub mod cache;
+pub mod cfg;
+pub mod check;
+pub mod convert;
+pub mod dataflow;
+pub mod debug;
+pub mod decompile;
+pub mod diff;
+pub mod difftest;
+pub mod encoding;
+pub mod error;
+pub mod explain;
+pub mod ext;
+pub mod fix;
+pub mod formatter;
+pub mod highlight;
+pub mod history;
+pub mod include;
+pub mod input;
+pub mod interpret;
+pub mod lexer;
+pub mod link;
+pub mod lint;
+pub mod macros;
+pub mod output;
+pub mod parser;
+pub mod passes;
+pub mod pgo;
+pub mod prune;
+pub mod random;
+pub mod repl;
+pub mod runner;
+pub mod runtime;
+pub mod simplify;
+pub mod stats;
+pub mod stream;
+pub mod suite;
+pub mod timeout;
+pub mod token;
+pub mod trace;
+
+// The statements the lexer finds and where each is, up to and including
+// EOF
+pub fn statements<'a, 'l>(
+    l: &'l mut Lexer<'a>,
+) -> impl Iterator<Item = Result<(Statement<'a>, Span), BbvmError>> + 'l {
+    std::iter::from_fn(move || {
+        let token = l.next()?;
+        Some(
+            token
+                .and_then(|t| Statement::try_from(t).map_err(|e| e.with_span(l.span())))
+                .map(|statement| (statement, l.span())),
+        )
+    })
+}
+
+// Every statement and where it is, ending with EOF
+pub fn lex<'a>(l: &mut Lexer<'a>) -> Result<(Vec<Statement<'a>>, Vec<Span>), BbvmError> {
+    statements(l)
+        .collect::<Result<Vec<_>, _>>()
+        .map(|s| s.into_iter().unzip())
+}
+
+// Every statement and where it is like lex, but carrying on past errors to
+// find them all. Statements with errors are left out.
+pub fn lex_all<'a>(l: &mut Lexer<'a>) -> (Vec<Statement<'a>>, Vec<Span>, Vec<BbvmError>) {
+    let (mut tokens, mut spans, mut errors) = (vec![], vec![], vec![]);
+    while let Some(token) = l.next() {
+        match token.and_then(|t| Statement::try_from(t).map_err(|e| e.with_span(l.span()))) {
+            Ok(statement) => {
+                tokens.push(statement);
+                spans.push(l.span());
+            }
+            Err(e) => {
+                errors.push(e);
+                l.recover();
+            }
+        }
+    }
+    (tokens, spans, errors)
+}
+
+// The program's tree, or everything wrong with it. It never panics,
+// whatever source it is given, so it is what the fuzz target calls.
+pub fn parse(source: &str) -> Result<parser::Program<'_>, Vec<BbvmError>> {
+    let (statements, spans, errors) = lex_all(&mut Lexer::new(source));
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    parser::parse(&statements, &spans).map_err(|e| vec![e])
+}
+
+// Every variable used in the program, in the order they are first used
+pub fn collect_variables<'a, S: Borrow<Statement<'a>>>(
+    tokens: impl IntoIterator<Item = S>,
+) -> Vec<&'a str> {
+    let mut seen = HashSet::new();
+    tokens
+        .into_iter()
+        .flat_map(|t| {
+            use Statement::*;
+            match t.borrow() {
+                // Proc names aren't variables
+                EOF | Fluff | End | Else | EndIf | Breakpoint | Proc(_) | EndProc | Call(_) => {
+                    vec![]
+                }
+                While(v) => v.get_variables(),
+                If(v) => v.get_variables(),
+                Assert(v) => v.get_variables(),
+                Set(v) => v.get_variables(),
+                Rand(v) => v.get_variables(),
+                Array(_) => vec![],
+                Indexed(v) => v.get_variables(),
+                OneParam(v) => v.get_variables(),
+                TwoParam(v) => v.get_variables(),
+            }
+        })
+        .filter(|v| seen.insert(*v))
+        .collect()
+}
+
+// Every variable read by an input statement, sorted
+pub fn collect_inputs<'a, S: Borrow<Statement<'a>>>(
+    tokens: impl IntoIterator<Item = S>,
+) -> Vec<&'a str> {
+    let mut inputs: Vec<&str> = tokens
+        .into_iter()
+        .map(|t| match *t.borrow() {
+            Statement::OneParam(crate::token::OneParam {
+                one,
+                ty: OneParamType::Input,
+            }) => one.ident,
+            _ => "",
+        })
+        .filter(|x| !x.is_empty())
+        .collect();
+
+    inputs.sort();
+    inputs.dedup();
+    inputs
+}
+
+// Parses the statements and generates the IR for them, spans[i] being where
+// statements[i] is in source. Unused variables are pruned if the converter
+// was asked to, and counting loops are rewritten as arithmetic unless it is
+// at -O0.
+pub fn lower<'a>(
+    converter: &mut Converter<'a>,
+    tokens: &[Statement<'a>],
+    spans: &[Span],
+    source: &str,
+    verify_each: bool,
+) -> Result<(), BbvmError> {
+    let mut program = parser::parse(tokens, spans)?;
+    // Pruned first, so what is left out doesn't depend on the -O level
+    if converter.prunes_unused() {
+        let live = prune::live(&program.body);
+        prune::prune(&mut program.body, &live);
+        converter.print_only(&live);
+    }
+    if converter.rewrites_loops() {
+        simplify::simplify(&mut program.body);
+    }
+    converter.add_block(&program.body, &mut |converter, span| {
+        if !verify_each {
+            return Ok(());
+        }
+        converter
+            .verify_so_far()
+            .map_err(|reason| BbvmError::InvalidIr {
+                statement: source[span.start..span.end].to_string(),
+                reason,
+                span,
+            })
+    })?;
+    // EOF verifies the whole module itself
+    converter.set_span(program.eof);
+    converter.add_eof()
+}
+
+// The variables and inputs, as collect_variables and collect_inputs give
+// them, keeping none of the statements
+pub fn scan<'a>(l: &mut Lexer<'a>) -> Result<(Vec<&'a str>, Vec<&'a str>), BbvmError> {
+    let (mut variables, mut inputs) = (vec![], vec![]);
+    let mut seen = HashSet::new();
+    for statement in statements(l) {
+        let (statement, _) = statement?;
+        variables.extend(
+            collect_variables([statement])
+                .into_iter()
+                .filter(|v| seen.insert(*v)),
+        );
+        inputs.extend(collect_inputs([statement]));
+    }
+    inputs.sort();
+    inputs.dedup();
+    Ok((variables, inputs))
+}
+
+// Generates the IR for each statement as it is lexed, for programs too big
+// to hold all at once. Without the tree nothing is pruned or rewritten, and
+// blocks are matched up by the converter as they close.
+pub fn lower_streaming<'a>(
+    converter: &mut Converter<'a>,
+    l: &mut Lexer<'a>,
+    extensions: &[Extension],
+) -> Result<(), BbvmError> {
+    for statement in statements(l) {
+        let (statement, span) = statement?;
+        ext::check(&[statement], &[span], extensions)?;
+        converter.set_span(span);
+        converter.add_statement(statement)?;
+    }
+    Ok(())
+}
+
+// A checked program, ready to run or lower to LLVM
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompiledProgram<'a> {
+    pub source: &'a str,
+    pub statements: Vec<Statement<'a>>,
+    pub spans: Vec<Span>,
+    pub variables: Vec<&'a str>,
+    pub inputs: Vec<&'a str>,
+}
+
+// Lexes source and checks its blocks are balanced
+pub fn compile_source(source: &str) -> Result<CompiledProgram<'_>, BbvmError> {
+    let (statements, spans) = lex(&mut Lexer::new(source))?;
+    parser::parse(&statements, &spans)?;
+
+    Ok(CompiledProgram {
+        source,
+        variables: collect_variables(&statements),
+        inputs: collect_inputs(&statements),
+        statements,
+        spans,
+    })
+}
+
+impl<'a> CompiledProgram<'a> {
+    // Runs the program in the interpreter with the given input values,
+    // returning every variable's final value. Inputs left out start at 0. A
+    // program that halts gives the values it had when it did.
+    pub fn run(&self, inputs: &HashMap<&str, u64>) -> Result<HashMap<&'a str, u64>, BbvmError> {
+        let inputs: Vec<(&str, u64)> = self
+            .inputs
+            .iter()
+            .map(|&i| (i, inputs.get(i).copied().unwrap_or(0)))
+            .collect();
+        let mut interpreter = Interpreter::new(&self.statements, &self.spans)?;
+        interpreter.execute(&inputs)?;
+        Ok(interpreter.results(&self.variables).into_iter().collect())
+    }
+
+    // Runs the program in the interpreter like run, but writes what it
+    // prints and then every variable to out, as bbvm --interpret would print
+    // them. Gives the code it halted with, if it did.
+    pub fn run_with_writer(
+        &self,
+        inputs: &HashMap<&str, u64>,
+        out: &mut impl Write,
+    ) -> Result<Option<i32>, BbvmError> {
+        let args: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|i| inputs.get(i).copied().unwrap_or(0).to_string())
+            .collect();
+        let mut interpreter = Interpreter::new(&self.statements, &self.spans)?;
+        let (_, halted) = interpreter.run_with_writer(
+            &self.inputs,
+            Some(&args.join(",")),
+            &self.variables,
+            out,
+        )?;
+        Ok(halted)
+    }
+
+    // Generates the program's LLVM IR into converter, which must have been
+    // created from this program's variables and inputs
+    pub fn lower(&self, converter: &mut Converter<'a>) -> Result<(), BbvmError> {
+        lower(converter, &self.statements, &self.spans, self.source, false)
+    }
+
+    // Compiles the program with the JIT and runs it, calling callback with
+    // the name and value of each variable it prints and then of every
+    // variable at the end, instead of writing them to stdout. Inputs left
+    // out start at 0. Gives how it stopped if it halted or failed, in which
+    // case the values at the end aren't given.
+    pub fn run_jit(
+        &self,
+        inputs: &HashMap<&str, u64>,
+        callback: OutputCallback,
+    ) -> Result<Option<Stopped>, BbvmError> {
+        let context = Context::create();
+        let mut converter = Converter::new(self.variables.clone(), &self.inputs, &context);
+        converter.set_output_callback(callback);
+        self.lower(&mut converter)?;
+        converter.optimise();
+        let values: Vec<u64> = self
+            .inputs
+            .iter()
+            .map(|i| inputs.get(i).copied().unwrap_or(0))
+            .collect();
+        let (_, _, stopped) = converter.run_with_output(&values);
+        Ok(stopped)
+    }
+
+    // The program's LLVM IR after optimising at level, as `bbvm ir` prints
+    // it, for comparing against a known good copy
+    pub fn ir_string(&self, level: OptimizationLevel) -> Result<String, BbvmError> {
+        let context = Context::create();
+        let mut converter = Converter::new(self.variables.clone(), &self.inputs, &context);
+        converter.set_optimization_level(level);
+        self.lower(&mut converter)?;
+        converter.optimise();
+        Ok(converter.ir_string())
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// C compilers that can link an object file with libc, in order of
+// preference. $CC comes first when it is set. Windows has no cc, but
+// Visual Studio's cl (or LLVM's clang-cl) does the same. Its link (or
+// lld-link) can be picked with --linker, but can't compile the runtime.
+#[cfg(not(windows))]
+const LINKERS: &[&str] = &["cc", "clang", "gcc"];
+#[cfg(windows)]
+const LINKERS: &[&str] = &["clang-cl", "cl", "clang", "gcc"];
+
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    if name.contains(std::path::MAIN_SEPARATOR) {
+        return Some(PathBuf::from(name)).filter(|p| p.is_file());
+    }
+    // On Windows cl is cl.exe
+    let exe = format!("{}{}", name, env::consts::EXE_SUFFIX);
+    env::split_paths(&env::var_os("PATH")?)
+        .flat_map(|dir| [dir.join(name), dir.join(&exe)])
+        .find(|p| p.is_file())
+}
+
+// What arguments the linker takes
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Flavour {
+    // cc, gcc and clang
+    Cc,
+    // MSVC's compiler driver, cl or clang-cl
+    Cl,
+    // MSVC's linker, link or lld-link, which can't compile the runtime
+    Link,
+}
+
+fn flavour(linker: &Path) -> Flavour {
+    let name = linker
+        .file_stem()
+        .map_or(String::new(), |s| s.to_string_lossy().to_lowercase());
+    match name.as_str() {
+        "cl" | "clang-cl" => Flavour::Cl,
+        "link" | "lld-link" => Flavour::Link,
+        _ => Flavour::Cc,
+    }
+}
+
+// The C runtime libraries MSVC's linker needs
+fn msvc_libraries(static_: bool) -> &'static [&'static str] {
+    if static_ {
+        &["libcmt.lib", "libucrt.lib", "libvcruntime.lib"]
+    } else {
+        &["msvcrt.lib", "ucrt.lib", "vcruntime.lib"]
+    }
+}
+
+// How the executable is linked, from --static, --pie, --no-pie, --linker
+// and the arguments after --
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct LinkOptions {
+    // Used instead of looking for a C compiler
+    pub linker: Option<PathBuf>,
+    pub static_: bool,
+    // Whether the executable is position independent, or the linker's
+    // default if None. The object is always PIC, so either links.
+    pub pie: Option<bool>,
+    // Passed on last
+    pub args: Vec<String>,
+}
+
+pub fn find_linker() -> Option<PathBuf> {
+    env::var("CC")
+        .ok()
+        .into_iter()
+        .chain(LINKERS.iter().map(|l| l.to_string()))
+        .find_map(|l| find_in_path(&l))
+}
+
+// Links object into the executable output, compiling the C files in
+// runtime into it too
+pub fn link(
+    object: &Path,
+    runtime: &[&Path],
+    output: &Path,
+    options: &LinkOptions,
+) -> Result<(), String> {
+    let linker = match &options.linker {
+        Some(linker) => linker.clone(),
+        None => find_linker().ok_or(format!(
+            "No C compiler found to link with (tried $CC, {}). Install one, or link {} yourself",
+            LINKERS.join(", "),
+            object.display()
+        ))?,
+    };
+    let mut command = Command::new(&linker);
+    // Executables are always position independent on Windows, so --pie
+    // and --no-pie only matter to cc
+    match flavour(&linker) {
+        Flavour::Cc => {
+            command.arg(object).args(runtime).arg("-o").arg(output);
+            match (options.static_, options.pie) {
+                (true, Some(true)) => command.arg("-static-pie"),
+                (true, _) => command.arg("-static"),
+                (false, Some(true)) => command.arg("-pie"),
+                (false, Some(false)) => command.arg("-no-pie"),
+                (false, None) => &mut command,
+            };
+            command.args(&options.args);
+        }
+        Flavour::Cl => {
+            let crt = if options.static_ { "/MT" } else { "/MD" };
+            command
+                .args(["/nologo", crt])
+                .arg(object)
+                .args(runtime)
+                .arg(format!("/Fe{}", output.display()))
+                .args(&options.args);
+        }
+        Flavour::Link => {
+            if !runtime.is_empty() {
+                return Err(format!(
+                    "{} can't compile the C runtime this program needs, link with cl or clang-cl instead (--linker)",
+                    linker.display()
+                ));
+            }
+            command
+                .args(["/nologo", "/SUBSYSTEM:CONSOLE"])
+                .arg(object)
+                .arg(format!("/OUT:{}", output.display()))
+                .args(msvc_libraries(options.static_))
+                .args(&options.args);
+        }
+    }
+    let status = command
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", linker.display(), e))?;
+    if !status.success() {
+        return Err(format!(
+            "{} failed to link {} ({})",
+            linker.display(),
+            object.display(),
+            status
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,564 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+use crate::{
+    fix::Suggestion,
+    lexer::Span,
+    token::{
+        Bound, Comparison, ElementOp, If, Indexed, OneParam, OneParamType, Statement,
+        StatementImpl, TwoParam, TwoParamType, While,
+    },
+};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub enum Lint {
+    CopyToSelf,
+    IncrDecr,
+    EmptyLoop,
+    ConstantFalseWhile,
+    LateInput,
+    ReadBeforeWrite,
+    InfiniteLoop,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 7] = [
+        Lint::CopyToSelf,
+        Lint::IncrDecr,
+        Lint::EmptyLoop,
+        Lint::ConstantFalseWhile,
+        Lint::LateInput,
+        Lint::ReadBeforeWrite,
+        Lint::InfiniteLoop,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::CopyToSelf => "copy_to_self",
+            Lint::IncrDecr => "incr_decr",
+            Lint::EmptyLoop => "empty_loop",
+            Lint::ConstantFalseWhile => "constant_false_while",
+            Lint::LateInput => "late_input",
+            Lint::ReadBeforeWrite => "read_before_write",
+            Lint::InfiniteLoop => "infinite_loop",
+        }
+    }
+
+    // Stable code for bbvm --explain
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lint::CopyToSelf => "W0001",
+            Lint::IncrDecr => "W0002",
+            Lint::EmptyLoop => "W0003",
+            Lint::ConstantFalseWhile => "W0004",
+            Lint::LateInput => "W0005",
+            Lint::ReadBeforeWrite => "W0006",
+            Lint::InfiniteLoop => "W0007",
+        }
+    }
+}
+
+impl FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lint::ALL
+            .iter()
+            .find(|l| l.name() == s)
+            .copied()
+            .ok_or(format!("Unknown lint {}", s))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+// How each lint is reported. Everything warns unless configured otherwise.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct LintConfig {
+    levels: HashMap<Lint, Level>,
+    deny_warnings: bool,
+}
+
+impl LintConfig {
+    pub fn set(&mut self, lint: Lint, level: Level) -> () {
+        self.levels.insert(lint, level);
+    }
+
+    // Makes every lint that would warn an error instead. Allowed lints stay
+    // allowed.
+    pub fn deny_warnings(&mut self) -> () {
+        self.deny_warnings = true;
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        match self.levels.get(&lint).copied().unwrap_or(Level::Warn) {
+            Level::Warn if self.deny_warnings => Level::Deny,
+            level => level,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Warning {
+    pub lint: Lint,
+    pub level: Level,
+    pub span: Span,
+    pub message: String,
+    pub suggestion: Option<Suggestion>,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}]: line {}: {} [{}]",
+            if self.level == Level::Deny {
+                "ERROR"
+            } else {
+                "WARNING"
+            },
+            self.lint.code(),
+            self.span.line,
+            self.message,
+            self.lint.name()
+        )?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n         help: {} (bbvm fix)", suggestion.message)?;
+        }
+        Ok(())
+    }
+}
+
+struct Known<'a> {
+    values: HashMap<&'a str, Option<u64>>,
+    // Value of the variables not in values
+    default: Option<u64>,
+}
+
+impl<'a> Known<'a> {
+    fn get(&self, var: &str) -> Option<u64> {
+        self.values.get(var).copied().unwrap_or(self.default)
+    }
+
+    fn set(&mut self, var: &'a str, value: Option<u64>) {
+        self.values.insert(var, value);
+    }
+
+    fn forget(&mut self) {
+        self.values.clear();
+        self.default = None;
+    }
+}
+
+// The variables a statement only reads, and the ones it gives a value.
+// incr, decr and the arithmetic statements count as writes: starting them
+// from 0 is how BareBones programs are written.
+pub fn reads_and_writes<'a>(statement: &Statement<'a>) -> (Vec<&'a str>, Vec<&'a str>) {
+    match *statement {
+        Statement::TwoParam(TwoParam {
+            one,
+            two,
+            ty: TwoParamType::Swap,
+        }) => (vec![one.ident, two.ident], vec![one.ident, two.ident]),
+        Statement::TwoParam(TwoParam {
+            one,
+            two,
+            ty: TwoParamType::Move,
+        }) => (vec![one.ident], vec![one.ident, two.ident]),
+        Statement::TwoParam(TwoParam { one, two, .. }) => (vec![one.ident], vec![two.ident]),
+        Statement::OneParam(OneParam {
+            one,
+            ty: OneParamType::Print | OneParamType::Halt,
+        }) => (vec![one.ident], vec![]),
+        Statement::OneParam(OneParam { one, .. }) => (vec![], vec![one.ident]),
+        Statement::While(w) => (w.get_variables(), vec![]),
+        Statement::If(If { param, .. }) => (vec![param.ident], vec![]),
+        Statement::Assert(a) => (vec![a.param.ident], vec![]),
+        Statement::Set(s) => (vec![], vec![s.var.ident]),
+        Statement::Rand(r) => (vec![], vec![r.var.ident]),
+        Statement::Indexed(Indexed { element, op }) => match op {
+            ElementOp::Load(to) => (vec![element.index.ident], vec![to.ident]),
+            ElementOp::Store(from) => (vec![element.index.ident, from.ident], vec![]),
+            _ => (vec![element.index.ident], vec![]),
+        },
+        _ => (vec![], vec![]),
+    }
+}
+
+// What the loop variable must be for a loop to stop
+fn exit_condition(cmp: Comparison, bound: Bound) -> String {
+    match cmp {
+        Comparison::Ne => bound.to_string(),
+        Comparison::Lt => format!("at least {}", bound),
+        Comparison::Gt => format!("at most {}", bound),
+    }
+}
+
+// Runs the lints over the statements, spans[i] being where statements[i] is
+// in the source. Lints allowed by the config, or by an allow pragma on the
+// statement (see Lexer::allowed), are left out.
+pub fn lint(
+    statements: &[Statement],
+    spans: &[Span],
+    config: &LintConfig,
+    allowed: &[(usize, &str)],
+) -> Vec<Warning> {
+    let statements: Vec<(Statement, Span)> = statements
+        .iter()
+        .copied()
+        .zip(spans.iter().copied())
+        .filter(|(s, _)| *s != Statement::Fluff)
+        .collect();
+
+    let mut warnings: Vec<Warning> = vec![];
+    // Returns whether the warning was reported
+    let mut warn = |lint: Lint, span: Span, message: String, suggestion: Option<Suggestion>| {
+        let level = config.level(lint);
+        let enabled = level != Level::Allow && !allowed.contains(&(span.start, lint.name()));
+        if enabled {
+            warnings.push(Warning {
+                lint,
+                level,
+                span,
+                message,
+                suggestion,
+            });
+        }
+        enabled
+    };
+    // Removals of loops that never run, found once the loop's end is reached
+    let mut loop_removals: Vec<Suggestion> = vec![];
+
+    // Values known at this point of the straight-line code. Everything
+    // starts at 0 apart from inputs.
+    let mut known = Known {
+        values: statements
+            .iter()
+            .filter_map(|(s, _)| match s {
+                Statement::OneParam(OneParam {
+                    one,
+                    ty: OneParamType::Input,
+                }) => Some((one.ident, None)),
+                _ => None,
+            })
+            .collect(),
+        default: Some(0),
+    };
+    // Loop variables and checks of the enclosing loops, whether the loop
+    // never runs, and where its while is
+    let mut loops: Vec<(&str, Comparison, Bound, bool, Span, usize)> = vec![];
+    // Variables mentioned so far
+    let mut used: Vec<&str> = vec![];
+    // Variables given a value so far (inputs have one from the start), and
+    // the ones any proc gives a value, which are written wherever the proc is
+    // called. Reads inside procs aren't checked, as they depend on where the
+    // proc is called from.
+    let mut written: HashSet<&str> = known.values.keys().copied().collect();
+    let mut proc_writes: HashSet<&str> = HashSet::new();
+    let mut in_proc = false;
+    for (statement, _) in &statements {
+        match statement {
+            Statement::Proc(_) => in_proc = true,
+            Statement::EndProc => in_proc = false,
+            _ if in_proc => proc_writes.extend(reads_and_writes(statement).1),
+            _ => {}
+        }
+    }
+    in_proc = false;
+
+    for (i, (statement, span)) in statements.iter().enumerate() {
+        let next = statements.get(i + 1).map(|s| s.0);
+        let mentioned = match *statement {
+            Statement::TwoParam(TwoParam { one, two, .. }) => vec![one.ident, two.ident],
+            Statement::OneParam(OneParam { one, .. }) => vec![one.ident],
+            Statement::While(w) => w.get_variables(),
+            Statement::If(If { param, .. }) => vec![param.ident],
+            Statement::Assert(a) => vec![a.param.ident],
+            Statement::Set(s) => vec![s.var.ident],
+            Statement::Rand(r) => vec![r.var.ident],
+            Statement::Indexed(i) => i.get_variables(),
+            _ => vec![],
+        };
+        let (reads, writes) = reads_and_writes(statement);
+        for var in reads {
+            // Counting it as written means it is only reported once
+            if !in_proc && written.insert(var) {
+                // Later in the loop it may be given one for the next time
+                // round
+                let value = if loops.is_empty() {
+                    "always 0 here"
+                } else {
+                    "0 here the first time round the loop"
+                };
+                warn(
+                    Lint::ReadBeforeWrite,
+                    *span,
+                    format!(
+                        "{} is read before it is given a value, so it is {}",
+                        var, value
+                    ),
+                    None,
+                );
+            }
+        }
+        written.extend(writes);
+        match *statement {
+            Statement::TwoParam(TwoParam {
+                one,
+                two,
+                ty: TwoParamType::Copy,
+            }) => {
+                if one.ident == two.ident {
+                    warn(
+                        Lint::CopyToSelf,
+                        *span,
+                        format!("copying {} to itself has no effect", one.ident),
+                        Some(Suggestion::remove(
+                            "remove this statement".to_string(),
+                            span.start,
+                            span.end,
+                        )),
+                    );
+                }
+                known.set(two.ident, known.get(one.ident));
+            }
+            Statement::TwoParam(TwoParam {
+                one,
+                two,
+                ty: TwoParamType::Swap,
+            }) => {
+                if one.ident == two.ident {
+                    warn(
+                        Lint::CopyToSelf,
+                        *span,
+                        format!("swapping {} with itself has no effect", one.ident),
+                        Some(Suggestion::remove(
+                            "remove this statement".to_string(),
+                            span.start,
+                            span.end,
+                        )),
+                    );
+                }
+                let (a, b) = (known.get(one.ident), known.get(two.ident));
+                known.set(one.ident, b);
+                known.set(two.ident, a);
+            }
+            Statement::TwoParam(TwoParam {
+                one,
+                two,
+                ty: TwoParamType::Move,
+            }) => {
+                if one.ident == two.ident {
+                    warn(
+                        Lint::CopyToSelf,
+                        *span,
+                        format!(
+                            "moving {} to itself only clears it, use `clear {}` instead",
+                            one.ident, one.ident
+                        ),
+                        None,
+                    );
+                }
+                known.set(two.ident, known.get(one.ident));
+                known.set(one.ident, Some(0));
+            }
+            Statement::TwoParam(TwoParam { one, two, ty }) => {
+                let value = match (known.get(one.ident), known.get(two.ident)) {
+                    (Some(from), Some(to)) => match ty {
+                        TwoParamType::Copy | TwoParamType::Swap | TwoParamType::Move => Some(from),
+                        TwoParamType::Add => Some(to.wrapping_add(from)),
+                        TwoParamType::Sub => Some(to.saturating_sub(from)),
+                        TwoParamType::Mul => Some(to.wrapping_mul(from)),
+                    },
+                    _ => None,
+                };
+                known.set(two.ident, value);
+            }
+            Statement::OneParam(OneParam { one, ty }) => match ty {
+                OneParamType::Incr => {
+                    if next
+                        == Some(Statement::OneParam(OneParam {
+                            one,
+                            ty: OneParamType::Decr,
+                        }))
+                    {
+                        warn(
+                            Lint::IncrDecr,
+                            *span,
+                            format!(
+                                "incr {} followed by decr {} has no effect",
+                                one.ident, one.ident
+                            ),
+                            Some(Suggestion::remove(
+                                "remove both statements".to_string(),
+                                span.start,
+                                statements[i + 1].1.end,
+                            )),
+                        );
+                    }
+                    known.set(one.ident, known.get(one.ident).map(|v| v.wrapping_add(1)));
+                }
+                OneParamType::Decr => {
+                    known.set(one.ident, known.get(one.ident).map(|v| v.saturating_sub(1)));
+                }
+                OneParamType::Clear => {
+                    known.set(one.ident, Some(0));
+                }
+                OneParamType::Print | OneParamType::Halt => {}
+                OneParamType::Input => {
+                    if !loops.is_empty() || used.contains(&one.ident) {
+                        warn(
+                            Lint::LateInput,
+                            *span,
+                            format!(
+                                "{} is read when the program starts, not here; move this to the top",
+                                one.ident
+                            ),
+                            None,
+                        );
+                    }
+                }
+            },
+            Statement::While(While { param, cmp, bound }) => {
+                if next == Some(Statement::End) {
+                    warn(
+                        Lint::EmptyLoop,
+                        *span,
+                        format!(
+                            "empty loop body: this never terminates unless {} is already {}",
+                            param.ident,
+                            exit_condition(cmp, bound)
+                        ),
+                        None,
+                    );
+                }
+                let mut never_runs = false;
+                let check = match bound {
+                    Bound::Number(n) => Some(n.value as u64),
+                    Bound::Variable(v) => known.get(v.ident),
+                };
+                if let (Some(value), Some(check)) = (known.get(param.ident), check) {
+                    if !cmp.holds(value, check) {
+                        never_runs = warn(
+                            Lint::ConstantFalseWhile,
+                            *span,
+                            format!(
+                                "{} is always {} here, so this loop body never runs",
+                                param.ident, value
+                            ),
+                            None,
+                        );
+                    }
+                }
+                // Anything can change over the iterations
+                known.forget();
+                loops.push((param.ident, cmp, bound, never_runs, *span, i));
+            }
+            Statement::End => {
+                // The loop only exits once its condition is met
+                known.forget();
+                if let Some((var, cmp, check, never_runs, start, first)) = loops.pop() {
+                    // Empty loops are empty_loop's, and a loop that never
+                    // runs can't run forever
+                    let body = &statements[first + 1..i];
+                    if !body.is_empty() && !never_runs && !can_exit(body, var, check, &proc_writes)
+                    {
+                        warn(
+                            Lint::InfiniteLoop,
+                            start,
+                            format!(
+                                "{} is never changed in this loop, so it can never terminate unless {} was already {}",
+                                var,
+                                var,
+                                exit_condition(cmp, check)
+                            ),
+                            None,
+                        );
+                    }
+                    // Only `not` a number says exactly what the value is
+                    // afterwards
+                    if let (Comparison::Ne, Bound::Number(n)) = (cmp, check) {
+                        known.set(var, Some(n.value as u64));
+                    }
+                    if never_runs {
+                        loop_removals.push(Suggestion::remove(
+                            "remove the loop".to_string(),
+                            start.start,
+                            span.end,
+                        ));
+                    }
+                }
+            }
+            Statement::Assert(a) => {
+                // Execution only continues past an assert that held
+                known.set(a.param.ident, Some(a.num.value as u64));
+            }
+            Statement::Set(s) => known.set(s.var.ident, Some(s.num.value as u64)),
+            Statement::Rand(r) => known.set(r.var.ident, None),
+            // The elements aren't followed
+            Statement::Indexed(Indexed {
+                op: ElementOp::Load(to),
+                ..
+            }) => known.set(to.ident, None),
+            Statement::Indexed(_) | Statement::Array(_) => {}
+            // Either branch may have run
+            Statement::Else | Statement::EndIf => known.forget(),
+            // A proc can be called with any values, and can change any of them
+            Statement::Proc(_) => {
+                in_proc = true;
+                known.forget();
+            }
+            Statement::EndProc => {
+                in_proc = false;
+                known.forget();
+            }
+            Statement::Call(_) => {
+                written.extend(&proc_writes);
+                known.forget();
+            }
+            Statement::If(_) | Statement::Breakpoint | Statement::Fluff | Statement::EOF => {}
+        }
+        used.extend(mentioned);
+    }
+
+    for removal in loop_removals {
+        if let Some(warning) = warnings
+            .iter_mut()
+            .find(|w| w.lint == Lint::ConstantFalseWhile && w.span.start == removal.start)
+        {
+            warning.suggestion = Some(removal);
+        }
+    }
+
+    warnings
+}
+
+// Whether anything in a loop's body can change the loop variable var or
+// the variable it is checked against, or stop the program, so that the loop
+// might end once it has started
+fn can_exit(
+    body: &[(Statement, Span)],
+    var: &str,
+    check: Bound,
+    proc_writes: &HashSet<&str>,
+) -> bool {
+    let changes =
+        |written: &str| written == var || matches!(check, Bound::Variable(v) if v.ident == written);
+    body.iter().any(|(statement, _)| match statement {
+        Statement::OneParam(OneParam {
+            ty: OneParamType::Halt,
+            ..
+        }) => true,
+        // Whatever proc it is, it may be one that changes it
+        Statement::Call(_) => proc_writes.iter().any(|w| changes(w)),
+        statement => reads_and_writes(statement).1.into_iter().any(changes),
+    })
+}
@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::error::BbvmError;
+use crate::lexer::Span;
+use crate::token::{is_keyword, Identifier, TokenImpl};
+
+lazy_static! {
+    static ref DEFINITION: Regex = Regex::new(r"^(?i:macro)\s+(\w*)\s*\(([^)]*)\)").unwrap();
+    static ref INVOCATION: Regex = Regex::new(r"^([a-zA-Z]\w*)\s*\(([^)]*)\)").unwrap();
+}
+
+// A piece of the expanded text: a range of the source copied as it is, or a
+// word put in place of the one at an offset of the source
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Piece {
+    Copy(usize, usize),
+    Word(String, usize),
+}
+
+struct Macro<'s> {
+    params: Vec<&'s str>,
+    // Where the body is in the source, between the definition and endmacro
+    body: (usize, usize),
+    // How many macros were defined before this one, which are the only
+    // ones it can use
+    order: usize,
+}
+
+struct Expander<'s> {
+    source: &'s str,
+    macros: HashMap<&'s str, Macro<'s>>,
+    // Every word of the source, so that new names don't clash with any
+    used: HashSet<&'s str>,
+    expansions: usize,
+}
+
+// The words of text as the lexer splits them, with where each starts.
+// Comments are left out.
+fn words(text: &str) -> Vec<(usize, &str)> {
+    let mut words = vec![];
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        if c.is_whitespace() || c == ';' {
+            i += c.len_utf8();
+        } else if c == '#' {
+            i += text[i..].find('\n').unwrap_or(text.len() - i);
+        } else {
+            let len = text[i..]
+                .find(|c: char| c.is_whitespace() || c == ';')
+                .unwrap_or(text.len() - i);
+            words.push((i, &text[i..i + len]));
+            i += len;
+        }
+    }
+    words
+}
+
+fn is_variable(word: &str) -> bool {
+    Identifier::identify(word) && !is_keyword(word)
+}
+
+// The names given between the brackets of a definition or invocation
+fn split_list(list: &str) -> Vec<&str> {
+    list.split(',')
+        .map(|a| a.trim())
+        .filter(|a| !a.is_empty())
+        .collect()
+}
+
+impl<'s> Expander<'s> {
+    fn span(&self, start: usize, end: usize) -> Span {
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        Span {
+            line: self.source[..start].matches('\n').count() + 1,
+            column: start - line_start + 1,
+            start,
+            end,
+        }
+    }
+
+    // The span from start to the end of its line
+    fn line_span(&self, start: usize) -> Span {
+        let end = self.source[start..]
+            .find('\n')
+            .map_or(self.source.len(), |i| start + i);
+        self.span(start, end)
+    }
+
+    // The invocation of a macro defined before the order'th one at start,
+    // its name, arguments and end
+    fn invocation(&self, start: usize, order: usize) -> Option<(&'s str, Vec<&'s str>, usize)> {
+        let source = self.source;
+        let captures = INVOCATION.captures(&source[start..])?;
+        let name = captures.get(1).unwrap().as_str();
+        match self.macros.get(name) {
+            Some(m) if m.order < order => Some((
+                name,
+                split_list(captures.get(2).unwrap().as_str()),
+                start + captures[0].len(),
+            )),
+            _ => None,
+        }
+    }
+
+    fn check_arguments(
+        &self,
+        name: &str,
+        args: &[&str],
+        start: usize,
+        end: usize,
+    ) -> Result<(), BbvmError> {
+        let expected = self.macros[name].params.len();
+        if args.len() != expected {
+            return Err(BbvmError::MacroArguments {
+                name: name.to_string(),
+                expected,
+                found: args.len(),
+                span: self.span(start, end),
+            });
+        }
+        match args.iter().find(|a| !is_variable(a)) {
+            Some(arg) => Err(BbvmError::ExpectedIdentifier {
+                keyword: name.to_string(),
+                found: format!("`{}`", arg),
+                span: self.span(start, end),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // Reads the definition at start, returning where its endmacro ends
+    fn define(&mut self, start: usize) -> Result<usize, BbvmError> {
+        let source = self.source;
+        let line = self.line_span(start);
+        let bad = || BbvmError::BadMacro {
+            found: format!("`{}`", source[line.start..line.end].trim()),
+            span: line,
+        };
+        let captures = DEFINITION.captures(&source[start..]).ok_or_else(bad)?;
+        let name = captures.get(1).unwrap().as_str();
+        let params = split_list(captures.get(2).unwrap().as_str());
+        let unique: HashSet<&&str> = params.iter().collect();
+        if !is_variable(name)
+            || !params.iter().all(|p| is_variable(p))
+            || unique.len() != params.len()
+        {
+            return Err(bad());
+        }
+        if self.macros.contains_key(name) {
+            return Err(BbvmError::DuplicateMacro {
+                name: name.to_string(),
+                span: self.line_span(start),
+            });
+        }
+
+        let body_start = start + captures[0].len();
+        let mut body_end = None;
+        for (i, word) in words(&source[body_start..]) {
+            match word.to_lowercase().as_str() {
+                "macro" => {
+                    let at = body_start + i;
+                    return Err(BbvmError::NestedMacro {
+                        span: self.span(at, at + word.len()),
+                    });
+                }
+                "endmacro" => {
+                    body_end = Some(body_start + i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let body_end = body_end.ok_or_else(|| BbvmError::UnclosedMacro {
+            span: self.line_span(start),
+        })?;
+
+        // Checked here so that expanding it later can't fail
+        let order = self.macros.len();
+        for (i, _) in words(&source[body_start..body_end]) {
+            let at = body_start + i;
+            if let Some((inner, args, end)) = self.invocation(at, order) {
+                self.check_arguments(inner, &args, at, end)?;
+            }
+        }
+        self.macros.insert(
+            name,
+            Macro {
+                params,
+                body: (body_start, body_end),
+                order,
+            },
+        );
+        Ok(body_end + "endmacro".len())
+    }
+
+    // The pieces for the body of name, with each parameter replaced by its
+    // argument and every other variable by one only this expansion uses
+    fn expand_body(&mut self, name: &str, args: &[String]) -> Vec<Piece> {
+        self.expansions += 1;
+        let expansion = self.expansions;
+        let source = self.source;
+        let (from, to) = self.macros[name].body;
+        let order = self.macros[name].order;
+        let mut names: HashMap<&str, String> = self.macros[name]
+            .params
+            .iter()
+            .copied()
+            .zip(args.iter().cloned())
+            .collect();
+        let mut pieces = vec![];
+        let mut copied = from;
+        let mut previous = String::new();
+        for (i, word) in words(&source[from..to]) {
+            let start = from + i;
+            if start < copied {
+                continue;
+            }
+            if let Some((inner, inner_args, end)) = self.invocation(start, order) {
+                let inner_args: Vec<String> = inner_args
+                    .into_iter()
+                    .map(|a| self.rename(&mut names, a, expansion))
+                    .collect();
+                pieces.push(Piece::Copy(copied, start));
+                pieces.extend(self.expand_body(inner, &inner_args));
+                copied = end;
+            } else if is_variable(word) && !matches!(previous.as_str(), "call" | "proc") {
+                // Proc names are left alone, procs aren't per expansion
+                let renamed = self.rename(&mut names, word, expansion);
+                pieces.push(Piece::Copy(copied, start));
+                pieces.push(Piece::Word(renamed, start));
+                copied = start + word.len();
+            }
+            previous = word.to_lowercase();
+        }
+        pieces.push(Piece::Copy(copied, to));
+        pieces
+    }
+
+    // What a variable of the body is called in the expansion'th expansion
+    fn rename(
+        &self,
+        names: &mut HashMap<&'s str, String>,
+        word: &'s str,
+        expansion: usize,
+    ) -> String {
+        names
+            .entry(word)
+            .or_insert_with(|| {
+                let mut name = format!("{}_{}", word, expansion);
+                while self.used.contains(name.as_str()) {
+                    name.push('_');
+                }
+                name
+            })
+            .clone()
+    }
+}
+
+// Expands every `macro NAME(A, B); ... endmacro;` in source: the definitions
+// are taken out, and each `NAME(X, Y);` after one is replaced by its body
+// with A and B replaced by X and Y. Any other variable in the body gets a
+// new name for each expansion, so macros can't change the caller's
+// variables by accident.
+pub fn expand(source: &str) -> Result<Vec<Piece>, BbvmError> {
+    let mut expander = Expander {
+        source,
+        macros: HashMap::new(),
+        used: words(source).into_iter().map(|(_, word)| word).collect(),
+        expansions: 0,
+    };
+    let mut pieces = vec![];
+    let mut copied = 0;
+    for (start, word) in words(source) {
+        if start < copied {
+            continue;
+        }
+        match word.to_lowercase().as_str() {
+            "macro" => {
+                pieces.push(Piece::Copy(copied, start));
+                copied = expander.define(start)?;
+            }
+            "endmacro" => {
+                return Err(BbvmError::UnmatchedEndMacro {
+                    span: expander.span(start, start + word.len()),
+                })
+            }
+            _ => {
+                if let Some((name, args, end)) = expander.invocation(start, usize::MAX) {
+                    expander.check_arguments(name, &args, start, end)?;
+                    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                    pieces.push(Piece::Copy(copied, start));
+                    pieces.extend(expander.expand_body(name, &args));
+                    copied = end;
+                }
+            }
+        }
+    }
+    pieces.push(Piece::Copy(copied, source.len()));
+    Ok(pieces)
+}
@@ -1,124 +1,2231 @@
-#![feature(iter_zip)]
+#![allow(clippy::unused_unit)]
 
-use crate::convert::Converter;
-use crate::lexer::Lexer;
-use crate::token::{OneParamType, Statement, StatementImpl};
-use clap::{crate_authors, crate_description, crate_name, crate_version, App};
-use inkwell::context::Context;
-use std::fs;
+use bbvm::bignum::Natural;
+use bbvm::bytecode::Bytecode;
+use bbvm::cache::Cache;
+use bbvm::convert::{run_executable, Converter, Temps};
+use bbvm::encoding::Encoding;
+use bbvm::ext::Extension;
+use bbvm::include::Source;
+use bbvm::link::LinkOptions;
+use bbvm::lint::{Level, Lint, LintConfig, Warning};
+use bbvm::output::OutputFormat;
+use bbvm::runner::Stopped;
+use bbvm::stats::Stats;
+use bbvm::{
+    analyze, backend_c, bench, cfg, check, collect_inputs, collect_variables, dataflow, debug,
+    decompile, diff, difftest, explain, ext, fix, formatter, highlight, history, input, lex,
+    lex_all, lint, lower, parser, passes, pgo, prune, repl, runner, simplify, stream, suite, token,
+    BbvmError, Interpreter, Lexer, Span, Statement,
+};
+use clap::{
+    crate_authors, crate_description, crate_name, crate_version, App, AppSettings, Arg, ArgMatches,
+};
+use inkwell::{context::Context, OptimizationLevel};
+use std::{
+    fs,
+    iter::zip,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-mod convert;
-mod lexer;
-mod token;
+mod crash;
 
-fn main() -> () {
-    let starttime = chrono::Utc::now();
-    let matches = App::new(crate_name!())
-        .version(crate_version!())
-        .author(crate_authors!())
-        .about(crate_description!())
-        .arg("-c     'Tries to compile the code to native'")
-        .arg("<INPUT>'Sets the input file to use'")
-        .get_matches();
+static QUIET: AtomicBool = AtomicBool::new(false);
 
-    let compile = matches.is_present("c");
-    let filename = matches.value_of("INPUT").unwrap();
+// println! for progress and timing messages, which --quiet leaves out
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Prints the error pointing into the source and stops
+fn report(error: BbvmError, source: &str, filename: &str) -> ! {
+    eprintln!("{}", error.render(source, filename));
+    stop(&error)
+}
+
+// report, pointing into whichever file of the source the error is in
+fn report_in(error: BbvmError, source: &Source) -> ! {
+    eprintln!("{}", source.render_error(&error));
+    stop(&error)
+}
+
+fn stop(error: &BbvmError) -> ! {
+    eprintln!(
+        "For more information about this error, try `bbvm --explain {}`.",
+        error.code()
+    );
+    std::process::exit(1);
+}
+
+// Turns the program into bytecode and decompiles that, then compiles the
+// result again and checks both give the same statements and the same IR.
+// Any difference is a bug in the lexer, the bytecode, the decompiler or the
+// backend.
+fn self_check(filename: &str) -> () {
+    let file = fs::read_to_string(filename).expect("ERROR: Failed to read the file");
+    crash::set_source(&file);
+    let mut l = Lexer::new(&file);
+    let (tokens, spans) = lex(&mut l).unwrap_or_else(|e| report(e, &file, filename));
+
+    crash::set_phase("decompiling");
+    let bytecode = Bytecode::decode(&Bytecode::from_statements(&tokens).encode())
+        .unwrap_or_else(|e| panic!("Bytecode for {} doesn't decode: {}", filename, e));
+    let decompiled = decompile::decompile(&bytecode.to_statements());
+    let (tokens2, spans2) = lex(&mut Lexer::new(&decompiled))
+        .unwrap_or_else(|e| panic!("The decompiled source doesn't lex: {}:\n{}", e, decompiled));
+
+    let without_fluff = |tokens: &[Statement<'_>]| -> Vec<String> {
+        tokens
+            .iter()
+            .filter(|t| **t != Statement::Fluff)
+            .map(|t| format!("{:?}", t))
+            .collect()
+    };
+    if without_fluff(&tokens) != without_fluff(&tokens2) {
+        panic!(
+            "Decompiling {} changed its statements, the decompiled source was:\n{}",
+            filename, decompiled
+        );
+    }
+
+    crash::set_phase("generating LLVM IR");
+    let context = Context::create();
+    let mut ir = vec![];
+    for (tokens, spans, source) in [(&tokens, &spans, &file), (&tokens2, &spans2, &decompiled)] {
+        let inputs = collect_inputs(tokens);
+        let mut converter = Converter::new(collect_variables(tokens), &inputs, &context);
+        lower(&mut converter, tokens, spans, source, false)
+            .unwrap_or_else(|e| report(e, &file, filename));
+        ir.push(converter.ir_string());
+    }
+    if ir[0] != ir[1] {
+        panic!(
+            "Decompiling {} changed the generated IR, the decompiled source was:\n{}",
+            filename, decompiled
+        );
+    }
+    println!("{} round trips through the decompiler unchanged", filename);
+}
+
+// Renames a variable throughout the file in place
+fn rename(filename: &str, old: &str, new: &str) -> () {
+    let file = fs::read_to_string(filename).expect("ERROR: Failed to read the file");
+    let mut l = Lexer::new(&file);
+    let (tokens, spans) = lex(&mut l).unwrap_or_else(|e| report(e, &file, filename));
+    let variables = collect_variables(&tokens);
+
+    let problem = if !variables.contains(&old) {
+        Some(format!("{} is not a variable in {}", old, filename))
+    } else if token::is_keyword(new) {
+        Some(format!(
+            "{} is a keyword, so it can't be used as a variable name",
+            new
+        ))
+    } else if !new.starts_with(|c: char| c.is_ascii_alphabetic())
+        || !new.chars().all(|c| c.is_alphanumeric() || c == '_')
+    {
+        Some(format!("{} is not a valid variable name", new))
+    } else if variables.contains(&new) {
+        Some(format!("{} is already a variable in {}", new, filename))
+    } else {
+        None
+    };
+    if let Some(problem) = problem {
+        eprintln!("ERROR: {}", problem);
+        std::process::exit(1);
+    }
+
+    let (renamed, count) = fix::apply(&file, &fix::rename(&file, &spans, old, new));
+
+    fs::write(filename, renamed).expect("ERROR: Failed to write the file");
+    println!(
+        "Renamed {} uses of {} to {} in {}",
+        count, old, new, filename
+    );
+}
+
+// Parses the program, with its includes and macros expanded, and prints the
+// tree as source again, after pruning and simplifying it if asked to
+fn roundtrip(filename: &str, extensions: &[Extension], prune_unused: bool, simplify: bool) -> () {
+    let source = Source::expand(Path::new(filename)).unwrap_or_else(|(error, rendered)| {
+        eprintln!("{}", rendered);
+        stop(&error)
+    });
+    let (tokens, spans) =
+        lex(&mut Lexer::new(&source.text)).unwrap_or_else(|e| report_in(e, &source));
+    ext::check(&tokens, &spans, extensions).unwrap_or_else(|e| report_in(e, &source));
+    let mut program = parser::parse(&tokens, &spans).unwrap_or_else(|e| report_in(e, &source));
+    if prune_unused {
+        let live = prune::live(&program.body);
+        prune::prune(&mut program.body, &live);
+    }
+    if simplify {
+        simplify::simplify(&mut program.body);
+    }
+    let printed = program.to_source();
+
+    // Printing anything that doesn't parse back the same is a bug here
+    let (tokens, spans) = lex(&mut Lexer::new(&printed))
+        .unwrap_or_else(|e| panic!("The printed program doesn't lex ({}):\n{}", e, printed));
+    let reparsed = parser::parse(&tokens, &spans)
+        .unwrap_or_else(|e| panic!("The printed program doesn't parse ({}):\n{}", e, printed));
+    if reparsed.to_source() != printed {
+        panic!("Parsing the printed program changed it:\n{}", printed);
+    }
+    print!("{}", printed);
+}
+
+// Prints the loop nesting, each loop's variables and the complexity
+// estimate of each program, for `bbvm analyze`
+fn analyze_files(matches: &ArgMatches, extensions: &[Extension]) -> () {
+    for filename in matches.values_of("INPUT").unwrap() {
+        let source = Source::expand(Path::new(filename)).unwrap_or_else(|(error, rendered)| {
+            eprintln!("{}", rendered);
+            stop(&error)
+        });
+        let (tokens, spans) =
+            lex(&mut Lexer::new(&source.text)).unwrap_or_else(|e| report_in(e, &source));
+        ext::check(&tokens, &spans, extensions).unwrap_or_else(|e| report_in(e, &source));
+        let program = parser::parse(&tokens, &spans).unwrap_or_else(|e| report_in(e, &source));
+        let analysis = analyze::analyze(&program);
+        if matches.is_present("json") {
+            print!("{}", analysis.render_json(filename));
+        } else {
+            print!("{}", analysis.render(filename));
+        }
+    }
+}
+
+// Prints what each loop of each file reads, writes and leaves unchanged,
+// and which inputs affect which outputs
+fn explain_files(matches: &ArgMatches, extensions: &[Extension]) -> () {
+    for filename in matches.values_of("INPUT").unwrap() {
+        let source = Source::expand(Path::new(filename)).unwrap_or_else(|(error, rendered)| {
+            eprintln!("{}", rendered);
+            stop(&error)
+        });
+        let (tokens, spans) =
+            lex(&mut Lexer::new(&source.text)).unwrap_or_else(|e| report_in(e, &source));
+        ext::check(&tokens, &spans, extensions).unwrap_or_else(|e| report_in(e, &source));
+        let program = parser::parse(&tokens, &spans).unwrap_or_else(|e| report_in(e, &source));
+        let variables = collect_variables(&tokens);
+        let flow = dataflow::flow(&program, &collect_inputs(&tokens), &variables);
+        print!(
+            "{}",
+            dataflow::explain(filename, &analyze::analyze(&program), &flow, &variables)
+        );
+    }
+}
+
+fn milliseconds(duration: chrono::Duration) -> String {
+    format!(
+        "{:.3} ms",
+        duration.num_nanoseconds().unwrap_or_default() as f64 / 1e6
+    )
+}
+
+// Runs one program many times with each of the --backends, for `bbvm
+// bench prog.bb`, then prints how the run times spread in a table and
+// writes every run's time to the --csv file
+fn bench_program(
+    matches: &ArgMatches,
+    program: &Path,
+    level: OptimizationLevel,
+    extensions: &[Extension],
+) -> () {
+    let number = |name: &str, default: usize| match matches.value_of(name) {
+        Some(n) => n.parse().unwrap_or_else(|_| {
+            eprintln!("ERROR: --{} must be a whole number, not {:?}", name, n);
+            std::process::exit(1);
+        }),
+        None => default,
+    };
+    let (runs, warmup) = (number("runs", 10).max(1), number("warmup", 1));
+    let backends: Vec<bench::Backend> = matches
+        .value_of("backends")
+        .unwrap_or("jit")
+        .split(',')
+        .map(|name| {
+            bench::Backend::parse(name.trim()).unwrap_or_else(|| {
+                eprintln!(
+                    "ERROR: --backends takes jit, aot and interpreter, not {:?}",
+                    name
+                );
+                std::process::exit(1);
+            })
+        })
+        .collect();
+
+    let mut rows = vec![[
+        "backend".to_string(),
+        "min".to_string(),
+        "median".to_string(),
+        "mean".to_string(),
+        "stddev".to_string(),
+    ]];
+    let mut csv = "backend,run,milliseconds\n".to_string();
+    let mut failed = false;
+    for backend in backends {
+        let durations = match bench::repeat(program, backend, level, extensions, warmup, runs) {
+            Ok(durations) => durations,
+            Err(e) => {
+                eprintln!("{}", e);
+                failed = true;
+                continue;
+            }
+        };
+        for (run, &duration) in durations.iter().enumerate() {
+            csv.push_str(&format!(
+                "{},{},{:.6}\n",
+                backend.name(),
+                run + 1,
+                bench::milliseconds(duration)
+            ));
+        }
+        let summary = bench::summarise(&durations);
+        let time = |ms: f64| format!("{:.3} ms", ms);
+        rows.push([
+            backend.name().to_string(),
+            time(summary.min),
+            time(summary.median),
+            time(summary.mean),
+            time(summary.stddev),
+        ]);
+    }
+
+    println!(
+        "{}: {} runs after {} to warm up",
+        program.display(),
+        runs,
+        warmup
+    );
+    let width = rows.iter().map(|row| row[0].len()).max().unwrap_or(0);
+    for row in &rows {
+        println!(
+            "{:width$}  {:>12}  {:>12}  {:>12}  {:>12}",
+            row[0], row[1], row[2], row[3], row[4]
+        );
+    }
+    if let Some(path) = matches.value_of("csv") {
+        fs::write(path, csv).unwrap_or_else(|e| {
+            eprintln!("ERROR: Failed to write {}: {}", path, e);
+            std::process::exit(1);
+        });
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+// Compiles and runs every program in the directory on a few threads at
+// once, for `bbvm bench`, then prints how long each took in a table. A
+// single program is run many times instead by bench_program.
+fn bench_dir(matches: &ArgMatches, extensions: &[Extension]) -> () {
+    let dir = Path::new(matches.value_of("PATH").unwrap());
+    let level = opt_level(matches);
+    if dir.is_file() {
+        return bench_program(matches, dir, level, extensions);
+    }
+    let jobs = matches.value_of("jobs").map_or(4, |jobs| {
+        jobs.parse().unwrap_or_else(|_| {
+            eprintln!("ERROR: --jobs must be a whole number, not {:?}", jobs);
+            std::process::exit(1);
+        })
+    });
+    let programs = suite::programs(dir).unwrap_or_else(|e| {
+        eprintln!("ERROR: Failed to read {}: {}", dir.display(), e);
+        std::process::exit(1);
+    });
+    let timings = bench::bench(programs.clone(), level, extensions, jobs);
+
+    let mut rows = vec![(
+        "program".to_string(),
+        "compile".to_string(),
+        "run".to_string(),
+    )];
+    let mut failed = vec![];
+    for (program, timing) in zip(&programs, timings) {
+        let name = program
+            .file_name()
+            .map_or(program.display().to_string(), |n| {
+                n.to_string_lossy().into_owned()
+            });
+        match timing {
+            Some(Ok(timing)) => {
+                rows.push((name, milliseconds(timing.compile), milliseconds(timing.run)))
+            }
+            Some(Err(e)) => {
+                rows.push((name, "error".to_string(), "-".to_string()));
+                failed.push(e);
+            }
+            None => rows.push((name, "crashed".to_string(), "-".to_string())),
+        }
+    }
+    let widths = rows.iter().fold((0, 0), |(a, b), (name, compile, _)| {
+        (a.max(name.len()), b.max(compile.len()))
+    });
+    for (name, compile, run) in &rows {
+        println!(
+            "{:a$}  {:>b$}  {:>12}",
+            name,
+            compile,
+            run,
+            a = widths.0,
+            b = widths.1
+        );
+    }
+    for e in &failed {
+        eprintln!("{}", e);
+    }
+}
+
+// Runs every program in the directory that has an .expected file and
+// compares its final values, for `bbvm test`. Exits with 1 if any failed.
+fn test_dir(matches: &ArgMatches, extensions: &[Extension]) -> () {
+    let dir = Path::new(matches.value_of("DIR").unwrap());
+    let runner = if matches.is_present("jit") {
+        suite::Runner::Jit(opt_level(matches))
+    } else {
+        suite::Runner::Interpreter
+    };
+    let fuel = matches.value_of("fuel").map(|fuel| {
+        fuel.parse().unwrap_or_else(|_| {
+            eprintln!("ERROR: --fuel must be a whole number, not {:?}", fuel);
+            std::process::exit(1);
+        })
+    });
+    let colour = std::env::var_os("NO_COLOR").is_none();
+
+    let (programs, untested) = suite::cases(dir).unwrap_or_else(|e| {
+        eprintln!("ERROR: Failed to read {}: {}", dir.display(), e);
+        std::process::exit(1);
+    });
+    for program in &untested {
+        println!("SKIP {} (no .expected file)", program.display());
+    }
+    let mut failed = 0;
+    for program in &programs {
+        match suite::run(program, runner, extensions, fuel, colour) {
+            suite::Outcome::Passed => println!("PASS {}", program.display()),
+            suite::Outcome::Failed(diff) => {
+                failed += 1;
+                println!("FAIL {}", program.display());
+                print!("{}", diff);
+            }
+            suite::Outcome::Error(error) => {
+                failed += 1;
+                println!("ERROR {}", program.display());
+                println!("{}", error);
+            }
+        }
+    }
+    println!(
+        "{} passed, {} failed, {} without an .expected file",
+        programs.len() - failed,
+        failed,
+        untested.len()
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
 
-    let file = fs::read_to_string(filename).expect("Failed to read the file");
-    let l = Lexer::new(&file);
+// Runs random programs under both backends, reporting the ones they
+// disagree on and exiting with 1 if there are any
+fn difftest_programs(matches: &ArgMatches, extensions: &[Extension]) -> () {
+    let number = |name: &str, default: u64| match matches.value_of(name) {
+        Some(n) => n.parse().unwrap_or_else(|_| {
+            eprintln!("ERROR: --{} must be a whole number, not {:?}", name, n);
+            std::process::exit(1);
+        }),
+        None => default,
+    };
+    let count = number("count", 100);
+    let size = number("size", 8) as usize;
+    let seed = number("seed", chrono::Utc::now().timestamp() as u64);
+    let levels = match matches.value_of("opt-level") {
+        Some(level) => vec![(format!("-O{}", level), opt_level(matches))],
+        None => vec![
+            ("-O0".to_string(), OptimizationLevel::None),
+            ("-O3".to_string(), OptimizationLevel::Aggressive),
+        ],
+    };
+    let save = matches.value_of("save").map(Path::new);
+    if let Some(dir) = save {
+        fs::create_dir_all(dir).unwrap_or_else(|e| {
+            eprintln!("ERROR: Failed to create {}: {}", dir.display(), e);
+            std::process::exit(1);
+        });
+    }
+    let arith = extensions.contains(&Extension::Arith);
 
-    println!("Interpreting file...");
-    let mut tokens: Vec<Statement> = vec![];
-    loop {
-        let statement = l.get_token().try_into().unwrap();
-        if statement == Statement::EOF {
-            tokens.push(statement);
+    println!("Trying {} programs from seed {}", count, seed);
+    let mut diverged = 0;
+    for seed in (0..count).map(|i| seed.wrapping_add(i)) {
+        let case = difftest::generate(seed, size, arith);
+        for (flag, level) in &levels {
+            let divergence = match difftest::compare(&case, *level) {
+                Ok(Some(divergence)) => divergence,
+                Ok(None) => continue,
+                // The programs are made to be valid, so this is a bug too
+                Err(e) => {
+                    diverged += 1;
+                    println!("ERROR seed {} {}", seed, flag);
+                    println!(
+                        "{}",
+                        e.render(&case.source, &format!("difftest-{}.bb", seed))
+                    );
+                    break;
+                }
+            };
+            diverged += 1;
+            println!("DIVERGED seed {} {}", seed, flag);
+            print!("{}", case.source);
+            if !case.inputs.is_empty() {
+                print!("with inputs:\n{}", case.input_file());
+            }
+            print!("{}", divergence.render());
+            if let Some(dir) = save {
+                let program = dir.join(format!("difftest-{}.bb", seed));
+                fs::write(&program, &case.source)
+                    .and_then(|_| {
+                        fs::write(
+                            program.with_extension("expected"),
+                            divergence.expected(&case),
+                        )
+                    })
+                    .unwrap_or_else(|e| {
+                        eprintln!("ERROR: Failed to save {}: {}", program.display(), e);
+                        std::process::exit(1);
+                    });
+                println!("Saved as {}", program.display());
+            }
             break;
         }
-        tokens.push(statement);
     }
+    println!("{} of {} programs diverged", diverged, count);
+    if diverged > 0 {
+        std::process::exit(1);
+    }
+}
 
-    let mut variables: Vec<&str> = tokens
+// Formats the file in place, or with check only says whether it would
+// change, exiting with 1 if so
+fn format_file(filename: &str, check: bool) -> () {
+    let file = fs::read_to_string(filename).expect("ERROR: Failed to read the file");
+    let formatted = formatter::format(&file).unwrap_or_else(|e| report(e, &file, filename));
+    if formatted == file {
+        println!("{} is already formatted", filename);
+    } else if check {
+        eprintln!("{} is not formatted, run `bbvm fmt {}`", filename, filename);
+        std::process::exit(1);
+    } else {
+        fs::write(filename, formatted).expect("ERROR: Failed to write the file");
+        println!("Formatted {}", filename);
+    }
+}
+
+// Applies the lint suggestions to the file in place
+fn fix(filename: &str, lints: &LintConfig) -> () {
+    let file = fs::read_to_string(filename).expect("ERROR: Failed to read the file");
+    let mut l = Lexer::new(&file);
+    let (tokens, spans) = lex(&mut l).unwrap_or_else(|e| report(e, &file, filename));
+
+    let suggestions: Vec<fix::Suggestion> = lint::lint(&tokens, &spans, lints, &l.allowed())
+        .into_iter()
+        .filter_map(|w| w.suggestion)
+        .collect();
+    let (fixed, applied) = fix::apply(&file, &suggestions);
+
+    fs::write(filename, fixed).expect("ERROR: Failed to write the file");
+    println!("Applied {} fixes to {}", applied, filename);
+}
+
+// Prints the lints found in the source read from filename, stopping if any
+// of them were denied
+fn print_warnings(warnings: &[Warning], source: &Source, filename: &str) -> () {
+    for warning in warnings {
+        // Line numbers of the file the warning is in, not the spliced source
+        let location = source.locate(warning.span);
+        let located = Warning {
+            span: location.span,
+            ..warning.clone()
+        };
+        eprintln!("{}", located);
+        if location.name != filename {
+            eprintln!("         note: in {}", location.name);
+        }
+    }
+    if let Some(warning) = warnings.first() {
+        eprintln!(
+            "For more information about a diagnostic, try `bbvm --explain {}`.",
+            warning.lint.code()
+        );
+    }
+    if warnings.iter().any(|w| w.level == Level::Deny) {
+        eprintln!("ERROR: Denied lints were found, stopping");
+        std::process::exit(1);
+    }
+}
+
+// Reports every problem check::check finds and every lint, without running
+// the program or making an LLVM context, for `bbvm check`
+fn check_file(
+    filename: &str,
+    lints: &LintConfig,
+    extensions: &[Extension],
+    matches: &ArgMatches,
+) -> () {
+    let source = Source::expand(Path::new(filename)).unwrap_or_else(|(error, rendered)| {
+        eprintln!("{}", rendered);
+        stop(&error)
+    });
+    let mut l = Lexer::new(&source.text);
+    if matches.is_present("case-sensitive") {
+        l.set_case_sensitive();
+    }
+    let (tokens, spans, mut errors) = lex_all(&mut l);
+    // What is left after a statement is dropped would only show more
+    // errors that aren't there, so it is only checked further when it lexed
+    if errors.is_empty() {
+        ext::check(&tokens, &spans, extensions).unwrap_or_else(|e| report_in(e, &source));
+        errors = check::check(&source.text, &tokens, &spans);
+        if matches.is_present("strict") {
+            errors.extend(check::strict(&source.text, &tokens, &spans));
+            errors.sort_by_key(|e| e.span().start);
+        }
+    }
+    for error in &errors {
+        eprintln!("{}", source.render_error(error));
+    }
+    let warnings = lint::lint(&tokens, &spans, lints, &l.allowed());
+    if let Some(error) = errors.first() {
+        // Lints are shown too, but can't stop it any more than it is
+        print_warnings(
+            &warnings
+                .into_iter()
+                .filter(|w| w.level != Level::Deny)
+                .collect::<Vec<_>>(),
+            &source,
+            filename,
+        );
+        eprintln!("ERROR: {} problems found in {}", errors.len(), filename);
+        stop(error);
+    }
+    print_warnings(&warnings, &source, filename);
+    if warnings.is_empty() {
+        println!("{}: no problems found", filename);
+    }
+}
+
+extern "C" {
+    fn fflush(stream: *mut std::ffi::c_void) -> i32;
+}
+
+// --timeout for bitcode, which was compiled without the checks the JIT's
+// programs stop themselves with, so all of bbvm is ended once it has passed
+fn exit_after(timeout: std::time::Duration) -> () {
+    std::thread::spawn(move || {
+        std::thread::sleep(timeout);
+        // Keep what the program printed before it was stopped
+        std::io::Write::flush(&mut std::io::stdout()).ok();
+        unsafe {
+            fflush(std::ptr::null_mut());
+        }
+        eprintln!(
+            "ERROR: the program was still running after {} seconds, stopping it (--timeout)",
+            timeout.as_secs_f64()
+        );
+        std::process::exit(1);
+    });
+}
+
+// --args (or --input-file) as the arguments of a compiled program, which
+// reads its inputs from them, or from stdin when there are none
+fn executable_args(inputs: &[&str], args: Option<&str>) -> Vec<String> {
+    args.map_or_else(Vec::new, |args| {
+        input::parse_args::<u64>(inputs, args)
+            .unwrap_or_else(|e| {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            })
+            .iter()
+            .map(u64::to_string)
+            .collect()
+    })
+}
+
+// Where --emit writes each kind of output without -o
+fn default_output(kind: &str) -> &'static str {
+    match kind {
+        "llvm-ir" => "out.ll",
+        "llvm-bc" => "out.bc",
+        "asm" => "out.s",
+        "obj" => "out.o",
+        _ => "bbvm.out",
+    }
+}
+
+// Where -c and --emit exe write the executable without -o: the input's
+// name with .out (or .exe for Windows), so compiling different files
+// doesn't write over the same one
+fn executable_output(filename: &str, windows: bool) -> PathBuf {
+    let stem = Path::new(filename)
+        .file_stem()
+        .map_or("bbvm".into(), |s| s.to_string_lossy());
+    let extension = if windows { "exe" } else { "out" };
+    PathBuf::from(format!("{}.{}", stem, extension))
+}
+
+// Everything that changes the executable -c or --emit exe writes, besides
+// the source, for the cache's key
+fn cache_options(matches: &ArgMatches, filename: &str) -> Vec<(&'static str, String)> {
+    let mut options: Vec<(&str, String)> = [
+        "opt-level",
+        "int-width",
+        "detect-overflow",
+        "fuel",
+        "target",
+        "cpu",
+        "features",
+        "prune-unused",
+        "json",
+        "output-format",
+        "ext",
+        "trace",
+        "static",
+        "pie",
+        "no-pie",
+        "linker",
+        "link-args",
+        "profile-generate",
+        "passes",
+    ]
+    .into_iter()
+    .map(|name| {
+        let values: Option<Vec<&str>> = matches.values_of(name).map(Iterator::collect);
+        (
+            name,
+            format!("{} {:?}", matches.occurrences_of(name), values),
+        )
+    })
+    .collect();
+    options.push(("cc", std::env::var("CC").unwrap_or_default()));
+    // The weights come from the profile, not its name
+    if let Some(path) = matches.value_of("profile-use") {
+        options.push(("profile-use", fs::read_to_string(path).unwrap_or_default()));
+    }
+    // The debug info says where the source is
+    if matches.is_present("g") || matches.is_present("annotate-ir") {
+        options.push(("g", filename.to_string()));
+    }
+    options
+}
+
+// Where the object file an executable is linked from goes, from
+// --temp-dir and --keep-temps
+fn temps(matches: &ArgMatches) -> Temps {
+    let mut temps = Temps::default();
+    if let Some(dir) = matches.value_of("temp-dir") {
+        temps.dir = PathBuf::from(dir);
+    }
+    temps.keep = matches.is_present("keep-temps");
+    temps
+}
+
+// How much to optimise, from -O
+fn opt_level(matches: &ArgMatches) -> OptimizationLevel {
+    match matches.value_of("opt-level") {
+        Some("0") => OptimizationLevel::None,
+        Some("1") => OptimizationLevel::Less,
+        Some("2") => OptimizationLevel::Default,
+        _ => OptimizationLevel::Aggressive,
+    }
+}
+
+// The language extensions --ext enables
+fn extensions(matches: &ArgMatches) -> Vec<Extension> {
+    matches
+        .values_of("ext")
+        .into_iter()
+        .flatten()
+        .map(|e| e.parse().unwrap())
+        .collect()
+}
+
+// How the executable is linked, from --static, --pie, --no-pie, --linker
+// and what comes after --
+fn link_options(matches: &ArgMatches) -> LinkOptions {
+    LinkOptions {
+        linker: matches.value_of("linker").map(PathBuf::from),
+        static_: matches.is_present("static"),
+        pie: if matches.is_present("pie") {
+            Some(true)
+        } else if matches.is_present("no-pie") {
+            Some(false)
+        } else {
+            None
+        },
+        args: matches
+            .values_of("link-args")
+            .map_or(vec![], |args| args.map(str::to_string).collect()),
+    }
+}
+
+fn write_output(
+    converter: &Converter,
+    kind: &str,
+    output: &Path,
+    temps: &Temps,
+    options: &LinkOptions,
+) -> Result<(), String> {
+    match kind {
+        "llvm-ir" => converter.write_ir(output),
+        "llvm-bc" => converter.write_bitcode(output),
+        "asm" => converter.write_assembly(output),
+        "obj" => converter.write_object(output),
+        _ => converter.write_executable(output, temps, options),
+    }
+}
+
+// What a file's functions are named after when it is compiled with others:
+// its name without .bb, with anything that can't go in a symbol made a _
+fn symbol_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map_or("main".into(), |s| s.to_string_lossy());
+    let name: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+// Compiles every file given to `bbvm compile`, each into its own output in
+// --out-dir, or else all into one module where each is a bbvm_NAME_main
+// function and main runs the one chosen by --entry
+fn compile_files(matches: &ArgMatches, lints: &LintConfig, extensions: &[Extension]) -> () {
+    let paths: Vec<&Path> = matches.values_of("INPUT").unwrap().map(Path::new).collect();
+    let kind = matches.value_of("emit").unwrap();
+    let out_dir = matches.value_of("out-dir").map(Path::new);
+    if out_dir.is_some() && matches.is_present("jit") {
+        eprintln!("ERROR: --jit runs one of the files, so it can't be used with --out-dir");
+        std::process::exit(1);
+    }
+    let opt_level = opt_level(matches);
+    let names: Vec<String> = paths.iter().map(|p| symbol_name(p)).collect();
+    for (i, name) in names.iter().enumerate() {
+        if let Some(j) = names[..i].iter().position(|n| n == name) {
+            eprintln!(
+                "ERROR: {} and {} would both be called {}, rename one of them",
+                paths[j].display(),
+                paths[i].display(),
+                name
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let sources: Vec<Source> = paths
         .iter()
-        .flat_map(|t| {
-            use Statement::*;
-            match t {
-                EOF | Fluff | End => {
-                    vec![]
-                }
-                While(v) => v.get_variables(),
-                OneParam(v) => v.get_variables(),
-                TwoParam(v) => v.get_variables(),
+        .map(|path| {
+            Source::expand(path).unwrap_or_else(|(error, rendered)| {
+                eprintln!("{}", rendered);
+                stop(&error)
+            })
+        })
+        .collect();
+    let programs: Vec<_> = zip(&sources, &paths)
+        .map(|(source, path)| {
+            let mut l = Lexer::new(&source.text);
+            l.set_int_width(64);
+            if matches.is_present("case-sensitive") {
+                l.set_case_sensitive();
             }
+            let (tokens, spans) = lex(&mut l).unwrap_or_else(|e| report_in(e, source));
+            ext::check(&tokens, &spans, extensions).unwrap_or_else(|e| report_in(e, source));
+            let warnings = lint::lint(&tokens, &spans, lints, &l.allowed());
+            print_warnings(&warnings, source, &path.to_string_lossy());
+            (tokens, spans, l.unrolls())
         })
         .collect();
 
-    variables.sort();
-    variables.dedup();
+    crash::set_phase("generating LLVM IR");
+    let context = Context::create();
+    let mut converters: Vec<Converter> = zip(zip(&sources, &programs), &names)
+        .map(|((source, (tokens, spans, unrolls)), name)| {
+            let (variables, inputs) = (collect_variables(tokens), collect_inputs(tokens));
+            let mut converter = match out_dir {
+                Some(_) => Converter::new(variables, &inputs, &context),
+                None => Converter::for_file(variables, &inputs, &context, 64, name),
+            };
+            converter.set_optimization_level(opt_level);
+            converter.set_unrolls(unrolls);
+            if matches.is_present("detect-overflow") {
+                converter.enable_overflow_detection();
+            }
+            lower(&mut converter, tokens, spans, &source.text, false)
+                .unwrap_or_else(|e| report_in(e, source));
+            converter
+        })
+        .collect();
 
-    let mut inputs: Vec<&str> = tokens
+    crash::set_phase("writing the output");
+    let written = if let Some(dir) = out_dir {
+        fs::create_dir_all(dir).expect("ERROR: Failed to create the output directory");
+        zip(&mut converters, &names).try_for_each(|(converter, name)| {
+            converter.optimise();
+            let output = match Path::new(default_output(kind)).extension() {
+                Some(extension) => dir.join(name).with_extension(extension),
+                None => dir.join(name),
+            };
+            write_output(
+                converter,
+                kind,
+                &output,
+                &temps(matches),
+                &link_options(matches),
+            )?;
+            status!("Wrote {}", output.display());
+            Ok(())
+        })
+    } else {
+        let entry = match matches.value_of("entry") {
+            Some(entry) => entry,
+            None if names.len() == 1 => names[0].as_str(),
+            None if names.iter().any(|n| n == "main") => "main",
+            None => {
+                eprintln!("ERROR: --entry is needed to pick which of the files main runs");
+                std::process::exit(1);
+            }
+        };
+        let index = names.iter().position(|n| n == entry).unwrap_or_else(|| {
+            eprintln!(
+                "ERROR: --entry {} isn't one of the files, which are called {}",
+                entry,
+                names.join(", ")
+            );
+            std::process::exit(1);
+        });
+        let mut module = converters.remove(index);
+        let inputs = collect_inputs(&programs[index].0);
+        let linked = converters
+            .into_iter()
+            .try_for_each(|converter| module.link(converter));
+        if matches.is_present("jit") {
+            // The entry's converter made the module, so its program is the
+            // one run and its variables the ones given back
+            linked.map(|_| {
+                module.optimise();
+                crash::set_phase("running the JIT");
+                if let (_, _, Some(stopped)) = module.run(inputs, matches.value_of("args")) {
+                    std::process::exit(stopped.status());
+                }
+            })
+        } else {
+            let output = Path::new(matches.value_of("output").unwrap_or(default_output(kind)));
+            linked
+                .and_then(|_| module.add_entry(entry, &inputs))
+                .and_then(|_| {
+                    module.optimise();
+                    write_output(
+                        &module,
+                        kind,
+                        output,
+                        &temps(matches),
+                        &link_options(matches),
+                    )
+                })
+                .map(|_| status!("Wrote {}", output.display()))
+        }
+    };
+    if let Err(e) = written {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// Prints the --stats report, if it was asked for, to stderr so it doesn't
+// mix with the program's output
+fn print_stats(stats: &Stats, format: Option<&str>) -> () {
+    match format {
+        Some("json") => eprint!("{}", stats.render_json()),
+        Some(_) => eprint!("{}", stats.render()),
+        None => {}
+    }
+}
+
+// The --profile table, on stderr so it stays apart from the results
+fn print_profile(loops: &[(Span, String, u64)], source: &Source) -> () {
+    if loops.is_empty() {
+        eprintln!("The program has no loops to profile");
+        return;
+    }
+    let rows: Vec<(String, String)> = loops
         .iter()
-        .map(|t| match t {
-            Statement::OneParam(crate::token::OneParam {
-                one,
-                ty: OneParamType::Input,
-            }) => one.ident,
-            _ => "",
+        .map(|(span, _, count)| {
+            let location = source.locate(*span);
+            (
+                count.to_string(),
+                format!("{}:{}", location.name, location.span.line),
+            )
         })
-        .filter(|x| !x.is_empty())
         .collect();
+    let counts = rows
+        .iter()
+        .map(|r| r.0.len())
+        .max()
+        .unwrap()
+        .max("iterations".len());
+    let lines = rows
+        .iter()
+        .map(|r| r.1.len())
+        .max()
+        .unwrap()
+        .max("line".len());
+    eprintln!("{:>counts$}  {:<lines$}  loop", "iterations", "line");
+    for ((count, line), (_, text, _)) in rows.iter().zip(loops) {
+        eprintln!("{:>counts$}  {:<lines$}  {}", count, line, text);
+    }
+}
 
-    inputs.sort();
-    inputs.dedup();
+// The arguments for running or compiling a program, taken by bbvm itself
+// and by its run, build and ir subcommands
+fn program_args(app: App<'static>) -> App<'static> {
+    app
+    .arg("-c     'Compiles the program to an executable without running it'")
+    .arg("--run  'Runs the executable once -c has compiled it, passing on its exit status (implies -c)'")
+    .arg("--temp-dir [DIR] 'Where -c and --emit exe write the object file they link (default: the system temp directory)'")
+    .arg("--keep-temps 'Leaves the object file -c and --emit exe link in --temp-dir'")
+    .arg("--no-cache 'Compiles the executable for -c and --emit exe even if the cache has one for the same source and options'")
+    .arg("--static 'Links the executable statically, so it runs without a libc installed'")
+    .arg(
+        Arg::new("pie")
+            .long("pie")
+            .overrides_with("no-pie")
+            .about("Links a position independent executable"),
+    )
+    .arg(
+        Arg::new("no-pie")
+            .long("no-pie")
+            .overrides_with("pie")
+            .about("Links an executable that isn't position independent"),
+    )
+    .arg("--linker [PATH] 'The C compiler to link the executable with (default: $CC, cc, clang or gcc)'")
+    .arg(
+        Arg::new("link-args")
+            .value_name("LINKER ARGS")
+            .index(2)
+            .last(true)
+            .multiple_values(true)
+            .about("Passed on to the linker, after --"),
+    )
+    .arg("-g     'Adds debug info, so gdb can step through the .bb source of -c and --emit output and print its variables'")
+    .arg("--annotate-ir 'Writes each line of source as a comment before the LLVM IR and assembly generated from it (adds debug info like -g)'")
+    .arg("--profile-generate [FILE] 'Builds an executable that counts which way each while and if goes and writes the counts to FILE when it exits, for --profile-use'")
+    .arg("--profile-use [FILE] 'Optimises the hot loops and branches of the program, as counted by an executable built with --profile-generate'")
+    .arg("--ir-budget [N] 'Warns when the generated IR has more than N instructions (default 100000)'")
+    .arg("--fuel [N] 'Stops the program after N loop iterations and proc calls'")
+    .arg("--timeout [SECONDS] 'Stops the program if it runs for longer than this (JIT and --interpret)'")
+    .arg("--output-file [FILE] 'Writes what the program prints to FILE instead of stdout'")
+    .arg(
+        Arg::new("detect-overflow")
+            .long("detect-overflow")
+            .visible_alias("checked")
+            .about("Aborts with the variable and line when incr wraps around"),
+    )
+    .arg(
+        Arg::new("emit")
+            .long("emit")
+            .value_name("KIND")
+            .takes_value(true)
+            .possible_values(&["llvm-ir", "llvm-bc", "asm", "obj", "exe", "c", "cfg-dot"])
+            .about("Writes the compiled program to a file instead of running it"),
+    )
+    .arg("--target [TRIPLE] 'Emits code for another machine, such as aarch64-linux-gnu (needs --emit)'")
+    .arg("--cpu [CPU] 'The cpu to emit code for (default: the host cpu, or generic with --target)'")
+    .arg("--features [FEATURES] 'The cpu features to emit code for, such as +neon,-sve'")
+    .arg("-o, --output [FILE] 'Where -c and --emit write to, - for stdout with llvm-ir, c and cfg-dot (default: out.ll, out.bc, out.s, out.o, out.c, out.dot, or the input's name with .out for executables)'")
+    .arg(
+        Arg::new("opt-level")
+            .short('O')
+            .value_name("LEVEL")
+            .takes_value(true)
+            .possible_values(&["0", "1", "2", "3"])
+            .about("How much to optimise, -O0 skips the optimiser entirely (default: 3)"),
+    )
+    .arg(
+        Arg::new("int-width")
+            .long("int-width")
+            .value_name("BITS")
+            .takes_value(true)
+            .possible_values(&["8", "16", "32", "64", "128"])
+            .about("How many bits the variables have, wrapping around past that (default: 64)"),
+    )
+    .arg("--args [VALUES] 'The inputs, comma separated in sorted order of their names, instead of reading them from stdin'")
+    .arg("--entry [NAME] 'Which program to run of bitcode from `bbvm compile --emit llvm-bc` holding several, by its file name without .bb (default: the one main runs)'")
+    .arg(
+        Arg::new("input-file")
+            .long("input-file")
+            .value_name("FILE")
+            .takes_value(true)
+            .conflicts_with("args")
+            .about("Reads the inputs from a file, one value per line in sorted order of their names or NAME=value lines"),
+    )
+    .arg(
+        Arg::new("output-format")
+            .long("output-format")
+            .value_name("FORMAT")
+            .takes_value(true)
+            .possible_values(&["pretty", "plain", "json"])
+            .default_value("pretty")
+            .about("How the final values are printed; plain and json print nothing else"),
+    )
+    .arg("--json 'Prints only the final values, as one JSON object (the same as --output-format json)'")
+    .arg("-q, --quiet 'Leaves out progress and timing messages'")
+    .arg("--interpret 'Runs the program in an interpreter instead of LLVM'")
+    .arg("--bignum 'Lets variables grow without limit, running the program in the interpreter'")
+    .arg("--stream 'Reads the program a chunk at a time and lowers it as it is lexed, for very large generated programs (no includes, lints, pruning or loop rewriting)'")
+    .arg(
+        Arg::new("encoding")
+            .long("encoding")
+            .value_name("ENCODING")
+            .takes_value(true)
+            .possible_values(&["utf-8", "lossy", "latin1"])
+            .about("How the source is read: anything not UTF-8 is an error (utf-8, the default), becomes U+FFFD (lossy) or each byte is a character (latin1)"),
+    )
+    .arg("--passes [PIPELINE] 'Runs these LLVM passes in order instead of the -O level's, separated by commas, e.g. mem2reg,loop-rotate,indvars,loop-unroll'")
+    .arg("--show-opt-diff 'Prints a diff of the IR before and after optimisation'")
+    .arg(
+        Arg::new("stats")
+            .long("stats")
+            .value_name("FORMAT")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .default_missing_value("text")
+            .possible_values(&["text", "json"])
+            .about("Prints how long each phase took and the size of the program and its IR to stderr, as text or json"),
+    )
+    .arg("--prune-unused 'Leaves out variables that never affect a print, assert, while or if, in the code and the final values'")
+    .arg(
+        Arg::new("verify-ir")
+            .long("verify-ir")
+            .visible_alias("verify-each")
+            .about("Runs the LLVM verifier after every statement is lowered and after optimising, reporting the statement that broke the IR"),
+    )
+    .arg("--redact-crash-report 'Leaves names, numbers and comments out of the source in crash reports'")
+    .arg("--chrome-trace 'Writes the loops and proc calls executed to trace.json for chrome://tracing (JIT only)'")
+    .arg("--profile 'Prints how many times each loop ran its body once the program ends, the most first (JIT only)'")
+    .arg("--history [VARIABLES] 'Records the comma separated variables each time a loop goes round and plots how they changed once the program ends (JIT only)'")
+    .arg("--trace 'Prints every statement as it runs with the value it left, and every loop iteration with its count'")
+}
 
-    let context = Context::create();
-    let mut converter = Converter::new(variables, &inputs, &context);
+fn main() -> () {
+    let app = App::new(crate_name!())
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about(crate_description!())
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            App::new("fix")
+                .about("Applies the suggested fixes for lints in place")
+                .arg("<INPUT>'Sets the input file to fix'"),
+        )
+        .subcommand(
+            App::new("tokens")
+                .about("Prints every word of the source with its offset, length and kind, for editors and for debugging the lexer")
+                .arg("--json 'Prints them as a JSON array'")
+                .arg("<INPUT>'Sets the input file to use'"),
+        )
+        .subcommand(
+            App::new("bench")
+                .about("Compiles and runs every .bb file in a directory under the JIT, several at once, and compares how long each took, or runs one program many times and summarises its run times")
+                .arg(
+                    Arg::new("opt-level")
+                        .short('O')
+                        .value_name("LEVEL")
+                        .takes_value(true)
+                        .possible_values(&["0", "1", "2", "3"])
+                        .about("How much to optimise (default: 3)"),
+                )
+                .arg("-j, --jobs [N] 'How many programs to compile and run at once (default: 4)'")
+                .arg("--runs [N] 'How many times to time a single program (default: 10)'")
+                .arg("--warmup [N] 'How many untimed runs of a single program come first (default: 1)'")
+                .arg("--backends [LIST] 'Which of jit, aot and interpreter to time a single program with, separated by commas (default: jit)'")
+                .arg("--csv [FILE] 'Writes the time of each run of a single program to FILE'")
+                .arg("<PATH>'The directory of programs, or a single program'"),
+        )
+        .subcommand(
+            App::new("test")
+                .about("Runs each .bb file in a directory with the inputs in its .expected file and checks the final values")
+                .arg("--jit 'Runs the programs under the JIT instead of the interpreter'")
+                .arg(
+                    Arg::new("opt-level")
+                        .short('O')
+                        .value_name("LEVEL")
+                        .takes_value(true)
+                        .possible_values(&["0", "1", "2", "3"])
+                        .about("How much to optimise under the JIT (default: 3)"),
+                )
+                .arg("--fuel [N] 'Fails a program that runs more than N loop iterations and calls'")
+                .arg("<DIR>'The directory of programs'"),
+        )
+        .subcommand(
+            App::new("difftest")
+                .about("Runs random small programs in the interpreter and under the JIT and reports any that end with different values")
+                .arg("--count [N] 'How many programs to try (default: 100)'")
+                .arg("--seed [SEED] 'The seed of the first program, the rest following on from it (default: from the time)'")
+                .arg("--size [N] 'About how many statements each program has at the top level (default: 8)'")
+                .arg(
+                    Arg::new("opt-level")
+                        .short('O')
+                        .value_name("LEVEL")
+                        .takes_value(true)
+                        .possible_values(&["0", "1", "2", "3"])
+                        .about("Only tries the JIT at this level (default: both -O0 and -O3)"),
+                )
+                .arg("--save [DIR] 'Writes each program that diverges to DIR with an .expected file from the interpreter, for bbvm test --jit'"),
+        )
+        .subcommand(
+            App::new("roundtrip")
+                .about("Parses the program and prints the tree as source, with includes and macros expanded")
+                .arg("--prune-unused 'Leaves out what --prune-unused would when compiling'")
+                .arg("--simplify 'Rewrites counting loops as arithmetic first, as -O1 and up do'")
+                .arg("<INPUT>'Sets the input file to use'"),
+        )
+        .subcommand(
+            App::new("analyze")
+                .about("Reports how deeply each program's loops nest, what each loop reads and writes, and a big-O estimate from the nesting")
+                .arg("--json 'Prints each program's report as one JSON object per line'")
+                .arg("<INPUT>... 'Sets the input files to analyze'"),
+        )
+        .subcommand(
+            App::new("explain")
+                .about("Lists what each loop reads, writes and leaves unchanged, and which inputs can affect each output, for reviewing unfamiliar programs")
+                .arg("<INPUT>... 'Sets the input files to explain'"),
+        )
+        .subcommand(
+            App::new("fmt")
+                .about("Lays the source out the canonical way in place, keeping its comments")
+                .arg("--check 'Only says whether the file would change, exiting with 1 if so'")
+                .arg("<INPUT>'Sets the input file to format'"),
+        )
+        .subcommand(
+            App::new("rename")
+                .about("Renames a variable throughout a file")
+                .arg("<INPUT>'Sets the input file to change'")
+                .arg("<OLD>'The variable to rename'")
+                .arg("<NEW>'Its new name'"),
+        )
+        .subcommand(
+            App::new("bytecode")
+                .about("Writes the program as .bbc bytecode")
+                .arg("-o, --output [FILE] 'Where to write the bytecode (default: INPUT with a .bbc extension)'")
+                .arg("<INPUT>'Sets the input file to use'"),
+        )
+        .subcommand(
+            App::new("decompile")
+                .about("Prints .bbc bytecode as BareBones source")
+                .arg("<INPUT>'Sets the bytecode file to decompile'"),
+        )
+        .subcommand(
+            App::new("self-check")
+                .about("Checks that decompiling and recompiling the program changes nothing")
+                .arg("<INPUT>'Sets the input file to check'"),
+        )
+        .subcommand(
+            App::new("debug")
+                .about("Runs the program in the interpreter a statement at a time, with breakpoints")
+                .arg("<INPUT>'Sets the input file to debug'"),
+        )
+        .subcommand(
+            App::new("compile")
+                .about("Compiles several files into one module with a function for each, or each into its own output with --out-dir")
+                .arg("--entry [NAME] 'The file main runs, or --jit runs, by its name without .bb (default: main, or the only file)'")
+                .arg("--jit 'Runs the entry under the JIT instead of writing the module'")
+                .arg("--args [VALUES] 'The inputs of the entry for --jit, comma separated in sorted order of their names, instead of reading them from stdin'")
+                .arg("--out-dir [DIR] 'Compiles each file into its own output in DIR instead, named after the file'")
+                .arg(
+                    Arg::new("emit")
+                        .long("emit")
+                        .value_name("KIND")
+                        .takes_value(true)
+                        .possible_values(&["llvm-ir", "llvm-bc", "asm", "obj", "exe"])
+                        .default_value("exe")
+                        .about("What to write"),
+                )
+                .arg("-o, --output [FILE] 'Where to write the module (default: out.ll, out.bc, out.s, out.o or bbvm.out)'")
+                .arg("--temp-dir [DIR] 'Where the object files executables are linked from are written (default: the system temp directory)'")
+                .arg("--keep-temps 'Leaves the object files executables are linked from in --temp-dir'")
+                .arg("--static 'Links the executables statically, so they run without a libc installed'")
+                .arg(
+                    Arg::new("pie")
+                        .long("pie")
+                        .overrides_with("no-pie")
+                        .about("Links position independent executables"),
+                )
+                .arg(
+                    Arg::new("no-pie")
+                        .long("no-pie")
+                        .overrides_with("pie")
+                        .about("Links executables that aren't position independent"),
+                )
+                .arg("--linker [PATH] 'The C compiler to link the executables with (default: $CC, cc, clang or gcc)'")
+                .arg(
+                    Arg::new("opt-level")
+                        .short('O')
+                        .value_name("LEVEL")
+                        .takes_value(true)
+                        .possible_values(&["0", "1", "2", "3"])
+                        .about("How much to optimise (default: 3)"),
+                )
+                .arg("--detect-overflow 'Aborts with the variable and line when incr wraps around'")
+                .arg("<INPUT>... 'The files to compile'")
+                .arg(
+                    Arg::new("link-args")
+                        .value_name("LINKER ARGS")
+                        .index(2)
+                        .last(true)
+                        .multiple_values(true)
+                        .about("Passed on to the linker, after --"),
+                ),
+        )
+        .subcommand(
+            program_args(App::new("run"))
+                .about("Runs the program under the JIT, or in the interpreter with --interpret (the same as leaving out the subcommand)")
+                .arg("<INPUT>'Sets the input file to run'"),
+        )
+        .subcommand(
+            program_args(App::new("build"))
+                .about("Compiles the program to an executable (the input's name with .out unless -o is given) without running it, or to what --emit asks for")
+                .arg("<INPUT>'Sets the input file to build'"),
+        )
+        .subcommand(
+            program_args(App::new("ir"))
+                .about("Prints the program's optimised LLVM IR, or writes it to -o")
+                .arg("<INPUT>'Sets the input file to use'"),
+        )
+        .subcommand(
+            App::new("check")
+                .about("Checks the program for errors and lints without running or compiling it")
+                .arg("<INPUT>'Sets the input file to check'"),
+        )
+        .subcommand(
+            App::new("clean-cache")
+                .about("Deletes the executables kept so that unchanged programs aren't compiled again"),
+        )
+        .subcommand(
+            App::new("repl")
+                .about("Runs statements as they are typed, keeping the variables between them"),
+        )
+        .subcommand(
+            App::new("highlight")
+                .about("Prints the source with syntax highlighting")
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["html", "ansi"])
+                        .default_value("ansi")
+                        .about("html for web pages and handouts, ansi for terminals"),
+                )
+                .arg("<INPUT>'Sets the input file to highlight'"),
+        );
+    let matches = program_args(app)
+        .arg(
+            Arg::new("allow")
+                .short('A')
+                .long("allow")
+                .value_name("LINT")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .global(true)
+                .about("Disables a lint: copy_to_self, incr_decr, empty_loop, constant_false_while, late_input, read_before_write or infinite_loop"),
+        )
+        .arg(
+            Arg::new("warn")
+                .short('W')
+                .long("warn")
+                .value_name("LINT")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .global(true)
+                .about("Reports a lint as a warning (the default)"),
+        )
+        .arg(
+            Arg::new("deny")
+                .short('D')
+                .long("deny")
+                .value_name("LINT")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .global(true)
+                .about("Reports a lint as an error, stopping compilation"),
+        )
+        .arg(
+            Arg::new("deny-warnings")
+                .long("deny-warnings")
+                .global(true)
+                .about("Reports every lint that would warn as an error"),
+        )
+        .arg(
+            Arg::new("case-sensitive")
+                .long("case-sensitive")
+                .global(true)
+                .about("Only reads keywords in lower case, so that `End` or `Copy` can be variables"),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .global(true)
+                .about("Only accepts the standard grammar, with each fluff word where it belongs and every while and if comparing with something"),
+        )
+        .arg(
+            Arg::new("ext")
+                .long("ext")
+                .value_name("EXTENSION")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .global(true)
+                .possible_values(&["arith", "arrays"])
+                .about("Enables a language extension: arith adds add, sub and mul, arrays adds array and A[I]"),
+        )
+        .arg(
+            Arg::new("explain")
+                .long("explain")
+                .value_name("CODE")
+                .takes_value(true)
+                .about("Explains a diagnostic code such as E0001 in detail"),
+        )
+        .arg(Arg::from("[INPUT] 'Sets the input file to use'").required_unless_present("explain"))
+        .get_matches();
 
-    println!("Generating LLVM IR...");
-    for statement in tokens {
-        use Statement::*;
-        match statement {
-            Fluff => {}
-            End => converter.add_end(),
-            EOF => converter.add_eof(),
-            While(v) => v.compile(&mut converter),
-            OneParam(v) => v.compile(&mut converter),
-            TwoParam(v) => v.compile(&mut converter),
+    if let Some(code) = matches.value_of("explain") {
+        match explain::explain(code) {
+            Some(explanation) => print!("{}", explanation),
+            None => {
+                eprintln!("ERROR: {} is not a bbvm diagnostic code", code);
+                std::process::exit(1);
+            }
         }
+        return;
     }
 
-    if converter.optimise() {
-        println!("Optimisations took place :)");
+    // run, build and ir take the same arguments as bbvm without a
+    // subcommand, and then carry on the same way
+    let (command, matches) = match matches.subcommand() {
+        Some((command @ ("run" | "build" | "ir"), matches)) => (Some(command), matches),
+        _ => (None, &matches),
+    };
+
+    crash::install(matches.is_present("redact-crash-report"));
+
+    let compile = matches.is_present("c") || matches.is_present("run");
+    let chrome_trace = matches.is_present("chrome-trace");
+    let log_statements = matches.is_present("trace");
+    let profile = matches.is_present("profile");
+    let history_variables = matches.value_of("history");
+    let detect_overflow = matches.is_present("detect-overflow");
+    let verify_each = matches.is_present("verify-ir");
+    let show_opt_diff = matches.is_present("show-opt-diff");
+    let bignum = matches.is_present("bignum");
+    // Only the interpreter can hold numbers of any size
+    let interpret = matches.is_present("interpret") || bignum;
+    let prune_unused = matches.is_present("prune-unused");
+    let stream = matches.is_present("stream") && !interpret;
+    if matches.is_present("stream") && interpret {
+        eprintln!("WARNING: the interpreter needs the whole program, ignoring --stream");
+    }
+    if stream && (prune_unused || verify_each) {
+        eprintln!("WARNING: --prune-unused and --verify-ir's checks between statements need the whole program, ignoring them with --stream");
+    }
+    let strict = matches.is_present("strict");
+    if stream && strict {
+        eprintln!("WARNING: --strict needs the whole program, ignoring it with --stream");
+    }
+    let stats_format = matches.value_of("stats");
+    let mut stats = Stats::default();
+    let emit = match command {
+        Some("build") => Some(matches.value_of("emit").unwrap_or("exe")),
+        Some("ir") => Some("llvm-ir"),
+        _ => matches.value_of("emit"),
+    };
+    // ir prints the IR to stdout without -o, and -o - prints the IR or C,
+    // with nothing else
+    let to_stdout = (command == Some("ir") && !matches.is_present("output"))
+        || matches.value_of("output") == Some("-");
+    if to_stdout && !matches!(emit, Some("llvm-ir" | "c" | "cfg-dot")) {
+        eprintln!(
+            "ERROR: Only --emit llvm-ir, --emit c and --emit cfg-dot can be written to stdout with -o -"
+        );
+        std::process::exit(1);
+    }
+    let args = matches.value_of("args");
+    let format: OutputFormat = if matches.is_present("json") {
+        OutputFormat::Json
+    } else {
+        matches.value_of("output-format").unwrap().parse().unwrap()
+    };
+    QUIET.store(
+        matches.is_present("quiet") || format != OutputFormat::Pretty || to_stdout,
+        Ordering::Relaxed,
+    );
+    let opt_level = opt_level(matches);
+    let target = matches.value_of("target");
+    let cpu = matches.value_of("cpu");
+    let features = matches.value_of("features");
+    if (target.is_some() || cpu.is_some() || features.is_some()) && emit.is_none() {
+        eprintln!("ERROR: --target, --cpu and --features only apply with --emit, code for another machine can't be run here");
+        std::process::exit(1);
+    }
+    let int_width: Option<u32> = matches.value_of("int-width").map(|w| w.parse().unwrap());
+    let ir_budget = matches
+        .value_of("ir-budget")
+        .map(|n| n.parse().expect("ERROR: --ir-budget expects a number"))
+        .unwrap_or(100_000);
+    let fuel: Option<u64> = matches
+        .value_of("fuel")
+        .map(|n| n.parse().expect("ERROR: --fuel expects a number"));
+    let timeout = matches.value_of("timeout").map(|s| {
+        s.parse()
+            .ok()
+            .filter(|s: &f64| s.is_finite() && *s >= 0.0)
+            .map(std::time::Duration::from_secs_f64)
+            .expect("ERROR: --timeout expects a number of seconds")
+    });
+    // Created before anything runs, so a bad path doesn't waste the run
+    let mut output_file = matches.value_of("output-file").map(|path| {
+        fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("ERROR: Failed to create {}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+    let mut lints = LintConfig::default();
+    if matches.is_present("deny-warnings") {
+        lints.deny_warnings();
+    }
+    for (arg, level) in [
+        ("allow", Level::Allow),
+        ("warn", Level::Warn),
+        ("deny", Level::Deny),
+    ] {
+        for lint in matches.values_of(arg).into_iter().flatten() {
+            lints.set(
+                lint.parse::<Lint>().unwrap_or_else(|e| {
+                    eprintln!("ERROR: {}", e);
+                    std::process::exit(1);
+                }),
+                level,
+            );
+        }
     }
 
-    let endtime1 = chrono::Utc::now();
-    let duration = endtime1 - starttime;
+    let extensions = extensions(matches);
 
-    println!(
-        "LLVM IR compile took {} nanoseconds ({} milliseconds).",
-        duration.num_nanoseconds().unwrap_or_default(),
-        duration.num_milliseconds()
+    if let Some(matches) = matches.subcommand_matches("compile") {
+        return compile_files(matches, &lints, &self::extensions(matches));
+    }
+    if matches.subcommand_matches("clean-cache").is_some() {
+        let cache = Cache::default_dir().map(Cache::new).unwrap_or_else(|| {
+            eprintln!("ERROR: There is no cache directory, set $BBVM_CACHE_DIR or $HOME");
+            std::process::exit(1);
+        });
+        match cache.clean() {
+            Ok((count, bytes)) => println!(
+                "Removed {} cached executables ({} KiB) from {}",
+                count,
+                bytes / 1024,
+                cache.dir().display()
+            ),
+            Err(e) => {
+                eprintln!("ERROR: Failed to clean {}: {}", cache.dir().display(), e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if matches.subcommand_matches("repl").is_some() {
+        return repl::repl(detect_overflow, &extensions);
+    }
+    if let Some(matches) = matches.subcommand_matches("debug") {
+        let source = Source::expand(Path::new(matches.value_of("INPUT").unwrap())).unwrap_or_else(
+            |(error, rendered)| {
+                eprintln!("{}", rendered);
+                stop(&error)
+            },
+        );
+        return debug::debug(&source, detect_overflow, &extensions)
+            .unwrap_or_else(|e| report_in(e, &source));
+    }
+    if let Some(matches) = matches.subcommand_matches("check") {
+        return check_file(
+            matches.value_of("INPUT").unwrap(),
+            &lints,
+            &extensions,
+            matches,
+        );
+    }
+    if let Some(matches) = matches.subcommand_matches("fmt") {
+        return format_file(
+            matches.value_of("INPUT").unwrap(),
+            matches.is_present("check"),
+        );
+    }
+    if let Some(matches) = matches.subcommand_matches("fix") {
+        return fix(matches.value_of("INPUT").unwrap(), &lints);
+    }
+    if let Some(matches) = matches.subcommand_matches("highlight") {
+        let file = fs::read_to_string(matches.value_of("INPUT").unwrap())
+            .expect("ERROR: Failed to read the file");
+        let format = matches.value_of("format").unwrap().parse().unwrap();
+        print!("{}", highlight::highlight(&file, format));
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("bench") {
+        return bench_dir(matches, &extensions);
+    }
+    if let Some(matches) = matches.subcommand_matches("test") {
+        return test_dir(matches, &extensions);
+    }
+    if let Some(matches) = matches.subcommand_matches("difftest") {
+        return difftest_programs(matches, &extensions);
+    }
+    if let Some(matches) = matches.subcommand_matches("explain") {
+        return explain_files(matches, &extensions);
+    }
+    if let Some(matches) = matches.subcommand_matches("analyze") {
+        return analyze_files(matches, &extensions);
+    }
+    if let Some(matches) = matches.subcommand_matches("roundtrip") {
+        return roundtrip(
+            matches.value_of("INPUT").unwrap(),
+            &extensions,
+            matches.is_present("prune-unused"),
+            matches.is_present("simplify"),
+        );
+    }
+    if let Some(matches) = matches.subcommand_matches("tokens") {
+        let file = fs::read_to_string(matches.value_of("INPUT").unwrap())
+            .expect("ERROR: Failed to read the file");
+        print!("{}", highlight::tokens(&file, matches.is_present("json")));
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("bytecode") {
+        let filename = matches.value_of("INPUT").unwrap();
+        let file = fs::read_to_string(filename).expect("ERROR: Failed to read the file");
+        let (tokens, _) =
+            lex(&mut Lexer::new(&file)).unwrap_or_else(|e| report(e, &file, filename));
+        let output = matches.value_of("output").map_or_else(
+            || Path::new(filename).with_extension("bbc"),
+            |o| Path::new(o).to_path_buf(),
+        );
+        fs::write(&output, Bytecode::from_statements(&tokens).encode())
+            .expect("ERROR: Failed to write the bytecode");
+        println!("Bytecode written to {}", output.display());
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("decompile") {
+        let bytes =
+            fs::read(matches.value_of("INPUT").unwrap()).expect("ERROR: Failed to read the file");
+        let bytecode = Bytecode::decode(&bytes).unwrap_or_else(|e| {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        });
+        print!("{}", decompile::decompile(&bytecode.to_statements()));
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("self-check") {
+        return self_check(matches.value_of("INPUT").unwrap());
+    }
+    if let Some(matches) = matches.subcommand_matches("rename") {
+        return rename(
+            matches.value_of("INPUT").unwrap(),
+            matches.value_of("OLD").unwrap(),
+            matches.value_of("NEW").unwrap(),
+        );
+    }
+
+    let filename = matches.value_of("INPUT").unwrap();
+    // Bitcode from --emit llvm-bc already has its IR generated and
+    // optimised, so it goes straight to the JIT
+    if runner::is_bitcode(Path::new(filename)) {
+        if compile || emit.is_some() || interpret {
+            eprintln!(
+                "ERROR: {} is LLVM bitcode, which can only be run with the JIT",
+                filename
+            );
+            std::process::exit(1);
+        }
+        status!("Running JIT compiler on bitcode...");
+        crash::set_phase("running the JIT");
+        let path = Path::new(filename);
+        let entry = matches.value_of("entry");
+        if let Some(timeout) = timeout {
+            exit_after(timeout);
+        }
+        let (codegen, execution, stopped) = match &mut output_file {
+            Some(file) => runner::run(path, entry, args, format, file),
+            None => runner::run(path, entry, args, format, &mut std::io::stdout()),
+        }
+        .unwrap_or_else(|e| {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        });
+        stats.add("codegen", codegen);
+        stats.add("execution", execution);
+        print_stats(&stats, stats_format);
+        if let Some(stopped) = stopped {
+            std::process::exit(stopped.status());
+        }
+        return;
+    }
+    if matches.is_present("entry") {
+        eprintln!("WARNING: --entry only picks a program from bitcode holding several, ignoring");
+    }
+
+    let encoding: Encoding = matches
+        .value_of("encoding")
+        .map_or(Encoding::Utf8, |e| e.parse().unwrap());
+    // Streamed programs are read from the file as they are needed instead
+    let source = if stream {
+        Source::new(filename, String::new())
+    } else {
+        Source::expand_with(Path::new(filename), encoding).unwrap_or_else(|(error, rendered)| {
+            eprintln!("{}", rendered);
+            stop(&error)
+        })
+    };
+    let open = || fs::File::open(filename).expect("ERROR: Failed to read the file");
+    let file = source.text.as_str();
+    crash::set_source(file);
+    let mut l = Lexer::new(file);
+    // The interpreter's numbers are 64 bits, even with --bignum
+    l.set_int_width(if interpret {
+        64
+    } else {
+        int_width.unwrap_or(64)
+    });
+    if matches.is_present("case-sensitive") {
+        l.set_case_sensitive();
+    }
+
+    status!("Interpreting file...");
+    crash::set_phase("lexing");
+    // Streamed programs are lexed as they are lowered, with only their
+    // variables found here
+    let (tokens, spans) = if stream {
+        (vec![], vec![])
+    } else {
+        stats.time("lexing", || {
+            let (tokens, spans) = lex(&mut l).unwrap_or_else(|e| report_in(e, &source));
+            ext::check(&tokens, &spans, &extensions).unwrap_or_else(|e| report_in(e, &source));
+            if strict {
+                let errors = check::strict(file, &tokens, &spans);
+                if let Some(error) = errors.first() {
+                    for error in &errors {
+                        eprintln!("{}", source.render_error(error));
+                    }
+                    stop(error);
+                }
+            }
+            (tokens, spans)
+        })
+    };
+    stats.set_statements(
+        tokens
+            .iter()
+            .filter(|t| !matches!(t, Statement::Fluff | Statement::EOF))
+            .count(),
     );
 
-    let duration = if compile {
-        println!("Running normal compiler...");
+    crash::set_phase("linting");
+    let warnings = if stream {
+        vec![]
+    } else {
+        stats.time("linting", || {
+            lint::lint(&tokens, &spans, &lints, &l.allowed())
+        })
+    };
+    print_warnings(&warnings, &source, filename);
 
-        converter.dump_code();
-        let endtime2 = chrono::Utc::now();
-        endtime2 - endtime1
+    // Streamed programs keep only their names, which their IR borrows
+    let scanned = stream.then(|| {
+        stats.time("lexing", || {
+            stream::scan(open(), filename, encoding).unwrap_or_else(|(error, rendered)| {
+                eprintln!("{}", rendered);
+                stop(&error)
+            })
+        })
+    });
+    let (variables, inputs) = if let Some(scanned) = &scanned {
+        (scanned.variables(), scanned.inputs())
     } else {
-        println!("Running JIT compiler...");
+        (collect_variables(&tokens), collect_inputs(&tokens))
+    };
+    // Checked before anything runs, then read the same way as --args
+    let file_args = matches.value_of("input-file").map(|path| {
+        let text = fs::read_to_string(path).expect("ERROR: Failed to read the input file");
+        input::from_file(&inputs, &text).unwrap_or_else(|e| {
+            eprintln!("ERROR: {}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+    let args = file_args.as_deref().or(args);
+
+    // C is written from the tree, without LLVM
+    if emit == Some("c") {
+        if stream {
+            eprintln!("ERROR: --emit c needs the whole program, it can't be used with --stream");
+            std::process::exit(1);
+        }
+        if int_width.map_or(false, |width| width != 64) {
+            eprintln!("ERROR: --emit c writes 64 bit variables, it can't be used with another --int-width");
+            std::process::exit(1);
+        }
+        crash::set_phase("writing the output");
+        let program = parser::parse(&tokens, &spans).unwrap_or_else(|e| report_in(e, &source));
+        let output = Path::new(matches.value_of("output").unwrap_or("out.c"));
+        let c = stats.time("codegen", || {
+            backend_c::emit(
+                &program,
+                filename,
+                &variables,
+                &inputs,
+                &variables,
+                format,
+                detect_overflow,
+            )
+        });
+        if to_stdout {
+            print!("{}", c);
+            return print_stats(&stats, stats_format);
+        }
+        fs::write(output, c).expect("ERROR: Failed to write the C");
+        status!("Wrote {}", output.display());
+        return print_stats(&stats, stats_format);
+    }
+
+    // So is the control flow graph, laid out as the converter would lower
+    // the tree
+    if emit == Some("cfg-dot") {
+        if stream {
+            eprintln!(
+                "ERROR: --emit cfg-dot needs the whole program, it can't be used with --stream"
+            );
+            std::process::exit(1);
+        }
+        crash::set_phase("writing the output");
+        let mut program = parser::parse(&tokens, &spans).unwrap_or_else(|e| report_in(e, &source));
+        if prune_unused {
+            let live = prune::live(&program.body);
+            prune::prune(&mut program.body, &live);
+        }
+        // Drawn as the converter would lower it, which keeps the loops when
+        // they are counted or reported on
+        let instrumented = fuel.is_some()
+            || chrome_trace
+            || log_statements
+            || profile
+            || history_variables.is_some();
+        if opt_level != OptimizationLevel::None && !instrumented {
+            simplify::simplify(&mut program.body);
+        }
+        let dot = stats.time("codegen", || cfg::dot(&program, filename));
+        if to_stdout {
+            print!("{}", dot);
+            return print_stats(&stats, stats_format);
+        }
+        let output = Path::new(matches.value_of("output").unwrap_or("out.dot"));
+        fs::write(output, dot).expect("ERROR: Failed to write the graph");
+        status!("Wrote {}", output.display());
+        return print_stats(&stats, stats_format);
+    }
+
+    if interpret {
+        if compile || chrome_trace || log_statements || profile || history_variables.is_some() {
+            eprintln!("WARNING: -c, --chrome-trace, --trace, --profile and --history need LLVM, ignoring them with --interpret and --bignum");
+        }
+        if matches.is_present("profile-generate") || matches.is_present("profile-use") {
+            eprintln!("WARNING: --profile-generate and --profile-use need LLVM, ignoring them with --interpret and --bignum");
+        }
+        if int_width.is_some() {
+            eprintln!("WARNING: --int-width only applies to LLVM, the interpreter's variables are 64 bits (or unlimited with --bignum)");
+        }
+        // The interpreter runs every statement, only the final values are
+        // pruned
+        let printed: Vec<&str> = if prune_unused {
+            let program = parser::parse(&tokens, &spans).unwrap_or_else(|e| report_in(e, &source));
+            let live = prune::live(&program.body);
+            variables
+                .iter()
+                .copied()
+                .filter(|v| live.contains(v))
+                .collect()
+        } else {
+            variables.clone()
+        };
+        let mut interpreter =
+            Interpreter::new(&tokens, &spans).unwrap_or_else(|e| report_in(e, &source));
+        interpreter.set_output_format(format);
+        if detect_overflow {
+            interpreter.enable_overflow_detection();
+        }
+        if let Some(fuel) = fuel {
+            interpreter.set_fuel(fuel);
+        }
+        if let Some(timeout) = timeout {
+            interpreter.set_timeout(timeout);
+        }
+        status!("Running interpreter...");
+        crash::set_phase("interpreting");
+        let (duration, halted) = match (bignum, &mut output_file) {
+            (true, Some(file)) => interpreter
+                .holding::<Natural>()
+                .run_with_writer(&inputs, args, &printed, file),
+            (true, None) => interpreter
+                .holding::<Natural>()
+                .run(&inputs, args, &printed),
+            (false, Some(file)) => interpreter.run_with_writer(&inputs, args, &printed, file),
+            (false, None) => interpreter.run(&inputs, args, &printed),
+        }
+        .unwrap_or_else(|e| report_in(e, &source));
+        stats.add("execution", duration);
+        print_stats(&stats, stats_format);
+        if let Some(code) = halted {
+            std::process::exit(code);
+        }
+        return;
+    }
 
-        converter.run(inputs)
+    // -c, and --emit exe or bbvm build, copy the executable from the cache
+    // when the program was compiled the same way before
+    let windows = matches
+        .value_of("target")
+        .map_or(cfg!(windows), |triple| triple.contains("windows"));
+    let executable = (emit == Some("exe") || (compile && emit.is_none())).then(|| {
+        matches
+            .value_of("output")
+            .map_or_else(|| executable_output(filename, windows), PathBuf::from)
+    });
+    let cache = match &executable {
+        Some(_) if !stream && !matches.is_present("no-cache") => {
+            Cache::default_dir().map(Cache::new)
+        }
+        _ => None,
+    };
+    let cache_key = cache
+        .as_ref()
+        .map(|_| Cache::key(file, &cache_options(matches, filename)));
+    if let (Some(cache), Some(key), Some(executable)) = (&cache, &cache_key, &executable) {
+        if cache.get(key, executable) {
+            status!(
+                "{} is unchanged since it was last compiled, copied the cached executable to {}",
+                filename,
+                executable.display()
+            );
+            if !(compile && emit.is_none() && matches.is_present("run")) {
+                return print_stats(&stats, stats_format);
+            }
+            crash::set_phase("running the compiled program");
+            let out = output_file
+                .as_mut()
+                .map(|file| file as &mut dyn std::io::Write);
+            let arguments = executable_args(&inputs, args);
+            let (execution, status) =
+                run_executable(executable, &arguments, out).unwrap_or_else(|e| {
+                    eprintln!("ERROR: {}", e);
+                    std::process::exit(1);
+                });
+            stats.add("execution", execution);
+            print_stats(&stats, stats_format);
+            if status != 0 {
+                std::process::exit(status);
+            }
+            return;
+        }
+    }
+    // Caching is only worth a warning if it fails
+    let keep = |executable: &Path| {
+        if let (Some(cache), Some(key)) = (&cache, &cache_key) {
+            if let Err(e) = cache.put(key, executable) {
+                eprintln!(
+                    "WARNING: Failed to cache the executable in {}: {}",
+                    cache.dir().display(),
+                    e
+                );
+            }
+        }
     };
 
-    println!(
-        "LLVM IR execution took {} nanoseconds ({} milliseconds).",
-        duration.num_nanoseconds().unwrap_or_default(),
-        duration.num_milliseconds()
-    );
+    crash::set_phase("generating LLVM IR");
+    let context = Context::create();
+    let mut converter =
+        Converter::with_int_width(variables, &inputs, &context, int_width.unwrap_or(64));
+    converter.set_optimization_level(opt_level);
+    converter.set_unrolls(&l.unrolls());
+    if let Some(pipeline) = matches.value_of("passes") {
+        match passes::parse(pipeline) {
+            Ok(passes) => converter.set_passes(passes),
+            Err(e) => {
+                eprintln!("ERROR: Bad --passes: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    converter.set_output_format(format);
+    if detect_overflow {
+        converter.enable_overflow_detection();
+    }
+    if prune_unused {
+        converter.enable_pruning();
+    }
+    if let Some(fuel) = fuel {
+        converter.set_fuel(fuel);
+    }
+    if let Some(timeout) = timeout {
+        if compile || emit.is_some() {
+            eprintln!("WARNING: --timeout only applies when running under the JIT or --interpret, ignoring");
+        } else {
+            converter.set_timeout(timeout);
+        }
+    }
+    if emit.is_some() {
+        if let Err(e) = converter.set_target(target, cpu, features) {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if log_statements {
+        converter.enable_statement_log();
+    }
+    if let Some(path) = matches.value_of("profile-generate") {
+        if executable.is_none() {
+            eprintln!("WARNING: --profile-generate only applies to executables (-c or --emit exe), ignoring");
+        } else {
+            // The executable may be run from anywhere
+            let path =
+                std::env::current_dir().map_or_else(|_| PathBuf::from(path), |dir| dir.join(path));
+            converter.enable_profile_generate(&path.display().to_string());
+        }
+    }
+    let profile_use = matches.value_of("profile-use").map(|path| {
+        let profile = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| pgo::Profile::parse(&text));
+        profile.unwrap_or_else(|e| {
+            eprintln!("ERROR: Failed to read the profile {}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+    let profiled = profile_use.as_ref().map_or(0, |p| p.branches.len());
+    if let Some(profile) = profile_use {
+        converter.use_profile(profile);
+    }
+    if profile {
+        if compile || emit.is_some() {
+            eprintln!("WARNING: --profile is only available under the JIT, ignoring");
+        } else {
+            converter.enable_profile();
+        }
+    }
+    if let Some(names) = history_variables {
+        if compile || emit.is_some() {
+            eprintln!("WARNING: --history is only available under the JIT, ignoring");
+        } else {
+            let names: Vec<&str> = names.split(',').map(str::trim).collect();
+            if let Err(e) = converter.enable_history(&names) {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if matches.is_present("g") {
+        converter.enable_debug_info(Path::new(filename));
+    }
+    if matches.is_present("annotate-ir") {
+        converter.annotate_source(file, Path::new(filename));
+    }
+    if chrome_trace {
+        if compile {
+            eprintln!("WARNING: Chrome tracing is only available under the JIT, ignoring");
+        } else {
+            converter.enable_trace();
+        }
+    }
+    if output_file.is_some() && !compile && emit.is_none() {
+        converter.capture_output();
+    }
+    if output_file.is_some() && compile && !matches.is_present("run") {
+        eprintln!(
+            "WARNING: -c doesn't run the program without --run, so --output-file is left empty"
+        );
+    }
+
+    status!("Generating LLVM IR...");
+    stats.time("ir_generation", || match &scanned {
+        Some(scanned) => stream::lower(
+            &mut converter,
+            scanned,
+            open(),
+            filename,
+            encoding,
+            &extensions,
+        )
+        .unwrap_or_else(|(error, rendered)| {
+            eprintln!("{}", rendered);
+            stop(&error)
+        }),
+        None => lower(&mut converter, &tokens, &spans, file, verify_each)
+            .unwrap_or_else(|e| report_in(e, &source)),
+    });
+
+    let (found, missing) = converter.profile_matches();
+    if missing > 0 || found < profiled {
+        eprintln!(
+            "WARNING: {} of the program's whiles and ifs aren't in the profile and {} of the profile's aren't in the program, it may be out of date (make it again with --profile-generate)",
+            missing,
+            profiled.saturating_sub(found)
+        );
+    }
+    let (blocks, instructions) = converter.ir_size();
+    stats.set_ir_size((blocks, instructions));
+    if instructions > ir_budget {
+        eprintln!(
+            "WARNING: Generated IR has {} instructions in {} basic blocks, over the budget of {}.",
+            instructions, blocks, ir_budget
+        );
+        eprintln!("         LLVM will be slow on this program; consider folding long runs of repeated statements before compiling.");
+    }
+
+    crash::set_phase("optimising");
+    let unoptimised = show_opt_diff.then(|| converter.ir_string());
+    if stats.time("optimisation", || converter.optimise()) {
+        status!("Optimisations took place :)");
+    }
+    stats.set_optimised_ir_size(converter.ir_size());
+    stats.set_passes(converter.passes_run());
+    if verify_each {
+        if let Err(e) = converter.verify() {
+            eprintln!(
+                "ERROR: LLVM's optimisations left invalid IR, please report this: {}",
+                e.trim()
+            );
+            std::process::exit(1);
+        }
+    }
+    if let Some(unoptimised) = unoptimised {
+        let colour = std::env::var_os("NO_COLOR").is_none();
+        print!(
+            "{}",
+            diff::ir_diff(&unoptimised, &converter.ir_string(), colour)
+        );
+    }
+
+    if to_stdout {
+        print!("{}", converter.ir_string());
+        return print_stats(&stats, stats_format);
+    }
+    if let Some(kind) = emit {
+        crash::set_phase("writing the output");
+        let output = match (&executable, matches.value_of("output")) {
+            (Some(executable), _) => executable.clone(),
+            (None, Some(output)) => PathBuf::from(output),
+            (None, None) => PathBuf::from(default_output(kind)),
+        };
+        let written = stats.time("codegen", || {
+            write_output(
+                &converter,
+                kind,
+                &output,
+                &temps(matches),
+                &link_options(matches),
+            )
+        });
+        if let Err(e) = written {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+        if kind == "exe" {
+            keep(&output);
+        }
+        status!("Wrote {}", output.display());
+        return print_stats(&stats, stats_format);
+    }
+
+    // Both stop with the status of a halt or a runtime failure, which bbvm
+    // exits with once the stats and traces are written
+    let (codegen, execution, status) = if compile {
+        status!("Running normal compiler...");
+        crash::set_phase("compiling to native");
+
+        let fail = |e: String| -> ! {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        };
+        let executable = executable.unwrap();
+        let temps = temps(matches);
+        let codegen = converter
+            .dump_code(&executable, &temps, &link_options(matches))
+            .unwrap_or_else(|e| fail(e));
+        keep(&executable);
+        status!(
+            "A compiled executable is available at {}",
+            executable.display()
+        );
+        if temps.keep {
+            status!("Its object file was kept in {}", temps.dir.display());
+        }
+        if !matches.is_present("run") {
+            stats.add("codegen", codegen);
+            return print_stats(&stats, stats_format);
+        }
+        crash::set_phase("running the compiled program");
+        let out = output_file
+            .as_mut()
+            .map(|file| file as &mut dyn std::io::Write);
+        let arguments = executable_args(&inputs, args);
+        let (execution, status) =
+            run_executable(&executable, &arguments, out).unwrap_or_else(|e| fail(e));
+        (codegen, execution, status)
+    } else {
+        status!("Running JIT compiler...");
+        crash::set_phase("running the JIT");
+
+        let (codegen, execution, stopped) = match &mut output_file {
+            Some(file) => converter.run_with_writer(inputs, args, file),
+            None => converter.run(inputs, args),
+        };
+        (codegen, execution, stopped.map_or(0, Stopped::status))
+    };
+
+    if chrome_trace && !compile {
+        converter
+            .write_trace(Path::new("trace.json"))
+            .expect("ERROR: Failed to write trace.json");
+        status!("Execution trace written to ./trace.json");
+    }
+    if profile && !compile {
+        print_profile(&converter.profile(), &source);
+    }
+    if history_variables.is_some() && !compile {
+        let (names, series) = converter.history();
+        eprint!("{}", history::render(&names, &series));
+    }
 
-    if compile {
-        println!("A compiled executable is available at ./bbvm.out");
+    stats.add("codegen", codegen);
+    stats.add("execution", execution);
+    print_stats(&stats, stats_format);
+    if status != 0 {
+        std::process::exit(status);
     }
 }
@@ -0,0 +1,72 @@
+use std::str::FromStr;
+
+use crate::bignum::Value;
+
+// How the final values of the variables are printed
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OutputFormat {
+    // `X: 5` between `-----` lines, as bbvm always has
+    Pretty,
+    // `X 5`, one per line and nothing else
+    Plain,
+    // {"X": 5, "Y": 0} on one line
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(Self::Pretty),
+            "plain" => Ok(Self::Plain),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown output format {}", s)),
+        }
+    }
+}
+
+impl OutputFormat {
+    // The printf format for the value of name, the index'th of count
+    // variables. %lld stands in for the value.
+    pub fn line(&self, name: &str, index: usize, count: usize) -> String {
+        match self {
+            OutputFormat::Pretty => format!("{}: %lld\n", name),
+            OutputFormat::Plain => format!("{} %lld\n", name),
+            OutputFormat::Json => format!(
+                "{}\"{}\": %lld{}",
+                if index == 0 { "{" } else { ", " },
+                name,
+                if index + 1 == count { "}\n" } else { "" }
+            ),
+        }
+    }
+
+    // What is printed instead when there are no variables at all
+    pub fn empty(&self) -> &'static str {
+        match self {
+            OutputFormat::Json => "{}\n",
+            _ => "",
+        }
+    }
+
+    // Whether the results are set off with `-----` lines
+    pub fn separators(&self) -> bool {
+        *self == OutputFormat::Pretty
+    }
+
+    // The results as the JIT's printf would print them, u64 values as signed
+    pub fn render<V: Value>(&self, results: &[(&str, V)]) -> String {
+        if results.is_empty() {
+            return self.empty().to_string();
+        }
+        results
+            .iter()
+            .enumerate()
+            .map(|(i, (name, value))| {
+                self.line(name, i, results.len())
+                    .replace("%lld", &value.show())
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,324 @@
+use std::fmt;
+
+use crate::error::BbvmError;
+use crate::lexer::Span;
+use crate::token::{
+    Array, Bound, Call, Comparison, Identifier, If, Indexed, Number, Proc, Statement, While,
+};
+
+// The statements with each while, if and proc holding the statements inside
+// it, instead of the flat list the lexer gives. Fluff is left out.
+pub type Block<'a> = Vec<Node<'a>>;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Node<'a> {
+    // A statement with no body, never one that opens or closes a block
+    Statement(Statement<'a>, Span),
+    // while var cmp check do; body end;
+    Loop {
+        var: Identifier<'a>,
+        cmp: Comparison,
+        check: Bound<'a>,
+        body: Block<'a>,
+        span: Span,
+        end: Span,
+    },
+    // if var cmp check then; then else; otherwise endif;
+    If {
+        var: Identifier<'a>,
+        cmp: Comparison,
+        check: Number,
+        then: Block<'a>,
+        // The span of the else, and its statements
+        otherwise: Option<(Span, Block<'a>)>,
+        span: Span,
+        end: Span,
+    },
+    // proc name; body endproc;
+    Proc {
+        name: Identifier<'a>,
+        body: Block<'a>,
+        span: Span,
+        end: Span,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Program<'a> {
+    pub body: Block<'a>,
+    // Where the end of the file is
+    pub eof: Span,
+}
+
+const INDENT: &str = "    ";
+
+impl Node<'_> {
+    // Writes the node as source, depth blocks in, one statement per line
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = INDENT.repeat(depth);
+        match self {
+            Node::Statement(statement, _) => writeln!(f, "{}{}", indent, statement),
+            Node::Loop {
+                var,
+                cmp,
+                check,
+                body,
+                ..
+            } => {
+                let start = Statement::While(While {
+                    param: *var,
+                    cmp: *cmp,
+                    bound: *check,
+                });
+                writeln!(f, "{}{}", indent, start)?;
+                write_block(f, body, depth + 1)?;
+                writeln!(f, "{}{}", indent, Statement::End)
+            }
+            Node::If {
+                var,
+                cmp,
+                check,
+                then,
+                otherwise,
+                ..
+            } => {
+                let start = Statement::If(If {
+                    param: *var,
+                    cmp: *cmp,
+                    num: *check,
+                });
+                writeln!(f, "{}{}", indent, start)?;
+                write_block(f, then, depth + 1)?;
+                if let Some((_, otherwise)) = otherwise {
+                    writeln!(f, "{}{}", indent, Statement::Else)?;
+                    write_block(f, otherwise, depth + 1)?;
+                }
+                writeln!(f, "{}{}", indent, Statement::EndIf)
+            }
+            Node::Proc { name, body, .. } => {
+                writeln!(f, "{}{}", indent, Statement::Proc(Proc { name: *name }))?;
+                write_block(f, body, depth + 1)?;
+                writeln!(f, "{}{}", indent, Statement::EndProc)
+            }
+        }
+    }
+}
+
+fn write_block(f: &mut fmt::Formatter<'_>, block: &Block, depth: usize) -> fmt::Result {
+    block.iter().try_for_each(|node| node.write(f, depth))
+}
+
+// The node as BareBones source, with the statements inside it indented
+impl fmt::Display for Node<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+// The whole program as BareBones source, one statement per line with the
+// bodies of blocks indented. Lexing and parsing it again gives the same
+// tree, apart from the spans.
+impl fmt::Display for Program<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_block(f, &self.body, 0)
+    }
+}
+
+impl Program<'_> {
+    pub fn to_source(&self) -> String {
+        self.to_string()
+    }
+}
+
+struct Parser<'a, 's> {
+    statements: &'s [Statement<'a>],
+    spans: &'s [Span],
+    next: usize,
+}
+
+impl<'a, 's> Parser<'a, 's> {
+    // The next statement that isn't fluff, or EOF once they run out
+    fn advance(&mut self) -> (Statement<'a>, Span) {
+        while let Some(&statement) = self.statements.get(self.next) {
+            let span = self.spans[self.next];
+            self.next += 1;
+            if statement != Statement::Fluff {
+                return (statement, span);
+            }
+        }
+        (
+            Statement::EOF,
+            self.spans.last().copied().unwrap_or_default(),
+        )
+    }
+
+    // Parses statements up to the end of the block, returning them and the
+    // statement that ended it: an end, else, endif, endproc or EOF
+    fn block(&mut self, in_block: bool) -> Result<(Block<'a>, (Statement<'a>, Span)), BbvmError> {
+        let mut block = vec![];
+        loop {
+            let (statement, span) = self.advance();
+            let node = match statement {
+                Statement::End
+                | Statement::Else
+                | Statement::EndIf
+                | Statement::EndProc
+                | Statement::EOF => return Ok((block, (statement, span))),
+                Statement::While(While { param, cmp, bound }) => {
+                    let (body, end) = self.block(true)?;
+                    Node::Loop {
+                        var: param,
+                        cmp,
+                        check: bound,
+                        body,
+                        span,
+                        end: closes(end, Statement::End, || BbvmError::UnclosedWhile { span })?,
+                    }
+                }
+                Statement::If(If { param, cmp, num }) => {
+                    let (then, (closing, closing_span)) = self.block(true)?;
+                    let (otherwise, end) = if closing == Statement::Else {
+                        let (otherwise, end) = self.block(true)?;
+                        (Some((closing_span, otherwise)), end)
+                    } else {
+                        (None, (closing, closing_span))
+                    };
+                    Node::If {
+                        var: param,
+                        cmp,
+                        check: num,
+                        then,
+                        otherwise,
+                        span,
+                        end: closes(end, Statement::EndIf, || BbvmError::UnclosedIf { span })?,
+                    }
+                }
+                // Procs run wherever they are called, so only make sense on
+                // their own
+                Statement::Proc(_) if in_block => return Err(BbvmError::NestedProc { span }),
+                Statement::Proc(Proc { name }) => {
+                    let (body, end) = self.block(true)?;
+                    Node::Proc {
+                        name,
+                        body,
+                        span,
+                        end: closes(end, Statement::EndProc, || BbvmError::UnclosedProc { span })?,
+                    }
+                }
+                _ => Node::Statement(statement, span),
+            };
+            block.push(node);
+        }
+    }
+}
+
+// The span of the statement that ended a block, if it is the expected one.
+// Otherwise the error for it, or unclosed if the file ended first.
+fn closes(
+    (statement, span): (Statement, Span),
+    expected: Statement,
+    unclosed: impl FnOnce() -> BbvmError,
+) -> Result<Span, BbvmError> {
+    if statement == expected {
+        return Ok(span);
+    }
+    Err(match statement {
+        Statement::EOF => unclosed(),
+        _ => unmatched(statement, span),
+    })
+}
+
+fn unmatched(statement: Statement, span: Span) -> BbvmError {
+    match statement {
+        Statement::End => BbvmError::UnmatchedEnd { span },
+        Statement::Else => BbvmError::UnmatchedElse { span },
+        Statement::EndIf => BbvmError::UnmatchedEndIf { span },
+        _ => BbvmError::UnmatchedEndProc { span },
+    }
+}
+
+// Every array declared more than once, too big, or used before it is
+// declared
+fn array_errors(statements: &[Statement], spans: &[Span], errors: &mut Vec<BbvmError>) -> () {
+    let mut declared = vec![];
+    for (statement, &span) in statements.iter().zip(spans) {
+        match statement {
+            Statement::Array(Array { name, size }) => {
+                if declared.contains(&name.ident) {
+                    errors.push(BbvmError::DuplicateArray {
+                        name: name.ident.to_string(),
+                        span,
+                    });
+                } else if size.value > u32::MAX as i128 {
+                    errors.push(BbvmError::ArrayTooBig {
+                        name: name.ident.to_string(),
+                        size: size.value,
+                        span,
+                    });
+                }
+                declared.push(name.ident);
+            }
+            Statement::Indexed(Indexed { element, .. })
+                if !declared.contains(&element.array.ident) =>
+            {
+                errors.push(BbvmError::UnknownArray {
+                    name: element.array.ident.to_string(),
+                    span,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+// Everything wrong with the procs and arrays: arrays as array_errors finds
+// them, then procs defined twice, then calls to procs that don't exist.
+// Doesn't need the blocks to match up.
+pub fn declaration_errors(statements: &[Statement], spans: &[Span]) -> Vec<BbvmError> {
+    let mut errors = vec![];
+    array_errors(statements, spans, &mut errors);
+
+    let mut defined: Vec<&str> = vec![];
+    for (statement, &span) in statements.iter().zip(spans) {
+        if let Statement::Proc(Proc { name }) = statement {
+            if defined.contains(&name.ident) {
+                errors.push(BbvmError::DuplicateProc {
+                    name: name.ident.to_string(),
+                    span,
+                });
+            }
+            defined.push(name.ident);
+        }
+    }
+    // Procs can be called before they are defined, so this needs them all
+    for (statement, &span) in statements.iter().zip(spans) {
+        if let Statement::Call(Call { name }) = statement {
+            if !defined.contains(&name.ident) {
+                errors.push(BbvmError::UnknownProc {
+                    name: name.ident.to_string(),
+                    span,
+                });
+            }
+        }
+    }
+    errors
+}
+
+// Builds the tree for the statements, spans[i] being where statements[i] is
+// in the source. Unmatched or unclosed blocks, misplaced procs, calls to
+// procs that don't exist and undeclared arrays are reported here.
+pub fn parse<'a>(statements: &[Statement<'a>], spans: &[Span]) -> Result<Program<'a>, BbvmError> {
+    let mut parser = Parser {
+        statements,
+        spans,
+        next: 0,
+    };
+    let (body, (statement, eof)) = parser.block(false)?;
+    if statement != Statement::EOF {
+        return Err(unmatched(statement, eof));
+    }
+    match declaration_errors(statements, spans).into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(Program { body, eof }),
+    }
+}
@@ -0,0 +1,134 @@
+// The LLVM passes --passes can name, by the names opt gives them, for
+// running a pipeline of one's own instead of the -O level's. bbvm is built
+// against LLVM 12, whose C API only has the legacy pass manager, so each
+// pass is one of its add_*_pass functions.
+
+use inkwell::{module::Module, passes::PassManager};
+
+// Every pass --passes knows, in order
+pub const NAMES: [&str; 47] = [
+    "adce",
+    "alignment-from-assumptions",
+    "always-inline",
+    "basic-aa",
+    "bdce",
+    "constmerge",
+    "correlated-propagation",
+    "deadargelim",
+    "dse",
+    "early-cse",
+    "function-attrs",
+    "globaldce",
+    "globalopt",
+    "gvn",
+    "indvars",
+    "inline",
+    "instcombine",
+    "instsimplify",
+    "ipsccp",
+    "jump-threading",
+    "licm",
+    "loop-deletion",
+    "loop-idiom",
+    "loop-reroll",
+    "loop-rotate",
+    "loop-unroll",
+    "loop-unroll-and-jam",
+    "loop-vectorize",
+    "lower-expect",
+    "lowerswitch",
+    "mem2reg",
+    "memcpyopt",
+    "mergefunc",
+    "mldst-motion",
+    "newgvn",
+    "partially-inline-libcalls",
+    "reassociate",
+    "reg2mem",
+    "sccp",
+    "scoped-noalias-aa",
+    "simplifycfg",
+    "slp-vectorizer",
+    "sroa",
+    "strip",
+    "strip-dead-prototypes",
+    "tailcallelim",
+    "tbaa",
+];
+
+// The passes in pipeline, separated by commas, e.g.
+// `mem2reg,loop-rotate,indvars,loop-unroll`
+pub fn parse(pipeline: &str) -> Result<Vec<&'static str>, String> {
+    pipeline
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            NAMES
+                .iter()
+                .copied()
+                .find(|&known| known == name)
+                .ok_or_else(|| {
+                    format!(
+                        "there is no pass called {:?}, the passes are {}",
+                        name,
+                        NAMES.join(", ")
+                    )
+                })
+        })
+        .collect()
+}
+
+// Adds the pass called name, one of NAMES, to pass_manager
+pub fn add(pass_manager: &PassManager<Module>, name: &str) -> () {
+    match name {
+        "adce" => pass_manager.add_aggressive_dce_pass(),
+        "alignment-from-assumptions" => pass_manager.add_alignment_from_assumptions_pass(),
+        "always-inline" => pass_manager.add_always_inliner_pass(),
+        "basic-aa" => pass_manager.add_basic_alias_analysis_pass(),
+        "bdce" => pass_manager.add_bit_tracking_dce_pass(),
+        "constmerge" => pass_manager.add_constant_merge_pass(),
+        "correlated-propagation" => pass_manager.add_correlated_value_propagation_pass(),
+        "deadargelim" => pass_manager.add_dead_arg_elimination_pass(),
+        "dse" => pass_manager.add_dead_store_elimination_pass(),
+        "early-cse" => pass_manager.add_early_cse_pass(),
+        "function-attrs" => pass_manager.add_function_attrs_pass(),
+        "globaldce" => pass_manager.add_global_dce_pass(),
+        "globalopt" => pass_manager.add_global_optimizer_pass(),
+        "gvn" => pass_manager.add_gvn_pass(),
+        "indvars" => pass_manager.add_ind_var_simplify_pass(),
+        "inline" => pass_manager.add_function_inlining_pass(),
+        "instcombine" => pass_manager.add_instruction_combining_pass(),
+        "instsimplify" => pass_manager.add_instruction_simplify_pass(),
+        "ipsccp" => pass_manager.add_ipsccp_pass(),
+        "jump-threading" => pass_manager.add_jump_threading_pass(),
+        "licm" => pass_manager.add_licm_pass(),
+        "loop-deletion" => pass_manager.add_loop_deletion_pass(),
+        "loop-idiom" => pass_manager.add_loop_idiom_pass(),
+        "loop-reroll" => pass_manager.add_loop_reroll_pass(),
+        "loop-rotate" => pass_manager.add_loop_rotate_pass(),
+        "loop-unroll" => pass_manager.add_loop_unroll_pass(),
+        "loop-unroll-and-jam" => pass_manager.add_loop_unroll_and_jam_pass(),
+        "loop-vectorize" => pass_manager.add_loop_vectorize_pass(),
+        "lower-expect" => pass_manager.add_lower_expect_intrinsic_pass(),
+        "lowerswitch" => pass_manager.add_lower_switch_pass(),
+        "mem2reg" => pass_manager.add_promote_memory_to_register_pass(),
+        "memcpyopt" => pass_manager.add_memcpy_optimize_pass(),
+        "mergefunc" => pass_manager.add_merge_functions_pass(),
+        "mldst-motion" => pass_manager.add_merged_load_store_motion_pass(),
+        "newgvn" => pass_manager.add_new_gvn_pass(),
+        "partially-inline-libcalls" => pass_manager.add_partially_inline_lib_calls_pass(),
+        "reassociate" => pass_manager.add_reassociate_pass(),
+        "reg2mem" => pass_manager.add_demote_memory_to_register_pass(),
+        "sccp" => pass_manager.add_sccp_pass(),
+        "scoped-noalias-aa" => pass_manager.add_scoped_no_alias_aa_pass(),
+        "simplifycfg" => pass_manager.add_cfg_simplification_pass(),
+        "slp-vectorizer" => pass_manager.add_slp_vectorize_pass(),
+        "sroa" => pass_manager.add_scalar_repl_aggregates_pass_ssa(),
+        "strip" => pass_manager.add_strip_symbol_pass(),
+        "strip-dead-prototypes" => pass_manager.add_strip_dead_prototypes_pass(),
+        "tailcallelim" => pass_manager.add_tail_call_elimination_pass(),
+        "tbaa" => pass_manager.add_type_based_alias_analysis_pass(),
+        _ => unreachable!("passes are checked by parse"),
+    }
+}
@@ -0,0 +1,127 @@
+// Profile guided optimisation of executables. An executable built with
+// --profile-generate counts which way each while and if went and writes the
+// counts to a profile when it exits. Building again with --profile-use gives
+// them to LLVM as the weights of the branches, so it lays out and unrolls
+// the hot loops rather than guessing which ones are. Each while and if is
+// found in the profile by where it is in the source, so one that moved
+// since the profile was made has no weights.
+
+use std::collections::BTreeMap;
+
+use crate::lexer::Span;
+
+// How often each while went round and each if took its then (held), and
+// how often not, by line and column
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Profile {
+    pub branches: BTreeMap<(usize, usize), (u64, u64)>,
+}
+
+impl Profile {
+    // Reads a profile an executable wrote, `while LINE:COLUMN HELD NOT_HELD`
+    // or `if ...` on each line. Lines for the same place are added together,
+    // so the profiles of several runs can be joined into one.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut profile = Profile::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = || format!("line {} isn't a while or if and its counts", number + 1);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (place, held, not_held) = match words[..] {
+                ["while" | "if", place, held, not_held] => (place, held, not_held),
+                _ => return Err(bad()),
+            };
+            let (row, column) = place.split_once(':').ok_or_else(bad)?;
+            let number = |n: &str| n.parse::<u64>().map_err(|_| bad());
+            let place = (number(row)? as usize, number(column)? as usize);
+            let counts = profile.branches.entry(place).or_default();
+            counts.0 = counts.0.saturating_add(number(held)?);
+            counts.1 = counts.1.saturating_add(number(not_held)?);
+        }
+        Ok(profile)
+    }
+
+    pub fn get(&self, span: Span) -> Option<(u64, u64)> {
+        self.branches.get(&(span.line, span.column)).copied()
+    }
+}
+
+// Counts as LLVM's branch weights, which are 32 bits. Big counts are scaled
+// down together, and each has 1 added so a way never taken is still
+// possible, as clang does.
+pub fn weights(held: u64, not_held: u64) -> (u32, u32) {
+    let scale = held.max(not_held) / u32::MAX as u64 + 1;
+    (
+        (held / scale + 1).min(u32::MAX as u64) as u32,
+        (not_held / scale + 1).min(u32::MAX as u64) as u32,
+    )
+}
+
+// A C string literal of text
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// The C compiled into executables built with --profile-generate: the
+// counters the generated code adds to, two for each while or if in sites
+// (held, then not), and bbvm_pgo_start, which the program calls first to
+// have the counts written to path when the executable exits, halts
+// included
+pub fn runtime(sites: &[String], path: &str) -> String {
+    let names: Vec<String> = sites.iter().map(|site| quote(site)).collect();
+    format!(
+        r##"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#define BBVM_PGO_SITES {count}
+
+uint64_t bbvm_pgo_counts[BBVM_PGO_SITES * 2 + 2];
+static const char *bbvm_pgo_sites[BBVM_PGO_SITES + 1] = {{{names}}};
+
+static void bbvm_pgo_write(void) {{
+    FILE *file = fopen({path}, "w");
+    if (!file) {{
+        fprintf(stderr, "bbvm: failed to write the profile to %s\n", {path});
+        return;
+    }}
+    fputs("# bbvm profile: how often each while went round and each if took its then, and how often not\n", file);
+    for (int i = 0; i < BBVM_PGO_SITES; i++) {{
+        fprintf(file, "%s %llu %llu\n", bbvm_pgo_sites[i],
+                (unsigned long long)bbvm_pgo_counts[i * 2],
+                (unsigned long long)bbvm_pgo_counts[i * 2 + 1]);
+    }}
+    fclose(file);
+}}
+
+void bbvm_pgo_start(void) {{
+    static int started;
+    if (!started) {{
+        started = 1;
+        atexit(bbvm_pgo_write);
+    }}
+}}
+"##,
+        count = sites.len(),
+        names = if names.is_empty() {
+            "0".to_string()
+        } else {
+            names.join(", ")
+        },
+        path = quote(path),
+    )
+}
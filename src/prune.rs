@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+
+use crate::parser::{Block, Node};
+use crate::token::{
+    Assert, Bound, OneParam, OneParamType, Rand, Set, Statement, StatementImpl, TwoParam,
+    TwoParamType,
+};
+
+// Calls f with each statement of the block that isn't itself a block, and
+// the variables each while and if checks
+fn walk<'a>(block: &Block<'a>, f: &mut dyn FnMut(Option<&Statement<'a>>, Option<&'a str>)) {
+    for node in block {
+        match node {
+            Node::Statement(statement, _) => f(Some(statement), None),
+            Node::Loop {
+                var, check, body, ..
+            } => {
+                f(None, Some(var.ident));
+                if let Bound::Variable(bound) = check {
+                    f(None, Some(bound.ident));
+                }
+                walk(body, f);
+            }
+            Node::If {
+                var,
+                then,
+                otherwise,
+                ..
+            } => {
+                f(None, Some(var.ident));
+                walk(then, f);
+                if let Some((_, otherwise)) = otherwise {
+                    walk(otherwise, f);
+                }
+            }
+            Node::Proc { body, .. } => walk(body, f),
+        }
+    }
+}
+
+// The variables that matter to what the program does: the ones it prints
+// with `print`, exits with, checks with `assert` or decides a while or if
+// on, and every
+// variable copied or added into those. Arrays aren't followed, so every
+// variable used with one counts.
+pub fn live<'a>(block: &Block<'a>) -> HashSet<&'a str> {
+    let mut live = HashSet::new();
+    // Each copy, add, sub, mul or move as (from, to), and each swap both ways
+    let mut flows: Vec<(&str, &str)> = vec![];
+    walk(block, &mut |statement, checked| {
+        live.extend(checked);
+        match statement {
+            Some(Statement::OneParam(OneParam {
+                one,
+                ty: OneParamType::Print | OneParamType::Halt,
+            })) => {
+                live.insert(one.ident);
+            }
+            Some(Statement::Assert(Assert { param, .. })) => {
+                live.insert(param.ident);
+            }
+            Some(Statement::TwoParam(TwoParam { one, two, ty })) => {
+                flows.push((one.ident, two.ident));
+                if *ty == TwoParamType::Swap {
+                    flows.push((two.ident, one.ident));
+                }
+            }
+            Some(Statement::Indexed(indexed)) => live.extend(indexed.get_variables()),
+            _ => {}
+        }
+    });
+    loop {
+        let before = live.len();
+        for &(from, to) in &flows {
+            if live.contains(to) {
+                live.insert(from);
+            }
+        }
+        if live.len() == before {
+            return live;
+        }
+    }
+}
+
+// Removes the statements that only change variables that aren't live,
+// returning how many were removed. Inputs are kept, they are read whether
+// or not the statement is there.
+pub fn prune(block: &mut Block, live: &HashSet<&str>) -> usize {
+    let before = block.len();
+    block.retain(|node| match node {
+        Node::Statement(Statement::OneParam(OneParam { one, ty }), _) => {
+            *ty == OneParamType::Input || live.contains(one.ident)
+        }
+        Node::Statement(Statement::TwoParam(TwoParam { one, two, ty }), _) => {
+            // A swap or move changes both of its variables
+            live.contains(two.ident)
+                || (matches!(ty, TwoParamType::Swap | TwoParamType::Move)
+                    && live.contains(one.ident))
+        }
+        Node::Statement(Statement::Set(Set { var, .. }), _)
+        | Node::Statement(Statement::Rand(Rand { var, .. }), _) => live.contains(var.ident),
+        _ => true,
+    });
+    let mut removed = before - block.len();
+    for node in block.iter_mut() {
+        match node {
+            Node::Loop { body, .. } | Node::Proc { body, .. } => removed += prune(body, live),
+            Node::If {
+                then, otherwise, ..
+            } => {
+                removed += prune(then, live);
+                if let Some((_, otherwise)) = otherwise {
+                    removed += prune(otherwise, live);
+                }
+            }
+            Node::Statement(..) => {}
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The variables live in the program, and the program once pruned
+    fn pruned(source: &str) -> (Vec<&str>, String) {
+        let mut program = crate::parse(source).unwrap();
+        let live = live(&program.body);
+        prune(&mut program.body, &live);
+        let mut live: Vec<&str> = live.into_iter().collect();
+        live.sort_unstable();
+        (live, program.to_source())
+    }
+
+    #[test]
+    fn keeps_what_is_printed() {
+        let (live, source) = pruned("incr X; incr Y; copy X to Z; print Z;");
+        assert_eq!(live, ["X", "Z"]);
+        assert_eq!(source, "incr X;\ncopy X to Z;\nprint Z;\n");
+    }
+
+    #[test]
+    fn keeps_swaps_and_moves_into_or_out_of_live_variables() {
+        // Z comes from Y through the move and Y from X through the swap,
+        // while nothing printed comes from W or V
+        let (live, source) =
+            pruned("incr X; incr W; swap X Y; swap W V; move Y to Z; incr V; print Z;");
+        assert_eq!(live, ["X", "Y", "Z"]);
+        assert_eq!(source, "incr X;\nswap X Y;\nmove Y to Z;\nprint Z;\n");
+
+        // Moving a live variable out clears it, so the move stays even though
+        // where it goes is never printed
+        let (live, source) = pruned("incr X; move X to Y; print X;");
+        assert_eq!(live, ["X"]);
+        assert_eq!(source, "incr X;\nmove X to Y;\nprint X;\n");
+    }
+
+    #[test]
+    fn keeps_what_loops_check_and_inputs() {
+        let (live, source) = pruned("input X; input Y; while X not 0 do; incr Y; decr X; end;");
+        assert_eq!(live, ["X"]);
+        assert_eq!(
+            source,
+            "input X;\ninput Y;\nwhile X not 0 do;\n    decr X;\nend;\n"
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+// The generator behind `rand X N`, an xorshift64*. 0 until first used, when
+// it is seeded differently for each run.
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+fn next() -> u64 {
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        // RandomState is keyed randomly for each process
+        x = RandomState::new().build_hasher().finish() | 1;
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    STATE.store(x, Ordering::Relaxed);
+    x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+// A uniformly random number below bound, or 0 if bound is 0. Called by the
+// interpreter, and by the JIT'd code for `rand`.
+pub extern "C" fn bbvm_rand(bound: u64) -> u64 {
+    if bound == 0 {
+        return 0;
+    }
+    // Numbers past the last whole multiple of bound would favour the
+    // smaller results, so those are drawn again
+    let limit = u64::MAX - u64::MAX % bound;
+    loop {
+        let n = next();
+        if n < limit {
+            return n % bound;
+        }
+    }
+}
+
+// bbvm_rand in C, compiled into executables that use `rand`, as bbvm isn't
+// there for them to call
+pub const RUNTIME: &str = r#"#include <stdint.h>
+#include <time.h>
+
+static uint64_t state;
+
+uint64_t bbvm_rand(uint64_t bound) {
+    if (bound == 0) {
+        return 0;
+    }
+    if (state == 0) {
+        state = ((uint64_t)time(NULL) << 20) ^ (uint64_t)clock() ^ (uint64_t)(uintptr_t)&state;
+        state |= 1;
+    }
+    uint64_t limit = UINT64_MAX - UINT64_MAX % bound;
+    for (;;) {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        uint64_t n = state * 0x2545f4914f6cdd1dULL;
+        if (n < limit) {
+            return n % bound;
+        }
+    }
+}
+"#;
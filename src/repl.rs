@@ -0,0 +1,112 @@
+use std::{
+    io::{stdin, stdout, Write},
+    iter::zip,
+};
+
+use crate::error::BbvmError;
+use crate::ext::{self, Extension};
+use crate::input;
+use crate::interpret::Interpreter;
+use crate::lexer::Lexer;
+use crate::{collect_inputs, collect_variables, lex};
+
+// Whether the last line of code (ignoring comments) is still missing its ;
+fn unfinished(buffer: &str) -> bool {
+    buffer
+        .lines()
+        .map(str::trim)
+        .rfind(|l| !l.is_empty() && !l.starts_with('#'))
+        .map_or(false, |l| !l.ends_with(';'))
+}
+
+fn has_code(buffer: &str) -> bool {
+    buffer
+        .lines()
+        .map(str::trim)
+        .any(|l| !l.is_empty() && !l.starts_with('#'))
+}
+
+fn value_of(values: &[(String, u64)], var: &str) -> u64 {
+    values
+        .iter()
+        .find(|(v, _)| v == var)
+        .map_or(0, |&(_, value)| value)
+}
+
+// Runs one complete piece of the program, starting from the values so far.
+// A piece that fails leaves the variables as they were.
+fn run_piece(
+    source: &str,
+    values: &mut Vec<(String, u64)>,
+    detect_overflow: bool,
+    extensions: &[Extension],
+) -> Result<(), BbvmError> {
+    let (statements, spans) = lex(&mut Lexer::new(source))?;
+    ext::check(&statements, &spans, extensions)?;
+    let mut interpreter = Interpreter::new(&statements, &spans)?;
+    if detect_overflow {
+        interpreter.enable_overflow_detection();
+    }
+
+    let variables = collect_variables(&statements);
+    let inputs = collect_inputs(&statements);
+    let answers = input::prompt(&inputs);
+    let mut start: Vec<(&str, u64)> = variables
+        .iter()
+        .map(|&v| (v, value_of(values, v)))
+        .collect();
+    start.extend(zip(inputs.iter().copied(), answers));
+
+    interpreter.execute(&start)?;
+    for (var, value) in interpreter.results(&variables) {
+        match values.iter_mut().find(|(v, _)| v == var) {
+            Some(old) => old.1 = value,
+            None => values.push((var.to_string(), value)),
+        }
+    }
+    Ok(())
+}
+
+// Reads statements from stdin, running each as soon as it is complete (a
+// while once its end has been typed) and printing every variable after it.
+// Variables keep their values until `quit` or the end of the input.
+pub fn repl(detect_overflow: bool, extensions: &[Extension]) -> () {
+    let mut values: Vec<(String, u64)> = vec![];
+    let mut buffer = String::new();
+    loop {
+        print!("{}", if buffer.is_empty() { "bb> " } else { "... " });
+        stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin().read_line(&mut line).unwrap() == 0 {
+            println!();
+            return;
+        }
+        if buffer.is_empty() && line.trim() == "quit" {
+            return;
+        }
+        buffer.push_str(&line);
+        if !has_code(&buffer) {
+            buffer.clear();
+            continue;
+        }
+        if unfinished(&buffer) {
+            continue;
+        }
+
+        match run_piece(&buffer, &mut values, detect_overflow, extensions) {
+            Ok(()) => {
+                // printf's %lld in the JIT prints the values as signed
+                for (var, value) in &values {
+                    println!("{}: {}", var, *value as i64);
+                }
+            }
+            Err(
+                BbvmError::UnclosedWhile { .. }
+                | BbvmError::UnclosedIf { .. }
+                | BbvmError::UnclosedProc { .. },
+            ) => continue,
+            Err(e) => eprintln!("{}", e.render(&buffer, "<repl>")),
+        }
+        buffer.clear();
+    }
+}
@@ -0,0 +1,286 @@
+// Runs programs from the bitcode `bbvm build --emit llvm-bc` writes, so the
+// IR is generated and optimised once and the JIT runs it again with
+// different inputs each time. Nothing in the bitcode itself says which
+// function is the program or which of its values are which variables, so
+// the converter keeps that in the module's metadata as its interface.
+
+use std::{fs, io::Read, io::Write, iter::zip, path::Path};
+
+use chrono::{DateTime, Duration, Utc};
+use inkwell::{
+    context::Context,
+    execution_engine::{ExecutionEngine, JitFunction},
+    module::Module,
+    values::BasicMetadataValueEnum,
+    OptimizationLevel,
+};
+
+use crate::bignum::Value;
+use crate::convert;
+use crate::input;
+use crate::output::OutputFormat;
+
+// The named metadata holding each program's interface, one node of
+// key=value strings per file in the module
+pub const INTERFACE: &str = "bbvm.interface";
+// The name of the main add_entry picked, in modules of several files
+pub const ENTRY: &str = "bbvm.entry";
+
+// What a program's main returns when it reaches its end, and when it stops
+// after printing a runtime failure (an assert, running out of fuel or
+// overflowing). A halt returns its status, which is 32 bits, so it can't be
+// mistaken for either.
+pub const FINISHED: i64 = -1;
+pub const FAILED: i64 = -2;
+
+// How a program stopped before its end, from what its main returned
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Stopped {
+    Halted(i32),
+    Failed,
+}
+
+impl Stopped {
+    pub fn from_main(returned: i64) -> Option<Self> {
+        match returned {
+            FINISHED => None,
+            FAILED => Some(Stopped::Failed),
+            status => Some(Stopped::Halted(status as i32)),
+        }
+    }
+
+    // The status bbvm exits with, as a compiled program would
+    pub fn status(self) -> i32 {
+        match self {
+            Stopped::Halted(status) => status,
+            Stopped::Failed => 1,
+        }
+    }
+}
+
+// What calling a program's main needs
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Interface {
+    pub main: String,
+    // The bits in each input and result, at least 64
+    pub width: u32,
+    // Read in this order, as they are sorted
+    pub inputs: Vec<String>,
+    // Given back in this order
+    pub printed: Vec<String>,
+}
+
+impl Interface {
+    // The strings stored in the interface's metadata node
+    pub fn fields(&self) -> Vec<String> {
+        vec![
+            format!("main={}", self.main),
+            format!("width={}", self.width),
+            format!("inputs={}", self.inputs.join(",")),
+            format!("printed={}", self.printed.join(",")),
+        ]
+    }
+
+    pub fn parse(fields: &[&str]) -> Result<Self, String> {
+        let field = |key: &str| {
+            fields
+                .iter()
+                .find_map(|f| f.strip_prefix(key)?.strip_prefix('='))
+                .ok_or_else(|| format!("its interface has no {}", key))
+        };
+        // Variable names never have commas in them
+        let list = |value: &str| {
+            value
+                .split(',')
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        Ok(Self {
+            main: field("main")?.to_string(),
+            width: field("width")?
+                .parse()
+                .map_err(|_| "its interface has a bad width".to_string())?,
+            inputs: list(field("inputs")?),
+            printed: list(field("printed")?),
+        })
+    }
+}
+
+// Whether the file at path is LLVM bitcode, going by its first bytes: the
+// bitcode magic, or the wrapper some platforms put around it
+pub fn is_bitcode(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .is_ok()
+        && (magic == *b"BC\xC0\xDE" || magic == [0xDE, 0xC0, 0x17, 0x0B])
+}
+
+// The strings in each node of the named metadata key
+fn nodes(module: &Module, key: &str) -> Vec<Vec<String>> {
+    module
+        .get_global_metadata(key)
+        .into_iter()
+        .map(|node| {
+            node.get_node_values()
+                .into_iter()
+                .filter_map(|value| match value {
+                    BasicMetadataValueEnum::MetadataValue(m) => {
+                        Some(m.get_string_value()?.to_string_lossy().into_owned())
+                    }
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// The interface of the program the module runs: the file called entry if
+// given (its name without .bb, as `bbvm compile` named its functions), or
+// else its only one, or the one add_entry picked when there are several
+pub fn interface(module: &Module, entry: Option<&str>) -> Result<Interface, String> {
+    let interfaces = nodes(module, INTERFACE)
+        .iter()
+        .map(|fields| Interface::parse(&fields.iter().map(String::as_str).collect::<Vec<_>>()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(entry) = entry {
+        let main = format!("bbvm_{}_main", entry);
+        return match interfaces.iter().position(|i| i.main == main) {
+            Some(index) => Ok(interfaces.into_iter().nth(index).unwrap()),
+            None => Err(format!(
+                "it has no program called {}, only {}",
+                entry,
+                file_names(&interfaces).join(", ")
+            )),
+        };
+    }
+    if interfaces.len() <= 1 {
+        return interfaces
+            .into_iter()
+            .next()
+            .ok_or_else(|| "it has no bbvm interface, it wasn't written by bbvm".to_string());
+    }
+    let entry = nodes(module, ENTRY)
+        .into_iter()
+        .flatten()
+        .next()
+        .ok_or_else(|| {
+            format!(
+                "it holds {} programs and none was picked to run",
+                interfaces.len()
+            )
+        })?;
+    interfaces
+        .into_iter()
+        .find(|i| i.main == entry)
+        .ok_or_else(|| format!("its entry {} isn't one of its programs", entry))
+}
+
+// The names of the files the programs were compiled from, or their
+// functions' names for a module of one program, which isn't named after
+// its file
+fn file_names(interfaces: &[Interface]) -> Vec<String> {
+    interfaces
+        .iter()
+        .map(|i| {
+            i.main
+                .strip_prefix("bbvm_")
+                .and_then(|name| name.strip_suffix("_main"))
+                .unwrap_or(&i.main)
+                .to_string()
+        })
+        .collect()
+}
+
+// Loads the bitcode at path and runs its program, or the one called entry,
+// under the JIT, with the inputs from args (as given to --args) or stdin,
+// writing the results to out in format. What print statements print still
+// goes to stdout. Returns how long loading and compiling the program took,
+// and then running it, and how it stopped if it halted or failed.
+pub fn run(
+    path: &Path,
+    entry: Option<&str>,
+    args: Option<&str>,
+    format: OutputFormat,
+    out: &mut impl Write,
+) -> Result<(Duration, Duration, Option<Stopped>), String> {
+    let compile_start = Utc::now();
+    let context = Context::create();
+    let module = Module::parse_bitcode_from_path(path, &context).map_err(|e| {
+        format!(
+            "Failed to load {}: {}",
+            path.display(),
+            e.to_string().trim()
+        )
+    })?;
+    let interface =
+        interface(&module, entry).map_err(|e| format!("Can't run {}: {}", path.display(), e))?;
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::Aggressive)
+        .map_err(|e| format!("Unable to create execution engine: {}", e))?;
+    convert::map_runtime(&module, &execution_engine);
+    let program = Program {
+        interface: &interface,
+        args,
+        format,
+        compile_start,
+    };
+    if interface.width > 64 {
+        program.run::<u128>(&execution_engine, out)
+    } else {
+        program.run::<u64>(&execution_engine, out)
+    }
+}
+
+struct Program<'i> {
+    interface: &'i Interface,
+    args: Option<&'i str>,
+    format: OutputFormat,
+    compile_start: DateTime<Utc>,
+}
+
+impl Program<'_> {
+    // Calls main as Converter::run_with does, passing the inputs and
+    // results as V, which must be the same size as the interface's width
+    fn run<V: Value + 'static>(
+        &self,
+        execution_engine: &ExecutionEngine,
+        out: &mut impl Write,
+    ) -> Result<(Duration, Duration, Option<Stopped>), String> {
+        let mut write = |text: &str| {
+            out.write_all(text.as_bytes())
+                .map_err(|e| format!("Failed to write the program's output: {}", e))
+        };
+        let main: JitFunction<unsafe extern "C" fn(*const V, *mut V) -> i64> = unsafe {
+            execution_engine
+                .get_function(&self.interface.main)
+                .map_err(|_| format!("There is no function {} to run", self.interface.main))?
+        };
+        let compile_time = Utc::now() - self.compile_start;
+        let inputs: Vec<&str> = self.interface.inputs.iter().map(String::as_str).collect();
+        if self.format.separators() {
+            write("-----\n")?;
+        }
+        let values: Vec<V> = input::read(&inputs, self.args);
+        if self.format.separators() {
+            write("-----\n")?;
+        }
+
+        let mut results = vec![V::default(); self.interface.printed.len().max(1)];
+        let start = Utc::now();
+        let stopped =
+            Stopped::from_main(unsafe { main.call(values.as_ptr(), results.as_mut_ptr()) });
+        let duration = Utc::now() - start;
+        if stopped.is_some() {
+            return Ok((compile_time, duration, stopped));
+        }
+        let printed = self.interface.printed.iter().map(String::as_str);
+        let results: Vec<(&str, V)> = zip(printed, results).collect();
+        write(&self.format.render(&results))?;
+        if self.format.separators() {
+            write("-----\n")?;
+        }
+        Ok((compile_time, duration, None))
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    ffi::CStr,
+    io::{stdout, Write},
+    os::raw::c_char,
+};
+
+// What the generated code calls to print, instead of printf, so it doesn't
+// depend on how a target passes C varargs. It prints before, the number and
+// after, each of the strings NUL terminated or null for none. kind says
+// what the number is: NONE leaves it out, SIGNED prints low as an i64 and
+// UNSIGNED prints high and low as the halves of a u128.
+pub const NONE: u64 = 0;
+pub const SIGNED: u64 = 1;
+pub const UNSIGNED: u64 = 2;
+
+fn text<'s>(s: *const c_char) -> std::borrow::Cow<'s, str> {
+    if s.is_null() {
+        "".into()
+    } else {
+        unsafe { CStr::from_ptr(s) }.to_string_lossy()
+    }
+}
+
+// bbvm_print for the JIT. It is flushed each time, as a halt exits without
+// flushing Rust's stdout.
+pub extern "C" fn bbvm_print(
+    before: *const c_char,
+    kind: u64,
+    low: u64,
+    high: u64,
+    after: *const c_char,
+) {
+    let number = match kind {
+        SIGNED => (low as i64).to_string(),
+        UNSIGNED => ((high as u128) << 64 | low as u128).to_string(),
+        _ => String::new(),
+    };
+    let mut out = stdout().lock();
+    out.write_all(format!("{}{}{}", text(before), number, text(after)).as_bytes())
+        .and_then(|_| out.flush())
+        .ok();
+}
+
+// bbvm_print in C, compiled into executables as bbvm isn't there for them
+// to call, and bbvm_read_input, which their main reads the inputs with.
+// Each print is one write, so nothing is left in a buffer when the program
+// exits. Only write, read and exit are needed from the C library, which a
+// freestanding target can provide itself.
+pub const RUNTIME: &str = r#"#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+#ifdef _WIN32
+#include <io.h>
+#define write _write
+#define read _read
+#else
+#include <unistd.h>
+#endif
+
+// The decimal digits of high:low, ending just before end
+static char *bbvm_digits(char *end, uint64_t high, uint64_t low) {
+    do {
+        // high:low / 10, a 32 bit part at a time
+        uint64_t rem = high % 10;
+        high /= 10;
+        uint64_t part = (rem << 32) | (low >> 32);
+        uint64_t upper = part / 10;
+        part = ((part % 10) << 32) | (low & 0xffffffff);
+        low = (upper << 32) | (part / 10);
+        *--end = (char)('0' + part % 10);
+    } while (high != 0 || low != 0);
+    return end;
+}
+
+void bbvm_print(const char *before, uint64_t kind, uint64_t low, uint64_t high, const char *after) {
+    char buffer[512];
+    char number[48];
+    char *start = number + sizeof(number);
+    if (kind == 1) {
+        int negative = (int64_t)low < 0;
+        start = bbvm_digits(start, 0, negative ? 0 - low : low);
+        if (negative) {
+            *--start = '-';
+        }
+    } else if (kind == 2) {
+        start = bbvm_digits(start, high, low);
+    }
+    size_t used = 0;
+    const char *parts[3] = {before, start, after};
+    size_t lengths[3] = {
+        before ? strlen(before) : 0,
+        (size_t)(number + sizeof(number) - start),
+        after ? strlen(after) : 0,
+    };
+    for (int i = 0; i < 3; i++) {
+        // Names can be long, so a full buffer is written out first
+        if (used + lengths[i] > sizeof(buffer)) {
+            write(1, buffer, used);
+            used = 0;
+            if (lengths[i] > sizeof(buffer)) {
+                write(1, parts[i], lengths[i]);
+                continue;
+            }
+        }
+        memcpy(buffer + used, parts[i], lengths[i]);
+        used += lengths[i];
+    }
+    write(1, buffer, used);
+}
+
+static void bbvm_fail(const char *before, const char *name) {
+    write(2, before, strlen(before));
+    write(2, name, strlen(name));
+    write(2, "\n", 1);
+    exit(1);
+}
+
+// Adds digit to value, failing if it doesn't fit in 64 bits
+static uint64_t bbvm_digit(uint64_t value, char digit, const char *name) {
+    uint64_t d = (uint64_t)(digit - '0');
+    if (value > (UINT64_MAX - d) / 10) {
+        bbvm_fail("ERROR: the value is too big for ", name);
+    }
+    return value * 10 + d;
+}
+
+// The input at index of the count the program has, called name: the
+// executable's argument for it when it was given one for each input, in
+// sorted order of their names, or else the next number on stdin
+uint64_t bbvm_read_input(int argc, char **argv, uint64_t index, uint64_t count, const char *name) {
+    uint64_t value = 0;
+    if (argc > 1) {
+        if ((uint64_t)argc != count + 1) {
+            bbvm_fail("ERROR: give one value for each input, in sorted order of their names, or none to read them from stdin", "");
+        }
+        const char *arg = argv[index + 1];
+        if (*arg == '\0') {
+            bbvm_fail("ERROR: no value was given for ", name);
+        }
+        for (; *arg != '\0'; arg++) {
+            if (*arg < '0' || *arg > '9') {
+                bbvm_fail("ERROR: not a valid value for ", name);
+            }
+            value = bbvm_digit(value, *arg, name);
+        }
+        return value;
+    }
+    char c;
+    long got;
+    while ((got = (long)read(0, &c, 1)) == 1 && (c == ' ' || c == '\t' || c == '\r' || c == '\n')) {
+    }
+    if (got != 1 || c < '0' || c > '9') {
+        bbvm_fail("ERROR: no value was given for ", name);
+    }
+    do {
+        value = bbvm_digit(value, c, name);
+    } while ((got = (long)read(0, &c, 1)) == 1 && c >= '0' && c <= '9');
+    if (got == 1 && c != ' ' && c != '\t' && c != '\r' && c != '\n') {
+        bbvm_fail("ERROR: not a valid value for ", name);
+    }
+    return value;
+}
+"#;
@@ -0,0 +1,156 @@
+use crate::lexer::Span;
+use crate::parser::{Block, Node};
+use crate::token::{
+    Bound, Comparison, Identifier, Number, OneParam, OneParamType, Statement, TwoParam,
+    TwoParamType,
+};
+
+// Rewrites the counting loops BareBones programs are built from into the
+// arithmetic they work out, before lowering. LLVM doesn't always see
+// through the saturating decr, so without this a multiplication takes
+// time proportional to the product. The loops rewritten are
+//
+//   while X not 0 do;
+//       incr Y;
+//       decr X;
+//   end;
+//
+// in any order, and with any number of other variables each incremented
+// or decremented once, which becomes `add X to Y; clear X;` (`sub X from
+// Y;` for a decr). Returns how many loops were rewritten.
+pub fn simplify(block: &mut Block) -> usize {
+    let mut rewritten = 0;
+    let nodes = std::mem::take(block);
+    for mut node in nodes {
+        match &mut node {
+            Node::Loop {
+                var,
+                cmp,
+                check,
+                body,
+                span,
+                ..
+            } => {
+                // Inner loops first, so the outer one can see what they became
+                rewritten += simplify(body);
+                if *cmp == Comparison::Ne && *check == Bound::Number(Number { value: 0 }) {
+                    if let Some(arithmetic) = counting_loop(*var, body, *span) {
+                        rewritten += 1;
+                        block.extend(arithmetic);
+                        continue;
+                    }
+                }
+            }
+            Node::If {
+                then, otherwise, ..
+            } => {
+                rewritten += simplify(then);
+                if let Some((_, otherwise)) = otherwise {
+                    rewritten += simplify(otherwise);
+                }
+            }
+            Node::Proc { body, .. } => rewritten += simplify(body),
+            Node::Statement(..) => {}
+        }
+        block.push(node);
+    }
+    rewritten
+}
+
+// The statements the loop over var is the same as, if it is a counting loop
+fn counting_loop<'a>(var: Identifier<'a>, body: &Block<'a>, span: Span) -> Option<Block<'a>> {
+    let mut decrs = 0;
+    let mut counted: Vec<(Identifier, TwoParamType)> = vec![];
+    for node in body {
+        let (one, ty) = match node {
+            Node::Statement(Statement::OneParam(OneParam { one, ty }), _) => (*one, *ty),
+            _ => return None,
+        };
+        if one == var {
+            if ty != OneParamType::Decr {
+                return None;
+            }
+            decrs += 1;
+            continue;
+        }
+        // Counting by more than one at a time would need a multiplication
+        if counted.iter().any(|&(counter, _)| counter == one) {
+            return None;
+        }
+        counted.push((
+            one,
+            match ty {
+                OneParamType::Incr => TwoParamType::Add,
+                OneParamType::Decr => TwoParamType::Sub,
+                _ => return None,
+            },
+        ));
+    }
+    if decrs != 1 {
+        return None;
+    }
+
+    let mut arithmetic: Block = counted
+        .into_iter()
+        .map(|(two, ty)| Node::Statement(Statement::TwoParam(TwoParam { one: var, two, ty }), span))
+        .collect();
+    arithmetic.push(Node::Statement(
+        Statement::OneParam(OneParam {
+            one: var,
+            ty: OneParamType::Clear,
+        }),
+        span,
+    ));
+    Some(arithmetic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The program after simplify, and how many loops it rewrote
+    fn simplified(source: &str) -> (String, usize) {
+        let mut program = crate::parse(source).unwrap();
+        let rewritten = simplify(&mut program.body);
+        (program.to_source(), rewritten)
+    }
+
+    #[test]
+    fn rewrites_counting_loops() {
+        let (source, rewritten) =
+            simplified("while X not 0 do; incr Y; decr X; decr Z; end; incr X;");
+        assert_eq!(source, "add X to Y;\nsub X from Z;\nclear X;\nincr X;\n");
+        assert_eq!(rewritten, 1);
+    }
+
+    #[test]
+    fn rewrites_inner_loops_first() {
+        let (source, rewritten) =
+            simplified("while X not 0 do; while Y not 0 do; decr Y; incr Z; end; decr X; end;");
+        assert_eq!(
+            source,
+            "while X not 0 do;\n    add Y to Z;\n    clear Y;\n    decr X;\nend;\n"
+        );
+        assert_eq!(rewritten, 1);
+    }
+
+    #[test]
+    fn leaves_other_loops_alone() {
+        for source in [
+            // Counting Y up twice a time round
+            "while X not 0 do; incr Y; incr Y; decr X; end;",
+            // Counting X down twice
+            "while X not 0 do; incr Y; decr X; decr X; end;",
+            // Never counting X down
+            "while X not 0 do; incr Y; end;",
+            // Stopping somewhere other than 0
+            "while X not 1 do; incr Y; decr X; end;",
+            // Doing more than counting
+            "while X not 0 do; incr Y; clear Z; decr X; end;",
+        ] {
+            let (simplified, rewritten) = simplified(source);
+            assert_eq!(simplified, crate::parse(source).unwrap().to_source());
+            assert_eq!(rewritten, 0, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::fs;
+
+use chrono::{Duration, Utc};
+
+// Peak resident set size of this process in KiB, where the OS exposes it.
+pub fn peak_memory_kib() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find(|l| l.starts_with("VmHWM:"))?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+// What --stats reports: how long each phase took, in the order they ran,
+// and how big the program and its IR are
+#[derive(Debug, Default)]
+pub struct Stats {
+    phases: Vec<(&'static str, Duration)>,
+    statements: usize,
+    // Basic blocks and instructions as generated, then after optimisation
+    ir: Option<(u32, usize)>,
+    optimised_ir: Option<(u32, usize)>,
+    // Each pass or pipeline optimisation ran and whether it changed the IR
+    passes: Vec<(String, bool)>,
+}
+
+impl Stats {
+    // Runs f, adding how long it took to the phase
+    pub fn time<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Utc::now();
+        let result = f();
+        self.add(phase, Utc::now() - start);
+        result
+    }
+
+    pub fn add(&mut self, phase: &'static str, duration: Duration) -> () {
+        match self.phases.iter_mut().find(|(p, _)| *p == phase) {
+            Some((_, total)) => *total += duration,
+            None => self.phases.push((phase, duration)),
+        }
+    }
+
+    pub fn set_statements(&mut self, statements: usize) -> () {
+        self.statements = statements;
+    }
+
+    pub fn set_ir_size(&mut self, size: (u32, usize)) -> () {
+        self.ir = Some(size);
+    }
+
+    pub fn set_optimised_ir_size(&mut self, size: (u32, usize)) -> () {
+        self.optimised_ir = Some(size);
+    }
+
+    pub fn set_passes(&mut self, passes: &[(String, bool)]) -> () {
+        self.passes = passes.to_vec();
+    }
+
+    // One line for each number, aligned
+    pub fn render(&self) -> String {
+        let mut lines: Vec<(String, String)> =
+            vec![("statements".to_string(), self.statements.to_string())];
+        for (name, size) in [("generated", self.ir), ("optimised", self.optimised_ir)] {
+            if let Some((blocks, instructions)) = size {
+                lines.push((format!("{} basic blocks", name), blocks.to_string()));
+                lines.push((format!("{} instructions", name), instructions.to_string()));
+            }
+        }
+        if !self.passes.is_empty() {
+            for (changed, label) in [
+                (true, "passes that changed the IR"),
+                (false, "passes that didn't"),
+            ] {
+                let names: Vec<&str> = self
+                    .passes
+                    .iter()
+                    .filter(|(_, c)| *c == changed)
+                    .map(|(name, _)| name.as_str())
+                    .collect();
+                let names = if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                };
+                lines.push((label.to_string(), names));
+            }
+        }
+        for (phase, duration) in &self.phases {
+            lines.push((
+                format!("{} time", phase.replace('_', " ")),
+                format!(
+                    "{:.3} ms",
+                    duration.num_nanoseconds().unwrap_or_default() as f64 / 1e6
+                ),
+            ));
+        }
+        if let Some(peak) = peak_memory_kib() {
+            lines.push(("peak memory".to_string(), format!("{} KiB", peak)));
+        }
+        let width = lines.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        lines
+            .iter()
+            .map(|(name, value)| format!("{:width$}  {}\n", name, value, width = width))
+            .collect()
+    }
+
+    // The same as one JSON object, with the times in nanoseconds
+    pub fn render_json(&self) -> String {
+        let mut fields = vec![format!("\"statements\": {}", self.statements)];
+        for (name, size) in [("generated", self.ir), ("optimised", self.optimised_ir)] {
+            if let Some((blocks, instructions)) = size {
+                fields.push(format!("\"{}_basic_blocks\": {}", name, blocks));
+                fields.push(format!("\"{}_instructions\": {}", name, instructions));
+            }
+        }
+        let times: Vec<String> = self
+            .phases
+            .iter()
+            .map(|(phase, duration)| {
+                format!(
+                    "\"{}\": {}",
+                    phase,
+                    duration.num_nanoseconds().unwrap_or_default()
+                )
+            })
+            .collect();
+        fields.push(format!("\"times_ns\": {{{}}}", times.join(", ")));
+        if !self.passes.is_empty() {
+            let passes: Vec<String> = self
+                .passes
+                .iter()
+                .map(|(name, changed)| {
+                    format!("{{\"name\": \"{}\", \"changed\": {}}}", name, changed)
+                })
+                .collect();
+            fields.push(format!("\"passes\": [{}]", passes.join(", ")));
+        }
+        if let Some(peak) = peak_memory_kib() {
+            fields.push(format!("\"peak_memory_kib\": {}", peak));
+        }
+        format!("{{{}}}\n", fields.join(", "))
+    }
+}
@@ -0,0 +1,278 @@
+// Reading a program a chunk at a time from anything that implements Read,
+// for --stream. The file is gone over twice: scan finds the names in it,
+// which are all that is kept, and lower lexes it again and generates the IR
+// statement by statement. Neither holds more than a chunk of the source.
+
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader, Read},
+};
+
+use crate::convert::Converter;
+use crate::encoding::{self, Encoding};
+use crate::error::BbvmError;
+use crate::ext::{self, Extension};
+use crate::lexer::{Lexer, Span};
+use crate::token::Statement;
+use crate::{collect_inputs, collect_variables, statements};
+
+// How much of the file is read before it is lexed, at least. Chunks only
+// end between statements, so one can be longer.
+const CHUNK_SIZE: usize = 1 << 20;
+
+// Whole lines of the file, starting at line and byte offset of the text
+// before it
+struct Chunk {
+    text: String,
+    line: usize,
+    offset: usize,
+}
+
+impl Chunk {
+    // A span in the chunk as a span in the whole file
+    fn global(&self, span: Span) -> Span {
+        Span {
+            line: span.line + self.line - 1,
+            column: span.column,
+            start: span.start + self.offset,
+            end: span.end + self.offset,
+        }
+    }
+
+    // The error, with its span in the whole file, and it rendered against
+    // the chunk if that is where it is
+    fn render(&self, error: BbvmError, name: &str) -> (BbvmError, String) {
+        let span = error.span();
+        if span.start < self.offset || span.end > self.offset + self.text.len() {
+            return unkept(error, name);
+        }
+        // Offsets into the chunk, but the line in the file
+        let local = Span {
+            start: span.start - self.offset,
+            end: span.end - self.offset,
+            ..span
+        };
+        let rendered = error.clone().with_span(local).render(&self.text, name);
+        (error, rendered)
+    }
+}
+
+// An error in a part of the file that has already been let go of, with
+// where it is but not the line itself
+fn unkept(error: BbvmError, name: &str) -> (BbvmError, String) {
+    let span = error.span();
+    let rendered = format!(
+        "ERROR[{}]: {}\n --> {}:{}:{}",
+        error.code(),
+        error.message(),
+        name,
+        span.line,
+        span.column
+    );
+    (error, rendered)
+}
+
+// Whether every statement is finished after the line, which is when it
+// ends with a `;` once its comment is taken off. Blank lines leave it as
+// the line before did.
+fn ends_statement(line: &[u8], before: bool) -> bool {
+    let code = line.split(|&b| b == b'#').next().unwrap();
+    match code.iter().rposition(|b| !b.is_ascii_whitespace()) {
+        Some(i) => code[i] == b';',
+        None => before,
+    }
+}
+
+struct Chunks<'n, R: Read> {
+    reader: BufReader<R>,
+    encoding: Encoding,
+    name: &'n str,
+    line: usize,
+    offset: usize,
+}
+
+impl<'n, R: Read> Chunks<'n, R> {
+    fn new(reader: R, encoding: Encoding, name: &'n str) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            encoding,
+            name,
+            line: 1,
+            offset: 0,
+        }
+    }
+
+    // The next chunk, or None at the end of the file
+    fn next(&mut self) -> Result<Option<Chunk>, (BbvmError, String)> {
+        let mut bytes = vec![];
+        let mut lines = 0;
+        let mut ended = true;
+        loop {
+            let start = bytes.len();
+            let read = self
+                .reader
+                .read_until(b'\n', &mut bytes)
+                .expect("ERROR: Failed to read the file");
+            if read == 0 {
+                break;
+            }
+            let line = &bytes[start..];
+            // Includes are spliced in by reading the whole file
+            let indent = line.iter().take_while(|b| b.is_ascii_whitespace()).count();
+            if line[indent..].starts_with(b"#include") {
+                let text = String::from_utf8_lossy(line).trim_end().to_string();
+                let error = BbvmError::IncludeFailed {
+                    file: text
+                        .trim_start()
+                        .trim_start_matches("#include")
+                        .trim()
+                        .trim_matches('"')
+                        .to_string(),
+                    reason: "--stream doesn't read #include, leave it off".to_string(),
+                    span: Span {
+                        line: self.line + lines,
+                        column: 1,
+                        start: 0,
+                        end: text.len(),
+                    },
+                };
+                let rendered = error.render(&text, self.name);
+                return Err((error, rendered));
+            }
+            lines += 1;
+            ended = ends_statement(line, ended);
+            if bytes.len() >= CHUNK_SIZE && ended {
+                break;
+            }
+        }
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+        let (line, offset) = (self.line, self.offset);
+        let text = encoding::decode(bytes, self.encoding).map_err(|(error, text)| {
+            let chunk = Chunk { text, line, offset };
+            let span = chunk.global(error.span());
+            chunk.render(error.with_span(span), self.name)
+        })?;
+        self.line += lines;
+        self.offset += text.len();
+        Ok(Some(Chunk { text, line, offset }))
+    }
+}
+
+// The names in a streamed program, found by scan, which lower's statements
+// borrow theirs from
+#[derive(Debug, Default)]
+pub struct Scanned {
+    names: HashSet<String>,
+    variables: Vec<String>,
+    inputs: Vec<String>,
+}
+
+impl Scanned {
+    // Every variable, in the order they are first used, as collect_variables
+    // gives them
+    pub fn variables(&self) -> Vec<&str> {
+        self.variables.iter().map(String::as_str).collect()
+    }
+
+    // Every input, sorted, as collect_inputs gives them
+    pub fn inputs(&self) -> Vec<&str> {
+        self.inputs.iter().map(String::as_str).collect()
+    }
+
+    fn intern<'a>(&'a self, statement: Statement) -> Statement<'a> {
+        statement.map_names(|name| {
+            self.names
+                .get(name)
+                .map(String::as_str)
+                .expect("ERROR: The file changed while it was being read")
+        })
+    }
+}
+
+// Lexes the program from reader, keeping only its names
+pub fn scan<R: Read>(
+    reader: R,
+    name: &str,
+    encoding: Encoding,
+) -> Result<Scanned, (BbvmError, String)> {
+    let mut scanned = Scanned::default();
+    let mut seen = HashSet::new();
+    let mut chunks = Chunks::new(reader, encoding, name);
+    while let Some(chunk) = chunks.next()? {
+        for statement in statements(&mut Lexer::new(&chunk.text)) {
+            let (statement, _) = statement.map_err(|e| {
+                let span = chunk.global(e.span());
+                chunk.render(e.with_span(span), name)
+            })?;
+            statement.map_names(|n| {
+                if !scanned.names.contains(n) {
+                    scanned.names.insert(n.to_string());
+                }
+                n
+            });
+            for variable in collect_variables([statement]) {
+                if seen.insert(variable.to_string()) {
+                    scanned.variables.push(variable.to_string());
+                }
+            }
+            scanned
+                .inputs
+                .extend(collect_inputs([statement]).into_iter().map(str::to_string));
+        }
+    }
+    scanned.inputs.sort();
+    scanned.inputs.dedup();
+    Ok(scanned)
+}
+
+// Generates the IR for the program from reader as each chunk is lexed.
+// Without the tree nothing is pruned or rewritten, and blocks are matched
+// up by the converter as they close.
+pub fn lower<'a, R: Read>(
+    converter: &mut Converter<'a>,
+    scanned: &'a Scanned,
+    reader: R,
+    name: &str,
+    encoding: Encoding,
+    extensions: &[Extension],
+) -> Result<(), (BbvmError, String)> {
+    let mut chunks = Chunks::new(reader, encoding, name);
+    let mut eof = Span::default();
+    while let Some(chunk) = chunks.next()? {
+        let mut l = Lexer::new(&chunk.text);
+        // Token by token, as statements does, to look up the unroll hint of
+        // each while in the lexer
+        while let Some(token) = l.next() {
+            let statement = token
+                .and_then(|t| Statement::try_from(t).map_err(|e| e.with_span(l.span())))
+                .map(|statement| (statement, l.span()));
+            let (statement, span) = statement.map_err(|e| {
+                let span = chunk.global(e.span());
+                chunk.render(e.with_span(span), name)
+            })?;
+            if let Statement::While(_) = statement {
+                if let Some(&(_, count)) = l.unrolls().iter().find(|&&(s, _)| s == span.start) {
+                    converter.set_unrolls(&[(chunk.global(span).start, count)]);
+                }
+            }
+            let span = chunk.global(span);
+            // Each chunk ends with one, only the last is the file's
+            if statement == Statement::EOF {
+                eof = span;
+                continue;
+            }
+            let statement = scanned.intern(statement);
+            ext::check(&[statement], &[span], extensions).map_err(|e| chunk.render(e, name))?;
+            converter.set_span(span);
+            converter
+                .add_statement(statement)
+                .map_err(|e| chunk.render(e, name))?;
+        }
+    }
+    converter.set_span(eof);
+    converter
+        .add_statement(Statement::EOF)
+        .map_err(|e| unkept(e, name))
+}
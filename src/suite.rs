@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    iter::zip,
+    path::{Path, PathBuf},
+};
+
+use inkwell::{context::Context, OptimizationLevel};
+
+use crate::bignum::Value;
+use crate::convert::Converter;
+use crate::diff;
+use crate::error::BbvmError;
+use crate::ext::{self, Extension};
+use crate::include::Source;
+use crate::input;
+use crate::interpret::Interpreter;
+use crate::lexer::Lexer;
+use crate::runner::Stopped;
+use crate::{collect_inputs, collect_variables, lex, lower};
+
+// How `bbvm test` runs each program
+#[derive(Debug, Clone, Copy)]
+pub enum Runner {
+    Interpreter,
+    Jit(OptimizationLevel),
+}
+
+// An .expected file: the inputs as --input-file takes them, then a line of
+// just `---`, then a `NAME=value` line for each variable to check. Without
+// a `---` the whole file is the values, for programs with no inputs.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Expected {
+    pub inputs: String,
+    pub values: Vec<(String, u64)>,
+}
+
+// Values are written the way bbvm prints them, signed, but anything that
+// fits in 64 bits is taken
+fn parse_value(value: &str) -> Option<u64> {
+    value
+        .parse::<u64>()
+        .ok()
+        .or_else(|| value.parse::<i64>().ok().map(|v| v as u64))
+}
+
+pub fn parse_expected(text: &str) -> Result<Expected, String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let (inputs, values) = match lines.iter().position(|l| l.trim() == "---") {
+        Some(i) => (lines[..i].join("\n"), &lines[i + 1..]),
+        None => (String::new(), &lines[..]),
+    };
+    let values = values
+        .iter()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|line| {
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{:?} should be NAME=value", line))?;
+            let value = parse_value(value.trim())
+                .ok_or_else(|| format!("{:?} is not a valid value", value.trim()))?;
+            Ok((name.trim().to_string(), value))
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(Expected { inputs, values })
+}
+
+// How one program did
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Outcome {
+    Passed,
+    // A diff of the expected values against the ones it ended with
+    Failed(String),
+    // The program or its .expected file couldn't be run or read
+    Error(String),
+}
+
+// Every .bb file in dir, sorted
+pub fn programs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut programs: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<_>>()?;
+    programs.retain(|p| p.extension().map_or(false, |e| e == "bb"));
+    programs.sort();
+    Ok(programs)
+}
+
+// Every .bb file in dir that has an .expected file next to it, sorted, and
+// the ones that don't
+pub fn cases(dir: &Path) -> io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    Ok(programs(dir)?
+        .into_iter()
+        .partition(|p| p.with_extension("expected").is_file()))
+}
+
+// The final values of every variable, running the program with the
+// inputs. Errors are rendered pointing into the source.
+fn final_values<'a>(
+    runner: Runner,
+    source: &'a Source,
+    expected: &Expected,
+    extensions: &[Extension],
+    fuel: Option<u64>,
+) -> Result<Vec<(&'a str, u64)>, String> {
+    let render = |e: BbvmError| source.render_error(&e);
+    let (statements, spans) = lex(&mut Lexer::new(&source.text)).map_err(render)?;
+    ext::check(&statements, &spans, extensions).map_err(render)?;
+    let (variables, inputs) = (collect_variables(&statements), collect_inputs(&statements));
+    let values: Vec<u64> = input::from_file(&inputs, &expected.inputs)
+        .and_then(|args| input::parse_args(&inputs, &args))
+        .map_err(|e| format!("bad inputs: {}", e))?;
+    match runner {
+        Runner::Interpreter => {
+            let mut interpreter = Interpreter::new(&statements, &spans).map_err(render)?;
+            if let Some(fuel) = fuel {
+                interpreter.set_fuel(fuel);
+            }
+            let values: Vec<(&str, u64)> = zip(inputs.iter().copied(), values).collect();
+            interpreter.execute(&values).map_err(render)?;
+            Ok(interpreter.results(&variables))
+        }
+        Runner::Jit(level) => {
+            let context = Context::create();
+            let mut converter = Converter::new(variables.clone(), &inputs, &context);
+            converter.set_optimization_level(level);
+            if let Some(fuel) = fuel {
+                converter.set_fuel(fuel);
+            }
+            lower(&mut converter, &statements, &spans, &source.text, false).map_err(render)?;
+            converter.optimise();
+            // A failure has printed what went wrong, a halt gives the values
+            // it stopped with as the interpreter does
+            match converter.run_for_results(&values) {
+                (_, _, _, Some(Stopped::Failed)) => {
+                    Err("it failed at run time under the JIT, see the message above".to_string())
+                }
+                (_, _, results, _) => Ok(zip(variables, results).collect()),
+            }
+        }
+    }
+}
+
+// Runs the program and compares the variables its .expected file lists
+pub fn run(
+    program: &Path,
+    runner: Runner,
+    extensions: &[Extension],
+    fuel: Option<u64>,
+    colour: bool,
+) -> Outcome {
+    let expected_path = program.with_extension("expected");
+    let expected = match fs::read_to_string(&expected_path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| parse_expected(&text))
+    {
+        Ok(expected) => expected,
+        Err(e) => return Outcome::Error(format!("{}: {}", expected_path.display(), e)),
+    };
+    let source = match Source::expand(program) {
+        Ok(source) => source,
+        Err((_, rendered)) => return Outcome::Error(rendered),
+    };
+    let results: HashMap<&str, u64> =
+        match final_values(runner, &source, &expected, extensions, fuel) {
+            Ok(results) => results.into_iter().collect(),
+            Err(e) => return Outcome::Error(e),
+        };
+
+    let wanted: Vec<String> = expected
+        .values
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value.show()))
+        .collect();
+    let got: Vec<String> = expected
+        .values
+        .iter()
+        .map(|(name, _)| match results.get(name.as_str()) {
+            Some(value) => format!("{}={}", name, value.show()),
+            None => format!("{} is not a variable of the program", name),
+        })
+        .collect();
+    if wanted == got {
+        return Outcome::Passed;
+    }
+    let wanted: Vec<&str> = wanted.iter().map(String::as_str).collect();
+    let got: Vec<&str> = got.iter().map(String::as_str).collect();
+    Outcome::Failed(diff::unified(&wanted, &got, 1, colour))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_inputs_and_values() {
+        let expected = parse_expected("X=3\nY = 4\n---\n# the results\nZ=7\n\nW = -1\n").unwrap();
+        assert_eq!(expected.inputs, "X=3\nY = 4");
+        assert_eq!(
+            expected.values,
+            vec![("Z".to_string(), 7), ("W".to_string(), u64::MAX)]
+        );
+    }
+
+    #[test]
+    fn parses_values_without_inputs() {
+        let expected = parse_expected("X=18446744073709551615\n").unwrap();
+        assert_eq!(expected.inputs, "");
+        assert_eq!(expected.values, vec![("X".to_string(), u64::MAX)]);
+        assert_eq!(parse_expected("").unwrap().values, vec![]);
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        assert!(parse_expected("X 3").is_err());
+        assert!(parse_expected("X=three").is_err());
+        assert!(parse_expected("X=18446744073709551616").is_err());
+        assert!(parse_expected("A=1\n---\nX=").is_err());
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// Times a run for --timeout. Programs run under the JIT as plain machine
+// code, so rather than being stopped from outside they check the flag the
+// timer sets wherever they use fuel, and fail once it is set.
+pub struct Timer {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// Clears timed_out and sets it once timeout has passed, unless the Timer
+// has been dropped by then
+pub fn start(timeout: Duration, timed_out: Arc<AtomicBool>) -> Timer {
+    timed_out.store(false, Ordering::SeqCst);
+    let (stop, stopped) = mpsc::channel();
+    let thread = thread::spawn(move || {
+        if let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(timeout) {
+            timed_out.store(true, Ordering::SeqCst);
+        }
+    });
+    Timer {
+        stop: Some(stop),
+        thread: Some(thread),
+    }
+}
+
+impl Drop for Timer {
+    // Waits for the thread, so it can't set the flag during a later run
+    fn drop(&mut self) -> () {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
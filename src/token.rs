@@ -1,8 +1,10 @@
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::str::FromStr;
+use std::{cmp::Ordering, fmt, str::FromStr};
 
 use crate::convert::Converter;
+use crate::error::BbvmError;
+use crate::lexer::Span;
 
 macro_rules! matches_token {
     ($str:literal, $i:ty) => {
@@ -37,50 +39,250 @@ pub trait TokenImpl {
     }
 }
 
+// What a single word of source is, for tools like the highlighter
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub enum TokenKind {
+    Keyword,
+    Fluff,
+    Identifier,
+    // An element of an array, A[I]
+    Element,
+    Number,
+    Comment,
+    Unknown,
+}
+
+impl TokenKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenKind::Keyword => "keyword",
+            TokenKind::Fluff => "fluff",
+            TokenKind::Identifier => "identifier",
+            TokenKind::Element => "element",
+            TokenKind::Number => "number",
+            TokenKind::Comment => "comment",
+            TokenKind::Unknown => "unknown",
+        }
+    }
+}
+
+pub fn classify(word: &str) -> TokenKind {
+    if word.starts_with('#') {
+        TokenKind::Comment
+    } else if Fluff::identify(word) {
+        TokenKind::Fluff
+    } else if is_keyword(word) {
+        TokenKind::Keyword
+    } else if Identifier::identify(word) {
+        TokenKind::Identifier
+    } else if Element::identify(word) {
+        TokenKind::Element
+    } else if Number::identify(word) {
+        TokenKind::Number
+    } else {
+        TokenKind::Unknown
+    }
+}
+
+// Whether the lexer would read word as something other than an identifier
+pub fn is_keyword(word: &str) -> bool {
+    While::identify(word)
+        || Assert::identify(word)
+        || TwoParam::identify(word)
+        || OneParam::identify(word)
+        || Fluff::identify(word)
+        || End::identify(word)
+        || Breakpoint::identify(word)
+        || Comparison::identify(word)
+        || If::identify(word)
+        || Else::identify(word)
+        || EndIf::identify(word)
+        || Proc::identify(word)
+        || EndProc::identify(word)
+        || Call::identify(word)
+        || Set::identify(word)
+        || Rand::identify(word)
+        || Array::identify(word)
+        || Macro::identify(word)
+        || EndMacro::identify(word)
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub enum Token<'b> {
     Number(Number),
     Identifier(Identifier<'b>),
+    Element(Element<'b>),
+    Comparison(Comparison),
     While(While<'b>),
+    If(If<'b>),
+    Else,
+    EndIf,
+    Proc(Proc<'b>),
+    EndProc,
+    Call(Call<'b>),
+    Assert(Assert<'b>),
+    Set(Set<'b>),
+    Rand(Rand<'b>),
+    Array(Array<'b>),
+    Indexed(Indexed<'b>),
     TwoParam(TwoParam<'b>),
     OneParam(OneParam<'b>),
     Fluff,
     End,
+    Breakpoint,
     EOF,
 }
 pub trait StatementImpl<'a> {
     fn get_variables(&self) -> Vec<&'a str> {
         vec![]
     }
-    fn compile(&self, _: &mut Converter<'a>) -> () {}
+    fn compile(&self, _: &mut Converter<'a>) -> Result<(), BbvmError> {
+        Ok(())
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub enum Statement<'b> {
     While(While<'b>),
+    If(If<'b>),
+    Else,
+    EndIf,
+    Proc(Proc<'b>),
+    EndProc,
+    Call(Call<'b>),
+    Assert(Assert<'b>),
+    Set(Set<'b>),
+    Rand(Rand<'b>),
+    Array(Array<'b>),
+    Indexed(Indexed<'b>),
     TwoParam(TwoParam<'b>),
     OneParam(OneParam<'b>),
     Fluff,
     End,
+    Breakpoint,
     EOF,
 }
 
+// The statement as BareBones source, as decompile::statement writes it.
+// Fluff and EOF are empty.
+impl fmt::Display for Statement<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            crate::decompile::statement(self).unwrap_or_default()
+        )
+    }
+}
+
+impl<'b> Statement<'b> {
+    // The same statement with each name in it, of variables, arrays and
+    // procs, replaced by what f gives for it
+    pub fn map_names<'c>(self, mut f: impl FnMut(&'b str) -> &'c str) -> Statement<'c> {
+        let mut id = |i: Identifier<'b>| Identifier { ident: f(i.ident) };
+        use Statement::*;
+        match self {
+            While(self::While { param, cmp, bound }) => While(self::While {
+                param: id(param),
+                cmp,
+                bound: match bound {
+                    Bound::Number(n) => Bound::Number(n),
+                    Bound::Variable(v) => Bound::Variable(id(v)),
+                },
+            }),
+            If(self::If { param, cmp, num }) => If(self::If {
+                param: id(param),
+                cmp,
+                num,
+            }),
+            Proc(self::Proc { name }) => Proc(self::Proc { name: id(name) }),
+            Call(self::Call { name }) => Call(self::Call { name: id(name) }),
+            Assert(self::Assert { param, num }) => Assert(self::Assert {
+                param: id(param),
+                num,
+            }),
+            Set(self::Set { var, num }) => Set(self::Set { var: id(var), num }),
+            Rand(self::Rand { var, bound }) => Rand(self::Rand {
+                var: id(var),
+                bound,
+            }),
+            Array(self::Array { name, size }) => Array(self::Array {
+                name: id(name),
+                size,
+            }),
+            Indexed(self::Indexed { element, op }) => Indexed(self::Indexed {
+                element: Element {
+                    array: id(element.array),
+                    index: id(element.index),
+                },
+                op: match op {
+                    ElementOp::Clear => ElementOp::Clear,
+                    ElementOp::Decr => ElementOp::Decr,
+                    ElementOp::Incr => ElementOp::Incr,
+                    ElementOp::Load(i) => ElementOp::Load(id(i)),
+                    ElementOp::Store(i) => ElementOp::Store(id(i)),
+                },
+            }),
+            TwoParam(self::TwoParam { one, two, ty }) => TwoParam(self::TwoParam {
+                one: id(one),
+                two: id(two),
+                ty,
+            }),
+            OneParam(self::OneParam { one, ty }) => OneParam(self::OneParam { one: id(one), ty }),
+            Else => Else,
+            EndIf => EndIf,
+            EndProc => EndProc,
+            Fluff => Fluff,
+            End => End,
+            Breakpoint => Breakpoint,
+            EOF => EOF,
+        }
+    }
+}
+
 impl<'a> TryFrom<Token<'a>> for Statement<'a> {
     fn try_from(t: Token<'a>) -> Result<Self, Self::Error> {
         use Token::*;
         match t {
-            Number(v) => Err(format!("{:?} is not a statement!", v)),
-            Identifier(v) => Err(format!("{:?} is not a statement!", v)),
+            // The lexer knows where these are, see crate::lex
+            Number(v) => Err(BbvmError::NotAStatement {
+                found: format!("the number {}", v.value),
+                span: Span::default(),
+            }),
+            Identifier(v) => Err(BbvmError::NotAStatement {
+                found: format!("the variable {}", v.ident),
+                span: Span::default(),
+            }),
+            Element(e) => Err(BbvmError::NotAStatement {
+                found: format!("the element {}[{}]", e.array.ident, e.index.ident),
+                span: Span::default(),
+            }),
+            Comparison(c) => Err(BbvmError::NotAStatement {
+                found: format!("the comparison `{}`", c.keyword()),
+                span: Span::default(),
+            }),
             While(v) => Ok(Statement::While(v)),
+            If(v) => Ok(Statement::If(v)),
+            Else => Ok(Statement::Else),
+            EndIf => Ok(Statement::EndIf),
+            Proc(v) => Ok(Statement::Proc(v)),
+            EndProc => Ok(Statement::EndProc),
+            Call(v) => Ok(Statement::Call(v)),
+            Assert(v) => Ok(Statement::Assert(v)),
+            Set(v) => Ok(Statement::Set(v)),
+            Rand(v) => Ok(Statement::Rand(v)),
+            Array(v) => Ok(Statement::Array(v)),
+            Indexed(v) => Ok(Statement::Indexed(v)),
             OneParam(v) => Ok(Statement::OneParam(v)),
             TwoParam(v) => Ok(Statement::TwoParam(v)),
             Fluff => Ok(Statement::Fluff),
             End => Ok(Statement::End),
+            Breakpoint => Ok(Statement::Breakpoint),
             EOF => Ok(Statement::EOF),
         }
     }
 
-    type Error = String;
+    type Error = BbvmError;
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
@@ -88,27 +290,114 @@ pub struct Identifier<'b> {
     pub ident: &'b str,
 }
 
-matches_token!("[a-zA-Z]\\w*", Identifier<'_>);
+matches_token!("^[a-zA-Z]\\w*$", Identifier<'_>);
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct Number {
     pub value: i128,
 }
 
-matches_token!("\\d+", Number);
+// Decimal or 0x hex, with underscores anywhere after the first digit to
+// group them, as in 1_000_000 or 0xffff_ffff
+matches_token!(
+    "^([0-9][0-9_]*|0x[0-9a-fA-F_]*[0-9a-fA-F][0-9a-fA-F_]*)$",
+    Number
+);
+
+impl Number {
+    // The value of a word Number::identify accepts, or None if it is too big
+    // for an i128
+    pub fn parse(word: &str) -> Option<i128> {
+        let digits = word.replace('_', "");
+        match digits.strip_prefix("0x") {
+            Some(hex) => i128::from_str_radix(hex, 16).ok(),
+            None => i128::from_str(&digits).ok(),
+        }
+    }
+}
+
+// How a while compares its variable with its number. The loop runs for as
+// long as the comparison holds.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Comparison {
+    // `not` or `ne`
+    Ne,
+    Lt,
+    Gt,
+}
+
+impl FromStr for Comparison {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "not" | "ne" => Ok(Self::Ne),
+            "lt" => Ok(Self::Lt),
+            "gt" => Ok(Self::Gt),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Comparison {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Comparison::Ne => "not",
+            Comparison::Lt => "lt",
+            Comparison::Gt => "gt",
+        }
+    }
+
+    pub fn holds(&self, value: u64, check: u64) -> bool {
+        self.holds_for(value.cmp(&check))
+    }
+
+    // Whether it holds for a value ordered this way against the check
+    pub fn holds_for(&self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Ne => ordering != Ordering::Equal,
+            Comparison::Lt => ordering == Ordering::Less,
+            Comparison::Gt => ordering == Ordering::Greater,
+        }
+    }
+}
+
+statement_token!(["not", "ne", "lt", "gt"], Comparison);
+
+// What a while compares its variable with: a number, or another variable
+// as it is each time round the loop, `while X not Y do;`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Bound<'b> {
+    Number(Number),
+    Variable(Identifier<'b>),
+}
+
+impl fmt::Display for Bound<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bound::Number(n) => write!(f, "{}", n.value),
+            Bound::Variable(v) => write!(f, "{}", v.ident),
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct While<'b> {
     pub param: Identifier<'b>,
-    pub num: Number,
+    pub cmp: Comparison,
+    pub bound: Bound<'b>,
 }
 
 impl<'a> StatementImpl<'a> for While<'a> {
     fn get_variables(&self) -> Vec<&'a str> {
-        vec![self.param.ident]
+        match self.bound {
+            Bound::Number(_) => vec![self.param.ident],
+            Bound::Variable(v) => vec![self.param.ident, v.ident],
+        }
     }
-    fn compile(&self, cont: &mut Converter<'a>) -> () {
-        cont.add_while(self.param.ident, self.num.value);
+    fn compile(&self, cont: &mut Converter<'a>) -> Result<(), BbvmError> {
+        cont.add_while(self.param.ident, self.cmp, self.bound);
+        Ok(())
     }
 }
 
@@ -118,22 +407,196 @@ impl<'b> TokenImpl for While<'b> {
     }
 }
 
+// Runs the statements up to the else (or the endif) if the comparison
+// holds, and the ones after the else otherwise
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct If<'b> {
+    pub param: Identifier<'b>,
+    pub cmp: Comparison,
+    pub num: Number,
+}
+
+impl<'a> StatementImpl<'a> for If<'a> {
+    fn get_variables(&self) -> Vec<&'a str> {
+        vec![self.param.ident]
+    }
+    fn compile(&self, cont: &mut Converter<'a>) -> Result<(), BbvmError> {
+        cont.add_if(self.param.ident, self.cmp, self.num.value);
+        Ok(())
+    }
+}
+
+statement_token!(["if"], If<'_>);
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Assert<'b> {
+    pub param: Identifier<'b>,
+    pub num: Number,
+}
+
+impl<'a> StatementImpl<'a> for Assert<'a> {
+    fn get_variables(&self) -> Vec<&'a str> {
+        vec![self.param.ident]
+    }
+    fn compile(&self, cont: &mut Converter<'a>) -> Result<(), BbvmError> {
+        cont.add_assert(self.param.ident, self.num.value);
+        Ok(())
+    }
+}
+
+statement_token!(["assert"], Assert<'_>);
+
+// Gives a variable a constant value, `set X to 5;` or `copy 5 to X;`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Set<'b> {
+    pub var: Identifier<'b>,
+    pub num: Number,
+}
+
+impl<'a> StatementImpl<'a> for Set<'a> {
+    fn get_variables(&self) -> Vec<&'a str> {
+        vec![self.var.ident]
+    }
+    fn compile(&self, cont: &mut Converter<'a>) -> Result<(), BbvmError> {
+        cont.add_set(self.var.ident, self.num.value);
+        Ok(())
+    }
+}
+
+statement_token!(["set"], Set<'_>);
+
+// `rand X 6;` gives X a random value from 0 up to but not including 6
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Rand<'b> {
+    pub var: Identifier<'b>,
+    pub bound: Number,
+}
+
+impl<'a> StatementImpl<'a> for Rand<'a> {
+    fn get_variables(&self) -> Vec<&'a str> {
+        vec![self.var.ident]
+    }
+    fn compile(&self, cont: &mut Converter<'a>) -> Result<(), BbvmError> {
+        cont.add_rand(self.var.ident, self.bound.value);
+        Ok(())
+    }
+}
+
+statement_token!(["rand"], Rand<'_>);
+
+// The arrays extension. `array A 100;` declares A with 100 elements, all 0
+// when the program starts, wherever the declaration is.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Array<'b> {
+    pub name: Identifier<'b>,
+    pub size: Number,
+}
+
+impl<'a> StatementImpl<'a> for Array<'a> {
+    fn compile(&self, cont: &mut Converter<'a>) -> Result<(), BbvmError> {
+        cont.add_array(self.name.ident, self.size.value)
+    }
+}
+
+statement_token!(["array"], Array<'_>);
+
+// One element of an array, `A[I]`, indexed by the value of a variable
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Element<'b> {
+    pub array: Identifier<'b>,
+    pub index: Identifier<'b>,
+}
+
+matches_token!("^[a-zA-Z]\\w*\\[[a-zA-Z]\\w*\\]$", Element<'_>);
+
+impl<'b> Element<'b> {
+    // The element a word matching identify names
+    pub fn parse(word: &'b str) -> Element<'b> {
+        let (array, index) = word.split_once('[').unwrap();
+        Element {
+            array: Identifier { ident: array },
+            index: Identifier {
+                ident: index.strip_suffix(']').unwrap(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum ElementOp<'b> {
+    Clear,
+    Decr,
+    Incr,
+    // copy A[I] to X
+    Load(Identifier<'b>),
+    // copy X to A[I]
+    Store(Identifier<'b>),
+}
+
+// A statement on an element of an array instead of a variable
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Indexed<'b> {
+    pub element: Element<'b>,
+    pub op: ElementOp<'b>,
+}
+
+impl<'a> StatementImpl<'a> for Indexed<'a> {
+    // The array itself isn't a variable
+    fn get_variables(&self) -> Vec<&'a str> {
+        match self.op {
+            ElementOp::Load(var) | ElementOp::Store(var) => {
+                vec![self.element.index.ident, var.ident]
+            }
+            _ => vec![self.element.index.ident],
+        }
+    }
+    fn compile(&self, cont: &mut Converter<'a>) -> Result<(), BbvmError> {
+        cont.add_indexed(self.element.array.ident, self.element.index.ident, self.op)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub enum TwoParamType {
     Copy,
+    // The arith extension, two = two op one
+    Add,
+    Sub,
+    Mul,
+    // Gives one the value of two and two the value of one
+    Swap,
+    // Copies one to two, then clears one
+    Move,
 }
 
 impl FromStr for TwoParamType {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
+        match s.to_lowercase().as_str() {
             "copy" => Ok(Self::Copy),
+            "add" => Ok(Self::Add),
+            "sub" => Ok(Self::Sub),
+            "mul" => Ok(Self::Mul),
+            "swap" => Ok(Self::Swap),
+            "move" => Ok(Self::Move),
             _ => Err(()),
         }
     }
 }
 
+impl TwoParamType {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            TwoParamType::Copy => "copy",
+            TwoParamType::Add => "add",
+            TwoParamType::Sub => "sub",
+            TwoParamType::Mul => "mul",
+            TwoParamType::Swap => "swap",
+            TwoParamType::Move => "move",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct TwoParam<'b> {
     pub one: Identifier<'b>,
@@ -145,14 +608,20 @@ impl<'a> StatementImpl<'a> for TwoParam<'a> {
     fn get_variables(&self) -> Vec<&'a str> {
         vec![self.one.ident, self.two.ident]
     }
-    fn compile(&self, cont: &mut Converter<'a>) -> () {
+    fn compile(&self, cont: &mut Converter<'a>) -> Result<(), BbvmError> {
         match self.ty {
             TwoParamType::Copy => cont.add_copy(self.one.ident, self.two.ident),
+            TwoParamType::Add => cont.add_addition(self.one.ident, self.two.ident),
+            TwoParamType::Sub => cont.add_subtraction(self.one.ident, self.two.ident),
+            TwoParamType::Mul => cont.add_multiplication(self.one.ident, self.two.ident),
+            TwoParamType::Swap => cont.add_swap(self.one.ident, self.two.ident),
+            TwoParamType::Move => cont.add_move(self.one.ident, self.two.ident),
         }
+        Ok(())
     }
 }
 
-statement_token!(["copy"], TwoParam<'_>);
+statement_token!(["copy", "add", "sub", "mul", "swap", "move"], TwoParam<'_>);
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub enum OneParamType {
@@ -160,17 +629,22 @@ pub enum OneParamType {
     Decr,
     Incr,
     Input,
+    Print,
+    // Stops the program, exiting with the variable's value
+    Halt,
 }
 
 impl FromStr for OneParamType {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
+        match s.to_lowercase().as_str() {
             "clear" => Ok(Self::Clear),
             "decr" => Ok(Self::Decr),
             "incr" => Ok(Self::Incr),
             "input" => Ok(Self::Input),
+            "print" => Ok(Self::Print),
+            "halt" => Ok(Self::Halt),
             _ => Err(()),
         }
     }
@@ -186,22 +660,28 @@ impl<'a> StatementImpl<'a> for OneParam<'a> {
     fn get_variables(&self) -> Vec<&'a str> {
         vec![self.one.ident]
     }
-    fn compile(&self, cont: &mut Converter<'a>) -> () {
+    fn compile(&self, cont: &mut Converter<'a>) -> Result<(), BbvmError> {
         match self.ty {
             OneParamType::Clear => cont.add_clear(self.one.ident),
             OneParamType::Decr => cont.add_decr(self.one.ident),
             OneParamType::Incr => cont.add_incr(self.one.ident),
             OneParamType::Input => {}
+            OneParamType::Print => cont.add_print(self.one.ident),
+            OneParamType::Halt => cont.add_halt(self.one.ident),
         }
+        Ok(())
     }
 }
 
-statement_token!(["clear", "decr", "incr", "input"], OneParam<'_>);
+statement_token!(
+    ["clear", "decr", "incr", "input", "print", "halt"],
+    OneParam<'_>
+);
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct Fluff {}
 
-statement_token!(["do", "not", "to"], Fluff);
+statement_token!(["do", "then", "to", "is", "from", "into"], Fluff);
 
 impl StatementImpl<'_> for Fluff {}
 
@@ -211,11 +691,97 @@ pub struct End {}
 statement_token!(["end"], End);
 
 impl StatementImpl<'_> for End {
-    fn compile(&self, cont: &mut Converter) -> () {
+    fn compile(&self, cont: &mut Converter) -> Result<(), BbvmError> {
         cont.add_end()
     }
 }
 
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Else {}
+
+statement_token!(["else"], Else);
+
+impl StatementImpl<'_> for Else {
+    fn compile(&self, cont: &mut Converter) -> Result<(), BbvmError> {
+        cont.add_else()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct EndIf {}
+
+statement_token!(["endif"], EndIf);
+
+impl StatementImpl<'_> for EndIf {
+    fn compile(&self, cont: &mut Converter) -> Result<(), BbvmError> {
+        cont.add_endif()
+    }
+}
+
+// A procedure, run wherever it is called rather than where it is written.
+// Procs share all their variables with the rest of the program.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Proc<'b> {
+    pub name: Identifier<'b>,
+}
+
+impl<'a> StatementImpl<'a> for Proc<'a> {
+    fn compile(&self, cont: &mut Converter<'a>) -> Result<(), BbvmError> {
+        cont.add_proc(self.name.ident)
+    }
+}
+
+statement_token!(["proc"], Proc<'_>);
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct EndProc {}
+
+statement_token!(["endproc"], EndProc);
+
+impl StatementImpl<'_> for EndProc {
+    fn compile(&self, cont: &mut Converter) -> Result<(), BbvmError> {
+        cont.add_endproc()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Call<'b> {
+    pub name: Identifier<'b>,
+}
+
+impl<'a> StatementImpl<'a> for Call<'a> {
+    fn compile(&self, cont: &mut Converter<'a>) -> Result<(), BbvmError> {
+        cont.add_call(self.name.ident);
+        Ok(())
+    }
+}
+
+statement_token!(["call"], Call<'_>);
+
+// Only seen by the macro expander, which takes every definition out before
+// the lexer runs
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Macro {}
+
+statement_token!(["macro"], Macro);
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct EndMacro {}
+
+statement_token!(["endmacro"], EndMacro);
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Breakpoint {}
+
+statement_token!(["breakpoint"], Breakpoint);
+
+impl StatementImpl<'_> for Breakpoint {
+    fn compile(&self, cont: &mut Converter) -> Result<(), BbvmError> {
+        cont.add_breakpoint();
+        Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct EOF {}
 
@@ -226,7 +792,75 @@ impl TokenImpl for EOF {
 }
 
 impl StatementImpl<'_> for EOF {
-    fn compile(&self, cont: &mut Converter) -> () {
+    fn compile(&self, cont: &mut Converter) -> Result<(), BbvmError> {
         cont.add_eof()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    // The error lexing source with width bit variables gives
+    fn lex_error(source: &str, width: u32) -> BbvmError {
+        let mut l = Lexer::new(source);
+        l.set_int_width(width);
+        crate::lex(&mut l).unwrap_err()
+    }
+
+    #[test]
+    fn parses_hex_and_underscored_numbers() {
+        for (word, value) in [
+            ("0", 0),
+            ("1_000_000", 1_000_000),
+            ("0xff", 0xff),
+            ("0xFFFF_ffff", 0xffff_ffff),
+            ("0x_1", 1),
+            ("1__0_", 10),
+        ] {
+            assert!(Number::identify(word), "{}", word);
+            assert_eq!(Number::parse(word), Some(value), "{}", word);
+        }
+        for word in ["_1", "0x", "0x_", "0xg", "1x1", "1a"] {
+            assert!(!Number::identify(word), "{}", word);
+        }
+    }
+
+    #[test]
+    fn rejects_numbers_too_wide_for_the_variables() {
+        let too_big = format!("0x1{}", "0".repeat(32));
+        assert!(Number::identify(&too_big));
+        assert_eq!(Number::parse(&too_big), None);
+        let source = format!("while X not {} do; end;", too_big);
+        assert!(matches!(
+            lex_error(&source, 64),
+            BbvmError::NumberTooBig { number, .. } if number == too_big
+        ));
+
+        assert!(matches!(
+            lex_error("while X not 0x1_0000_0000 do; end;", 32),
+            BbvmError::NumberOutOfRange { number, width: 32, .. } if number == "0x1_0000_0000"
+        ));
+        let mut l = Lexer::new("while X not 0xffff_ffff do; end;");
+        l.set_int_width(32);
+        assert!(crate::lex(&mut l).is_ok());
+    }
+
+    #[test]
+    fn rejects_negative_numbers() {
+        for word in ["-1", "-0x10", "-1_000"] {
+            assert!(!Number::identify(word));
+            let source = format!("while X not {} do; end;", word);
+            assert!(matches!(
+                lex_error(&source, 64),
+                BbvmError::NegativeNumber { number, .. } if number == word
+            ));
+        }
+        // Not a number after the -, so just a word bbvm doesn't know
+        assert!(matches!(
+            lex_error("while X not -Y do; end;", 64),
+            BbvmError::UnknownWord { .. }
+        ));
+    }
+}
@@ -0,0 +1,52 @@
+use std::{fs, io, path::Path, sync::Mutex, time::Instant};
+
+use lazy_static::lazy_static;
+
+// Events recorded by the JIT'd code through the callbacks below.
+// (phase, id, microseconds since the first event)
+lazy_static! {
+    static ref START: Instant = Instant::now();
+    static ref EVENTS: Mutex<Vec<(char, u64, f64)>> = Mutex::new(vec![]);
+}
+
+fn record(phase: char, id: u64) {
+    let ts = START.elapsed().as_secs_f64() * 1_000_000.0;
+    EVENTS.lock().unwrap().push((phase, id, ts));
+}
+
+pub extern "C" fn bbvm_trace_begin(id: u64) {
+    record('B', id);
+}
+
+pub extern "C" fn bbvm_trace_end(id: u64) {
+    record('E', id);
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Writes every recorded event as a Chrome tracing / Perfetto JSON file,
+// using `names` to label the ids the generated code passed in.
+pub fn write(path: &Path, names: &[String]) -> io::Result<()> {
+    let events = EVENTS.lock().unwrap();
+    let body = events
+        .iter()
+        .map(|(phase, id, ts)| {
+            format!(
+                "{{\"name\":\"{}\",\"cat\":\"bbvm\",\"ph\":\"{}\",\"ts\":{:.3},\"pid\":1,\"tid\":1}}",
+                escape(&names[*id as usize]),
+                phase,
+                ts
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(",\n");
+    fs::write(
+        path,
+        format!(
+            "{{\"traceEvents\":[\n{}\n],\"displayTimeUnit\":\"ns\"}}\n",
+            body
+        ),
+    )
+}